@@ -0,0 +1,40 @@
+#ifndef LUMI_H
+#define LUMI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LumiEngine LumiEngine;
+
+/* Creates an interpreter. Release it with lumi_engine_free. */
+LumiEngine *lumi_engine_new(void);
+void lumi_engine_free(LumiEngine *engine);
+
+/*
+ * Runs `source` with its output captured. Returns the exit code of the last
+ * command, or -1 if the input could not be parsed or executed, in which case
+ * lumi_last_error describes what went wrong.
+ */
+int lumi_eval(LumiEngine *engine, const char *source);
+
+/*
+ * Output captured by the last lumi_eval call. The returned strings are owned
+ * by the engine and stay valid until the next lumi_eval or lumi_engine_free.
+ */
+const char *lumi_stdout(const LumiEngine *engine);
+const char *lumi_stderr(const LumiEngine *engine);
+const char *lumi_last_error(const LumiEngine *engine);
+
+/* Returns 0 on success, -1 if the name or value is invalid. */
+int lumi_set_var(LumiEngine *engine, const char *name, const char *value);
+
+/* Returns null if unset; otherwise release the result with lumi_string_free. */
+char *lumi_get_var(const LumiEngine *engine, const char *name);
+void lumi_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,171 @@
+// A minimal C ABI for hosting the interpreter from other languages.
+// See include/lumi.h for the matching declarations.
+
+use std::ffi::{ CStr, CString };
+use std::os::raw::{ c_char, c_int };
+use std::ptr::{ null, null_mut };
+use shell::parsing::{ ShellLexer, ShellParser };
+use shell::context::ExecContext;
+
+pub struct LumiEngine {
+    context: ExecContext,
+    stdout: CString,
+    stderr: CString,
+    error: CString,
+}
+
+impl LumiEngine {
+    fn new() -> LumiEngine {
+        LumiEngine {
+            context: ExecContext::new(),
+            stdout: CString::default(),
+            stderr: CString::default(),
+            error: CString::default(),
+        }
+    }
+
+    fn eval( &mut self, source: &str ) -> Result<i32, String> {
+        let tokens = ShellLexer::new( source ).tokenize().map_err( | e | e.to_string() )?;
+        let seg = ShellParser::new( tokens ).parse_all().map_err( | e | e.to_string() )?;
+        let res = seg.execute( &mut self.context, true, None ).map_err( | e | {
+            self.context.set_last_status( 1 );
+            e.to_string()
+        } )?;
+
+        self.context.set_last_status( res.code().unwrap_or( 1 ) );
+
+        self.stdout = to_c_string( res.stdout().map( | x | x.join( "\n" ) ).unwrap_or_default() );
+        self.stderr = to_c_string( res.stderr().map( | x | x.join( "\n" ) ).unwrap_or_default() );
+
+        Ok( res.code().unwrap_or( -1 ) )
+    }
+}
+
+// interior NULs can't cross the C boundary, so they are dropped
+fn to_c_string( s: String ) -> CString {
+    CString::new( s.replace( '\0', "" ) ).unwrap_or_default()
+}
+
+unsafe fn to_str<'a>( s: *const c_char ) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr( s ).to_str().ok()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn lumi_engine_new() -> *mut LumiEngine {
+    Box::into_raw( Box::new( LumiEngine::new() ) )
+}
+
+/// # Safety
+/// `engine` must be null or a pointer returned by `lumi_engine_new` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_engine_free( engine: *mut LumiEngine ) {
+    if !engine.is_null() {
+        drop( Box::from_raw( engine ) );
+    }
+}
+
+/// Runs `source` with its output captured. Returns the exit code of the last
+/// command, or -1 if the input could not be parsed or executed (see `lumi_last_error`).
+///
+/// # Safety
+/// `engine` must come from `lumi_engine_new` and `source` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_eval( engine: *mut LumiEngine, source: *const c_char ) -> c_int {
+    let engine = match engine.as_mut() {
+        Some( x ) => x,
+        None => return -1,
+    };
+
+    engine.stdout = CString::default();
+    engine.stderr = CString::default();
+    engine.error = CString::default();
+
+    let source = match to_str( source ) {
+        Some( x ) => x,
+        None => {
+            engine.error = to_c_string( "source is null or not valid UTF-8".to_string() );
+            return -1;
+        }
+    };
+
+    match engine.eval( source ) {
+        Ok( code ) => code,
+        Err( e ) => {
+            engine.error = to_c_string( e );
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// `engine` must come from `lumi_engine_new`. The returned pointer stays valid
+/// until the next `lumi_eval` call on the same engine, or until it is freed.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_stdout( engine: *const LumiEngine ) -> *const c_char {
+    engine.as_ref().map_or( null(), | e | e.stdout.as_ptr() )
+}
+
+/// # Safety
+/// See `lumi_stdout`.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_stderr( engine: *const LumiEngine ) -> *const c_char {
+    engine.as_ref().map_or( null(), | e | e.stderr.as_ptr() )
+}
+
+/// # Safety
+/// See `lumi_stdout`.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_last_error( engine: *const LumiEngine ) -> *const c_char {
+    engine.as_ref().map_or( null(), | e | e.error.as_ptr() )
+}
+
+/// # Safety
+/// `engine` must come from `lumi_engine_new`; `name` and `value` must be
+/// null or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_set_var( engine: *mut LumiEngine, name: *const c_char, value: *const c_char ) -> c_int {
+    let engine = match engine.as_mut() {
+        Some( x ) => x,
+        None => return -1,
+    };
+
+    match ( to_str( name ), to_str( value ) ) {
+        ( Some( name ), Some( value ) ) if !name.is_empty() && !name.contains( '=' ) => {
+            engine.context.set_var( name, value );
+            0
+        },
+
+        _ => -1,
+    }
+}
+
+/// Returns a copy of the variable's value that must be released with
+/// `lumi_string_free`, or null if the variable isn't set.
+///
+/// # Safety
+/// `engine` must come from `lumi_engine_new`; `name` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_get_var( engine: *const LumiEngine, name: *const c_char ) -> *mut c_char {
+    let engine = match engine.as_ref() {
+        Some( x ) => x,
+        None => return null_mut(),
+    };
+
+    match to_str( name ).and_then( | name | engine.context.var( name ).ok() ) {
+        Some( value ) => to_c_string( value ).into_raw(),
+        None => null_mut(),
+    }
+}
+
+/// # Safety
+/// `s` must be null or a string returned by this library that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lumi_string_free( s: *mut c_char ) {
+    if !s.is_null() {
+        drop( CString::from_raw( s ) );
+    }
+}
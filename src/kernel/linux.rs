@@ -1,17 +1,735 @@
 use std::process::ExitStatus;
 use std::os::unix::process::ExitStatusExt;
+use std::io::{ Result, Error, ErrorKind, Write };
+use std::process::{ Child, Command, Stdio };
+use std::os::unix::process::CommandExt;
+use std::sync::Mutex;
+use std::path::{ Path, PathBuf };
+use std::env::var;
+use std::fs::{ read, read_dir, read_to_string };
+use dirs::home_dir;
+use kernel::{ Battery, ColorSupport, ProcessStatus };
+use std::time::Duration;
+use std::sync::atomic::{ AtomicBool, AtomicI32, Ordering };
+use libc::{
+    c_int,
+    c_void,
+    pid_t,
+    sigaction,
+    siginfo_t,
+    sighandler_t,
+    sigset_t,
+    sigemptyset,
+    sigaddset,
+    pthread_sigmask,
+    tcsetpgrp,
+    getpgid,
+    getpgrp,
+    SIGINT,
+    SIGQUIT,
+    SIGCHLD,
+    SIGTSTP,
+    SIGTTOU,
+    SIG_BLOCK,
+    SIG_SETMASK,
+    P_PID,
+    WEXITED,
+    WSTOPPED,
+    WNOWAIT,
+    CLD_STOPPED,
+    EINTR,
+    SA_SIGINFO,
+    SA_RESTART,
+    SA_NOCLDSTOP,
+    SI_USER,
+    termios,
+    tcgetattr,
+    tcsetattr,
+    isatty,
+    STDIN_FILENO,
+    STDOUT_FILENO,
+    TCSANOW,
+    ICANON,
+    ECHO,
+    ISIG,
+    IEXTEN,
+    IXON,
+    ICRNL,
+    VMIN,
+    VTIME,
+    poll,
+    pollfd,
+    POLLIN
+};
 
-pub fn clear_screen() {
+static INTERRUPTED: AtomicBool = AtomicBool::new( false );
+static CHILD_EXITED: AtomicBool = AtomicBool::new( false );
+
+// whether foreground programs get process groups of their own and the terminal with them
+static JOB_CONTROL: AtomicBool = AtomicBool::new( false );
+
+// pids of the foreground children, 0 for unused slots; a plain array because
+// the signal handler can't take a lock
+static FOREGROUND: [AtomicI32; 32] = [ const { AtomicI32::new( 0 ) }; 32 ];
+
+lazy_static! {
+    // terminal settings from before raw mode was enabled
+    static ref ORIGINAL_MODE: Mutex<Option<termios>> = Mutex::new( None );
+}
+
+/// # Safety
+/// Writes directly to the console; don't call while another thread is drawing.
+pub unsafe fn clear_screen() {
     print!( "\x1B[2J\x1B[H" );
+    let _ = std::io::stdout().flush();
+}
+
+/// # Safety
+/// Changes process-wide signal handling.
+pub unsafe fn handle_signals() {
+    let mut action: sigaction = std::mem::zeroed();
+    action.sa_sigaction = on_interrupt as extern "C" fn( c_int, *mut siginfo_t, *mut c_void ) as sighandler_t;
+    action.sa_flags = SA_SIGINFO | SA_RESTART;
+    sigemptyset( &mut action.sa_mask );
+    libc::sigaction( SIGINT, &action, std::ptr::null_mut() );
+
+    // Ctrl-\ shouldn't kill the shell; as with SIGTSTP this is a handler that does
+    // nothing rather than SIG_IGN, so programs we start get the default back on exec
+    action.sa_sigaction = on_stop as extern "C" fn( c_int ) as sighandler_t;
+    action.sa_flags = SA_RESTART;
+    libc::sigaction( SIGQUIT, &action, std::ptr::null_mut() );
+
+    action.sa_sigaction = on_child_exit as extern "C" fn( c_int ) as sighandler_t;
+    action.sa_flags = SA_RESTART | SA_NOCLDSTOP;
+    libc::sigaction( SIGCHLD, &action, std::ptr::null_mut() );
+}
+
+// Children are left for the job table to reap, since a `Child` that was
+// waited for behind std's back can't report its status any more.
+extern "C" fn on_child_exit( _: c_int ) {
+    CHILD_EXITED.store( true, Ordering::SeqCst );
+}
+
+// Whether a child process has exited since the last call, clearing the flag.
+pub fn take_child_exited() -> bool {
+    CHILD_EXITED.swap( false, Ordering::SeqCst )
+}
+
+extern "C" fn on_interrupt( _: c_int, info: *mut siginfo_t, _: *mut c_void ) {
+    INTERRUPTED.store( true, Ordering::SeqCst );
+
+    // Ctrl-C typed at the terminal already reaches every process in the
+    // foreground group, only a SIGINT sent to the shell itself needs passing on
+    if info.is_null() || unsafe { ( *info ).si_code } != SI_USER {
+        return;
+    }
+
+    for slot in FOREGROUND.iter() {
+        let pid = slot.load( Ordering::SeqCst );
+        if pid <= 0 {
+            continue;
+        }
+
+        unsafe {
+            let group = libc::getpgid( pid );
+            if group > 0 && group != libc::getpgrp() {
+                libc::kill( -group, SIGINT );
+            } else {
+                libc::kill( pid, SIGINT );
+            }
+        }
+    }
+}
+
+/// # Safety
+/// Changes process-wide signal handling and the terminal's foreground process group.
+pub unsafe fn enable_job_control() {
+    if !stdin_is_tty() {
+        return;
+    }
+
+    // Ctrl-Z at the prompt shouldn't stop the shell itself. This is a handler rather
+    // than ignoring the signal because programs inherit ignored signals, but not handlers.
+    let mut action: sigaction = std::mem::zeroed();
+    action.sa_sigaction = on_stop as extern "C" fn( c_int ) as sighandler_t;
+    action.sa_flags = SA_RESTART;
+    sigemptyset( &mut action.sa_mask );
+    libc::sigaction( SIGTSTP, &action, std::ptr::null_mut() );
+
+    // fails harmlessly when the shell already leads its own group
+    libc::setpgid( 0, 0 );
+
+    if give_terminal( getpgrp() ) {
+        JOB_CONTROL.store( true, Ordering::SeqCst );
+    }
+}
+
+extern "C" fn on_stop( _: c_int ) {}
+
+// Makes `group` the terminal's foreground process group.
+fn give_terminal( group: pid_t ) -> bool {
+    unsafe {
+        // taking the terminal back from a job happens while the shell is in the
+        // background, which would otherwise stop it with SIGTTOU
+        let mut block: sigset_t = std::mem::zeroed();
+        let mut orig: sigset_t = std::mem::zeroed();
+        sigemptyset( &mut block );
+        sigaddset( &mut block, SIGTTOU );
+        pthread_sigmask( SIG_BLOCK, &block, &mut orig );
+
+        let ok = tcsetpgrp( STDIN_FILENO, group ) == 0;
+        pthread_sigmask( SIG_SETMASK, &orig, std::ptr::null_mut() );
+        ok
+    }
+}
+
+// Starts `proc` in the process group led by `leader`, or a new one of its own,
+// so Ctrl-C and Ctrl-Z only reach the job they were meant for.
+pub fn new_process_group( proc: &mut Command, leader: Option<u32> ) {
+    if JOB_CONTROL.load( Ordering::SeqCst ) {
+        proc.process_group( leader.map_or( 0, | x | x as pid_t ) );
+    }
+}
+
+// Waits for a child to exit, or returns None if it was stopped instead.
+pub fn wait_or_stop( child: &mut Child ) -> Result<Option<ExitStatus>> {
+    let mut info: siginfo_t = unsafe { std::mem::zeroed() };
+
+    // WNOWAIT leaves an exited child for `Child` to reap, so it keeps track of the status
+    while unsafe { libc::waitid( P_PID, child.id(), &mut info, WEXITED | WSTOPPED | WNOWAIT ) } != 0 {
+        let e = Error::last_os_error();
+        if e.raw_os_error() != Some( EINTR ) {
+            return Err( e );
+        }
+    }
+
+    if info.si_code == CLD_STOPPED {
+        return Ok( None );
+    }
+
+    let status = child.wait()?;
+
+    // Ctrl-C only went to the job's own process group, so the shell learns about
+    // it from how the job ended
+    if status.signal() == Some( SIGINT ) {
+        INTERRUPTED.store( true, Ordering::SeqCst );
+    }
+
+    Ok( Some( status ) )
+}
+
+// Whether Ctrl-C was pressed since the last call, clearing the flag.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap( false, Ordering::SeqCst )
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load( Ordering::SeqCst )
+}
+
+// Marks a child process as running in the foreground until the guard is dropped,
+// so an interrupt sent to the shell is forwarded to it. With job control the
+// child's process group also owns the terminal meanwhile.
+pub struct Foreground {
+    slot: Option<usize>,
+    terminal: bool,
+}
+
+impl Foreground {
+    pub fn new( pid: u32 ) -> Foreground {
+        let slot = FOREGROUND.iter().position( | x | {
+            x.compare_exchange( 0, pid as pid_t, Ordering::SeqCst, Ordering::SeqCst ).is_ok()
+        } );
+
+        let group = unsafe { getpgid( pid as pid_t ) };
+        let terminal = JOB_CONTROL.load( Ordering::SeqCst ) && group > 0 && group != unsafe { getpgrp() } && give_terminal( group );
+
+        Foreground { slot, terminal }
+    }
+}
+
+impl Drop for Foreground {
+    fn drop( &mut self ) {
+        if let Some( i ) = self.slot {
+            FOREGROUND[i].store( 0, Ordering::SeqCst );
+        }
+
+        if self.terminal {
+            give_terminal( unsafe { getpgrp() } );
+        }
+    }
+}
+
+pub fn process_status( status: ExitStatus ) -> ProcessStatus {
+    match ( status.code(), status.signal() ) {
+        ( Some( x ), _ ) => ProcessStatus::Exited( x ),
+        ( None, Some( x ) ) => ProcessStatus::Signaled( x ),
+        ( None, None ) => ProcessStatus::Unknown,
+    }
+}
+
+const SIGNALS: &[( i32, &str )] = &[
+    ( libc::SIGHUP, "SIGHUP" ),
+    ( libc::SIGINT, "SIGINT" ),
+    ( libc::SIGQUIT, "SIGQUIT" ),
+    ( libc::SIGILL, "SIGILL" ),
+    ( libc::SIGTRAP, "SIGTRAP" ),
+    ( libc::SIGABRT, "SIGABRT" ),
+    ( libc::SIGBUS, "SIGBUS" ),
+    ( libc::SIGFPE, "SIGFPE" ),
+    ( libc::SIGKILL, "SIGKILL" ),
+    ( libc::SIGUSR1, "SIGUSR1" ),
+    ( libc::SIGSEGV, "SIGSEGV" ),
+    ( libc::SIGUSR2, "SIGUSR2" ),
+    ( libc::SIGPIPE, "SIGPIPE" ),
+    ( libc::SIGALRM, "SIGALRM" ),
+    ( libc::SIGTERM, "SIGTERM" ),
+    ( libc::SIGCHLD, "SIGCHLD" ),
+    ( libc::SIGCONT, "SIGCONT" ),
+    ( libc::SIGSTOP, "SIGSTOP" ),
+    ( libc::SIGTSTP, "SIGTSTP" ),
+    ( libc::SIGTTIN, "SIGTTIN" ),
+    ( libc::SIGTTOU, "SIGTTOU" ),
+    ( libc::SIGURG, "SIGURG" ),
+    ( libc::SIGXCPU, "SIGXCPU" ),
+    ( libc::SIGXFSZ, "SIGXFSZ" ),
+    ( libc::SIGVTALRM, "SIGVTALRM" ),
+    ( libc::SIGPROF, "SIGPROF" ),
+    ( libc::SIGWINCH, "SIGWINCH" ),
+    ( libc::SIGSYS, "SIGSYS" ),
+];
+
+// e.g. SIGSEGV for 11
+pub fn signal_name( signal: i32 ) -> Option<&'static str> {
+    SIGNALS.iter().find( | x | x.0 == signal ).map( | x | x.1 )
+}
+
+// Whether starting a program failed because it's neither a binary for this
+// system nor a script with a #! line.
+pub fn is_exec_format_error( e: &Error ) -> bool {
+    e.raw_os_error() == Some( libc::ENOEXEC )
+}
+
+// Room left for a new program's arguments: ARG_MAX covers them and the
+// environment together, and some is kept back for what the loader adds.
+pub fn argument_space() -> usize {
+    let max = match unsafe { libc::sysconf( libc::_SC_ARG_MAX ) } {
+        x if x > 0 => x as usize,
+        _ => 128 * 1024,
+    };
+
+    let env: usize = std::env::vars_os().map( | ( k, v ) | k.len() + v.len() + 2 + size_of::<usize>() ).sum();
+    max.saturating_sub( env ).saturating_sub( 4096 )
+}
+
+// How much of that room one argument takes: its bytes, the NUL after them and
+// the pointer to it. Linux refuses any single one over 128KiB outright.
+pub fn argument_size( arg: &str ) -> usize {
+    if arg.len() >= 32 * 4096 {
+        return usize::MAX;
+    }
+
+    arg.len() + 1 + size_of::<usize>()
+}
+
+// Every signal `kill` knows by name, as (number, name).
+pub fn signals() -> &'static [( i32, &'static str )] {
+    SIGNALS
+}
+
+// The signal called `name`, with or without the SIG and in any case, or given by number.
+pub fn signal_number( name: &str ) -> Option<i32> {
+    if let Ok( x ) = name.parse() {
+        return Some( x );
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix( "SIG" ).unwrap_or( &name );
+    SIGNALS.iter().find( | x | &x.1[3..] == name ).map( | x | x.0 )
+}
+
+pub fn send_signal( pid: u32, signal: i32 ) -> Result<()> {
+    if unsafe { libc::kill( pid as libc::pid_t, signal ) } == 0 {
+        Ok( () )
+    } else {
+        Err( Error::last_os_error() )
+    }
+}
+
+pub fn stdin_is_tty() -> bool {
+    unsafe { isatty( STDIN_FILENO ) == 1 }
+}
+
+pub fn stdout_is_tty() -> bool {
+    unsafe { isatty( STDOUT_FILENO ) == 1 }
+}
+
+// Waits up to `timeout` for something to read on stdin, returning false if nothing came.
+pub fn wait_for_input( timeout: Duration ) -> Result<bool> {
+    let mut fd = pollfd { fd: STDIN_FILENO, events: POLLIN, revents: 0 };
+    match unsafe { poll( &mut fd, 1, timeout.as_millis() as c_int ) } {
+        -1 => {
+            let e = Error::last_os_error();
+
+            // a signal such as SIGCHLD or SIGWINCH is as good as a timeout
+            if e.raw_os_error() == Some( EINTR ) { Ok( false ) } else { Err( e ) }
+        },
+
+        x => Ok( x > 0 ),
+    }
+}
+
+pub fn get_color_support() -> ColorSupport {
+    if unsafe { isatty( STDOUT_FILENO ) } != 1 {
+        return ColorSupport::None;
+    }
+
+    if let Ok( x ) = var( "COLORTERM" ) {
+        if x == "truecolor" || x == "24bit" {
+            return ColorSupport::TrueColor;
+        }
+    }
+
+    let term = match var( "TERM" ) {
+        Ok( x ) if !x.is_empty() && x != "dumb" => x,
+        _ => return ColorSupport::None,
+    };
+
+    if term.ends_with( "-direct" ) {
+        return ColorSupport::TrueColor;
+    }
+
+    match terminfo_colors( &term ) {
+        Some( x ) if x >= 0x1000000 => ColorSupport::TrueColor,
+        Some( x ) if x >= 256 => ColorSupport::Colors256,
+        Some( x ) if x >= 8 => ColorSupport::Default,
+        Some( _ ) => ColorSupport::None,
+
+        // no terminfo entry to go by, so trust the name
+        None if term.contains( "256color" ) => ColorSupport::Colors256,
+        None => ColorSupport::Default,
+    }
+}
+
+// The `colors` capability from the compiled terminfo entry for `term`.
+fn terminfo_colors( term: &str ) -> Option<i32> {
+    let first = term.chars().next()?;
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Ok( x ) = var( "TERMINFO" ) {
+        dirs.push( x.into() );
+    }
+
+    if let Some( home ) = home_dir() {
+        dirs.push( home.join( ".terminfo" ) );
+    }
+
+    dirs.extend( [ "/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo" ].iter().map( PathBuf::from ) );
+
+    // entries live under either their first letter or its hex code, depending on the system
+    let entry = dirs.iter()
+        .flat_map( | dir | vec![ dir.join( first.to_string() ).join( term ), dir.join( format!( "{:x}", first as u32 ) ).join( term ) ] )
+        .find( | x | Path::is_file( x ) )?;
+
+    let data = read( entry ).ok()?;
+    let short = | i: usize | data.get( i .. i + 2 ).map( | x | i16::from_le_bytes( [ x[0], x[1] ] ) as i32 );
+
+    // the 32-bit format only differs in the size of the numbers
+    let width = match short( 0 )? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+
+    let ( names, bools, numbers ) = ( short( 2 )? as usize, short( 4 )? as usize, short( 6 )? as usize );
+
+    // `colors` is the 14th number, and the numbers start on an even offset
+    const COLORS: usize = 13;
+    if numbers <= COLORS {
+        return None;
+    }
+
+    let start = ( 12 + names + bools + 1 ) & !1;
+    let at = start + COLORS * width;
+    let value = match width {
+        2 => short( at )?,
+        _ => data.get( at .. at + 4 ).map( | x | i32::from_le_bytes( [ x[0], x[1], x[2], x[3] ] ) )?,
+    };
+
+    Some( value ).filter( | x | *x >= 0 )
+}
+
+/// # Safety
+/// Must be paired with `disable_raw_mode` before handing the terminal to a child process.
+pub unsafe fn enable_raw_mode() -> Result<()> {
+    let mut original = ORIGINAL_MODE.lock().unwrap();
+    let mut mode: termios = std::mem::zeroed();
+    if tcgetattr( STDIN_FILENO, &mut mode ) != 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    if original.is_none() {
+        *original = Some( mode );
+    }
+
+    mode.c_lflag &= !( ICANON | ECHO | ISIG | IEXTEN );
+    mode.c_iflag &= !( IXON | ICRNL );
+    mode.c_cc[VMIN] = 1;
+    mode.c_cc[VTIME] = 0;
+
+    if tcsetattr( STDIN_FILENO, TCSANOW, &mode ) != 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    Ok( () )
+}
+
+/// # Safety
+/// Restores the terminal settings saved by `enable_raw_mode`.
+pub unsafe fn disable_raw_mode() -> Result<()> {
+    if let Some( mode ) = ORIGINAL_MODE.lock().unwrap().take() {
+        if tcsetattr( STDIN_FILENO, TCSANOW, &mode ) != 0 {
+            return Err( Error::last_os_error() );
+        }
+    }
+
+    Ok( () )
+}
+
+// Secrets live in the user's keyring: the Keychain through `security` on
+// macOS, or any libsecret provider through `secret-tool` elsewhere.
+
+fn keyring_error( e: Error ) -> Error {
+    if e.kind() == ErrorKind::NotFound {
+        Error::new( ErrorKind::NotFound, KEYRING_TOOL_MISSING )
+    } else {
+        e
+    }
+}
+
+#[cfg( target_os = "macos" )]
+const KEYRING_TOOL_MISSING: &str = "the 'security' tool is required to access the keychain";
+
+#[cfg( not( target_os = "macos" ) )]
+const KEYRING_TOOL_MISSING: &str = "the 'secret-tool' program (libsecret) is required to access the keyring";
+
+// The line that has `security -i` store a secret in the keychain. It goes to the
+// tool's stdin rather than its arguments, which anyone can see in the process list,
+// and the value is hex encoded so nothing in it needs quoting.
+pub fn keychain_store_command( name: &str, value: &str ) -> String {
+    let name = name.replace( '\\', "\\\\" ).replace( '"', "\\\"" );
+    let hex: String = value.bytes().map( | x | format!( "{:02x}", x ) ).collect();
+    format!( "add-generic-password -U -s lumi -a \"{}\" -X {}\n", name, hex )
+}
+
+#[cfg( target_os = "macos" )]
+pub fn store_secret( name: &str, value: &str ) -> Result<()> {
+    let mut child = Command::new( "security" )
+        .arg( "-i" )
+        .stdin( Stdio::piped() )
+        .stdout( Stdio::null() )
+        .stderr( Stdio::piped() )
+        .spawn()
+        .map_err( keyring_error )?;
+
+    if let Some( stdin ) = child.stdin.as_mut() {
+        stdin.write_all( keychain_store_command( name, value ).as_bytes() )?;
+    }
+
+    // in interactive mode a failed command is only reported, not reflected in the status
+    let output = child.wait_with_output()?;
+    if output.status.success() && output.stderr.is_empty() {
+        Ok( () )
+    } else {
+        Err( Error::other( "unable to write to the keychain" ) )
+    }
+}
+
+#[cfg( target_os = "macos" )]
+pub fn load_secret( name: &str ) -> Result<Option<String>> {
+    let output = Command::new( "security" )
+        .args( [ "find-generic-password", "-s", "lumi", "-a", name, "-w" ] )
+        .stderr( Stdio::null() )
+        .output()
+        .map_err( keyring_error )?;
+
+    if !output.status.success() {
+        return Ok( None );
+    }
+
+    let value = String::from_utf8_lossy( &output.stdout );
+    Ok( Some( value.trim_end_matches( '\n' ).to_string() ) )
 }
 
-pub unsafe fn disable_ctrl_c() {
-    // TODO
+#[cfg( not( target_os = "macos" ) )]
+pub fn store_secret( name: &str, value: &str ) -> Result<()> {
+    let mut child = Command::new( "secret-tool" )
+        .args( [ "store", &format!( "--label=lumi: {}", name ), "service", "lumi", "name", name ] )
+        .stdin( Stdio::piped() )
+        .spawn()
+        .map_err( keyring_error )?;
+
+    if let Some( stdin ) = child.stdin.as_mut() {
+        stdin.write_all( value.as_bytes() )?;
+    }
+
+    if child.wait()?.success() { Ok( () ) } else { Err( Error::other( "unable to write to the keyring" ) ) }
+}
+
+#[cfg( not( target_os = "macos" ) )]
+pub fn load_secret( name: &str ) -> Result<Option<String>> {
+    let output = Command::new( "secret-tool" )
+        .args( [ "lookup", "service", "lumi", "name", name ] )
+        .stderr( Stdio::null() )
+        .output()
+        .map_err( keyring_error )?;
+
+    if output.status.success() {
+        Ok( Some( String::from_utf8_lossy( &output.stdout ).into_owned() ) )
+    } else {
+        Ok( None )
+    }
+}
+
+// Shows `title` in the terminal's title bar or tab.
+pub fn set_title( title: &str ) {
+    if stdout_is_tty() {
+        // control characters would end the sequence early
+        let title: String = title.chars().filter( | x | !x.is_control() ).collect();
+        print!( "\x1B]0;{}\x07", title );
+        let _ = std::io::stdout().flush();
+    }
+}
+
+// Whether the terminal window currently has focus, or None if we can't tell.
+pub fn terminal_has_focus() -> Option<bool> {
+    // only X11 terminals advertise their window id
+    let window: u64 = std::env::var( "WINDOWID" ).ok()?.parse().ok()?;
+    let output = Command::new( "xdotool" )
+        .arg( "getactivewindow" )
+        .stderr( Stdio::null() )
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let active: u64 = String::from_utf8_lossy( &output.stdout ).trim().parse().ok()?;
+    Some( active == window )
+}
+
+#[cfg( target_os = "macos" )]
+pub fn notify( title: &str, body: &str ) -> Result<()> {
+    let quote = | s: &str | format!( "\"{}\"", s.replace( '\\', "\\\\" ).replace( '"', "\\\"" ) );
+    let script = format!( "display notification {} with title {}", quote( body ), quote( title ) );
+
+    Command::new( "osascript" )
+        .args( [ "-e", &script ] )
+        .stdout( Stdio::null() )
+        .stderr( Stdio::null() )
+        .spawn()
+        .map( | _ | () )
+}
+
+#[cfg( not( target_os = "macos" ) )]
+pub fn notify( title: &str, body: &str ) -> Result<()> {
+    Command::new( "notify-send" )
+        .args( [ "--app-name=lumi", title, body ] )
+        .stdout( Stdio::null() )
+        .stderr( Stdio::null() )
+        .spawn()
+        .map( | _ | () )
+}
+
+// Sends SIGCONT so a stopped background job carries on running.
+pub fn resume_process( pid: u32 ) -> Result<()> {
+    if unsafe { libc::kill( pid as libc::pid_t, libc::SIGCONT ) } == 0 {
+        return Ok( () );
+    }
+
+    // a process that has already exited has nothing to resume
+    match Error::last_os_error() {
+        ref e if e.raw_os_error() == Some( libc::ESRCH ) => Ok( () ),
+        e => Err( e ),
+    }
 }
 
-pub fn get_exit_code( status: ExitStatus ) -> Option<i32> {
-    match status.code() {
-        Some( x ) => Some( x ),
-        None => status.signal()
+// Asks a child to stop with SIGTERM, giving it the chance to clean up.
+pub fn terminate( child: &mut Child ) -> Result<()> {
+    if unsafe { libc::kill( child.id() as libc::pid_t, libc::SIGTERM ) } == 0 {
+        return Ok( () );
     }
+
+    match Error::last_os_error() {
+        ref e if e.raw_os_error() == Some( libc::ESRCH ) => Ok( () ),
+        e => Err( e ),
+    }
+}
+
+// Programs share the shell's terminal on unix, so they never need one made for them.
+pub fn run_in_pseudo_console( _proc: &Command ) -> Result<Option<i32>> {
+    Ok( None )
+}
+
+// CPU time used by all finished children so far, as (user, system).
+pub fn children_cpu_time() -> Option<( Duration, Duration )> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage( libc::RUSAGE_CHILDREN, &mut usage ) } != 0 {
+        return None;
+    }
+
+    let time = | x: libc::timeval | Duration::new( x.tv_sec as u64, x.tv_usec as u32 * 1000 );
+    Some( ( time( usage.ru_utime ), time( usage.ru_stime ) ) )
+}
+
+// Home directory of another user account, for ~user expansion.
+pub fn user_home( name: &str ) -> Option<PathBuf> {
+    use std::ffi::{ CStr, CString };
+
+    let name = CString::new( name ).ok()?;
+    unsafe {
+        let entry = libc::getpwnam( name.as_ptr() );
+        if entry.is_null() || ( *entry ).pw_dir.is_null() {
+            return None;
+        }
+
+        let dir = CStr::from_ptr( ( *entry ).pw_dir ).to_string_lossy().into_owned();
+        Some( PathBuf::from( dir ) )
+    }
+}
+
+// Local wall-clock time as (hours, minutes, seconds).
+pub fn local_time() -> ( u32, u32, u32 ) {
+    unsafe {
+        let now = libc::time( std::ptr::null_mut() );
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r( &now, &mut tm );
+
+        ( tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32 )
+    }
+}
+
+// Where PATH points when a login shell is started without one.
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin:/usr/local/sbin:/usr/sbin:/sbin";
+
+// Run by every login shell before the user's own login script.
+pub fn system_profile() -> Option<PathBuf> {
+    Some( PathBuf::from( "/etc/lumi/profile" ) )
+}
+
+// The first battery under /sys/class/power_supply, or None on machines without one.
+pub fn battery() -> Option<Battery> {
+    for entry in read_dir( "/sys/class/power_supply" ).ok()?.flatten() {
+        let dir = entry.path();
+        let read = | name: &str | read_to_string( dir.join( name ) ).map( | x | x.trim().to_string() ).unwrap_or_default();
+        if read( "type" ) != "Battery" {
+            continue;
+        }
+
+        if let Ok( percent ) = read( "capacity" ).parse() {
+            return Some( Battery { percent, charging: read( "status" ) == "Charging" } );
+        }
+    }
+
+    None
 }
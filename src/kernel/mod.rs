@@ -1,3 +1,65 @@
+use std::fmt::{ Display, Formatter };
+
+// How many colors the terminal behind stdout can show.
+#[derive( Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd )]
+pub enum ColorSupport {
+    // not a terminal, or one that can't show color at all
+    None,
+
+    // the 8 standard colors
+    Default,
+    Colors256,
+    TrueColor,
+}
+
+// How a program finished.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub enum ProcessStatus {
+    Exited( i32 ),
+
+    // killed by a signal, e.g. SIGSEGV; Unix only
+    Signaled( i32 ),
+
+    // for commands that never got as far as running anything
+    Unknown,
+}
+
+impl ProcessStatus {
+    // The status as $? shows it: signals are 128 plus their number, like in sh.
+    pub fn code( &self ) -> Option<i32> {
+        match *self {
+            ProcessStatus::Exited( x ) => Some( x ),
+            ProcessStatus::Signaled( x ) => Some( 128 + x ),
+            ProcessStatus::Unknown => None,
+        }
+    }
+
+    pub fn success( &self ) -> bool {
+        *self == ProcessStatus::Exited( 0 )
+    }
+}
+
+impl Display for ProcessStatus {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        match *self {
+            ProcessStatus::Exited( x ) => write!( formatter, "exited with status {}", x ),
+            ProcessStatus::Signaled( x ) => match signal_name( x ) {
+                Some( name ) => write!( formatter, "terminated by {}", name ),
+                None => write!( formatter, "terminated by signal {}", x ),
+            },
+
+            ProcessStatus::Unknown => write!( formatter, "finished with an unknown status" ),
+        }
+    }
+}
+
+// Charge of the machine's battery.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub struct Battery {
+    pub percent: u8,
+    pub charging: bool,
+}
+
 #[cfg( windows )]
 pub mod windows;
 
@@ -5,17 +5,85 @@ use winapi::um::wincon::{
     GetConsoleScreenBufferInfo,
     FillConsoleOutputCharacterA,
     FillConsoleOutputAttribute,
-    SetConsoleCursorPosition
+    SetConsoleCursorPosition,
+    GetConsoleWindow,
+    SetConsoleTitleW,
+    GenerateConsoleCtrlEvent,
+    ENABLE_LINE_INPUT,
+    ENABLE_ECHO_INPUT,
+    ENABLE_PROCESSED_INPUT,
+    ENABLE_VIRTUAL_TERMINAL_INPUT,
+    ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    CTRL_C_EVENT,
+    CTRL_BREAK_EVENT
 };
 
-use winapi::um::handleapi::INVALID_HANDLE_VALUE;
-use winapi::um::winbase::STD_OUTPUT_HANDLE;
+use winapi::um::handleapi::{ INVALID_HANDLE_VALUE, CloseHandle };
+use winapi::um::winbase::{
+    STD_INPUT_HANDLE,
+    STD_OUTPUT_HANDLE,
+    STARTUPINFOEXW,
+    EXTENDED_STARTUPINFO_PRESENT,
+    CREATE_UNICODE_ENVIRONMENT,
+    FILE_TYPE_PIPE,
+    INFINITE
+};
 use winapi::um::processenv::GetStdHandle;
-use winapi::shared::minwindef::{ DWORD, TRUE };
-use winapi::um::consoleapi::SetConsoleCtrlHandler;
-use std::process::ExitStatus;
+use winapi::um::processthreadsapi::{
+    PROCESS_INFORMATION,
+    LPPROC_THREAD_ATTRIBUTE_LIST,
+    CreateProcessW,
+    GetExitCodeProcess,
+    OpenProcess,
+    TerminateProcess,
+    InitializeProcThreadAttributeList,
+    UpdateProcThreadAttribute,
+    DeleteProcThreadAttributeList
+};
+use winapi::um::namedpipeapi::CreatePipe;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::fileapi::GetFileType;
+use winapi::um::ioapiset::CancelSynchronousIo;
+use winapi::um::libloaderapi::{ GetModuleHandleA, GetProcAddress };
+use winapi::um::winnt::{ HANDLE, HRESULT, PROCESS_TERMINATE };
+use winapi::shared::minwindef::{ BOOL, DWORD, TRUE, FALSE, HLOCAL, FARPROC };
+use winapi::shared::winerror::{ S_OK, ERROR_BAD_EXE_FORMAT };
+use winapi::um::dpapi::{ CryptProtectData, CryptUnprotectData };
+use winapi::um::wincrypt::DATA_BLOB;
+use winapi::um::winbase::{ LocalFree, GetSystemPowerStatus, SYSTEM_POWER_STATUS, WAIT_OBJECT_0 };
+use winapi::um::winuser::GetForegroundWindow;
+use winapi::um::minwinbase::SYSTEMTIME;
+use winapi::um::sysinfoapi::GetLocalTime;
+use winapi::um::consoleapi::{ SetConsoleCtrlHandler, GetConsoleMode, SetConsoleMode };
+use std::process::{ Child, Command, ExitStatus, Stdio };
+use std::io::{ Result, Error, ErrorKind, Read, Write, copy, stdin, stdout };
+use std::fs::{ File, create_dir_all, read, write };
+use std::env::vars_os;
+use std::ffi::{ OsStr, OsString };
+use std::collections::BTreeMap;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{ AsRawHandle, FromRawHandle, RawHandle };
+use std::mem::{ size_of, transmute, zeroed };
+use std::thread;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::ptr::{ null, null_mut };
+use std::slice::from_raw_parts;
+use dirs::data_dir;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicBool, Ordering };
 use empty::Empty;
+use kernel::{ Battery, ColorSupport, ProcessStatus };
 
+static INTERRUPTED: AtomicBool = AtomicBool::new( false );
+
+lazy_static! {
+    // console input and output modes from before raw mode was enabled
+    static ref ORIGINAL_MODE: Mutex<Option<( DWORD, DWORD )>> = Mutex::new( None );
+}
+
+/// # Safety
+/// Writes directly to the console; don't call while another thread is drawing.
 pub unsafe fn clear_screen() {
     let zero = COORD::empty();
     let mut buf = CONSOLE_SCREEN_BUFFER_INFO::empty();
@@ -33,12 +101,312 @@ pub unsafe fn clear_screen() {
     SetConsoleCursorPosition( handle, zero );
 }
 
-pub unsafe fn disable_ctrl_c() {
-    SetConsoleCtrlHandler( Option::None, TRUE );
+/// # Safety
+/// Changes process-wide signal handling.
+pub unsafe fn handle_signals() {
+    // a handler routine rather than ignoring the event outright, because
+    // children would inherit the ignore flag and become uninterruptible
+    SetConsoleCtrlHandler( Some( on_interrupt ), TRUE );
+}
+
+// The console sends CTRL_C_EVENT to every process attached to it, so the
+// foreground children get it directly; the shell only has to survive it.
+unsafe extern "system" fn on_interrupt( kind: DWORD ) -> BOOL {
+    match kind {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            INTERRUPTED.store( true, Ordering::SeqCst );
+            TRUE
+        },
+
+        _ => FALSE,
+    }
+}
+
+// There's no SIGCHLD to say when, so background jobs are checked for every time.
+pub fn take_child_exited() -> bool {
+    true
+}
+
+// Whether Ctrl-C was pressed since the last call, clearing the flag.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap( false, Ordering::SeqCst )
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load( Ordering::SeqCst )
+}
+
+/// # Safety
+/// Does nothing; consoles have no process groups to hand the terminal between.
+pub unsafe fn enable_job_control() {}
+
+// Console programs can't be moved into groups of their own without losing Ctrl-C.
+pub fn new_process_group( _proc: &mut Command, _leader: Option<u32> ) {}
+
+// Programs can't be stopped from the console, so they always run until they exit.
+pub fn wait_or_stop( child: &mut Child ) -> Result<Option<ExitStatus>> {
+    child.wait().map( Some )
+}
+
+// Children share the console, which already delivers Ctrl-C to them.
+pub struct Foreground;
+
+impl Foreground {
+    pub fn new( _pid: u32 ) -> Foreground {
+        Foreground
+    }
+}
+
+pub fn process_status( status: ExitStatus ) -> ProcessStatus {
+    match status.code() {
+        Some( x ) => ProcessStatus::Exited( x ),
+        None => ProcessStatus::Unknown,
+    }
+}
+
+// Windows has no signals, so processes are never reported as killed by one.
+pub fn signal_name( _signal: i32 ) -> Option<&'static str> {
+    None
+}
+
+// Whether starting a program failed because it isn't one Windows can run.
+pub fn is_exec_format_error( e: &Error ) -> bool {
+    e.raw_os_error() == Some( ERROR_BAD_EXE_FORMAT as i32 )
+}
+
+// Room for a new program's arguments: the whole command line, program name
+// included, is limited to 32767 UTF-16 characters.
+pub fn argument_space() -> usize {
+    32767
+}
+
+// How much of the command line one argument takes once quoted: a space before
+// it, the quotes around it, and a backslash for each quote inside.
+pub fn argument_size( arg: &str ) -> usize {
+    arg.encode_utf16().count() + 3 + arg.matches( '"' ).count()
+}
+
+// The few signals `kill` can imitate, numbered as on Unix.
+const SIGNALS: &[( i32, &str )] = &[
+    ( 2, "SIGINT" ),
+    ( 9, "SIGKILL" ),
+    ( 15, "SIGTERM" ),
+    ( 21, "SIGBREAK" ),
+];
+
+pub fn signals() -> &'static [( i32, &'static str )] {
+    SIGNALS
+}
+
+pub fn signal_number( name: &str ) -> Option<i32> {
+    if let Ok( x ) = name.parse() {
+        return SIGNALS.iter().find( | s | s.0 == x ).map( | s | s.0 );
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix( "SIG" ).unwrap_or( &name );
+    SIGNALS.iter().find( | x | &x.1[3..] == name ).map( | x | x.0 )
+}
+
+// Interrupts become a Ctrl-Break sent to the process's console group, which only
+// reaches it if it leads one; anything else ends the process outright.
+pub fn send_signal( pid: u32, signal: i32 ) -> Result<()> {
+    if signal == 2 || signal == 21 {
+        return match unsafe { GenerateConsoleCtrlEvent( CTRL_BREAK_EVENT, pid ) } {
+            0 => Err( Error::last_os_error() ),
+            _ => Ok( () ),
+        };
+    }
+
+    unsafe {
+        let process = OpenProcess( PROCESS_TERMINATE, FALSE, pid );
+        if process.is_null() {
+            return Err( Error::last_os_error() );
+        }
+
+        let ok = TerminateProcess( process, 1 );
+        let e = Error::last_os_error();
+        CloseHandle( process );
+
+        if ok == 0 { Err( e ) } else { Ok( () ) }
+    }
+}
+
+pub fn stdin_is_tty() -> bool {
+    let mut mode: DWORD = 0;
+    unsafe { GetConsoleMode( GetStdHandle( STD_INPUT_HANDLE ), &mut mode ) != 0 }
 }
 
-pub fn get_exit_code( status: ExitStatus ) -> Option<i32> {
-    status.code()
+pub fn stdout_is_tty() -> bool {
+    let mut mode: DWORD = 0;
+    unsafe { GetConsoleMode( GetStdHandle( STD_OUTPUT_HANDLE ), &mut mode ) != 0 }
+}
+
+// Shows `title` in the console window's title bar.
+pub fn set_title( title: &str ) {
+    let title = wide( OsStr::new( title ) );
+    unsafe { SetConsoleTitleW( title.as_ptr() ); }
+}
+
+// Waits up to `timeout` for console input, returning false if nothing came.
+pub fn wait_for_input( timeout: Duration ) -> Result<bool> {
+    let input = unsafe { GetStdHandle( STD_INPUT_HANDLE ) };
+    Ok( unsafe { WaitForSingleObject( input, timeout.as_millis() as DWORD ) } == WAIT_OBJECT_0 )
+}
+
+// Consoles that understand escape sequences take 24-bit colors as well, while
+// older ones only have their 16 attribute colors.
+pub fn get_color_support() -> ColorSupport {
+    let output = unsafe { GetStdHandle( STD_OUTPUT_HANDLE ) };
+    let mut mode: DWORD = 0;
+    if unsafe { GetConsoleMode( output, &mut mode ) } == 0 {
+        return ColorSupport::None;
+    }
+
+    if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+        return ColorSupport::TrueColor;
+    }
+
+    // see if escape sequences can be turned on without leaving them on
+    if unsafe { SetConsoleMode( output, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING ) } == 0 {
+        return ColorSupport::Default;
+    }
+
+    unsafe { SetConsoleMode( output, mode ); }
+    ColorSupport::TrueColor
+}
+
+/// # Safety
+/// Must be paired with `disable_raw_mode` before handing the terminal to a child process.
+pub unsafe fn enable_raw_mode() -> Result<()> {
+    let input = GetStdHandle( STD_INPUT_HANDLE );
+    let output = GetStdHandle( STD_OUTPUT_HANDLE );
+    let mut in_mode: DWORD = 0;
+    let mut out_mode: DWORD = 0;
+
+    if GetConsoleMode( input, &mut in_mode ) == 0 || GetConsoleMode( output, &mut out_mode ) == 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    let mut original = ORIGINAL_MODE.lock().unwrap();
+    if original.is_none() {
+        *original = Some( ( in_mode, out_mode ) );
+    }
+
+    // with virtual terminal input enabled the console hands us the same
+    // escape sequences for arrow keys etc. that a unix terminal would
+    let raw_in = ( in_mode & !( ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT ) )
+        | ENABLE_VIRTUAL_TERMINAL_INPUT;
+    let raw_out = out_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+
+    if SetConsoleMode( input, raw_in ) == 0 || SetConsoleMode( output, raw_out ) == 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    Ok( () )
+}
+
+/// # Safety
+/// Restores the terminal settings saved by `enable_raw_mode`.
+pub unsafe fn disable_raw_mode() -> Result<()> {
+    if let Some( ( in_mode, out_mode ) ) = ORIGINAL_MODE.lock().unwrap().take() {
+        if SetConsoleMode( GetStdHandle( STD_INPUT_HANDLE ), in_mode ) == 0 ||
+           SetConsoleMode( GetStdHandle( STD_OUTPUT_HANDLE ), out_mode ) == 0 {
+            return Err( Error::last_os_error() );
+        }
+    }
+
+    Ok( () )
+}
+
+// Secrets are encrypted with DPAPI for the current user and kept under
+// %APPDATA%\lumi\secrets, one file per name.
+
+fn secret_path( name: &str ) -> Result<PathBuf> {
+    let dir = data_dir()
+        .ok_or_else( || Error::other( "unable to locate the user's data directory" ) )?
+        .join( "lumi" )
+        .join( "secrets" );
+
+    Ok( dir.join( name ) )
+}
+
+unsafe fn take_blob( blob: DATA_BLOB ) -> Vec<u8> {
+    let bytes = from_raw_parts( blob.pbData, blob.cbData as usize ).to_vec();
+    LocalFree( blob.pbData as HLOCAL );
+    bytes
+}
+
+pub fn store_secret( name: &str, value: &str ) -> Result<()> {
+    let mut plain = value.as_bytes().to_vec();
+    let mut input = DATA_BLOB { cbData: plain.len() as DWORD, pbData: plain.as_mut_ptr() };
+    let mut output = DATA_BLOB { cbData: 0, pbData: null_mut() };
+
+    let encrypted = unsafe {
+        if CryptProtectData( &mut input, null(), null_mut(), null_mut(), null_mut(), 0, &mut output ) == 0 {
+            return Err( Error::last_os_error() );
+        }
+
+        take_blob( output )
+    };
+
+    let path = secret_path( name )?;
+    if let Some( dir ) = path.parent() {
+        create_dir_all( dir )?;
+    }
+
+    write( path, encrypted )
+}
+
+pub fn load_secret( name: &str ) -> Result<Option<String>> {
+    let mut encrypted = match read( secret_path( name )? ) {
+        Ok( x ) => x,
+        Err( ref e ) if e.kind() == ErrorKind::NotFound => return Ok( None ),
+        Err( e ) => return Err( e ),
+    };
+
+    let mut input = DATA_BLOB { cbData: encrypted.len() as DWORD, pbData: encrypted.as_mut_ptr() };
+    let mut output = DATA_BLOB { cbData: 0, pbData: null_mut() };
+
+    let plain = unsafe {
+        if CryptUnprotectData( &mut input, null_mut(), null_mut(), null_mut(), null_mut(), 0, &mut output ) == 0 {
+            return Err( Error::last_os_error() );
+        }
+
+        take_blob( output )
+    };
+
+    Ok( Some( String::from_utf8_lossy( &plain ).into_owned() ) )
+}
+
+// Whether the console window currently has focus, or None if we can't tell.
+pub fn terminal_has_focus() -> Option<bool> {
+    let console = unsafe { GetConsoleWindow() };
+    if console.is_null() {
+        return None;
+    }
+
+    Some( unsafe { GetForegroundWindow() } == console )
+}
+
+pub fn notify( title: &str, body: &str ) -> Result<()> {
+    let escape = | s: &str | s.replace( '&', "&amp;" ).replace( '<', "&lt;" ).replace( '>', "&gt;" ).replace( '\'', "''" );
+    let script = format!(
+        "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null;\
+         $xml = New-Object Windows.Data.Xml.Dom.XmlDocument;\
+         $xml.LoadXml('<toast><visual><binding template=\"ToastGeneric\"><text>{}</text><text>{}</text></binding></visual></toast>');\
+         $toast = New-Object Windows.UI.Notifications.ToastNotification $xml;\
+         [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('Lumi').Show($toast)",
+        escape( title ),
+        escape( body )
+    );
+
+    Command::new( "powershell" )
+        .args( &[ "-NoProfile", "-NonInteractive", "-Command", &script ] )
+        .stdout( Stdio::null() )
+        .stderr( Stdio::null() )
+        .spawn()
+        .map( | _ | () )
 }
 
 impl Empty for CONSOLE_SCREEN_BUFFER_INFO {
@@ -64,3 +432,315 @@ impl Empty for SMALL_RECT {
         SMALL_RECT { Top: 0, Right: 0, Bottom: 0, Left: 0 }
     }
 }
+
+impl Empty for SYSTEMTIME {
+    fn empty() -> Self {
+        SYSTEMTIME {
+            wYear: 0,
+            wMonth: 0,
+            wDayOfWeek: 0,
+            wDay: 0,
+            wHour: 0,
+            wMinute: 0,
+            wSecond: 0,
+            wMilliseconds: 0,
+        }
+    }
+}
+
+// Windows has no public API for resuming a suspended process as a whole.
+pub fn resume_process( _pid: u32 ) -> Result<()> {
+    Err( Error::new( ErrorKind::Unsupported, "resuming stopped jobs is not supported on Windows" ) )
+}
+
+// Console programs can't be asked to stop individually, so they're ended at once.
+pub fn terminate( child: &mut Child ) -> Result<()> {
+    child.kill()
+}
+
+// Windows only keeps CPU times per process handle, and ours are gone once a child exits.
+pub fn children_cpu_time() -> Option<( Duration, Duration )> {
+    None
+}
+
+// ~user isn't supported on Windows, where profiles don't have a fixed location.
+pub fn user_home( _name: &str ) -> Option<PathBuf> {
+    None
+}
+
+// Local wall-clock time as (hours, minutes, seconds).
+pub fn local_time() -> ( u32, u32, u32 ) {
+    let mut time = SYSTEMTIME::empty();
+    unsafe { GetLocalTime( &mut time ); }
+
+    ( time.wHour as u32, time.wMinute as u32, time.wSecond as u32 )
+}
+
+// Where PATH points when a login shell is started without one.
+pub const DEFAULT_PATH: &str = r"C:\Windows\system32;C:\Windows;C:\Windows\System32\Wbem";
+
+// Run by every login shell before the user's own login script.
+pub fn system_profile() -> Option<PathBuf> {
+    Some( PathBuf::from( std::env::var_os( "ProgramData" )? ).join( "lumi" ).join( "profile" ) )
+}
+
+// None on machines without a battery, or when Windows doesn't know its charge.
+pub fn battery() -> Option<Battery> {
+    let mut status: SYSTEM_POWER_STATUS = unsafe { zeroed() };
+    if unsafe { GetSystemPowerStatus( &mut status ) } == 0 {
+        return None;
+    }
+
+    // 128 is "no system battery" and 255 an unknown percentage
+    if status.BatteryFlag & 128 != 0 || status.BatteryLifePercent > 100 {
+        return None;
+    }
+
+    Some( Battery { percent: status.BatteryLifePercent, charging: status.BatteryFlag & 8 != 0 } )
+}
+
+// Pseudo consoles (ConPTY) only exist since Windows 10 1809, so they're looked
+// up at runtime rather than linked against, and older systems simply go without.
+type HPCON = HANDLE;
+type CreatePseudoConsoleFn = unsafe extern "system" fn( COORD, HANDLE, HANDLE, DWORD, *mut HPCON ) -> HRESULT;
+type ClosePseudoConsoleFn = unsafe extern "system" fn( HPCON );
+
+const PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE: usize = 0x0002_0016;
+
+struct ConPty {
+    create: CreatePseudoConsoleFn,
+    close: ClosePseudoConsoleFn,
+}
+
+fn conpty() -> Option<ConPty> {
+    unsafe {
+        let kernel32 = GetModuleHandleA( b"kernel32.dll\0".as_ptr() as *const i8 );
+        if kernel32.is_null() {
+            return None;
+        }
+
+        let create = GetProcAddress( kernel32, b"CreatePseudoConsole\0".as_ptr() as *const i8 );
+        let close = GetProcAddress( kernel32, b"ClosePseudoConsole\0".as_ptr() as *const i8 );
+        if create.is_null() || close.is_null() {
+            return None;
+        }
+
+        Some( ConPty {
+            create: transmute::<FARPROC, CreatePseudoConsoleFn>( create ),
+            close: transmute::<FARPROC, ClosePseudoConsoleFn>( close ),
+        } )
+    }
+}
+
+// Terminal hosts like mintty give the shell pipes instead of a console, and
+// programs that inherit those decide they aren't interactive: REPLs drop their
+// prompts and full-screen programs refuse to start.
+fn behind_pipes() -> bool {
+    unsafe {
+        GetFileType( GetStdHandle( STD_INPUT_HANDLE ) ) == FILE_TYPE_PIPE &&
+        GetFileType( GetStdHandle( STD_OUTPUT_HANDLE ) ) == FILE_TYPE_PIPE
+    }
+}
+
+fn anonymous_pipe() -> Result<( File, File )> {
+    let mut read: HANDLE = null_mut();
+    let mut write: HANDLE = null_mut();
+    if unsafe { CreatePipe( &mut read, &mut write, null_mut(), 0 ) } == 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    unsafe { Ok( ( File::from_raw_handle( read as RawHandle ), File::from_raw_handle( write as RawHandle ) ) ) }
+}
+
+// Size for a console nobody can measure, taken from $COLUMNS and $LINES.
+fn console_size() -> COORD {
+    let dimension = | name: &str, default: i16 | {
+        std::env::var( name ).ok().and_then( | x | x.parse().ok() ).unwrap_or( default )
+    };
+
+    COORD { X: dimension( "COLUMNS", 80 ), Y: dimension( "LINES", 24 ) }
+}
+
+// Quotes an argument the way the MSVC runtime splits command lines.
+fn quote_arg( arg: &OsStr ) -> OsString {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty() && !arg.contains( | c: char | c == ' ' || c == '\t' || c == '"' ) {
+        return arg.into_owned().into();
+    }
+
+    let mut quoted = String::from( "\"" );
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str( &"\\".repeat( backslashes * 2 + 1 ) );
+                backslashes = 0;
+            },
+
+            _ => {
+                quoted.push_str( &"\\".repeat( backslashes ) );
+                backslashes = 0;
+            },
+        }
+
+        if c != '\\' {
+            quoted.push( c );
+        }
+    }
+
+    quoted.push_str( &"\\".repeat( backslashes * 2 ) );
+    quoted.push( '"' );
+    quoted.into()
+}
+
+fn wide( s: &OsStr ) -> Vec<u16> {
+    s.encode_wide().chain( Some( 0 ) ).collect()
+}
+
+// NAME=value pairs for CreateProcessW, with the changes `proc` asks for applied.
+fn environment_block( proc: &Command ) -> Vec<u16> {
+    let mut env: BTreeMap<OsString, OsString> = vars_os().collect();
+    for ( name, value ) in proc.get_envs() {
+        match value {
+            Some( x ) => env.insert( name.to_owned(), x.to_owned() ),
+            None => env.remove( name ),
+        };
+    }
+
+    let mut block = Vec::new();
+    for ( name, value ) in env {
+        let mut pair = name;
+        pair.push( "=" );
+        pair.push( value );
+        block.extend( wide( &pair ) );
+    }
+
+    block.push( 0 );
+    block
+}
+
+// Runs `proc` attached to a pseudo console of its own, passing the terminal's
+// input to it and its output, escape sequences and all, straight back. Returns
+// None when the program should just be started normally: the shell already has
+// a console to share, or this version of Windows has no pseudo consoles.
+pub fn run_in_pseudo_console( proc: &Command ) -> Result<Option<i32>> {
+    let api = match conpty() {
+        Some( x ) if behind_pipes() => x,
+        _ => return Ok( None ),
+    };
+
+    let ( input_read, mut input_write ) = anonymous_pipe()?;
+    let ( mut output_read, output_write ) = anonymous_pipe()?;
+
+    let mut console: HPCON = null_mut();
+    let created = unsafe {
+        ( api.create )( console_size(), input_read.as_raw_handle() as HANDLE, output_write.as_raw_handle() as HANDLE, 0, &mut console )
+    };
+
+    // the pseudo console keeps its own copies of these
+    drop( input_read );
+    drop( output_write );
+
+    if created != S_OK {
+        return Err( Error::other( format!( "unable to create a pseudo console (HRESULT {:#x})", created ) ) );
+    }
+
+    let res = unsafe { spawn_attached( proc, console ) };
+    let info = match res {
+        Ok( x ) => x,
+        Err( e ) => {
+            unsafe { ( api.close )( console ); }
+            return Err( e );
+        }
+    };
+
+    let output = thread::spawn( move || {
+        let mut out = stdout();
+        let mut buf = [ 0u8; 4096 ];
+        while let Ok( n ) = output_read.read( &mut buf ) {
+            if n == 0 || out.write_all( &buf[ .. n ] ).and_then( | _ | out.flush() ).is_err() {
+                break;
+            }
+        }
+    } );
+
+    let input = thread::spawn( move || {
+        let _ = copy( &mut stdin(), &mut input_write );
+    } );
+
+    let mut code: DWORD = 0;
+    unsafe {
+        WaitForSingleObject( info.hProcess, INFINITE );
+        GetExitCodeProcess( info.hProcess, &mut code );
+        CloseHandle( info.hProcess );
+        CloseHandle( info.hThread );
+
+        // the input thread is stuck reading our stdin, which would swallow the
+        // next thing typed at the shell
+        CancelSynchronousIo( input.as_raw_handle() as HANDLE );
+
+        // closing flushes whatever the program wrote last and ends the output thread
+        ( api.close )( console );
+    }
+
+    let _ = input.join();
+    let _ = output.join();
+
+    Ok( Some( code as i32 ) )
+}
+
+unsafe fn spawn_attached( proc: &Command, console: HPCON ) -> Result<PROCESS_INFORMATION> {
+    let mut size = 0;
+    InitializeProcThreadAttributeList( null_mut(), 1, 0, &mut size );
+
+    let mut list = vec![ 0u8; size ];
+    let attrs = list.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+    if InitializeProcThreadAttributeList( attrs, 1, 0, &mut size ) == 0 {
+        return Err( Error::last_os_error() );
+    }
+
+    let res = ( || {
+        if UpdateProcThreadAttribute( attrs, 0, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE, console, size_of::<HPCON>(), null_mut(), null_mut() ) == 0 {
+            return Err( Error::last_os_error() );
+        }
+
+        let mut line = quote_arg( proc.get_program() );
+        for arg in proc.get_args() {
+            line.push( " " );
+            line.push( quote_arg( arg ) );
+        }
+
+        let mut line = wide( &line );
+        let mut env = environment_block( proc );
+        let cwd = proc.get_current_dir().map( | x | wide( x.as_os_str() ) );
+
+        let mut startup: STARTUPINFOEXW = zeroed();
+        startup.StartupInfo.cb = size_of::<STARTUPINFOEXW>() as DWORD;
+        startup.lpAttributeList = attrs;
+
+        // nothing is inherited, or the program would pick up our pipes instead of its console
+        let mut info: PROCESS_INFORMATION = zeroed();
+        let ok = CreateProcessW(
+            null(),
+            line.as_mut_ptr(),
+            null_mut(),
+            null_mut(),
+            FALSE,
+            EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT,
+            env.as_mut_ptr() as *mut _,
+            cwd.as_ref().map_or( null(), | x | x.as_ptr() ),
+            &mut startup.StartupInfo,
+            &mut info
+        );
+
+        if ok == 0 {
+            return Err( Error::last_os_error() );
+        }
+
+        Ok( info )
+    } )();
+
+    DeleteProcThreadAttributeList( attrs );
+    res
+}
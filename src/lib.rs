@@ -0,0 +1,29 @@
+extern crate dirs;
+extern crate yansi;
+extern crate whoami;
+extern crate crossterm;
+extern crate yaml_rust;
+extern crate unicode_width;
+extern crate rusqlite;
+extern crate serde_json;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[cfg( windows )]
+extern crate winapi;
+
+#[cfg( not( windows ) )]
+extern crate libc;
+
+#[macro_use]
+extern crate clap;
+
+pub mod peek;
+pub mod parsing;
+pub mod shell;
+pub mod kernel;
+pub mod ffi;
+
+#[cfg( windows )]
+mod empty;
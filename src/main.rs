@@ -1,259 +1,244 @@
 extern crate yansi;
-extern crate whoami;
-extern crate dirs;
-extern crate crossterm;
-
-#[macro_use]
-extern crate lazy_static;
-
-#[cfg( windows )]
-extern crate winapi;
+extern crate lumi;
 
 #[macro_use]
 extern crate clap;
 
-mod peek;
-mod parsing;
-mod shell;
-mod kernel;
-mod empty;
-
-use std::env::{ current_dir as env_current_dir };
-use std::io::{ Result, Error, ErrorKind, Write, stdin, stdout };
-use std::fmt::Display;
+use std::io::{ Write, stdin, stdout };
+use std::process::exit;
+use std::fs::read_to_string;
+use std::path::Path;
+use clap::{ App, AppSettings };
 use yansi::Paint;
-use crossterm::terminal;
-use kernel::{ clear_screen, disable_ctrl_c };
-use shell::parsing::*;
-use parsing::*;
+use lumi::kernel::{ clear_screen, handle_signals, enable_job_control };
+use lumi::shell::config::{ Config, color_allowed, set_color_enabled };
+use lumi::shell::context::ExecContext;
+use lumi::shell::repl::Repl;
+use lumi::shell::expand;
+use lumi::shell::login;
+use lumi::shell::profile;
+use lumi::shell::script::{ self, run_script };
+use lumi::shell::testing;
 
 fn main() {
-    unsafe {
-        disable_ctrl_c();
-        clear_screen();
+    let login_name = std::env::args().next().is_some_and( | x | login::is_login_name( &x ) );
+
+    let yaml = load_yaml!( "cli_args/lumi.yaml" );
+    let args = App::from_yaml( yaml )
+        .author( crate_authors!() )
+        .version( crate_version!() )
+        .setting( if color_allowed() { AppSettings::ColoredHelp } else { AppSettings::ColorNever } )
+        .setting( AppSettings::TrailingVarArg )
+        .get_matches();
+
+    if args.is_present( "profile-startup" ) {
+        profile::start();
     }
 
-    loop {
-        print_prompt();
-
-        let mut line = String::new();
-        match stdin().read_line( &mut line ) {
-            Ok( _ ) => {
-                if line.trim().len() == 0 {
-                    println!( "" );
-                    continue;
-                }
+    let login = login_name || args.is_present( "login" );
+    login::prepare_environment( login );
 
-                line = line.trim_end_matches( | c | c == '\r' || c == '\n' ).to_string();
-                let mut lexer = ShellLexer::new( line.clone() );
-                let tokens = match lexer.tokenize() {
-                    Ok( tks ) => tks,
-                    Err( e ) => {
-                        show_lex_error( e, &line );
-                        continue;
-                    },
-                };
-
-                let mut parser = ShellParser::new( tokens );
-                let seg = match parser.parse_all() {
-                    Ok( seg ) => seg,
-                    Err( e ) => {
-                        show_parse_error( e, &line );
-                        continue;
-                    },
-                };
-
-                //let res = seg.execute( false, None );
-                match seg.execute( false, None ) {
-                    Err( e ) => {
-                        println!( "" );
-                        error( e );
-                        println!( "" );
-                    },
-                    _ => {},
-                }
+    let no_color = args.is_present( "no-color" );
+    set_color_enabled( color_allowed() && !no_color );
 
-                //println!( "{:#?}", res );
-                stdout().flush().unwrap();
-            },
+    if args.is_present( "dump-ast" ) || args.is_present( "no-exec" ) {
+        let source = match read_source( args.value_of( "command" ), args.value_of( "SCRIPT" ) ) {
+            Ok( x ) => x,
             Err( e ) => {
-                error( format!( "unable to read from STDIN (reason: {})", e.to_string() ) );
+                eprintln!( "lumi: unable to read the script (reason: {})", e );
+                exit( 1 );
             }
-        }
+        };
+
+        exit( if args.is_present( "dump-ast" ) { dump_ast( &source ) } else { check( &source ) } );
     }
-}
 
-fn error<D: Display>( msg: D ) {
-    let painted = Paint::red( msg ).dimmed();
-    println!( "{}", painted );
-}
+    if let Some( command ) = args.value_of( "command" ) {
+        exit( run_command( command ) );
+    }
 
-fn show_lex_error( e: LexError, input: &String ) {
-    use parsing::LexErrorKind::*;
-
-    match e.kind() {
-        UnexpectedChar { character, codepoint } => {
-            error(
-                format!(
-                    "unexpected character '{0}' (0x{1:X}) at position {2}",
-                    character,
-                    codepoint,
-                    e.span().start.index
-                )
-            );
-
-            point_to( input, e.span().start.index );
-        },
+    if let Some( file ) = args.value_of( "SCRIPT" ) {
+        let script_args: Vec<&str> = args.values_of( "ARGS" ).map_or( Vec::new(), | x | x.collect() );
+        exit( run_script_file( file, &script_args ) );
+    }
 
-        UnexpectedEOI { reason } => {
-            error(
-                format!(
-                    "unexpected end-of-input ({0}) at position {1}",
-                    reason,
-                    e.span().start.index
-                )
-            );
+    if let Some( expand_args ) = args.subcommand_matches( "expand" ) {
+        exit( expand( expand_args.value_of( "STRING" ).unwrap() ) );
+    }
 
-            point_to( input, e.span().start.index );
-        },
+    if let Some( test_args ) = args.subcommand_matches( "test" ) {
+        if let Some( file ) = test_args.value_of( "run-file" ) {
+            exit( run_test_file( file ) );
+        }
+
+        let paths = test_args.values_of( "PATH" ).map_or( vec![ "." ], | x | x.collect() );
+        exit( run_tests( &paths ) );
+    }
+
+    unsafe {
+        handle_signals();
+        enable_job_control();
+        clear_screen();
     }
-}
 
-fn show_parse_error( e: ParseError, input: &String ) {
-    use parsing::ParseErrorKind::*;
+    // loaded after clearing the screen so problems with the file stay visible
+    let loaded = profile::measure( "config", || match args.value_of( "config" ) {
+        Some( path ) => Config::load_from( Path::new( path ) ),
+        None => Config::load(),
+    } );
 
-    match e.kind() {
-        ExpectSegment { found } => {
-            error(
-                format!(
-                    "expecting shell segment, found {0} at position {1}",
-                    found,
-                    e.span().unwrap().start.index
-                )
-            );
+    let mut config = loaded.unwrap_or_else( | e | {
+        eprintln!( "lumi: unable to load config, using the defaults (reason: {})", e );
+        Config::default()
+    } );
 
-            point_to( input, e.span().unwrap().start.index );
-        },
+    if no_color {
+        config.color = false;
+    }
 
-        ExpectString => {
-            error(
-                format!(
-                    "redirection target must be a string or string interpolation (at position {})",
-                    e.span().unwrap().start.index
-                )
-            );
+    let mut repl = Repl::new( config );
+    if login {
+        profile::measure( "login", || repl.run_login_profiles() );
+    }
 
-            point_to( input, e.span().unwrap().start.index );
-        },
+    exit( repl.run() );
+}
 
-        UnexpectedEOI => {
-            error(
-                format!(
-                    "unexpected end-of-input (malformed token stream, indicates an internal bug)"
-                )
-            );
+fn expand( source: &str ) -> i32 {
+    match expand::expand( &mut ExecContext::new(), source ) {
+        Ok( argv ) => {
+            let mut out = stdout();
+            for arg in argv {
+                out.write_all( arg.as_bytes() ).unwrap();
+                out.write_all( b"\0" ).unwrap();
+            }
+
+            out.flush().unwrap();
+            0
         },
 
-        Unexpected { expect, found } => {
-            error(
-                format!(
-                    "unexpected {0}, expecting {1} at position {2}",
-                    found,
-                    expect,
-                    e.span().unwrap().start.index
-                )
-            );
-
-            point_to( input, e.span().unwrap().start.index );
+        Err( e ) => {
+            eprintln!( "lumi: {}", e );
+            1
         }
     }
 }
 
-fn point_to( input: &String, at: usize ) {
-    let pad_size: usize = 10;
-    let prefix = "... ";
-    let term = terminal();
-    let ( w, _ ) = term.terminal_size();
-
-    let should_trim = at > pad_size && input.len() > w as usize;
-    let mut section = if should_trim {
-        format!(
-            "{0}{1}",
-            prefix,
-            &input[( at - pad_size )..]
-        )
-    } else {
-        input.clone()
-    };
+// The command string, the script file, or else standard input.
+fn read_source( command: Option<&str>, file: Option<&str> ) -> std::io::Result<String> {
+    match ( command, file ) {
+        ( Some( x ), _ ) => Ok( x.to_string() ),
+        ( None, Some( file ) ) => read_to_string( file ),
+        ( None, None ) => std::io::read_to_string( stdin() ),
+    }
+}
 
-    if section.len() > w as usize {
-        let suffix = " ...";
-        section = format!(
-            "{0}{1}",
-            &input[..( w as usize - suffix.len() )],
-            suffix
-        );
+fn check( source: &str ) -> i32 {
+    let errors = script::check_script( source );
+    for e in &errors {
+        eprintln!( "lumi: {}", e );
     }
 
-    let len = if should_trim {
-        pad_size + prefix.len()
-    } else {
-        at
-    };
+    if errors.is_empty() { 0 } else { 1 }
+}
+
+fn dump_ast( source: &str ) -> i32 {
+    match script::dump_ast( source ) {
+        Ok( tree ) => {
+            print!( "{}", tree );
+            0
+        },
 
-    let ws: String = ( 0 .. len ).map( | _ | ' ' ).collect();
-    let ln: String = ( 0 .. len ).map( | _ | '─' ).collect();
+        Err( e ) => {
+            eprintln!( "lumi: {}", e );
+            1
+        }
+    }
+}
 
-    println!( "" );
-    println!( "{}", section );
-    println!( "{}", Paint::red( format!( "{}^", ws ) ) );
-    println!( "{}", Paint::red( format!( "{}┘", ln ) ) );
-    stdout().flush().unwrap();
+fn run_command( command: &str ) -> i32 {
+    match run_script( &mut ExecContext::new(), command ) {
+        Ok( code ) => code,
+        Err( e ) => {
+            eprintln!( "lumi: {}", e.message );
+            1
+        }
+    }
 }
 
-fn print_prompt() {
-    use whoami::{ username, host as computer };
+fn run_script_file( file: &str, args: &[&str] ) -> i32 {
+    let source = match read_to_string( file ) {
+        Ok( x ) => x,
+        Err( e ) => {
+            eprintln!( "lumi: unable to read {} (reason: {})", file, e );
+            return 1;
+        }
+    };
 
-    fn get_current_dir() -> String {
-        let full_path = current_dir().unwrap();
+    let mut ctx = ExecContext::new();
+    ctx.set_var( "0", file );
+    for ( i, arg ) in args.iter().enumerate() {
+        ctx.set_var( &( i + 1 ).to_string(), arg );
+    }
 
-        // std::fs::canonicalize returns a full UNC path
-        // with preceeding \\?\ on Windows so we need to trim that.
-        if full_path.starts_with( "\\\\?\\" ) {
-            full_path[4..].to_string()
-        } else {
-            full_path
+    match run_script( &mut ctx, &source ) {
+        Ok( code ) => code,
+        Err( e ) => {
+            eprintln!( "lumi: {}: {}", file, e );
+            1
         }
     }
+}
 
-    print!(
-        "${user}@{machine}[{dir}]> ",
-        user    = Paint::green( username() ),
-        machine = Paint::yellow( computer() ).dimmed(),
-        dir     = Paint::cyan( get_current_dir() ).dimmed()
-    );
+fn run_test_file( file: &str ) -> i32 {
+    let source = match read_to_string( file ) {
+        Ok( x ) => x,
+        Err( e ) => {
+            eprintln!( "unable to read {} (reason: {})", file, e );
+            return 1;
+        }
+    };
 
-    stdout().flush().unwrap();
+    match run_script( &mut ExecContext::new(), &source ) {
+        Ok( _ ) => 0,
+        Err( e ) => {
+            eprintln!( "{}", e );
+            1
+        }
+    }
 }
 
-fn current_dir() -> Result<String> {
-    use dirs::home_dir;
-
-    let home = home_dir().ok_or(
-        Error::new(
-            ErrorKind::Other,
-            "unable to locate user's home dir!"
-        )
-    )?.canonicalize()?;
+fn run_tests( paths: &[&str] ) -> i32 {
+    let files: Vec<_> = paths.iter().flat_map( | p | testing::discover( Path::new( p ) ) ).collect();
+    if files.is_empty() {
+        eprintln!( "no *{} files found", testing::TEST_SUFFIX );
+        return 1;
+    }
 
-    let curr = env_current_dir()?.canonicalize()?;
+    let mut failed = 0;
+    for ( i, file ) in files.iter().enumerate() {
+        match testing::run_isolated( file, i ) {
+            Ok( ref outcome ) if outcome.passed => {
+                println!( "{} {}", Paint::green( "PASS" ), file.display() );
+            },
 
-    if curr.starts_with( &home ) {
-        let home = home.to_string_lossy().into_owned();
-        let curr = curr.to_string_lossy().into_owned();
+            Ok( outcome ) => {
+                failed += 1;
+                println!( "{} {}", Paint::red( "FAIL" ), file.display() );
+                for line in outcome.output.lines() {
+                    println!( "    {}", line );
+                }
+            },
 
-        Ok( curr.replace( &home, "~" ) )
-    } else {
-        Ok( curr.to_string_lossy().into_owned().to_string() )
+            Err( e ) => {
+                failed += 1;
+                println!( "{} {} (unable to run: {})", Paint::red( "FAIL" ), file.display(), e );
+            }
+        }
     }
+
+    println!();
+    println!( "{} passed, {} failed", files.len() - failed, failed );
+
+    if failed == 0 { 0 } else { 1 }
 }
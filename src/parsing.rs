@@ -1,6 +1,7 @@
-use peek::BufferedPeekable;
+use std::rc::Rc;
 use std::mem::discriminant;
 use std::fmt::{ Display, Formatter, Debug };
+use unicode_width::UnicodeWidthChar;
 
 pub trait SyntaxToken {
     type Kind: ToString;
@@ -11,8 +12,11 @@ pub trait SyntaxToken {
 
 #[derive( Debug, Clone, Eq, PartialEq, Hash )]
 pub struct Location {
+    // counted in characters from the start of the source
     pub index: usize,
     pub line: usize,
+
+    // counted in terminal cells, so wide characters such as 漢 take up two
     pub column: usize,
 }
 
@@ -44,6 +48,7 @@ pub enum ParseErrorKind {
     },
     ExpectSegment { found: String },
     ExpectString,
+    TooDeep { limit: usize },
 }
 
 #[derive( Debug )]
@@ -62,9 +67,19 @@ impl ParseError {
     }
 
     pub fn span( &self ) -> Option<&TextSpan> {
-        match self.span {
-            Some( ref x ) => Some( x ),
-            None => None,
+        self.span.as_ref()
+    }
+
+    // What went wrong, without where.
+    pub fn message( &self ) -> String {
+        use self::ParseErrorKind::*;
+
+        match &self.kind {
+            UnexpectedEOI => "unexpected end-of-input".to_string(),
+            Unexpected { expect, found } => format!( "unexpected {}, expecting {}", found, expect ),
+            ExpectSegment { found } => format!( "expecting shell segment, found {}", found ),
+            ExpectString => "redirection target must be a string or string interpolation".to_string(),
+            TooDeep { limit } => format!( "nested more than {} levels deep", limit ),
         }
     }
 
@@ -95,48 +110,72 @@ impl ParseError {
             Some( span )
         )
     }
+
+    pub fn too_deep( limit: usize, span: TextSpan ) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::TooDeep { limit },
+            Some( span )
+        )
+    }
 }
 
+impl Display for ParseError {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        formatter.write_str( &self.message() )?;
+        match &self.span {
+            Some( span ) => write!( formatter, " at {}", span ),
+            None => Ok( () ),
+        }
+    }
+}
+
+// Tokens are shared rather than owned so nested streams (e.g. the body of a
+// string interpolation) can be parsed without copying them out of their parent.
 pub struct TokenStream<T> {
-    tokens: BufferedPeekable<T>
+    tokens: Rc<[T]>,
+    position: usize,
 }
 
-impl<T: SyntaxToken + ToString + Debug> TokenStream<T>
+impl<T: SyntaxToken + ToString + Debug + Clone> TokenStream<T>
 {
     pub fn new( tokens: Vec<T> ) -> TokenStream<T> {
+        TokenStream::shared( tokens.into() )
+    }
+
+    pub fn shared( tokens: Rc<[T]> ) -> TokenStream<T> {
         TokenStream {
-            tokens: BufferedPeekable::new( tokens )
+            tokens,
+            position: 0,
         }
     }
 
-    pub fn is_empty( &mut self ) -> bool {
-        self.tokens.is_empty()
+    pub fn is_empty( &self ) -> bool {
+        self.position >= self.tokens.len()
     }
 
-    pub fn peek( &mut self ) -> Option<&T> {
-        self.tokens.peek()
+    pub fn peek( &self ) -> Option<&T> {
+        self.tokens.get( self.position )
     }
 
     #[allow( dead_code )]
-    pub fn peek_ahead( &mut self, distance: usize ) -> Option<&T> {
-        self.tokens.peek_ahead( distance )
+    pub fn peek_ahead( &self, distance: usize ) -> Option<&T> {
+        self.tokens.get( self.position + distance )
     }
 
-    pub fn match_a( &mut self, what: &T::Kind ) -> bool {
+    pub fn match_a( &self, what: &T::Kind ) -> bool {
         if let Some( tk ) = self.peek() {
-            if discriminant( tk.kind() ) == discriminant( what ) {
-                true
-            } else {
-                false
-            }
+            discriminant( tk.kind() ) == discriminant( what )
         } else {
             false
         }
     }
 
     pub fn consume( &mut self ) -> Result<T, ParseError> {
-        match self.tokens.consume() {
-            Some( x ) => Ok( x ),
+        match self.tokens.get( self.position ) {
+            Some( x ) => {
+                self.position += 1;
+                Ok( x.clone() )
+            },
             None => Err( ParseError::unexpected_eoi() )
         }
     }
@@ -164,6 +203,9 @@ pub enum LexErrorKind {
     UnexpectedEOI {
         reason: &'static str,
     },
+    TooDeep {
+        limit: usize,
+    },
 }
 
 #[derive( Debug )]
@@ -185,6 +227,15 @@ impl LexError {
         &self.span
     }
 
+    // What went wrong, without where.
+    pub fn message( &self ) -> String {
+        match &self.kind {
+            LexErrorKind::UnexpectedChar { character, codepoint } => format!( "unexpected character '{}' (0x{:X})", character, codepoint ),
+            LexErrorKind::UnexpectedEOI { reason } => format!( "unexpected end-of-input ({})", reason ),
+            LexErrorKind::TooDeep { limit } => format!( "interpolations nested more than {} levels deep", limit ),
+        }
+    }
+
     pub fn unexpected_char( c: char, span: TextSpan ) -> LexError {
         LexError::new(
             LexErrorKind::UnexpectedChar {
@@ -201,21 +252,39 @@ impl LexError {
             span
         )
     }
+
+    pub fn too_deep( limit: usize, span: TextSpan ) -> LexError {
+        LexError::new(
+            LexErrorKind::TooDeep { limit },
+            span
+        )
+    }
+}
+
+impl Display for LexError {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        write!( formatter, "{} at {}", self.message(), self.span )
+    }
 }
 
+// Walks a borrowed source string by byte offset. Positions reported through
+// `Location` count characters, while the text handed back is sliced straight
+// out of the source without copying.
 #[derive( Clone )]
-pub struct Scanner {
-    iter: BufferedPeekable<char>,
+pub struct Scanner<'a> {
+    source: &'a str,
+    offset: usize,
     markers: Vec<Location>,
     index: usize,
     line: usize,
     column: usize,
 }
 
-impl Scanner {
-    pub fn new( source: String, index: usize, line: usize, column: usize ) -> Scanner {
+impl<'a> Scanner<'a> {
+    pub fn new( source: &'a str, index: usize, line: usize, column: usize ) -> Scanner<'a> {
         Scanner {
-            iter: BufferedPeekable::new( source.chars().collect() ),
+            source,
+            offset: 0,
             markers: Vec::new(),
             index,
             line,
@@ -245,75 +314,69 @@ impl Scanner {
         let end = self.current_pos();
 
         Some( TextSpan {
-            start: start,
-            end: end
+            start,
+            end
         } )
     }
 
-    pub fn is_empty( &mut self ) -> bool {
-        self.iter.is_empty()
+    fn rest( &self ) -> &'a str {
+        &self.source[self.offset..]
     }
 
-    pub fn peek( &mut self ) -> Option<char> {
-        Some( *self.iter.peek()? )
+    pub fn is_empty( &self ) -> bool {
+        self.offset >= self.source.len()
     }
 
-    pub fn peek_ahead( &mut self, distance: usize ) -> Option<char> {
-        Some( *self.iter.peek_ahead( distance )? )
+    pub fn peek( &self ) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub fn peek_ahead( &self, distance: usize ) -> Option<char> {
+        self.rest().chars().nth( distance )
     }
 
     pub fn consume( &mut self ) -> Option<char> {
-        let c = self.iter.consume()?;
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
 
+        self.index += 1;
         if c == '\n' {
             self.line += 1;
-            self.column = 0;
+            self.column = 1;
+        } else {
+            self.column += c.width().unwrap_or( 0 );
         }
 
-        self.index += 1;
-        self.column += 1;
-
         Some( c )
     }
 
-    pub fn is_next( &mut self, s: &str ) -> bool {
-        for ( i, c ) in s.chars().enumerate() {
-            let found = match self.peek_ahead( i ) {
-                Some( x ) if x == c => true,
-                _ => false,
-            };
-
-            if !found { return false; }
-        }
-
-        true
+    pub fn is_next( &self, s: &str ) -> bool {
+        self.rest().starts_with( s )
     }
 
-    pub fn take_if_next( &mut self, s: &str ) -> Option<String> {
+    pub fn take_if_next( &mut self, s: &str ) -> Option<&'a str> {
         if self.is_next( s ) {
-            let mut buf = String::new();
-            for _ in 0 .. s.len() {
-                buf.push( self.consume()? )
+            let start = self.offset;
+            for _ in s.chars() {
+                self.consume();
             }
 
-            Some( buf )
+            Some( &self.source[start..self.offset] )
         } else {
             None
         }
     }
 
     pub fn skip_while<F: Fn( char ) -> bool>( &mut self, f: F ) {
-        while !self.is_empty() && f( self.peek().unwrap() ) {
+        while self.peek().is_some_and( &f ) {
             self.consume();
         }
     }
 
-    pub fn take_while<F: Fn( char ) -> bool>( &mut self, f: F ) -> String {
-        let mut s = String::new();
-        while !self.is_empty() && f( self.peek().unwrap() ) {
-            s.push( self.consume().unwrap() );
-        }
+    pub fn take_while<F: Fn( char ) -> bool>( &mut self, f: F ) -> &'a str {
+        let start = self.offset;
+        self.skip_while( f );
 
-        s
+        &self.source[start..self.offset]
     }
 }
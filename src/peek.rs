@@ -21,7 +21,7 @@ impl<T> BufferedPeekable<T> {
     }
 
     pub fn peek( &mut self ) -> Option<&T> {
-        if self.buf.len() == 0 {
+        if self.buf.is_empty() {
             self.buf.push_back( self.iter.next()? );
         }
 
@@ -43,7 +43,7 @@ impl<T> BufferedPeekable<T> {
     }
 
     pub fn consume( &mut self ) -> Option<T> {
-        if self.buf.len() > 0 {
+        if !self.buf.is_empty() {
             self.buf.pop_front()
         } else {
             self.iter.next()
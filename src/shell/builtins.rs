@@ -0,0 +1,1421 @@
+use std::io::{ BufRead, Result, Error, ErrorKind, Write, stdin };
+use std::collections::{ BTreeMap, VecDeque };
+use std::iter::Peekable;
+use std::str::Chars;
+use std::fs::read_to_string;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant };
+use std::process::{ Child, Stdio };
+use dirs::home_dir;
+use yansi::Paint;
+use clap::{ self, App, AppSettings };
+use yaml_rust::YamlLoader;
+use serde_json::{ self, Value };
+use kernel::{ clear_screen, store_secret, children_cpu_time, send_signal, signal_number, signals, interrupted, process_status, ProcessStatus };
+use shell::remote::quote_posix;
+use shell::completion::{ infer, spec_for, register, registered, unregister, UserCompletion };
+use shell::path;
+use shell::tilde;
+use shell::condition;
+use shell::secret;
+use shell::structured;
+use shell::context::{ ExecContext, Deadline };
+use shell::segments::{ Exec, Executable, ShellResult, SubProcess, Cmd, Text, timed, input_bytes };
+use shell::jobs::{ self, JOBS };
+use shell::history::HISTORY;
+use shell::frecency::VISITS;
+use shell::script::execute_script;
+use shell::transcript::strip_ansi;
+
+// A command the shell runs itself rather than starting a program for.
+pub trait Builtin: Sync {
+    fn name( &self ) -> &'static str;
+
+    // one line saying what the command does
+    fn help( &self ) -> &'static str;
+
+    // `argv` includes the command's own name, and `input` is whatever was piped into it
+    fn run( &self, ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult>;
+
+    // the full --help text, for builtins whose arguments clap parses
+    fn manual( &self ) -> Option<String> {
+        None
+    }
+
+    // how the command is written, e.g. cd [DIR]
+    fn usage( &self ) -> String {
+        self.name().to_string()
+    }
+}
+
+macro_rules! make_app {
+    ( $y: expr ) => {{
+        App::from_yaml( $y )
+            .author( crate_authors!() )
+            .version( crate_version!() )
+            .setting( AppSettings::ColoredHelp )
+            .setting( AppSettings::ColorAuto )
+    }}
+}
+
+// What a builtin does when clap stops it. --help and --version are its output,
+// so they can be paged or piped like any other; anything else is an error.
+// Colors are left in only for the terminal.
+fn usage( ctx: &ExecContext, e: clap::Error ) -> Result<ShellResult> {
+    let text = if ctx.capturing() || !Paint::is_enabled() { strip_ansi( &e.message ) } else { e.message };
+    let lines = text.trim_end().lines().map( | x | x.to_string() ).collect();
+
+    Ok( match e.kind {
+        clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => ShellResult::with_stdout( ProcessStatus::Exited( 0 ), lines ),
+
+        // 2, like sh and most programs use for bad usage
+        _ => ShellResult::with_stderr( ProcessStatus::Exited( 2 ), lines ),
+    } )
+}
+
+fn change_dir( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/cd.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let dir = args.value_of( "DIR" );
+
+            // `cd -` is the same as `cd -1`
+            let back = match dir {
+                Some( "-" ) => Some( 1 ),
+                Some( x ) if x.len() > 1 && x.starts_with( '-' ) => x[1 ..].parse::<usize>().ok(),
+                _ => None,
+            };
+
+            let target = match ( back, dir ) {
+                ( Some( n ), _ ) => match ctx.recent_dirs().get( n.wrapping_sub( 1 ) ) {
+                    Some( x ) => x.clone(),
+                    None if n == 1 => return ShellResult::failed( "cd: no previous directory".to_string() ),
+                    None => return ShellResult::failed( format!( "cd: no directory {} back (see 'recent')", n ) ),
+                },
+
+                ( None, Some( x ) ) => PathBuf::from( x ),
+                ( None, None ) => match home_dir() {
+                    Some( x ) => x,
+                    None => return ShellResult::failed( "cd: unable to locate the home directory".to_string() ),
+                },
+            };
+
+            if let Err( e ) = ctx.change_dir( &target ) {
+                return ShellResult::failed( format!( "cd: {}: {}", target.display(), e ) );
+            }
+
+            // like other shells, show where `cd -` ended up
+            if back.is_some() {
+                ShellResult::ok_with_text( target.to_string_lossy().into_owned() )
+            } else {
+                ShellResult::ok()
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// The directory stack the way `dirs` shows it: the working directory, then
+// the directories pushd saved, most recent first.
+fn dir_list( ctx: &ExecContext ) -> Vec<PathBuf> {
+    let mut dirs = vec![ ctx.cwd().to_path_buf() ];
+    dirs.extend( ctx.dir_stack().iter().cloned() );
+    dirs
+}
+
+fn show_dirs( ctx: &ExecContext ) -> String {
+    dir_list( ctx ).iter().map( | x | tilde::abbreviate( x ) ).collect::<Vec<_>>().join( " " )
+}
+
+// Whether `arg` is written as +N or -N, which count entries of the directory
+// stack from the left and right, starting at 0.
+fn is_stack_index( arg: &str ) -> bool {
+    arg.len() > 1 && arg.starts_with( [ '+', '-' ] ) && arg[1 ..].chars().all( | x | x.is_ascii_digit() )
+}
+
+fn stack_index( arg: &str, len: usize ) -> Option<usize> {
+    let n: usize = arg[1 ..].parse().ok().filter( | x | *x < len )?;
+    Some( if arg.starts_with( '+' ) { n } else { len - 1 - n } )
+}
+
+// Moves to `dirs[0]` and keeps the rest as the stack below it.
+fn set_dir_list( ctx: &mut ExecContext, name: &str, mut dirs: Vec<PathBuf> ) -> Result<ShellResult> {
+    if let Err( e ) = ctx.change_dir( &dirs[0] ) {
+        return ShellResult::failed( format!( "{}: {}: {}", name, dirs[0].display(), e ) );
+    }
+
+    ctx.set_dir_stack( dirs.split_off( 1 ) );
+    ShellResult::ok_with_text( show_dirs( ctx ) )
+}
+
+fn pushd( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/pushd.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let mut dirs = dir_list( ctx );
+            match args.value_of( "DIR" ) {
+                None if dirs.len() < 2 => return ShellResult::failed( "pushd: no other directory".to_string() ),
+                None => dirs.swap( 0, 1 ),
+                Some( x ) if is_stack_index( x ) => match stack_index( x, dirs.len() ) {
+                    Some( i ) => dirs.rotate_left( i ),
+                    None => return ShellResult::failed( format!( "pushd: {}: directory stack index out of range", x ) ),
+                },
+
+                Some( x ) => dirs.insert( 0, PathBuf::from( x ) ),
+            }
+
+            set_dir_list( ctx, "pushd", dirs )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn popd( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/popd.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let mut dirs = dir_list( ctx );
+            if dirs.len() < 2 {
+                return ShellResult::failed( "popd: directory stack empty".to_string() );
+            }
+
+            let index = match args.value_of( "INDEX" ) {
+                Some( x ) => match stack_index( x, dirs.len() ).filter( | _ | is_stack_index( x ) ) {
+                    Some( i ) => i,
+                    None => return ShellResult::failed( format!( "popd: {}: invalid directory stack index", x ) ),
+                },
+
+                None => 0,
+            };
+
+            dirs.remove( index );
+            set_dir_list( ctx, "popd", dirs )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn dirs( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/dirs.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            if args.is_present( "clear" ) {
+                ctx.set_dir_stack( Vec::new() );
+                return ShellResult::ok();
+            }
+
+            let full = args.is_present( "full" );
+            let dirs: Vec<String> = dir_list( ctx ).iter()
+                .map( | x | if full { x.to_string_lossy().into_owned() } else { tilde::abbreviate( x ) } )
+                .collect();
+
+            if args.is_present( "verbose" ) {
+                ShellResult::ok_with_lines( dirs.iter().enumerate().map( | ( i, x ) | format!( "{:>2}  {}", i, x ) ).collect() )
+            } else if args.is_present( "lines" ) {
+                ShellResult::ok_with_lines( dirs )
+            } else {
+                ShellResult::ok_with_text( dirs.join( " " ) )
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn recent( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/recent.yaml" );
+    if let Err( e ) = make_app!( yaml ).get_matches_from_safe( argv ) {
+        return usage( ctx, e );
+    }
+
+    let lines = ctx.recent_dirs().iter()
+        .enumerate()
+        .map( | ( i, x ) | format!( "{:>2}  {}", i + 1, tilde::abbreviate( x ) ) )
+        .collect();
+
+    ShellResult::ok_with_lines( lines )
+}
+
+fn jump( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/z.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let terms: Vec<String> = args.values_of( "QUERY" ).into_iter().flatten().map( | x | x.to_string() ).collect();
+
+            // an actual directory is simply changed to, like cd
+            if let [ ref dir ] = terms[..] {
+                if Path::new( dir ).is_dir() {
+                    ctx.change_dir( Path::new( dir ) )?;
+                    return ShellResult::ok();
+                }
+            }
+
+            let found = VISITS.lock().unwrap().ranked( &terms );
+            if terms.is_empty() || args.is_present( "list" ) {
+                let lines = found.iter().map( | ( score, x ) | format!( "{:>8.1}  {}", score, tilde::abbreviate( x ) ) ).collect();
+                return ShellResult::ok_with_lines( lines );
+            }
+
+            // skip the directory we're already in, so repeating a jump moves on to the next match
+            match found.into_iter().map( | ( _, x ) | x ).find( | x | x != ctx.cwd() ) {
+                Some( dir ) => {
+                    ctx.change_dir( &dir )?;
+                    ShellResult::ok_with_text( tilde::abbreviate( &dir ) )
+                },
+
+                None => ShellResult::failed( format!( "z: no directory matches '{}'", terms.join( " " ) ) ),
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn complete( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/complete.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let tool = args.value_of( "TOOL" ).unwrap();
+            if args.is_present( "remove" ) {
+                if !unregister( tool ) {
+                    return Err( Error::other( format!( "no completions registered for '{}'", tool ) ) );
+                }
+
+                return ShellResult::ok();
+            }
+
+            if let Some( command ) = args.value_of( "command" ) {
+                register( tool, UserCompletion::Command( command.to_string() ) );
+                return ShellResult::ok();
+            }
+
+            // `complete git checkout,branch status` offers all three
+            if let Some( words ) = args.values_of( "WORDS" ) {
+                let words = words.flat_map( | x | x.split( ',' ) ).filter( | x | !x.is_empty() ).map( | x | x.to_string() );
+                register( tool, UserCompletion::Words( words.collect() ) );
+                return ShellResult::ok();
+            }
+
+            if let Some( user ) = registered( tool ) {
+                return ShellResult::ok_with_lines( user.describe() );
+            }
+
+            let spec = if args.is_present( "infer" ) {
+                infer( tool )?
+            } else {
+                spec_for( tool ).ok_or_else( || Error::other(
+                    format!( "no completions known for '{0}' (try 'complete --infer {0}')", tool )
+                ) )?
+            };
+
+            ShellResult::ok_with_lines( spec.describe() )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn secret( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/secret.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => match args.subcommand() {
+            ( "set", Some( sub ) ) => {
+                let name = sub.value_of( "NAME" ).unwrap();
+                secret::check_name( name )?;
+
+                let value = match input {
+                    Some( lines ) => lines.join( "\n" ),
+                    None => secret::read_hidden( &format!( "value for {}: ", name ) )?,
+                };
+
+                store_secret( name, &value )?;
+                ShellResult::ok()
+            },
+
+            ( "get", Some( sub ) ) => ShellResult::ok_with_text( secret::lookup( sub.value_of( "NAME" ).unwrap() )? ),
+
+            _ => unreachable!(),
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// a malformed assertion has to fail the test rather than just print usage
+fn assertion_failed( message: String ) -> Result<ShellResult> {
+    Err( Error::other( format!( "assertion failed: {}", message ) ) )
+}
+
+fn assert_eq( _ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/assert_eq.yaml" );
+    let args = make_app!( yaml ).get_matches_from_safe( argv ).map_err( | e | Error::other( e.message ) )?;
+    let expected = args.value_of( "EXPECTED" ).unwrap();
+
+    let actual = match ( args.value_of( "ACTUAL" ), input ) {
+        ( Some( x ), _ ) => x.to_string(),
+        ( None, Some( lines ) ) => lines.join( "\n" ),
+        ( None, None ) => String::new(),
+    };
+
+    if actual == expected {
+        ShellResult::ok()
+    } else {
+        assertion_failed( format!( "expected '{}', found '{}'", expected, actual ) )
+    }
+}
+
+fn assert_status( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/assert_status.yaml" );
+    let args = make_app!( yaml ).get_matches_from_safe( argv ).map_err( | e | Error::other( e.message ) )?;
+    let expected: i32 = args.value_of( "STATUS" ).unwrap().parse()
+        .map_err( | _ | Error::other( "STATUS must be an integer" ) )?;
+
+    let mut words = args.values_of( "COMMAND" ).unwrap().map( | x | Box::new( Text( x.to_string() ) ) as Exec );
+    let cmd = Cmd {
+        command: words.next().unwrap(),
+        args: Some( words.collect() ),
+    };
+
+    let actual = cmd.execute( ctx, true, input )?.code();
+    if actual == Some( expected ) {
+        ShellResult::ok()
+    } else {
+        let actual = actual.map_or( "no status".to_string(), | x | x.to_string() );
+        assertion_failed( format!( "expected status {}, found {}", expected, actual ) )
+    }
+}
+
+fn assert_file( _ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/assert_file.yaml" );
+    let args = make_app!( yaml ).get_matches_from_safe( argv ).map_err( | e | Error::other( e.message ) )?;
+    let path = args.value_of( "PATH" ).unwrap();
+
+    if !Path::new( path ).is_file() {
+        return assertion_failed( format!( "'{}' does not exist", path ) );
+    }
+
+    if let Some( expected ) = args.value_of( "CONTENTS" ) {
+        let actual = read_to_string( path )?;
+        let actual = actual.strip_suffix( '\n' ).unwrap_or( &actual );
+
+        if actual != expected {
+            return assertion_failed( format!( "expected '{}' to contain '{}', found '{}'", path, expected, actual ) );
+        }
+    }
+
+    ShellResult::ok()
+}
+
+fn alias( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/alias.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let show = | name: &str, value: &str | format!( "{}={}", name, quote_posix( value ) );
+            let names = match args.values_of( "NAME" ) {
+                Some( x ) => x,
+                None => {
+                    let lines = ctx.aliases().iter().map( | ( k, v ) | show( k, v ) ).collect();
+                    return ShellResult::ok_with_lines( lines );
+                },
+            };
+
+            let mut lines = Vec::new();
+            let mut names = names.peekable();
+            while let Some( arg ) = names.next() {
+                match arg.find( '=' ) {
+                    Some( 0 ) => return ShellResult::failed( format!( "alias: '{}' is not a valid alias name", arg ) ),
+
+                    // ll="ls -l" arrives as two words, `ll=` and `ls -l`
+                    Some( i ) if i == arg.len() - 1 && names.peek().is_some() => {
+                        ctx.set_alias( &arg[..i], names.next().unwrap() );
+                    },
+
+                    Some( i ) => ctx.set_alias( &arg[..i], &arg[( i + 1 )..] ),
+                    None => match ctx.alias( arg ) {
+                        Some( value ) => lines.push( show( arg, &value ) ),
+                        None => return ShellResult::failed( format!( "alias: '{}' not found", arg ) ),
+                    },
+                }
+            }
+
+            ShellResult::ok_with_lines( lines )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn history( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/history.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let mut history = HISTORY.lock().unwrap();
+            if args.is_present( "clear" ) {
+                history.clear()?;
+                return ShellResult::ok();
+            }
+
+            let count = match args.value_of( "COUNT" ).map( | x | x.parse::<usize>() ) {
+                Some( Ok( x ) ) => x,
+                Some( Err( _ ) ) => return ShellResult::failed( "history: COUNT must be a number".to_string() ),
+                None => usize::MAX,
+            };
+
+            // where and how entries ran is only known for this session's, unless
+            // the history backend keeps it
+            let search = args.value_of( "search" );
+            let here = args.is_present( "here" );
+            let failed = args.is_present( "failed" );
+            let mut lines: Vec<String> = history.records()
+                .iter()
+                .enumerate()
+                .filter( | ( _, x ) | search.is_none_or( | s | x.command.contains( s ) ) )
+                .filter( | ( _, x ) | !here || x.cwd.as_deref() == Some( ctx.cwd() ) )
+                .filter( | ( _, x ) | !failed || x.status.is_some_and( | s | s != 0 ) )
+                .map( | ( i, x ) | format!( "{:>5}  {}", i + 1, x.command ) )
+                .collect();
+
+            lines.drain( .. lines.len().saturating_sub( count ) );
+            ShellResult::ok_with_lines( lines )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn which( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/which.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let all = args.is_present( "all" );
+            let mut lines = Vec::new();
+            let mut missing = Vec::new();
+
+            for name in args.values_of( "NAME" ).unwrap() {
+                let before = lines.len();
+                if let Some( value ) = ctx.alias( name ) {
+                    lines.push( format!( "{}: aliased to {}", name, value ) );
+                }
+
+                if is_builtin( name ) && ( all || lines.len() == before ) {
+                    lines.push( format!( "{}: shell builtin", name ) );
+                }
+
+                if all {
+                    lines.extend( path::search_all( name ).iter().map( | x | x.to_string_lossy().into_owned() ) );
+                } else if lines.len() == before {
+                    if let Some( exe ) = path::resolve( name ) {
+                        lines.push( exe.to_string_lossy().into_owned() );
+                    }
+                }
+
+                if lines.len() == before {
+                    missing.push( format!( "which: no {} in PATH", name ) );
+                }
+            }
+
+            let status = ProcessStatus::Exited( if missing.is_empty() { 0 } else { 1 } );
+            Ok( ShellResult { stderr: Some( missing ).filter( | x | !x.is_empty() ), ..ShellResult::with_stdout( status, lines ) } )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn export( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/export.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let names = match args.values_of( "NAME" ) {
+                Some( x ) => x,
+                None => {
+                    return ShellResult::ok_with_lines( ctx.exported() );
+                },
+            };
+
+            let mut missing = Vec::new();
+            for arg in names {
+                let ( name, value ) = match arg.find( '=' ) {
+                    Some( i ) => ( &arg[..i], Some( &arg[( i + 1 )..] ) ),
+                    None => ( arg, None ),
+                };
+
+                if name.is_empty() {
+                    return ShellResult::failed( format!( "export: '{}' is not a valid variable name", arg ) );
+                }
+
+                if !ctx.export( name, value ) {
+                    missing.push( format!( "export: variable '{}' not found", name ) );
+                }
+            }
+
+            if missing.is_empty() {
+                ShellResult::ok()
+            } else {
+                Ok( ShellResult::with_stderr( ProcessStatus::Exited( 1 ), missing ) )
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn exit( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/exit.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let code = match args.value_of( "CODE" ) {
+                Some( x ) => match x.parse() {
+                    Ok( x ) => x,
+                    Err( _ ) => return ShellResult::failed( format!( "exit: '{}' is not a number", x ) ),
+                },
+
+                None => ctx.last_status(),
+            };
+
+            ctx.request_exit( code );
+            Ok( ShellResult::with_status( ProcessStatus::Exited( code ) ) )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn job_id( args: &clap::ArgMatches ) -> Result<Option<usize>> {
+    match args.value_of( "JOB" ) {
+        Some( x ) => jobs::parse_id( x ).map( Some ).ok_or_else(
+            || Error::new( ErrorKind::InvalidInput, format!( "'{}' is not a job id", x ) )
+        ),
+
+        None => Ok( None ),
+    }
+}
+
+fn list_jobs( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/jobs.yaml" );
+    if let Err( e ) = make_app!( yaml ).get_matches_from_safe( argv ) {
+        return usage( ctx, e );
+    }
+
+    let mut table = JOBS.lock().unwrap();
+    let mut lines: Vec<String> = table.reap().into_iter()
+        .map( | ( job, code ) | format!( "[{}] Done ({})  {}", job.id, code, job.command ) )
+        .collect();
+
+    for job in table.jobs() {
+        let state = if job.stopped { "Stopped" } else { "Running" };
+        lines.push( format!( "[{}] {}  {}", job.id, state, job.command ) );
+    }
+
+    ShellResult::ok_with_lines( lines )
+}
+
+fn foreground( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/fg.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let id = job_id( &args )?;
+            let job = JOBS.lock().unwrap().take( id ).ok_or_else( || Error::other( "no such job" ) )?;
+
+            println!( "{}", job.command );
+            Ok( ShellResult::with_status( job.foreground()? ) )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn background( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/bg.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let id = job_id( &args )?;
+            let mut table = JOBS.lock().unwrap();
+            let job = table.get( id ).ok_or_else( || Error::other( "no such job" ) )?;
+
+            job.resume()?;
+            ShellResult::ok_with_text( format!( "[{}] {} &", job.id, job.command ) )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn kill( ctx: &mut ExecContext, mut argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    // the signal may come first as -TERM or -9, which clap can't parse
+    let mut signal = None;
+    if let Some( name ) = argv.get( 1 ).and_then( | x | x.strip_prefix( '-' ) ) {
+        if let Some( x ) = signal_number( name ) {
+            signal = Some( x );
+            argv.remove( 1 );
+        }
+    }
+
+    let yaml = load_yaml!( "cli_args/kill.yaml" );
+    let args = match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( x ) => x,
+        Err( e ) => return usage( ctx, e ),
+    };
+
+    if args.is_present( "list" ) {
+        return ShellResult::ok_with_lines( signals().iter().map( | ( n, name ) | format!( "{:>2} {}", n, &name[3..] ) ).collect() );
+    }
+
+    if let Some( name ) = args.value_of( "signal" ) {
+        match signal_number( name ) {
+            Some( x ) => signal = Some( x ),
+            None => return ShellResult::failed( format!( "kill: '{}' is not a signal", name ) ),
+        }
+    }
+
+    let signal = signal.or_else( || signal_number( "TERM" ) ).unwrap();
+    let mut errors = Vec::new();
+    for target in args.values_of( "TARGET" ).unwrap() {
+        let res = if target.starts_with( '%' ) {
+            match jobs::parse_id( target ) {
+                Some( id ) => match JOBS.lock().unwrap().get( Some( id ) ) {
+                    Some( job ) => job.signal( signal ),
+                    None => Err( Error::other( "no such job" ) ),
+                },
+
+                None => Err( Error::new( ErrorKind::InvalidInput, "not a job id" ) ),
+            }
+        } else {
+            match target.parse() {
+                Ok( pid ) => send_signal( pid, signal ),
+                Err( _ ) => Err( Error::new( ErrorKind::InvalidInput, "not a process id" ) ),
+            }
+        };
+
+        if let Err( e ) = res {
+            errors.push( format!( "kill: {}: {}", target, e ) );
+        }
+    }
+
+    if errors.is_empty() {
+        ShellResult::ok()
+    } else {
+        Ok( ShellResult::with_stderr( ProcessStatus::Exited( 1 ), errors ) )
+    }
+}
+
+// Parses durations like 10, 1.5s, 500ms, 2m or 1h; plain numbers are seconds.
+fn parse_duration( text: &str ) -> Option<Duration> {
+    let split = text.find( | c: char | !c.is_ascii_digit() && c != '.' ).unwrap_or( text.len() );
+    let ( number, unit ) = text.split_at( split );
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => return None,
+    };
+
+    Duration::try_from_secs_f64( seconds ).ok()
+}
+
+// status of a command stopped by `timeout`, the same as coreutils
+const TIMED_OUT: i32 = 124;
+
+fn timeout( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/timeout.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let duration = args.value_of( "DURATION" ).unwrap();
+            let limit = match parse_duration( duration ) {
+                Some( x ) => x,
+                None => return ShellResult::failed( format!( "timeout: '{}' is not a duration", duration ) ),
+            };
+
+            let kill_after = match args.value_of( "kill-after" ).map( | x | ( x, parse_duration( x ) ) ) {
+                Some( ( _, Some( x ) ) ) => Some( x ),
+                Some( ( x, None ) ) => return ShellResult::failed( format!( "timeout: '{}' is not a duration", x ) ),
+                None => None,
+            };
+
+            let mut words = args.values_of( "COMMAND" ).unwrap().map( | x | Box::new( Text( x.to_string() ) ) as Exec );
+            let cmd = Cmd {
+                command: words.next().unwrap(),
+                args: Some( words.collect() ),
+            };
+
+            let deadline = Deadline { at: Instant::now() + limit, kill_after };
+            let capture = ctx.capturing();
+            ctx.take_timed_out();
+            let mut res = ctx.with_deadline( deadline, | ctx | cmd.execute( ctx, capture, input ) )?;
+
+            // a command that finished on its own just before the deadline didn't time out
+            if ctx.take_timed_out() {
+                res.status = ProcessStatus::Exited( TIMED_OUT );
+            }
+
+            Ok( res )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// e.g. 0m1.250s, the format sh's `time` uses
+fn format_time( duration: Duration ) -> String {
+    let secs = duration.as_secs_f64();
+    format!( "{}m{:.3}s", ( secs / 60.0 ).floor(), secs % 60.0 )
+}
+
+fn time( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/time.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let mut words = args.values_of( "COMMAND" ).unwrap().map( | x | Box::new( Text( x.to_string() ) ) as Exec );
+            let cmd: Exec = Box::new( Cmd {
+                command: words.next().unwrap(),
+                args: Some( words.collect() ),
+            } );
+
+            let before = children_cpu_time();
+            let capture = ctx.capturing();
+            let mut res = timed( &cmd, ctx, capture, input )?;
+
+            let mut lines = vec![ format!( "real\t{}", format_time( res.elapsed.unwrap_or_default() ) ) ];
+            if let ( Some( ( user, sys ) ), Some( ( after_user, after_sys ) ) ) = ( before, children_cpu_time() ) {
+                lines.push( format!( "user\t{}", format_time( after_user.saturating_sub( user ) ) ) );
+                lines.push( format!( "sys\t{}", format_time( after_sys.saturating_sub( sys ) ) ) );
+            }
+
+            // like sh, the report goes to stderr so it doesn't mix with the command's output
+            res.stderr.get_or_insert_with( Vec::new ).extend( lines );
+            Ok( res )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// status of `each` when any run of its command failed, the same as xargs
+const EACH_FAILED: i32 = 123;
+
+// The words for one run of `each`'s command: a word that's just the
+// placeholder becomes the lines, one argument each, and one with it inside has
+// them joined by spaces. Without a placeholder the lines go on the end.
+fn each_argv( words: &[String], placeholder: &str, lines: &[String] ) -> Vec<String> {
+    if !words.iter().any( | x | x.contains( placeholder ) ) {
+        return words.iter().chain( lines ).cloned().collect();
+    }
+
+    let joined = lines.join( " " );
+    words.iter()
+        .flat_map( | x | if x == placeholder { lines.to_vec() } else { vec![ x.replace( placeholder, &joined ) ] } )
+        .collect()
+}
+
+// Runs each list of words as a program, up to `jobs` at a time. Output is
+// collected in the order the runs were started.
+fn each_parallel( ctx: &ExecContext, runs: Vec<Vec<String>>, jobs: usize ) -> Result<ShellResult> {
+    let capture = ctx.capturing();
+    let mut res = ShellResult::ok()?;
+    let mut running = VecDeque::new();
+
+    let finish = | res: &mut ShellResult, child: Child | -> Result<()> {
+        let out = child.wait_with_output()?;
+        if !process_status( out.status ).success() {
+            res.status = ProcessStatus::Exited( EACH_FAILED );
+        }
+
+        if capture {
+            let lines = | x: &[u8] | String::from_utf8_lossy( x ).lines().map( | x | x.to_string() ).collect::<Vec<_>>();
+            res.stdout.get_or_insert_with( Vec::new ).extend( lines( &out.stdout ) );
+            res.stderr.get_or_insert_with( Vec::new ).extend( lines( &out.stderr ) );
+        }
+
+        Ok( () )
+    };
+
+    for argv in runs {
+        if interrupted() {
+            break;
+        }
+
+        if running.len() >= jobs {
+            finish( &mut res, running.pop_front().unwrap() )?;
+        }
+
+        let mut proc = path::command( &argv[0] ).ok_or_else( || Error::new( ErrorKind::NotFound, format!( "each: {}: program not found", argv[0] ) ) )?;
+        proc.args( &argv[1..] );
+        if capture {
+            proc.stdout( Stdio::piped() ).stderr( Stdio::piped() );
+        }
+
+        running.push_back( proc.spawn()? );
+    }
+
+    for child in running {
+        finish( &mut res, child )?;
+    }
+
+    Ok( res )
+}
+
+fn each( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/each.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let count = | name: &str | match args.value_of( name ).map( | x | x.parse::<usize>() ) {
+                Some( Ok( 0 ) ) | Some( Err( _ ) ) => Err( format!( "each: {} must be a number above 0", name ) ),
+                Some( Ok( x ) ) => Ok( x ),
+                None => Ok( 1 ),
+            };
+
+            let ( batch, jobs ) = match ( count( "lines" ), count( "parallel" ) ) {
+                ( Ok( x ), Ok( y ) ) => ( x, y ),
+                ( Err( e ), _ ) | ( _, Err( e ) ) => return ShellResult::failed( e ),
+            };
+
+            let placeholder = args.value_of( "replace" ).unwrap_or( "{}" );
+            let words: Vec<String> = args.values_of( "COMMAND" ).unwrap().map( | x | x.to_string() ).collect();
+
+            // like xargs, lines come from stdin when nothing is piped in
+            let lines = match input {
+                Some( x ) => x,
+                None => stdin().lock().lines().collect::<Result<_>>()?,
+            };
+
+            let lines: Vec<String> = lines.into_iter().filter( | x | !x.trim().is_empty() ).collect();
+            let runs: Vec<Vec<String>> = lines.chunks( batch ).map( | x | each_argv( &words, placeholder, x ) ).collect();
+
+            // builtins, functions and aliases need the shell itself, so only programs run side by side
+            let name = &words[0];
+            let program = !is_builtin( name ) && ctx.function( name ).is_none() && ctx.alias( name ).is_none() && !name.contains( placeholder );
+            if jobs > 1 && program {
+                return each_parallel( ctx, runs, jobs );
+            }
+
+            let capture = ctx.capturing();
+            let mut res = ShellResult::ok()?;
+            for argv in runs {
+                if interrupted() {
+                    break;
+                }
+
+                let mut words = argv.into_iter().map( | x | Box::new( Text( x ) ) as Exec );
+                let cmd = Cmd {
+                    command: words.next().unwrap(),
+                    args: Some( words.collect() ),
+                };
+
+                let next = cmd.execute( ctx, capture, None )?;
+                if !next.status.success() {
+                    res.status = ProcessStatus::Exited( EACH_FAILED );
+                }
+
+                for ( all, part ) in [ ( &mut res.stdout, next.stdout ), ( &mut res.stderr, next.stderr ) ] {
+                    if let Some( part ) = part {
+                        all.get_or_insert_with( Vec::new ).extend( part );
+                    }
+                }
+            }
+
+            Ok( res )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn from_json( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/from-json.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let value = match args.value_of( "FILE" ) {
+                Some( file ) => structured::parse( &[ read_to_string( file )? ] ),
+                None => match structured::input( ctx, input ) {
+                    Ok( Some( x ) ) => Ok( x ),
+                    Ok( None ) => return ShellResult::failed( "from-json: nothing to read; pipe JSON into it or give a file".to_string() ),
+                    Err( e ) => Err( e ),
+                },
+            };
+
+            match value {
+                Ok( value ) => structured::result( structured::to_lines( &value ), value ),
+                Err( e ) => ShellResult::failed( format!( "from-json: {}", e ) ),
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn to_json( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/to-json.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            // plain text isn't JSON to begin with, so its lines are what's written
+            let value = match ctx.take_piped_value() {
+                Some( x ) => x,
+                None => Value::Array( input.unwrap_or_default().into_iter().map( Value::String ).collect() ),
+            };
+
+            let text = if args.is_present( "compact" ) { serde_json::to_string( &value ) } else { serde_json::to_string_pretty( &value ) };
+            let lines = text.map_err( Error::other )?.lines().map( | x | x.to_string() ).collect();
+            structured::result( lines, value )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn get( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/get.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let path = args.value_of( "PATH" ).unwrap();
+            let value = match structured::input( ctx, input ) {
+                Ok( Some( x ) ) => x,
+                Ok( None ) => return ShellResult::failed( "get: nothing to read; pipe JSON into it".to_string() ),
+                Err( e ) => return ShellResult::failed( format!( "get: {}", e ) ),
+            };
+
+            match structured::get( &value, path ) {
+                Some( x ) => structured::result( structured::to_lines( x ), x.clone() ),
+                None => ShellResult::failed( format!( "get: there's nothing at '{}'", path ) ),
+            }
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn clear( _ctx: &mut ExecContext, _argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    unsafe { clear_screen(); }
+    ShellResult::ok()
+}
+
+// The value of up to `max` digits in `radix` at the start of `chars`.
+fn digits( chars: &mut Peekable<Chars>, radix: u32, max: usize ) -> u32 {
+    let mut value = 0;
+    for _ in 0 .. max {
+        match chars.peek().and_then( | x | x.to_digit( radix ) ) {
+            Some( x ) => value = value * radix + x,
+            None => break,
+        }
+
+        chars.next();
+    }
+
+    value
+}
+
+// The text of `echo -e` arguments with backslash escapes replaced. The bool is
+// false when \c asked for nothing more to be printed.
+fn unescape( text: &str ) -> ( String, bool ) {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some( c ) = chars.next() {
+        if c != '\\' {
+            out.push( c );
+            continue;
+        }
+
+        match chars.next() {
+            Some( 'n' ) => out.push( '\n' ),
+            Some( 't' ) => out.push( '\t' ),
+            Some( 'r' ) => out.push( '\r' ),
+            Some( 'a' ) => out.push( '\x07' ),
+            Some( 'b' ) => out.push( '\x08' ),
+            Some( 'e' ) => out.push( '\x1b' ),
+            Some( 'f' ) => out.push( '\x0c' ),
+            Some( 'v' ) => out.push( '\x0b' ),
+            Some( '\\' ) => out.push( '\\' ),
+            Some( 'c' ) => return ( out, false ),
+            Some( '0' ) => out.extend( char::from_u32( digits( &mut chars, 8, 3 ) ) ),
+            Some( 'x' ) => out.extend( char::from_u32( digits( &mut chars, 16, 2 ) ) ),
+            Some( x ) => {
+                out.push( '\\' );
+                out.push( x );
+            },
+
+            None => out.push( '\\' ),
+        }
+    }
+
+    ( out, true )
+}
+
+// Options are read by hand like sh does, since anything that isn't exactly
+// one of them, like `echo --help`, is text to print.
+fn echo( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = argv.into_iter().skip( 1 ).peekable();
+
+    while let Some( flags ) = words.peek().and_then( | x | x.strip_prefix( '-' ) ) {
+        if flags.is_empty() || !flags.chars().all( | x | "neE".contains( x ) ) {
+            break;
+        }
+
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+
+        words.next();
+    }
+
+    let mut text = words.collect::<Vec<_>>().join( " " );
+    if escapes {
+        let ( unescaped, more ) = unescape( &text );
+        text = unescaped;
+        newline &= more;
+    }
+
+    // captured output is kept as lines, so only the terminal can go without the last newline
+    if !newline && !ctx.capturing() {
+        print!( "{}", text );
+        std::io::stdout().flush()?;
+        return ShellResult::ok();
+    }
+
+    ShellResult::ok_with_lines( text.split( '\n' ).map( | x | x.to_string() ).collect() )
+}
+
+fn pwd( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/pwd.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let dir = if args.is_present( "physical" ) {
+                ctx.cwd().canonicalize()?
+            } else {
+                ctx.cwd().to_path_buf()
+            };
+
+            ShellResult::ok_with_text( dir.to_string_lossy().into_owned() )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn rehash( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/rehash.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            if args.is_present( "list" ) {
+                let lines = path::cached().into_iter().map( | ( name, exe ) | format!( "{}={}", name, exe.display() ) ).collect();
+                return ShellResult::ok_with_lines( lines );
+            }
+
+            path::clear_cache();
+            ShellResult::ok()
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// Without arguments `env` lists the environment; anything else, like
+// `env -i FOO=1 prog`, is left to the env program.
+fn env( ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    // `NAME=value env` lists or passes on the variables given to it as well
+    let extra = ctx.take_command_env();
+    if argv.len() == 1 {
+        let mut lines: Vec<String> = ctx.exported().into_iter()
+            .filter( | x | !extra.iter().any( | ( name, _ ) | x.starts_with( &format!( "{}=", name ) ) ) )
+            .chain( extra.iter().map( | ( name, value ) | format!( "{}={}", name, value ) ) )
+            .collect();
+
+        lines.sort();
+        return ShellResult::ok_with_lines( lines );
+    }
+
+    let mut proc = path::command( "env" ).ok_or_else( || Error::new( ErrorKind::NotFound, "env: program not found" ) )?;
+    proc.args( &argv[1 ..] ).envs( extra );
+
+    let capture = ctx.capturing();
+    let input = input_bytes( ctx, input );
+    SubProcess::launch( proc, capture, input )?.result( ctx, argv.join( " " ) )
+}
+
+fn set( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    // clap only knows -e; +e and +o NAME, the usual ways to turn options back
+    // off, are read by hand
+    let mut off = Vec::new();
+    let mut rest = Vec::new();
+    let mut words = argv.into_iter();
+    while let Some( word ) = words.next() {
+        if word == "+o" {
+            match words.next() {
+                Some( name ) => off.push( name ),
+                None => return ShellResult::failed( "set: +o needs an option name".to_string() ),
+            }
+        } else if word.len() > 1 && word.starts_with( '+' ) {
+            for c in word[1 ..].chars() {
+                off.push( match c {
+                    'e' => "errexit",
+                    'x' => "xtrace",
+                    'u' => "nounset",
+                    _ => return ShellResult::failed( format!( "set: unknown option +{}", c ) ),
+                }.to_string() );
+            }
+        } else {
+            rest.push( word );
+        }
+    }
+
+    let yaml = load_yaml!( "cli_args/set.yaml" );
+    let args = match make_app!( yaml ).get_matches_from_safe( rest ) {
+        Ok( x ) => x,
+        Err( e ) => return usage( ctx, e ),
+    };
+
+    let mut on: Vec<String> = args.values_of( "option" ).map_or( Vec::new(), | x | x.map( | x | x.to_string() ).collect() );
+    on.extend( [ "errexit", "xtrace", "nounset" ].iter().filter( | x | args.is_present( x ) ).map( | x | x.to_string() ) );
+    if on.is_empty() && off.is_empty() {
+        let lines = ctx.variables().iter().map( | ( k, v ) | format!( "{}={}", k, quote_posix( v ) ) ).collect();
+        return ShellResult::ok_with_lines( lines );
+    }
+
+    let mut options = ctx.options();
+    for ( names, value ) in [ ( on, true ), ( off, false ) ] {
+        for name in names {
+            match options.flag_mut( &name ) {
+                Some( flag ) => *flag = value,
+                None => return ShellResult::failed( format!( "set: unknown option {}", name ) ),
+            }
+        }
+    }
+
+    *ctx.options_mut() = options;
+    ShellResult::ok()
+}
+
+fn help( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/help.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            if let Some( name ) = args.value_of( "NAME" ) {
+                let builtin = match lookup( name ) {
+                    Some( x ) => x,
+                    None => return ShellResult::failed( format!( "help: '{}' is not a builtin", name ) ),
+                };
+
+                let text = builtin.manual().unwrap_or_else( || format!( "{}\n{}", builtin.usage(), builtin.help() ) );
+                return ShellResult::ok_with_lines( text.trim_end().lines().map( | x | x.to_string() ).collect() );
+            }
+
+            let usages: Vec<String> = BUILTINS.values().map( | x | x.usage() ).collect();
+            let width = usages.iter().map( | x | x.chars().count() ).max().unwrap_or( 0 );
+            let lines = usages.iter()
+                .zip( BUILTINS.values() )
+                .map( | ( usage, x ) | format!( "{:<width$}  {}", usage, x.help(), width = width ) )
+                .collect();
+
+            ShellResult::ok_with_lines( lines )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// What running each name would do, checked in the same order commands are looked up.
+fn type_of( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/type.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let mut lines = Vec::new();
+            let mut missing = Vec::new();
+
+            for name in args.values_of( "NAME" ).unwrap() {
+                if let Some( value ) = ctx.alias( name ) {
+                    lines.push( format!( "{} is aliased to '{}'", name, value ) );
+                } else if ctx.function( name ).is_some() {
+                    lines.push( format!( "{} is a function", name ) );
+                } else if is_builtin( name ) {
+                    lines.push( format!( "{} is a shell builtin", name ) );
+                } else if let Some( exe ) = path::resolve( name ) {
+                    lines.push( format!( "{} is {}", name, exe.display() ) );
+                } else {
+                    missing.push( format!( "type: {}: not found", name ) );
+                }
+            }
+
+            let status = ProcessStatus::Exited( if missing.is_empty() { 0 } else { 1 } );
+            Ok( ShellResult { stderr: Some( missing ).filter( | x | !x.is_empty() ), ..ShellResult::with_stdout( status, lines ) } )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+fn source( ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let yaml = load_yaml!( "cli_args/source.yaml" );
+    match make_app!( yaml ).get_matches_from_safe( argv ) {
+        Ok( args ) => {
+            let file = args.value_of( "FILE" ).unwrap();
+            let text = read_to_string( file ).map_err( | e | Error::new( e.kind(), format!( "source: {}: {}", file, e ) ) )?;
+            let capture = ctx.capturing();
+
+            execute_script( ctx, &text, capture ).map_err( | e | Error::other( format!( "{}: {}", file, e ) ) )
+        },
+
+        Err( e ) => usage( ctx, e ),
+    }
+}
+
+// Like echo, the arguments are read by hand: `-f`, `!` and the rest are the
+// expression being tested rather than options.
+fn test( _ctx: &mut ExecContext, argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let usage_error = | message: String | Ok( ShellResult::with_stderr( ProcessStatus::Exited( 2 ), vec![ message ] ) );
+
+    let mut args = &argv[1 ..];
+    if argv[0] == "[" {
+        match args.split_last() {
+            Some( ( last, rest ) ) if last == "]" => args = rest,
+            _ => return usage_error( "[: missing ']'".to_string() ),
+        }
+    }
+
+    match condition::evaluate( args ) {
+        Ok( true ) => ShellResult::ok(),
+        Ok( false ) => Ok( ShellResult::with_status( ProcessStatus::Exited( 1 ) ) ),
+
+        Err( e ) => usage_error( format!( "{}: {}", argv[0], e ) ),
+    }
+}
+
+type Action = fn( &mut ExecContext, Vec<String>, Option<Vec<String>> ) -> Result<ShellResult>;
+
+// A builtin that is nothing more than a function of its arguments.
+struct Function {
+    name: &'static str,
+    help: &'static str,
+    run: Action,
+
+    // the clap definition of its arguments, as YAML
+    args: Option<&'static str>,
+}
+
+impl Function {
+    fn write_help( &self, colored: bool ) -> Option<String> {
+        let docs = YamlLoader::load_from_str( self.args? ).ok()?;
+        let app = if colored { make_app!( &docs[0] ) } else { App::from_yaml( &docs[0] ) };
+
+        let mut out = Vec::new();
+        app.write_help( &mut out ).ok()?;
+        String::from_utf8( out ).ok()
+    }
+}
+
+impl Builtin for Function {
+    fn name( &self ) -> &'static str {
+        self.name
+    }
+
+    fn help( &self ) -> &'static str {
+        self.help
+    }
+
+    fn run( &self, ctx: &mut ExecContext, argv: Vec<String>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        ( self.run )( ctx, argv, input )
+    }
+
+    fn manual( &self ) -> Option<String> {
+        self.write_help( true )
+    }
+
+    fn usage( &self ) -> String {
+        let text = self.write_help( false ).unwrap_or_default();
+        let mut lines = text.lines().skip_while( | x | *x != "USAGE:" ).skip( 1 );
+
+        match lines.next() {
+            Some( x ) => x.trim().to_string(),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+macro_rules! function {
+    ( $name: expr, $run: expr, $help: expr ) => {
+        Box::new( Function { name: $name, help: $help, run: $run, args: None } ) as Box<dyn Builtin>
+    };
+
+    ( $name: expr, $run: expr, $help: expr, $yaml: expr ) => {
+        Box::new( Function { name: $name, help: $help, run: $run, args: Some( include_str!( $yaml ) ) } ) as Box<dyn Builtin>
+    };
+}
+
+lazy_static! {
+    static ref BUILTINS: BTreeMap<&'static str, Box<dyn Builtin>> = {
+        let all = vec![
+            function!( "alias", alias, "Defines command aliases, or lists them.", "cli_args/alias.yaml" ),
+            function!( "assert_eq", assert_eq, "Fails unless two values are equal.", "cli_args/assert_eq.yaml" ),
+            function!( "assert_file", assert_file, "Fails unless a file exists and, optionally, has the given contents.", "cli_args/assert_file.yaml" ),
+            function!( "assert_status", assert_status, "Runs a command and fails unless it exits with the given status.", "cli_args/assert_status.yaml" ),
+            function!( "bg", background, "Resumes a stopped job in the background.", "cli_args/bg.yaml" ),
+            function!( "cd", change_dir, "Changes the current working directory.", "cli_args/cd.yaml" ),
+            function!( "clear", clear, "Clears the terminal." ),
+            function!( "cls", clear, "Clears the terminal." ),
+            function!( "complete", complete, "Shows, generates or defines Tab completions for a command.", "cli_args/complete.yaml" ),
+            function!( "dirs", dirs, "Shows the directory stack.", "cli_args/dirs.yaml" ),
+            function!( "each", each, "Runs a command once for each line piped into it, like xargs.", "cli_args/each.yaml" ),
+            function!( "echo", echo, "Prints its arguments." ),
+            function!( "env", env, "Lists the environment, or runs a program in a modified one." ),
+            function!( "exit", exit, "Leaves the shell.", "cli_args/exit.yaml" ),
+            function!( "export", export, "Exports shell variables to the environment of the programs lumi starts.", "cli_args/export.yaml" ),
+            function!( "fg", foreground, "Brings a background job to the foreground.", "cli_args/fg.yaml" ),
+            function!( "from-json", from_json, "Reads JSON so builtins like get can pick parts of it.", "cli_args/from-json.yaml" ),
+            function!( "get", get, "Picks part of the JSON piped into it, e.g. items.0.name.", "cli_args/get.yaml" ),
+            function!( "help", help, "Lists the builtins, or describes one.", "cli_args/help.yaml" ),
+            function!( "history", history, "Lists previously entered commands.", "cli_args/history.yaml" ),
+            function!( "jobs", list_jobs, "Lists the jobs running in the background.", "cli_args/jobs.yaml" ),
+            function!( "kill", kill, "Sends a signal to processes or jobs.", "cli_args/kill.yaml" ),
+            function!( "popd", popd, "Returns to the directory saved by the last pushd.", "cli_args/popd.yaml" ),
+            function!( "pushd", pushd, "Saves the working directory on the directory stack and changes to another.", "cli_args/pushd.yaml" ),
+            function!( "pwd", pwd, "Prints the current working directory.", "cli_args/pwd.yaml" ),
+            function!( "rehash", rehash, "Forgets where programs were found, to pick up newly installed ones.", "cli_args/rehash.yaml" ),
+            function!( "recent", recent, "Lists the directories cd has left, for cd -N.", "cli_args/recent.yaml" ),
+            function!( "secret", secret, "Stores and retrieves secrets in the operating system's keyring.", "cli_args/secret.yaml" ),
+            function!( "set", set, "Lists every variable, or turns shell options on and off.", "cli_args/set.yaml" ),
+            function!( "source", source, "Runs a script in the current shell.", "cli_args/source.yaml" ),
+            function!( ".", source, "Runs a script in the current shell.", "cli_args/source.yaml" ),
+            function!( "test", test, "Checks files, and compares strings and numbers." ),
+            function!( "[", test, "Checks files, and compares strings and numbers, up to a closing ]." ),
+            function!( "time", time, "Runs a command and reports how long it took.", "cli_args/time.yaml" ),
+            function!( "timeout", timeout, "Runs a command, stopping it if it's still running after a time limit.", "cli_args/timeout.yaml" ),
+            function!( "to-json", to_json, "Prints what's piped into it as JSON.", "cli_args/to-json.yaml" ),
+            function!( "type", type_of, "Says what kind of command each name is.", "cli_args/type.yaml" ),
+            function!( "which", which, "Shows what each command name runs.", "cli_args/which.yaml" ),
+            function!( "z", jump, "Jumps to a frequently used directory matching a query.", "cli_args/z.yaml" ),
+        ];
+
+        all.into_iter().map( | x | ( x.name(), x ) ).collect()
+    };
+}
+
+pub fn lookup( name: &str ) -> Option<&'static dyn Builtin> {
+    BUILTINS.get( name ).map( | x | x.as_ref() )
+}
+
+pub fn is_builtin( name: &str ) -> bool {
+    BUILTINS.contains_key( name )
+}
+
+// Whether a builtin does anything with what's piped into it, either itself or
+// through the command it runs.
+pub fn reads_input( name: &str ) -> bool {
+    const READERS: &[&str] = &[ "assert_eq", "assert_status", "each", "env", "from-json", "get", "secret", "time", "timeout", "to-json" ];
+    READERS.contains( &name )
+}
+
+// Every builtin's name, in alphabetical order.
+pub fn names() -> Vec<&'static str> {
+    BUILTINS.keys().cloned().collect()
+}
@@ -0,0 +1,303 @@
+use std::collections::{ BTreeSet, HashMap };
+use std::fs::{ create_dir_all, read_dir, read_to_string, write };
+use std::io::{ Error, ErrorKind, Result };
+use std::path::{ Path, PathBuf, MAIN_SEPARATOR };
+use std::process::{ Command, Stdio };
+use std::sync::Mutex;
+use dirs::cache_dir;
+use shell::remote::known_hosts;
+use shell::builtins;
+use shell::context::ExecContext;
+use shell::script::execute_script;
+
+#[derive( Debug, Clone, Default )]
+pub struct CompletionSpec {
+    pub flags: BTreeSet<String>,
+    pub subcommands: BTreeSet<String>,
+}
+
+// Completions registered with `complete`, usually from the profile script. They
+// take the place of anything inferred from a tool's --help.
+#[derive( Debug, Clone )]
+pub enum UserCompletion {
+    // a fixed list of words
+    Words( Vec<String> ),
+
+    // a command run on every Tab, whose output lines are the candidates; it can
+    // read the line so far from $COMP_LINE and the word being completed from $COMP_WORD
+    Command( String ),
+}
+
+lazy_static! {
+    static ref SPECS: Mutex<HashMap<String, CompletionSpec>> = Mutex::new( HashMap::new() );
+    static ref USER: Mutex<HashMap<String, UserCompletion>> = Mutex::new( HashMap::new() );
+}
+
+impl UserCompletion {
+    fn candidates( &self, line: &str, word: &str ) -> Vec<String> {
+        let all = match self {
+            UserCompletion::Words( words ) => words.clone(),
+            UserCompletion::Command( command ) => {
+                let mut ctx = ExecContext::new();
+                ctx.set_var( "COMP_LINE", line );
+                ctx.set_var( "COMP_WORD", word );
+
+                let res = execute_script( &mut ctx, command, true );
+
+                // whatever the command changed is put back
+                let _ = ctx.restore();
+                res.ok().and_then( | x | x.stdout().cloned() ).unwrap_or_default()
+            },
+        };
+
+        all.into_iter().filter( | x | x.starts_with( word ) ).collect()
+    }
+
+    pub fn describe( &self ) -> Vec<String> {
+        match self {
+            UserCompletion::Words( words ) => vec![ format!( "words: {}", words.join( " " ) ) ],
+            UserCompletion::Command( command ) => vec![ format!( "command: {}", command ) ],
+        }
+    }
+}
+
+pub fn register( tool: &str, completion: UserCompletion ) {
+    USER.lock().unwrap().insert( tool.to_string(), completion );
+}
+
+// Returns false if nothing was registered for `tool`.
+pub fn unregister( tool: &str ) -> bool {
+    USER.lock().unwrap().remove( tool ).is_some()
+}
+
+pub fn registered( tool: &str ) -> Option<UserCompletion> {
+    USER.lock().unwrap().get( tool ).cloned()
+}
+
+impl CompletionSpec {
+    // Heuristically pulls flags and subcommands out of a tool's --help text.
+    pub fn from_help( text: &str ) -> CompletionSpec {
+        let mut spec = CompletionSpec::default();
+        let mut in_commands = false;
+
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let indented = line.starts_with( char::is_whitespace );
+            if !indented && trimmed.ends_with( ':' ) {
+                in_commands = trimmed.to_lowercase().contains( "command" );
+                continue;
+            }
+
+            if trimmed.starts_with( '-' ) {
+                // the option list ends where the description begins,
+                // which is almost always marked by a run of spaces
+                let options = trimmed.split( "  " ).next().unwrap_or( "" );
+                for word in options.split( | c: char | c == ',' || c.is_whitespace() ) {
+                    if let Some( flag ) = CompletionSpec::clean_flag( word ) {
+                        spec.flags.insert( flag );
+                    }
+                }
+            } else if in_commands && indented {
+                let name = trimmed.split_whitespace().next().unwrap_or( "" );
+                let name = name.trim_end_matches( [ ',', ':' ] );
+                let is_word = name.starts_with( | c: char | c.is_ascii_lowercase() ) &&
+                    name.chars().all( | c | c.is_ascii_alphanumeric() || c == '-' || c == '_' );
+
+                if is_word {
+                    spec.subcommands.insert( name.to_string() );
+                }
+            }
+        }
+
+        spec
+    }
+
+    fn clean_flag( word: &str ) -> Option<String> {
+        if !word.starts_with( '-' ) || word == "-" || word == "--" {
+            return None;
+        }
+
+        let end = word.find( [ '=', '[', '<' ] ).unwrap_or( word.len() );
+        let flag = &word[..end];
+        let is_valid = flag.trim_start_matches( '-' )
+            .chars()
+            .all( | c | c.is_ascii_alphanumeric() || c == '-' || c == '_' );
+
+        if is_valid && flag.len() > 1 { Some( flag.to_string() ) } else { None }
+    }
+
+    fn parse_cache( text: &str ) -> CompletionSpec {
+        let mut spec = CompletionSpec::default();
+        for line in text.lines() {
+            match line.split_once( ' ' ) {
+                Some( ( "flag", x ) ) => { spec.flags.insert( x.to_string() ); },
+                Some( ( "command", x ) ) => { spec.subcommands.insert( x.to_string() ); },
+                _ => {},
+            }
+        }
+
+        spec
+    }
+
+    fn to_cache( &self ) -> String {
+        let flags = self.flags.iter().map( | x | format!( "flag {}\n", x ) );
+        let commands = self.subcommands.iter().map( | x | format!( "command {}\n", x ) );
+
+        flags.chain( commands ).collect()
+    }
+
+    pub fn describe( &self ) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.subcommands.is_empty() {
+            lines.push( format!( "commands: {}", self.subcommands.iter().cloned().collect::<Vec<_>>().join( " " ) ) );
+        }
+
+        if !self.flags.is_empty() {
+            lines.push( format!( "flags: {}", self.flags.iter().cloned().collect::<Vec<_>>().join( " " ) ) );
+        }
+
+        lines
+    }
+}
+
+fn cache_file( tool: &str ) -> Option<PathBuf> {
+    let name: String = tool.chars()
+        .map( | c | if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' } )
+        .collect();
+
+    Some( cache_dir()?.join( "lumi" ).join( "completions" ).join( name ) )
+}
+
+// Runs `<tool> --help`, derives a completion spec from it and caches it on disk.
+pub fn infer( tool: &str ) -> Result<CompletionSpec> {
+    let output = Command::new( tool )
+        .arg( "--help" )
+        .stdin( Stdio::null() )
+        .output()?;
+
+    // plenty of tools print their usage to stderr, so take whichever is longer
+    let text = if output.stdout.len() >= output.stderr.len() {
+        String::from_utf8_lossy( &output.stdout ).into_owned()
+    } else {
+        String::from_utf8_lossy( &output.stderr ).into_owned()
+    };
+
+    let spec = CompletionSpec::from_help( &text );
+    if spec.flags.is_empty() && spec.subcommands.is_empty() {
+        return Err( Error::new(
+            ErrorKind::InvalidData,
+            format!( "could not find any flags or commands in the output of '{} --help'", tool )
+        ) );
+    }
+
+    if let Some( path ) = cache_file( tool ) {
+        if let Some( dir ) = path.parent() {
+            create_dir_all( dir )?;
+        }
+
+        write( path, spec.to_cache() )?;
+    }
+
+    SPECS.lock().unwrap().insert( tool.to_string(), spec.clone() );
+    Ok( spec )
+}
+
+// Looks up the spec for a tool, loading it from the on-disk cache if needed.
+pub fn spec_for( tool: &str ) -> Option<CompletionSpec> {
+    let mut specs = SPECS.lock().unwrap();
+    if let Some( spec ) = specs.get( tool ) {
+        return Some( spec.clone() );
+    }
+
+    let text = read_to_string( cache_file( tool )? ).ok()?;
+    let spec = CompletionSpec::parse_cache( &text );
+    specs.insert( tool.to_string(), spec.clone() );
+
+    Some( spec )
+}
+
+fn complete_path( word: &str ) -> Vec<String> {
+    let ( dir, prefix ) = match word.rfind( [ '/', MAIN_SEPARATOR ] ) {
+        Some( i ) => ( &word[..=i], &word[( i + 1 )..] ),
+        None => ( "", word ),
+    };
+
+    let entries = match read_dir( if dir.is_empty() { Path::new( "." ) } else { Path::new( dir ) } ) {
+        Ok( x ) => x,
+        Err( _ ) => return Vec::new(),
+    };
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with( prefix ) || ( name.starts_with( '.' ) && !prefix.starts_with( '.' ) ) {
+            continue;
+        }
+
+        let is_dir = entry.file_type().map( | t | t.is_dir() ).unwrap_or( false );
+        found.push( format!( "{}{}{}", dir, name, if is_dir { "/" } else { "" } ) );
+    }
+
+    found.sort();
+    found
+}
+
+// Returns replacement candidates for the last word of `line`.
+pub fn complete( line: &str ) -> Vec<String> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    if line.is_empty() || line.ends_with( char::is_whitespace ) {
+        words.push( "" );
+    }
+
+    let word = words[words.len() - 1];
+    let mut candidates = BTreeSet::new();
+
+    if words.len() == 1 {
+        if let Some( host ) = word.strip_prefix( '@' ) {
+            for name in known_hosts() {
+                if name.starts_with( host ) {
+                    candidates.insert( format!( "@{}", name ) );
+                }
+            }
+
+            return candidates.into_iter().collect();
+        }
+
+        for name in builtins::names() {
+            if name.starts_with( word ) {
+                candidates.insert( name.to_string() );
+            }
+        }
+    } else if let Some( user ) = registered( words[0] ) {
+        candidates.extend( user.candidates( line, word ) );
+
+        // files are only offered when none of the registered words fit
+        if !candidates.is_empty() {
+            return candidates.into_iter().collect();
+        }
+    } else {
+        if let Some( spec ) = spec_for( words[0] ) {
+            let pool = if word.starts_with( '-' ) {
+                Some( &spec.flags )
+            } else if words.len() == 2 {
+                Some( &spec.subcommands )
+            } else {
+                None
+            };
+
+            for x in pool.into_iter().flatten().filter( | x | x.starts_with( word ) ) {
+                candidates.insert( x.clone() );
+            }
+        }
+    }
+
+    if !word.starts_with( '-' ) {
+        candidates.extend( complete_path( word ) );
+    }
+
+    candidates.into_iter().collect()
+}
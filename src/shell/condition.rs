@@ -0,0 +1,166 @@
+use std::fs::{ metadata, symlink_metadata, Metadata };
+use std::path::Path;
+
+// Evaluates the arguments of `test` (or `[`, without its closing bracket),
+// e.g. `-f file`, `$a = b` or `! 1 -lt 2 -o -z $x`.
+pub fn evaluate( args: &[String] ) -> Result<bool, String> {
+    let mut parser = Condition { args, pos: 0 };
+
+    // with no arguments the test is false, with one it's whether it's empty
+    match args.len() {
+        0 => return Ok( false ),
+        1 => return Ok( !args[0].is_empty() ),
+        _ => {},
+    }
+
+    let value = parser.or()?;
+    match parser.peek() {
+        None => Ok( value ),
+        Some( x ) => Err( format!( "unexpected '{}'", x ) ),
+    }
+}
+
+struct Condition<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+fn is_unary( op: &str ) -> bool {
+    matches!( op, "-e" | "-f" | "-d" | "-r" | "-w" | "-x" | "-s" | "-L" | "-h" | "-z" | "-n" )
+}
+
+fn is_binary( op: &str ) -> bool {
+    matches!( op, "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" )
+}
+
+fn number( text: &str ) -> Result<i64, String> {
+    text.trim().parse().map_err( | _ | format!( "'{}' is not a number", text ) )
+}
+
+#[cfg( not( windows ) )]
+fn has_mode( meta: &Metadata, mask: u32 ) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & mask != 0
+}
+
+#[cfg( windows )]
+fn has_mode( meta: &Metadata, mask: u32 ) -> bool {
+    // Windows only knows whether a file is read-only
+    mask & 0o222 == 0 || !meta.permissions().readonly()
+}
+
+fn unary( op: &str, arg: &str ) -> bool {
+    let meta = metadata( arg );
+    match op {
+        "-z" => arg.is_empty(),
+        "-n" => !arg.is_empty(),
+        "-e" => meta.is_ok(),
+        "-f" => meta.is_ok_and( | x | x.is_file() ),
+        "-d" => meta.is_ok_and( | x | x.is_dir() ),
+        "-s" => meta.is_ok_and( | x | x.len() > 0 ),
+        "-r" => meta.is_ok_and( | x | has_mode( &x, 0o444 ) ),
+        "-w" => meta.is_ok_and( | x | has_mode( &x, 0o222 ) ),
+        "-x" => meta.is_ok_and( | x | has_mode( &x, 0o111 ) ),
+        "-L" | "-h" => symlink_metadata( arg ).is_ok_and( | x | x.file_type().is_symlink() ),
+        _ => unreachable!(),
+    }
+}
+
+fn binary( left: &str, op: &str, right: &str ) -> Result<bool, String> {
+    let modified = | x: &str | metadata( Path::new( x ) ).and_then( | x | x.modified() ).ok();
+
+    Ok( match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => number( left )? == number( right )?,
+        "-ne" => number( left )? != number( right )?,
+        "-lt" => number( left )? < number( right )?,
+        "-le" => number( left )? <= number( right )?,
+        "-gt" => number( left )? > number( right )?,
+        "-ge" => number( left )? >= number( right )?,
+
+        // a file that doesn't exist is older than one that does
+        "-nt" => modified( left ) > modified( right ),
+        "-ot" => modified( left ) < modified( right ),
+        _ => unreachable!(),
+    } )
+}
+
+impl<'a> Condition<'a> {
+    fn peek( &self ) -> Option<&'a str> {
+        self.args.get( self.pos ).map( | x | x.as_str() )
+    }
+
+    fn peek_at( &self, offset: usize ) -> Option<&'a str> {
+        self.args.get( self.pos + offset ).map( | x | x.as_str() )
+    }
+
+    fn next( &mut self ) -> Result<&'a str, String> {
+        let arg = self.peek().ok_or_else( || "argument expected".to_string() )?;
+        self.pos += 1;
+
+        Ok( arg )
+    }
+
+    fn or( &mut self ) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek() == Some( "-o" ) {
+            self.pos += 1;
+            value |= self.and()?;
+        }
+
+        Ok( value )
+    }
+
+    fn and( &mut self ) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek() == Some( "-a" ) {
+            self.pos += 1;
+            value &= self.not()?;
+        }
+
+        Ok( value )
+    }
+
+    fn not( &mut self ) -> Result<bool, String> {
+        // `! = x` compares "!" with x rather than negating anything
+        if self.peek() == Some( "!" ) && !self.peek_at( 1 ).is_some_and( is_binary ) {
+            self.pos += 1;
+            return Ok( !self.not()? );
+        }
+
+        self.primary()
+    }
+
+    fn primary( &mut self ) -> Result<bool, String> {
+        let first = self.next()?;
+
+        if let Some( op ) = self.peek().filter( | x | is_binary( x ) ) {
+            if self.peek_at( 1 ).is_some() {
+                self.pos += 1;
+                let right = self.next()?;
+                return binary( first, op, right );
+            }
+        }
+
+        if first == "(" {
+            let value = self.or()?;
+            return match self.next() {
+                Ok( ")" ) => Ok( value ),
+                _ => Err( "')' expected".to_string() ),
+            };
+        }
+
+        if is_unary( first ) {
+            if let Some( arg ) = self.peek() {
+                self.pos += 1;
+                return Ok( unary( first, arg ) );
+            }
+        }
+
+        // a lone word is true when it isn't empty
+        Ok( !first.is_empty() )
+    }
+}
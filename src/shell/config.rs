@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::{ Result, Error, ErrorKind };
+use std::path::{ Path, PathBuf };
+use std::env::var_os;
+use std::time::Duration;
+use dirs::home_dir;
+use yaml_rust::{ Yaml, YamlLoader };
+use yansi::{ Color, Paint };
+use kernel::{ get_color_support, ColorSupport };
+use shell::tilde;
+use shell::prompt::{ Segments, Template };
+use shell::pager::Pager;
+use shell::profile;
+
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub enum PromptStyle {
+    // $user@machine[dir]>
+    Full,
+
+    // [dir]>
+    Compact,
+
+    // >
+    Minimal,
+
+    // any other string, e.g. "{user}@{host} {cwd} ❯ "
+    Template( Template ),
+}
+
+#[derive( Debug, Clone, Copy )]
+pub struct Palette {
+    pub user: Color,
+    pub host: Color,
+    pub dir: Color,
+    pub git: Color,
+    pub error: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        profile::measure( "palette", || Palette::for_support( get_color_support() ) )
+    }
+}
+
+impl Palette {
+    // Softer shades of the basic colors where the terminal can show them.
+    pub fn for_support( support: ColorSupport ) -> Palette {
+        match support {
+            ColorSupport::TrueColor => Palette {
+                user: Color::RGB( 0x98, 0xC3, 0x79 ),
+                host: Color::RGB( 0xE5, 0xC0, 0x7B ),
+                dir: Color::RGB( 0x56, 0xB6, 0xC2 ),
+                git: Color::RGB( 0xC6, 0x78, 0xDD ),
+                error: Color::RGB( 0xE0, 0x6C, 0x75 ),
+            },
+
+            ColorSupport::Colors256 => Palette {
+                user: Color::Fixed( 114 ),
+                host: Color::Fixed( 180 ),
+                dir: Color::Fixed( 73 ),
+                git: Color::Fixed( 176 ),
+                error: Color::Fixed( 168 ),
+            },
+
+            ColorSupport::Default | ColorSupport::None => Palette {
+                user: Color::Green,
+                host: Color::Yellow,
+                dir: Color::Cyan,
+                git: Color::Magenta,
+                error: Color::Red,
+            },
+        }
+    }
+}
+
+// How a background job finishing is made known, beyond the line printed before the next prompt.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub enum JobNotify {
+    // nothing until the next prompt
+    Prompt,
+
+    // the terminal bell rings as soon as the job finishes
+    Bell,
+
+    // an OSC 9 notification, which terminals like iTerm2 and Windows Terminal
+    // show on the desktop, is sent as soon as the job finishes
+    Osc,
+}
+
+// Where history is kept between sessions.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub enum HistoryBackend {
+    // ~/.lumi_history, one command per line
+    File,
+
+    // ~/.lumi_history.db, which also keeps where, when and how long each
+    // command ran and how it exited
+    Sqlite,
+}
+
+pub struct Config {
+    // colored prompt, highlighting and messages; off by default where $NO_COLOR is set
+    pub color: bool,
+
+    // foreground commands that run at least this long send a desktop
+    // notification when they finish, unless the terminal has focus
+    pub notify_after: Option<Duration>,
+
+    // what else happens, while waiting at the prompt, when a background job finishes
+    pub job_notify: JobNotify,
+
+    // commands that run at least this long are followed by "took 12s"
+    pub show_duration_after: Option<Duration>,
+
+    // number of lines kept in ~/.lumi_history
+    pub history_size: usize,
+
+    pub history_backend: HistoryBackend,
+
+    // commands matching any of these glob patterns, e.g. "*password*", are
+    // never saved, nor are those typed with a space in front
+    pub history_ignore: Vec<String>,
+
+    // pick up commands other sessions have saved before each prompt, so they
+    // can be recalled here too
+    pub share_history: bool,
+
+    // shown at the start of the prompt after a command exits with a non-zero status
+    pub failure_indicator: Option<String>,
+
+    pub prompt: PromptStyle,
+
+    // shown at the right edge of the terminal while there's room for it, e.g. "{status} {time}"
+    pub right_prompt: Option<Template>,
+
+    // what the prompt of each accepted line is redrawn as, e.g. "❯ ", so the
+    // scrollback isn't full of copies of it
+    pub transient_prompt: Option<Template>,
+
+    pub palette: Palette,
+
+    // show the git branch, and whether there are uncommitted changes, in the prompt
+    pub git: bool,
+
+    // show the working directory in the terminal's title at the prompt, and
+    // the name of the running command while it runs
+    pub title: bool,
+
+    // mark prompts, commands and their output, and report the working
+    // directory, with OSC 133 and OSC 7 for terminals that understand them
+    pub shell_integration: bool,
+
+    // offer the rest of a matching history entry, in dimmed text, while typing
+    pub autosuggest: bool,
+
+    // what shows builtin output too long for the screen
+    pub pager: Pager,
+
+    // segment name -> command whose first line of output it shows, e.g.
+    // kube -> "kubectl config current-context"
+    pub segments: BTreeMap<String, String>,
+
+    // run in place of commands that can't be found, given the name and
+    // arguments, e.g. to suggest a package that provides it
+    pub command_not_found: Option<String>,
+
+    // run with the command line before each one is executed
+    pub preexec: Option<String>,
+
+    // run before each prompt is drawn
+    pub precmd: Option<String>,
+
+    // command name -> replacement text, e.g. ll -> "ls -l"
+    pub aliases: BTreeMap<String, String>,
+
+    // script run before the first prompt, ~/.lumi_profile unless configured otherwise
+    pub profile: Option<PathBuf>,
+
+    // run before the profile when started as a login shell, ~/.lumi_login unless configured otherwise
+    pub login_profile: Option<PathBuf>,
+
+    // file every session's commands and their output are appended to
+    pub transcript: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            color: color_allowed(),
+            notify_after: Some( Duration::from_secs( 10 ) ),
+            job_notify: JobNotify::Prompt,
+            show_duration_after: Some( Duration::from_secs( 5 ) ),
+            history_size: 1000,
+            history_backend: HistoryBackend::File,
+            history_ignore: Vec::new(),
+            share_history: true,
+            failure_indicator: Some( "✘".to_string() ),
+            prompt: PromptStyle::Full,
+            right_prompt: None,
+            transient_prompt: None,
+            palette: Palette::default(),
+            git: true,
+            title: true,
+            shell_integration: false,
+            autosuggest: true,
+            pager: Pager::Builtin,
+            segments: BTreeMap::new(),
+            command_not_found: None,
+            preexec: None,
+            precmd: None,
+            aliases: BTreeMap::new(),
+            profile: home_dir().map( | home | home.join( ".lumi_profile" ) ),
+            login_profile: home_dir().map( | home | home.join( ".lumi_login" ) ),
+            transcript: None,
+        }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some( home_dir()?.join( ".config" ).join( "lumi" ).join( "config.yaml" ) )
+}
+
+// Colors are off when $NO_COLOR is set to anything (see https://no-color.org),
+// or when the terminal can't show them.
+pub fn color_allowed() -> bool {
+    var_os( "NO_COLOR" ).is_none_or( | x | x.is_empty() ) && get_color_support() != ColorSupport::None
+}
+
+// Turns colored output on or off for the whole process. Everything is painted
+// with yansi, from the prompt and highlighting to error messages, so this is
+// the one switch for all of it.
+pub fn set_color_enabled( enabled: bool ) {
+    if enabled {
+        Paint::enable();
+    } else {
+        Paint::disable();
+    }
+}
+
+fn invalid( key: &str, expected: &str ) -> Error {
+    Error::new( ErrorKind::InvalidData, format!( "'{}' must be {}", key, expected ) )
+}
+
+// A color written as a name, an index into the 256-color palette, or #rrggbb.
+pub fn color_named( name: &str ) -> Option<Color> {
+    if let Ok( x ) = name.parse::<u8>() {
+        return Some( Color::Fixed( x ) );
+    }
+
+    if let Some( hex ) = name.strip_prefix( '#' ) {
+        let channel = | i: usize | hex.get( i .. i + 2 ).and_then( | x | u8::from_str_radix( x, 16 ).ok() );
+        return match ( hex.len(), channel( 0 ), channel( 2 ), channel( 4 ) ) {
+            ( 6, Some( r ), Some( g ), Some( b ) ) => Some( Color::RGB( r, g, b ) ),
+            _ => None,
+        };
+    }
+
+    Some( match name.to_lowercase().as_str() {
+        "default" => Color::Default,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ => return None,
+    } )
+}
+
+fn parse_color( key: &str, value: &Yaml ) -> Result<Color> {
+    let expected = "a color name (default, black, red, green, yellow, blue, magenta, cyan or white), a number from 0 to 255 or #rrggbb";
+    let name = match value {
+        Yaml::String( x ) => x.clone(),
+        Yaml::Integer( x ) => x.to_string(),
+        _ => return Err( invalid( key, expected ) ),
+    };
+
+    color_named( &name ).ok_or_else( || invalid( key, expected ) )
+}
+
+impl Config {
+    // Reads ~/.config/lumi/config.yaml. Settings the file doesn't mention keep
+    // their defaults, and a missing file is the same as an empty one.
+    pub fn load() -> Result<Config> {
+        match config_path() {
+            Some( path ) if path.exists() => Config::load_from( &path ),
+            _ => Ok( Config::default() ),
+        }
+    }
+
+    // Unlike load, a file that isn't there is an error.
+    pub fn load_from( path: &Path ) -> Result<Config> {
+        Config::from_yaml( &read_to_string( path )? )
+    }
+
+    pub fn from_yaml( source: &str ) -> Result<Config> {
+        let doc = YamlLoader::load_from_str( source )
+            .map_err( | e | Error::new( ErrorKind::InvalidData, e.to_string() ) )?
+            .into_iter()
+            .next()
+            .unwrap_or( Yaml::Null );
+
+        let mut config = Config::default();
+
+        match doc["history_size"] {
+            Yaml::BadValue => {},
+            Yaml::Integer( x ) if x >= 0 => config.history_size = x as usize,
+            _ => return Err( invalid( "history_size", "a positive number" ) ),
+        }
+
+        match doc["history_backend"].as_str() {
+            None if doc["history_backend"].is_badvalue() => {},
+            Some( "file" ) => config.history_backend = HistoryBackend::File,
+            Some( "sqlite" ) => config.history_backend = HistoryBackend::Sqlite,
+            _ => return Err( invalid( "history_backend", "file or sqlite" ) ),
+        }
+
+        match doc["notify_after"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.notify_after = None,
+            Yaml::Integer( x ) if x >= 0 => config.notify_after = Some( Duration::from_secs( x as u64 ) ),
+            _ => return Err( invalid( "notify_after", "a number of seconds or false" ) ),
+        }
+
+        match doc["show_duration_after"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.show_duration_after = None,
+            Yaml::Integer( x ) if x >= 0 => config.show_duration_after = Some( Duration::from_secs( x as u64 ) ),
+            _ => return Err( invalid( "show_duration_after", "a number of seconds or false" ) ),
+        }
+
+        match doc["failure_indicator"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.failure_indicator = None,
+            Yaml::String( ref x ) => config.failure_indicator = Some( x.clone() ),
+            _ => return Err( invalid( "failure_indicator", "a string or false" ) ),
+        }
+
+        match doc["profile"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.profile = None,
+            Yaml::String( ref x ) => config.profile = Some( PathBuf::from( tilde::expand( x ) ) ),
+            _ => return Err( invalid( "profile", "a path or false" ) ),
+        }
+
+        match doc["login_profile"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.login_profile = None,
+            Yaml::String( ref x ) => config.login_profile = Some( PathBuf::from( tilde::expand( x ) ) ),
+            _ => return Err( invalid( "login_profile", "a path or false" ) ),
+        }
+
+        match doc["transcript"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.transcript = None,
+            Yaml::String( ref x ) => config.transcript = Some( PathBuf::from( tilde::expand( x ) ) ),
+            _ => return Err( invalid( "transcript", "a path or false" ) ),
+        }
+
+        for ( key, slot ) in [
+            ( "command_not_found", &mut config.command_not_found ),
+            ( "preexec", &mut config.preexec ),
+            ( "precmd", &mut config.precmd ),
+        ] {
+            match doc[key] {
+                Yaml::BadValue => {},
+                Yaml::Null | Yaml::Boolean( false ) => *slot = None,
+                Yaml::String( ref x ) => *slot = Some( x.clone() ),
+                _ => return Err( invalid( key, "a command or false" ) ),
+            }
+        }
+
+        match doc["color"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.color = x,
+            _ => return Err( invalid( "color", "true or false" ) ),
+        }
+
+        match doc["git"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.git = x,
+            _ => return Err( invalid( "git", "true or false" ) ),
+        }
+
+        match doc["title"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.title = x,
+            _ => return Err( invalid( "title", "true or false" ) ),
+        }
+
+        match doc["shell_integration"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.shell_integration = x,
+            _ => return Err( invalid( "shell_integration", "true or false" ) ),
+        }
+
+        match doc["history_ignore"] {
+            Yaml::BadValue | Yaml::Null => {},
+            Yaml::Array( ref items ) => {
+                config.history_ignore = items.iter()
+                    .map( | x | x.as_str().map( str::to_string ).ok_or_else( || invalid( "history_ignore", "a list of patterns" ) ) )
+                    .collect::<Result<_>>()?;
+            },
+
+            _ => return Err( invalid( "history_ignore", "a list of patterns" ) ),
+        }
+
+        match doc["share_history"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.share_history = x,
+            _ => return Err( invalid( "share_history", "true or false" ) ),
+        }
+
+        match doc["autosuggest"] {
+            Yaml::BadValue => {},
+            Yaml::Boolean( x ) => config.autosuggest = x,
+            _ => return Err( invalid( "autosuggest", "true or false" ) ),
+        }
+
+        match doc["job_notify"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.job_notify = JobNotify::Prompt,
+            Yaml::String( ref x ) if x == "prompt" => config.job_notify = JobNotify::Prompt,
+            Yaml::String( ref x ) if x == "bell" => config.job_notify = JobNotify::Bell,
+            Yaml::String( ref x ) if x == "osc" => config.job_notify = JobNotify::Osc,
+            _ => return Err( invalid( "job_notify", "one of prompt, bell or osc" ) ),
+        }
+
+        match doc["pager"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.pager = Pager::Off,
+            Yaml::String( ref x ) if x == "builtin" => config.pager = Pager::Builtin,
+
+            // $PAGER when it's set, otherwise the built-in one
+            Yaml::String( ref x ) if x == "$PAGER" => config.pager = match var_os( "PAGER" ) {
+                Some( x ) if !x.is_empty() => Pager::Command( x.to_string_lossy().into_owned() ),
+                _ => Pager::Builtin,
+            },
+
+            Yaml::String( ref x ) => config.pager = Pager::Command( x.clone() ),
+            _ => return Err( invalid( "pager", "builtin, $PAGER, a command or false" ) ),
+        }
+
+        match doc["prompt"].as_str() {
+            None if doc["prompt"].is_badvalue() => {},
+            Some( "full" ) => config.prompt = PromptStyle::Full,
+            Some( "compact" ) => config.prompt = PromptStyle::Compact,
+            Some( "minimal" ) => config.prompt = PromptStyle::Minimal,
+            Some( x ) => config.prompt = PromptStyle::Template( Template::parse( x )? ),
+            _ => return Err( invalid( "prompt", "one of full, compact, minimal or a template" ) ),
+        }
+
+        match doc["right_prompt"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.right_prompt = None,
+            Yaml::String( ref x ) => config.right_prompt = Some( Template::parse( x )? ),
+            _ => return Err( invalid( "right_prompt", "a template or false" ) ),
+        }
+
+        match doc["transient_prompt"] {
+            Yaml::BadValue => {},
+            Yaml::Null | Yaml::Boolean( false ) => config.transient_prompt = None,
+            Yaml::String( ref x ) => config.transient_prompt = Some( Template::parse( x )? ),
+            _ => return Err( invalid( "transient_prompt", "a template or false" ) ),
+        }
+
+        let colors = &doc["colors"];
+        for ( key, slot ) in [
+            ( "user", &mut config.palette.user ),
+            ( "host", &mut config.palette.host ),
+            ( "dir", &mut config.palette.dir ),
+            ( "git", &mut config.palette.git ),
+            ( "error", &mut config.palette.error ),
+        ] {
+            if !colors[key].is_badvalue() {
+                *slot = parse_color( &format!( "colors.{}", key ), &colors[key] )?;
+            }
+        }
+
+        if let Some( segments ) = doc["segments"].as_hash() {
+            for ( k, v ) in segments {
+                match ( k.as_str(), v.as_str() ) {
+                    ( Some( name ), Some( command ) ) => {
+                        config.segments.insert( name.to_string(), command.to_string() );
+                    },
+
+                    _ => return Err( invalid( "segments", "a map of names to commands" ) ),
+                }
+            }
+        }
+
+        let builtin = Segments::builtin();
+        let templates = match config.prompt {
+            PromptStyle::Template( ref x ) => vec![ x ],
+            _ => vec![],
+        };
+
+        for template in templates.into_iter().chain( config.right_prompt.as_ref() ).chain( config.transient_prompt.as_ref() ) {
+            for name in template.names() {
+                if !builtin.contains( name ) && !config.segments.contains_key( name ) {
+                    return Err( Error::new( ErrorKind::InvalidData, format!( "unknown placeholder '{{{}}}' in prompt template", name ) ) );
+                }
+            }
+        }
+
+        if let Some( aliases ) = doc["aliases"].as_hash() {
+            for ( k, v ) in aliases {
+                match ( k.as_str(), v.as_str() ) {
+                    ( Some( name ), Some( value ) ) => {
+                        config.aliases.insert( name.to_string(), value.to_string() );
+                    },
+
+                    _ => return Err( invalid( "aliases", "a map of names to command lines" ) ),
+                }
+            }
+        }
+
+        Ok( config )
+    }
+}
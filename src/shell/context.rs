@@ -0,0 +1,437 @@
+use std::cell::Cell;
+use std::collections::{ BTreeMap, HashMap };
+use std::env::{ self, VarError };
+use std::ffi::OsString;
+use std::io::Result;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+use std::time::{ Duration, Instant };
+use serde_json::Value;
+use shell::segments::{ Exec, Expansion };
+
+// Which captured streams are also shown on the terminal as they arrive, so a
+// long-running command still shows progress while its output is collected.
+#[derive( Debug, Clone, Copy, Default, Eq, PartialEq )]
+pub struct Tee {
+    pub stdout: bool,
+    pub stderr: bool,
+}
+
+impl Tee {
+    pub const ALL: Tee = Tee { stdout: true, stderr: true };
+}
+
+// When programs started under `timeout` are asked to stop.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub struct Deadline {
+    pub at: Instant,
+
+    // how long a program may take to stop before it's killed outright
+    pub kill_after: Option<Duration>,
+}
+
+// Options turned on and off with `set`.
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub struct ShellOptions {
+    // -e: a failing command stops the script
+    pub errexit: bool,
+
+    // -x: commands are printed as they run
+    pub xtrace: bool,
+
+    // -u: a variable that isn't set is an error rather than nothing
+    pub nounset: bool,
+
+    // -o pipefail: a pipeline fails if any stage does, not just the last
+    pub pipefail: bool,
+}
+
+impl ShellOptions {
+    // The option called `name`, as given to `set -o`.
+    pub fn flag_mut( &mut self, name: &str ) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some( &mut self.errexit ),
+            "xtrace" => Some( &mut self.xtrace ),
+            "nounset" => Some( &mut self.nounset ),
+            "pipefail" => Some( &mut self.pipefail ),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ShellOptions {
+    fn default() -> ShellOptions {
+        ShellOptions { errexit: false, xtrace: false, nounset: true, pipefail: false }
+    }
+}
+
+// how many directories `recent` remembers
+const RECENT_DIRS: usize = 20;
+
+// Everything a command line can change about the shell running it. Subshells
+// like $(...) run in a fork of their parent's context, so their assignments,
+// cd and exit are undone when they finish.
+//
+// The process's own working directory and environment always match the
+// context that is currently executing, since that's what child processes and
+// most of std see. `restore` puts them back after a fork has been used.
+#[derive( Clone )]
+pub struct ExecContext {
+    cwd: PathBuf,
+
+    // directories cd has left, most recent first and without repeats; `cd -N`
+    // goes to the Nth one
+    recent_dirs: Vec<PathBuf>,
+
+    // directories saved by pushd, most recent first; the working directory is
+    // the implicit top of the stack and isn't stored here
+    dir_stack: Vec<PathBuf>,
+
+    // shell-local variables; exported ones only live in `env`
+    vars: HashMap<String, String>,
+    env: BTreeMap<OsString, OsString>,
+
+    // command name -> replacement text, e.g. ll -> "ls -l"
+    aliases: BTreeMap<String, String>,
+    functions: HashMap<String, Rc<Exec>>,
+
+    // command run instead of one that can't be found, with its name and arguments
+    not_found_hook: Option<String>,
+    options: ShellOptions,
+
+    // variables from `NAME=value cmd`, waiting for the command they're meant for
+    command_env: Vec<( String, String )>,
+
+    // the JSON value behind the lines piped into the stage being run, for
+    // builtins that would rather have it than parse the lines again
+    piped_value: Option<Value>,
+
+    // the bytes behind the lines piped into the stage being run, so a program
+    // that's handed them gets exactly what the one before it wrote
+    piped_bytes: Option<Vec<u8>>,
+
+    // words a command expanded but couldn't start a program with, waiting for
+    // that command's execute
+    expansion: Option<Expansion>,
+
+    // exit code of the most recently executed command, exposed as $?
+    last_status: i32,
+    exit_code: Option<i32>,
+
+    // whether the builtin being run has its output captured
+    capture: bool,
+    tee: Tee,
+    deadline: Option<Deadline>,
+
+    // whether a program was stopped for running past the deadline
+    timed_out: Cell<bool>,
+}
+
+impl Default for ExecContext {
+    fn default() -> ExecContext {
+        ExecContext::new()
+    }
+}
+
+impl ExecContext {
+    // A context for the process as it is now.
+    pub fn new() -> ExecContext {
+        ExecContext {
+            cwd: env::current_dir().unwrap_or_default(),
+            recent_dirs: Vec::new(),
+            dir_stack: Vec::new(),
+            vars: HashMap::new(),
+            env: env::vars_os().collect(),
+            aliases: BTreeMap::new(),
+            functions: HashMap::new(),
+            not_found_hook: None,
+            options: ShellOptions::default(),
+            command_env: Vec::new(),
+            piped_value: None,
+            piped_bytes: None,
+            expansion: None,
+            last_status: 0,
+            exit_code: None,
+            capture: false,
+            tee: Tee::default(),
+            deadline: None,
+            timed_out: Cell::new( false ),
+        }
+    }
+
+    // A copy to run a subshell in. Call `restore` on this context once it's done.
+    pub fn fork( &self ) -> ExecContext {
+        // a subshell's output is only ever wanted by whoever ran it
+        ExecContext {
+            tee: Tee::default(),
+            piped_value: None,
+            piped_bytes: None,
+            expansion: None,
+            ..self.clone()
+        }
+    }
+
+    // Makes the process's working directory and environment match this context
+    // again, after a fork may have changed them.
+    pub fn restore( &self ) -> Result<()> {
+        if env::current_dir().ok().as_ref() != Some( &self.cwd ) {
+            env::set_current_dir( &self.cwd )?;
+        }
+
+        let current: BTreeMap<OsString, OsString> = env::vars_os().collect();
+        if current == self.env {
+            return Ok( () );
+        }
+
+        for name in current.keys().filter( | x | !self.env.contains_key( *x ) ) {
+            env::remove_var( name );
+        }
+
+        for ( name, value ) in &self.env {
+            if current.get( name ) != Some( value ) {
+                env::set_var( name, value );
+            }
+        }
+
+        Ok( () )
+    }
+
+    pub fn cwd( &self ) -> &Path {
+        &self.cwd
+    }
+
+    pub fn recent_dirs( &self ) -> &[PathBuf] {
+        &self.recent_dirs
+    }
+
+    pub fn dir_stack( &self ) -> &[PathBuf] {
+        &self.dir_stack
+    }
+
+    pub fn set_dir_stack( &mut self, dirs: Vec<PathBuf> ) {
+        self.dir_stack = dirs;
+    }
+
+    // Changes the working directory, keeping $PWD and $OLDPWD up to date.
+    pub fn change_dir( &mut self, target: &Path ) -> Result<()> {
+        env::set_current_dir( target )?;
+
+        let previous = std::mem::replace( &mut self.cwd, env::current_dir()? );
+        let pwd = self.cwd.to_string_lossy().into_owned();
+        self.set_env( "OLDPWD", &previous.to_string_lossy() );
+        self.set_env( "PWD", &pwd );
+
+        if previous != self.cwd {
+            let cwd = self.cwd.clone();
+            self.recent_dirs.retain( | x | *x != cwd && *x != previous );
+            self.recent_dirs.insert( 0, previous );
+            self.recent_dirs.truncate( RECENT_DIRS );
+        }
+
+        Ok( () )
+    }
+
+    // Looks a variable up in the shell's own variables first, then the environment.
+    pub fn var( &self, name: &str ) -> std::result::Result<String, VarError> {
+        if let Some( x ) = self.vars.get( name ) {
+            return Ok( x.clone() );
+        }
+
+        match self.env.get( &OsString::from( name ) ) {
+            Some( x ) => x.clone().into_string().map_err( VarError::NotUnicode ),
+            None => Err( VarError::NotPresent ),
+        }
+    }
+
+    // Assigns a variable. Exported variables stay exported so children see the
+    // new value; anything else becomes a shell-local variable.
+    pub fn set_var( &mut self, name: &str, value: &str ) {
+        if !self.vars.contains_key( name ) && self.env.contains_key( &OsString::from( name ) ) {
+            self.set_env( name, value );
+        } else {
+            self.vars.insert( name.to_string(), value.to_string() );
+        }
+    }
+
+    // Moves a variable into the environment of processes started from now on. Without
+    // a value, the variable's current shell-local value is exported. Returns false if
+    // there is no such variable.
+    pub fn export( &mut self, name: &str, value: Option<&str> ) -> bool {
+        let value = match ( value, self.vars.remove( name ) ) {
+            ( Some( x ), _ ) => x.to_string(),
+            ( None, Some( x ) ) => x,
+            ( None, None ) => return self.env.contains_key( &OsString::from( name ) ),
+        };
+
+        self.set_env( name, &value );
+        true
+    }
+
+    // Exported variables as NAME=value, sorted by name.
+    pub fn exported( &self ) -> Vec<String> {
+        self.env.iter().map( | ( k, v ) | format!( "{}={}", k.to_string_lossy(), v.to_string_lossy() ) ).collect()
+    }
+
+    // Every variable the shell can see, shell-local ones hiding exported ones of the same name.
+    pub fn variables( &self ) -> BTreeMap<String, String> {
+        let mut all: BTreeMap<String, String> = self.env.iter()
+            .map( | ( k, v ) | ( k.to_string_lossy().into_owned(), v.to_string_lossy().into_owned() ) )
+            .collect();
+
+        all.extend( self.vars.iter().map( | ( k, v ) | ( k.clone(), v.clone() ) ) );
+        all
+    }
+
+    fn set_env( &mut self, name: &str, value: &str ) {
+        env::set_var( name, value );
+        self.env.insert( name.into(), value.into() );
+    }
+
+    pub fn last_status( &self ) -> i32 {
+        self.last_status
+    }
+
+    pub fn set_last_status( &mut self, code: i32 ) {
+        self.last_status = code;
+    }
+
+    // Asks the REPL or script runner to stop once the current command finishes.
+    pub fn request_exit( &mut self, code: i32 ) {
+        self.exit_code = Some( code );
+    }
+
+    pub fn exit_requested( &self ) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn capturing( &self ) -> bool {
+        self.capture
+    }
+
+    pub fn with_capture<T, F: FnOnce( &mut ExecContext ) -> T>( &mut self, capture: bool, f: F ) -> T {
+        let orig = std::mem::replace( &mut self.capture, capture );
+        let res = f( self );
+        self.capture = orig;
+
+        res
+    }
+
+    pub fn deadline( &self ) -> Option<Deadline> {
+        self.deadline
+    }
+
+    // Runs `f` with programs it starts stopped at `deadline`, unless an
+    // enclosing timeout ends sooner.
+    pub fn with_deadline<T, F: FnOnce( &mut ExecContext ) -> T>( &mut self, deadline: Deadline, f: F ) -> T {
+        let inner = match self.deadline {
+            Some( x ) if x.at <= deadline.at => x,
+            _ => deadline,
+        };
+
+        let orig = self.deadline.replace( inner );
+        let res = f( self );
+        self.deadline = orig;
+
+        res
+    }
+
+    // Notes that a program was stopped at the deadline. Only a shared reference
+    // is needed, since that's all the code waiting for programs has.
+    pub fn note_timed_out( &self ) {
+        self.timed_out.set( true );
+    }
+
+    // Whether a program was stopped at the deadline since this was last asked.
+    pub fn take_timed_out( &self ) -> bool {
+        self.timed_out.replace( false )
+    }
+
+    pub fn tee( &self ) -> Tee {
+        self.tee
+    }
+
+    // Runs `f` with captured output also streamed to the terminal as `tee` says.
+    pub fn with_tee<T, F: FnOnce( &mut ExecContext ) -> T>( &mut self, tee: Tee, f: F ) -> T {
+        let orig = std::mem::replace( &mut self.tee, tee );
+        let res = f( self );
+        self.tee = orig;
+
+        res
+    }
+
+    pub fn set_aliases( &mut self, aliases: BTreeMap<String, String> ) {
+        self.aliases = aliases;
+    }
+
+    pub fn alias( &self, name: &str ) -> Option<String> {
+        self.aliases.get( name ).cloned()
+    }
+
+    pub fn set_alias( &mut self, name: &str, value: &str ) {
+        self.aliases.insert( name.to_string(), value.to_string() );
+    }
+
+    pub fn aliases( &self ) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    pub fn define_function( &mut self, name: &str, body: Exec ) {
+        self.functions.insert( name.to_string(), Rc::new( body ) );
+    }
+
+    pub fn function( &self, name: &str ) -> Option<Rc<Exec>> {
+        self.functions.get( name ).cloned()
+    }
+
+    pub fn set_not_found_hook( &mut self, hook: Option<String> ) {
+        self.not_found_hook = hook;
+    }
+
+    pub fn not_found_hook( &self ) -> Option<String> {
+        self.not_found_hook.clone()
+    }
+
+    pub fn options( &self ) -> ShellOptions {
+        self.options
+    }
+
+    pub fn options_mut( &mut self ) -> &mut ShellOptions {
+        &mut self.options
+    }
+
+    // Variables for only the next program started, on top of the environment.
+    pub fn set_command_env( &mut self, vars: Vec<( String, String )> ) {
+        self.command_env = vars;
+    }
+
+    pub fn take_command_env( &mut self ) -> Vec<( String, String )> {
+        std::mem::take( &mut self.command_env )
+    }
+
+    // The value behind the next stage's input, kept only until that stage has run.
+    pub fn set_piped_value( &mut self, value: Option<Value> ) {
+        self.piped_value = value;
+    }
+
+    pub fn take_piped_value( &mut self ) -> Option<Value> {
+        self.piped_value.take()
+    }
+
+    // Like the piped value, the bytes are kept only until the stage has run.
+    pub fn set_piped_bytes( &mut self, bytes: Option<Vec<u8>> ) {
+        self.piped_bytes = bytes;
+    }
+
+    pub fn take_piped_bytes( &mut self ) -> Option<Vec<u8>> {
+        self.piped_bytes.take()
+    }
+
+    // Kept only until the command it came from runs, like the piped value.
+    pub fn set_expansion( &mut self, expansion: Option<Expansion> ) {
+        self.expansion = expansion;
+    }
+
+    pub fn take_expansion( &mut self ) -> Option<Expansion> {
+        self.expansion.take()
+    }
+}
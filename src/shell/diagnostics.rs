@@ -0,0 +1,86 @@
+use unicode_width::UnicodeWidthChar;
+use yansi::{ Color, Paint };
+use parsing::{ LexError, ParseError, TextSpan };
+
+// how much of a long line is kept in front of the problem when it has to be cut
+const LEAD: usize = 10;
+
+// A problem found in a command line or script, and where it is when that's known.
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<TextSpan>,
+}
+
+impl<'a> From<&'a LexError> for Diagnostic {
+    fn from( e: &'a LexError ) -> Diagnostic {
+        Diagnostic { message: e.message(), span: Some( e.span().clone() ) }
+    }
+}
+
+impl<'a> From<&'a ParseError> for Diagnostic {
+    fn from( e: &'a ParseError ) -> Diagnostic {
+        Diagnostic { message: e.message(), span: e.span().cloned() }
+    }
+}
+
+fn columns( chars: &[char] ) -> usize {
+    chars.iter().map( | c | c.width().unwrap_or( 0 ) ).sum()
+}
+
+impl Diagnostic {
+    // The message and where it happened, then the line of `source` it's about
+    // with the span underlined, fitted into `width` columns. For example:
+    //
+    //     unexpected ), expecting <end-of-input> (line 2, column 6)
+    //     2 │ echo ) x
+    //       │      ^
+    pub fn render( &self, source: &str, width: usize, color: Color ) -> Vec<String> {
+        let span = match self.span {
+            Some( ref x ) => x,
+            None => return vec![ Paint::new( &self.message ).fg( color ).dimmed().to_string() ],
+        };
+
+        let header = format!( "{} (line {}, column {})", self.message, span.start.line, span.start.column );
+        let mut lines = vec![ Paint::new( header ).fg( color ).dimmed().to_string() ];
+
+        // only the line the span starts on is shown, underlined to its end if
+        // the span carries on past it
+        let chars: Vec<char> = source.chars().collect();
+        let at = span.start.index.min( chars.len() );
+        let start = chars[..at].iter().rposition( | x | *x == '\n' ).map_or( 0, | i | i + 1 );
+        let end = chars[at..].iter().position( | x | *x == '\n' ).map_or( chars.len(), | i | at + i );
+        let line = &chars[start..end];
+        let from = at - start;
+        let to = span.end.index.clamp( at, end ) - start;
+
+        let number = span.start.line.to_string();
+        let gutter = format!( "{} │ ", number );
+        let blank = format!( "{} │ ", " ".repeat( number.len() ) );
+        let room = width.saturating_sub( number.len() + 3 );
+
+        // long lines are cut down to the part around the problem
+        let ( mut shown, indent ) = if columns( line ) > room && from > LEAD {
+            let mut shown: Vec<char> = "... ".chars().collect();
+            shown.extend( &line[( from - LEAD )..] );
+            ( shown, 4 + columns( &line[( from - LEAD )..from] ) )
+        } else {
+            ( line.to_vec(), columns( &line[..from] ) )
+        };
+
+        if columns( &shown ) > room {
+            let mut used = 0;
+            shown.retain( | c | {
+                used += c.width().unwrap_or( 0 );
+                used + 4 <= room
+            } );
+
+            shown.extend( " ...".chars() );
+        }
+
+        let underline = columns( &line[from..to] ).max( 1 ).min( room.saturating_sub( indent ).max( 1 ) );
+        lines.push( format!( "{}{}", gutter, shown.iter().collect::<String>() ) );
+        lines.push( format!( "{}{}{}", blank, " ".repeat( indent ), Paint::new( "^".repeat( underline ) ).fg( color ) ) );
+
+        lines
+    }
+}
@@ -0,0 +1,466 @@
+use std::io::{ Read, Result, stdin };
+use std::mem::take;
+use std::time::Duration;
+use yansi::Style;
+use kernel::{ enable_raw_mode, disable_raw_mode, wait_for_input };
+use shell::completion::complete;
+use shell::render::{ Cell, Renderer };
+use shell::suggest::{ Suggester, FromHistory };
+
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub enum Key {
+    Char( char ),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Interrupt,
+    EndOfFile,
+    Search,
+    Cancel,
+    Unknown,
+}
+
+// how often the prompt is checked for segments that have finished in the background
+const REFRESH_INTERVAL: Duration = Duration::from_millis( 50 );
+
+// A prompt with parts that are still being worked out in the background, so
+// it can be drawn again as they arrive.
+pub trait PromptSource {
+    fn pending( &self ) -> bool;
+
+    // The prompts to show instead, left and right, if anything has arrived since the last call.
+    fn refresh( &mut self ) -> Option<( String, String )>;
+
+    // What the prompt is redrawn as once the line is accepted, if it's to be
+    // left behind smaller than it was.
+    fn transient( &self ) -> Option<String> {
+        None
+    }
+}
+
+pub( crate ) struct KeyReader<R: Read> {
+    input: R,
+
+    // Bytes read but not yet made into keys. Reads this large go around std's
+    // own buffer, so input is always either here or where wait_for_input sees it.
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> KeyReader<R> {
+    pub( crate ) fn new( input: R ) -> KeyReader<R> {
+        KeyReader::resume( input, Vec::new() )
+    }
+
+    // Picks up where another reader left off, with the bytes it hadn't used.
+    fn resume( input: R, unread: Vec<u8> ) -> KeyReader<R> {
+        KeyReader { input, buffer: unread, pos: 0 }
+    }
+
+    fn unread( mut self ) -> Vec<u8> {
+        self.buffer.split_off( self.pos )
+    }
+
+    fn has_buffered( &self ) -> bool {
+        self.pos < self.buffer.len()
+    }
+
+    fn byte( &mut self ) -> Result<Option<u8>> {
+        if !self.has_buffered() {
+            self.buffer.resize( 8192, 0 );
+            self.pos = 0;
+
+            match self.input.read( &mut self.buffer ) {
+                Ok( len ) => self.buffer.truncate( len ),
+                Err( e ) => {
+                    self.buffer.clear();
+                    return Err( e );
+                },
+            }
+
+            if self.buffer.is_empty() {
+                return Ok( None );
+            }
+        }
+
+        self.pos += 1;
+        Ok( Some( self.buffer[self.pos - 1] ) )
+    }
+
+    pub( crate ) fn next_key( &mut self ) -> Result<Option<Key>> {
+        let b = match self.byte()? {
+            Some( x ) => x,
+            None => return Ok( None ),
+        };
+
+        let key = match b {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7F | 0x08 => Key::Backspace,
+            0x01 => Key::Home,
+            0x05 => Key::End,
+            0x03 => Key::Interrupt,
+            0x04 => Key::EndOfFile,
+            0x07 => Key::Cancel,
+            0x12 => Key::Search,
+            0x1B => self.escape()?,
+            x if x < 0x20 => Key::Unknown,
+            x => self.utf8( x )?,
+        };
+
+        Ok( Some( key ) )
+    }
+
+    fn escape( &mut self ) -> Result<Key> {
+        let kind = match self.byte()? {
+            Some( x @ b'[' ) | Some( x @ b'O' ) => x,
+            _ => return Ok( Key::Unknown ),
+        };
+
+        let mut params = String::new();
+        loop {
+            let b = match self.byte()? {
+                Some( x ) => x,
+                None => return Ok( Key::Unknown ),
+            };
+
+            if b.is_ascii_digit() || b == b';' {
+                params.push( b as char );
+                continue;
+            }
+
+            return Ok( match ( kind, b, params.as_str() ) {
+                ( _, b'A', _ ) => Key::Up,
+                ( _, b'B', _ ) => Key::Down,
+                ( _, b'C', _ ) => Key::Right,
+                ( _, b'D', _ ) => Key::Left,
+                ( _, b'H', _ ) => Key::Home,
+                ( _, b'F', _ ) => Key::End,
+                ( b'[', b'~', "1" ) | ( b'[', b'~', "7" ) => Key::Home,
+                ( b'[', b'~', "4" ) | ( b'[', b'~', "8" ) => Key::End,
+                ( b'[', b'~', "3" ) => Key::Delete,
+                _ => Key::Unknown,
+            } );
+        }
+    }
+
+    fn utf8( &mut self, first: u8 ) -> Result<Key> {
+        let len = match first {
+            x if x & 0xE0 == 0xC0 => 2,
+            x if x & 0xF0 == 0xE0 => 3,
+            x if x & 0xF8 == 0xF0 => 4,
+            _ => 1,
+        };
+
+        let mut bytes = vec![ first ];
+        for _ in 1 .. len {
+            match self.byte()? {
+                Some( x ) => bytes.push( x ),
+                None => break,
+            }
+        }
+
+        Ok( match String::from_utf8_lossy( &bytes ).chars().next() {
+            Some( c ) => Key::Char( c ),
+            None => Key::Unknown,
+        } )
+    }
+}
+
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+    prompt: String,
+
+    // shown at the right edge of the terminal, for the next line only
+    right_prompt: String,
+
+    renderer: Renderer,
+    suggester: Option<Box<dyn Suggester>>,
+
+    // what the suggester offered for the current buffer, shown after it
+    suggestion: Option<String>,
+
+    // input that came after the end of the last line, e.g. the rest of a paste
+    typeahead: Vec<u8>,
+}
+
+impl Default for LineEditor {
+    fn default() -> LineEditor {
+        LineEditor::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor {
+            buffer: Vec::new(),
+            cursor: 0,
+            prompt: String::new(),
+            right_prompt: String::new(),
+            renderer: Renderer::new(),
+            suggester: Some( Box::new( FromHistory ) ),
+            suggestion: None,
+            typeahead: Vec::new(),
+        }
+    }
+
+    // Replaces what offers to finish lines as they're typed; None turns suggestions off.
+    pub fn set_suggester( &mut self, suggester: Option<Box<dyn Suggester>> ) {
+        self.suggester = suggester;
+    }
+
+    // Sets what the next line read has at the right edge of the terminal.
+    pub fn set_right_prompt( &mut self, prompt: &str ) {
+        self.right_prompt = prompt.to_string();
+    }
+
+    // Reads one line of input, returning None when the user signals end-of-input.
+    // `history` is what the up and down arrows cycle through, oldest first.
+    pub fn read_line( &mut self, prompt: &str, history: &[String] ) -> Result<Option<String>> {
+        self.read_line_with( prompt, history, None )
+    }
+
+    // The same as read_line, with the prompt redrawn from `source` while its
+    // slow parts arrive and nothing has been typed.
+    pub fn read_line_with( &mut self, prompt: &str, history: &[String], source: Option<&mut dyn PromptSource> ) -> Result<Option<String>> {
+        self.buffer.clear();
+        self.cursor = 0;
+        self.prompt = prompt.to_string();
+        self.renderer.reset();
+
+        let stdin = stdin();
+        let mut keys = KeyReader::resume( stdin.lock(), take( &mut self.typeahead ) );
+
+        unsafe { enable_raw_mode()?; }
+        let res = self.edit( &mut keys, history, source );
+        unsafe { disable_raw_mode()?; }
+
+        self.typeahead = keys.unread();
+        self.right_prompt.clear();
+        println!();
+        res
+    }
+
+    fn edit<R: Read>( &mut self, keys: &mut KeyReader<R>, history: &[String], mut source: Option<&mut dyn PromptSource> ) -> Result<Option<String>> {
+        self.suggestion = None;
+        self.redraw()?;
+
+        // index into `history`, where history.len() is the line being typed
+        let mut recalled = history.len();
+        let mut draft = Vec::new();
+        let mut pending = None;
+
+        loop {
+            if let Some( source ) = source.as_deref_mut() {
+                self.refresh_prompt( keys, source )?;
+            }
+
+            let key = match pending.take() {
+                Some( x ) => x,
+                None => match keys.next_key()? {
+                    Some( x ) => x,
+                    None => return Ok( None ),
+                },
+            };
+
+            // the suggestion is only left on screen while it can still be accepted
+            if matches!( key, Key::Enter | Key::Interrupt ) && self.suggestion.take().is_some() {
+                self.redraw()?;
+            }
+
+            match key {
+                Key::Enter => {
+                    if let Some( prompt ) = source.as_deref().and_then( | x | x.transient() ) {
+                        self.prompt = prompt;
+                        self.right_prompt.clear();
+                        self.redraw()?;
+                    }
+
+                    return Ok( Some( self.buffer.iter().collect() ) );
+                },
+                Key::EndOfFile if self.buffer.is_empty() => return Ok( None ),
+                Key::Interrupt => {
+                    self.buffer.clear();
+                    self.cursor = 0;
+                    print!( "^C\r\n" );
+                    self.renderer.reset();
+                },
+
+                Key::Char( c ) => {
+                    self.buffer.insert( self.cursor, c );
+                    self.cursor += 1;
+                },
+                Key::Backspace if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.buffer.remove( self.cursor );
+                },
+                Key::Delete | Key::EndOfFile if self.cursor < self.buffer.len() => {
+                    self.buffer.remove( self.cursor );
+                },
+                Key::Right | Key::End if self.cursor == self.buffer.len() && self.suggestion.is_some() => {
+                    let rest = self.suggestion.take().unwrap_or_default();
+                    self.buffer.extend( rest.chars() );
+                    self.cursor = self.buffer.len();
+                },
+                Key::Left if self.cursor > 0 => self.cursor -= 1,
+                Key::Right if self.cursor < self.buffer.len() => self.cursor += 1,
+                Key::Home => self.cursor = 0,
+                Key::End => self.cursor = self.buffer.len(),
+                Key::Tab => self.complete()?,
+                Key::Search => pending = self.search( keys, history )?,
+                Key::Up if recalled > 0 => {
+                    if recalled == history.len() {
+                        draft = self.buffer.clone();
+                    }
+
+                    recalled -= 1;
+                    self.set_buffer( history[recalled].chars().collect() );
+                },
+                Key::Down if recalled < history.len() => {
+                    recalled += 1;
+                    let line = match history.get( recalled ) {
+                        Some( x ) => x.chars().collect(),
+                        None => draft.clone(),
+                    };
+
+                    self.set_buffer( line );
+                },
+
+                _ => {},
+            }
+
+            self.suggest( history );
+            self.redraw()?;
+        }
+    }
+
+    // Redraws the prompt as its slow parts arrive, until they all have or a key is pressed.
+    fn refresh_prompt<R: Read>( &mut self, keys: &KeyReader<R>, source: &mut dyn PromptSource ) -> Result<()> {
+        while source.pending() && !keys.has_buffered() && !wait_for_input( REFRESH_INTERVAL )? {
+            if let Some( ( left, right ) ) = source.refresh() {
+                self.prompt = left;
+                self.right_prompt = right;
+                self.redraw()?;
+            }
+        }
+
+        Ok( () )
+    }
+
+    // Asks for a suggestion when the cursor is at the end of the line.
+    fn suggest( &mut self, history: &[String] ) {
+        let line: String = self.buffer.iter().collect();
+        self.suggestion = match self.suggester {
+            Some( ref x ) if self.cursor == self.buffer.len() => x.suggest( &line, history ),
+            _ => None,
+        };
+    }
+
+    // Incremental reverse search through `history`. Returns the key that ended the
+    // search so the caller can act on it, or None if the search was cancelled.
+    fn search<R: Read>( &mut self, keys: &mut KeyReader<R>, history: &[String] ) -> Result<Option<Key>> {
+        let original = self.buffer.clone();
+        let mut query = String::new();
+        let mut found = history.len();
+
+        loop {
+            let prompt = format!( "(reverse-i-search)'{}': ", query );
+            let cells: Vec<Cell> = self.buffer.iter().map( | &c | Cell::plain( c ) ).collect();
+            self.renderer.render( &prompt, "", &cells, self.cursor )?;
+
+            let key = match keys.next_key()? {
+                Some( x ) => x,
+                None => return Ok( None ),
+            };
+
+            // matches are looked for in entries older than `before`
+            let before = match key {
+                Key::Char( c ) => {
+                    query.push( c );
+                    ( found + 1 ).min( history.len() )
+                },
+                Key::Backspace => {
+                    query.pop();
+                    history.len()
+                },
+                Key::Search => found,
+                Key::Interrupt | Key::Cancel => {
+                    self.set_buffer( original );
+                    return Ok( None );
+                },
+
+                other => return Ok( Some( other ) ),
+            };
+
+            if let Some( i ) = history[..before].iter().rposition( | x | x.contains( &query ) ) {
+                found = i;
+                self.set_buffer( history[i].chars().collect() );
+
+                let at = history[i].find( &query ).unwrap_or( 0 );
+                self.cursor = history[i][..at].chars().count();
+            }
+        }
+    }
+
+    fn set_buffer( &mut self, buffer: Vec<char> ) {
+        self.cursor = buffer.len();
+        self.buffer = buffer;
+    }
+
+    fn complete( &mut self ) -> Result<()> {
+        let head: String = self.buffer[..self.cursor].iter().collect();
+        let candidates = complete( &head );
+        if candidates.is_empty() {
+            return Ok( () );
+        }
+
+        let start = head.rfind( char::is_whitespace ).map_or( 0, | i | i + 1 );
+        let word: String = head[start..].to_string();
+
+        let replacement = if candidates.len() == 1 {
+            let only = &candidates[0];
+            if only.ends_with( '/' ) { only.clone() } else { format!( "{} ", only ) }
+        } else {
+            let prefix = common_prefix( &candidates );
+            if prefix.chars().count() <= word.chars().count() {
+                print!( "\r\n{}\r\n", candidates.join( "  " ) );
+                self.renderer.reset();
+                return Ok( () );
+            }
+
+            prefix
+        };
+
+        let start = head[..start].chars().count();
+        self.buffer.splice( start .. self.cursor, replacement.chars() );
+        self.cursor = start + replacement.chars().count();
+
+        Ok( () )
+    }
+
+    fn redraw( &mut self ) -> Result<()> {
+        let mut cells: Vec<Cell> = self.buffer.iter().map( | &c | Cell::plain( c ) ).collect();
+        let ghost = Style::default().dimmed();
+        cells.extend( self.suggestion.iter().flat_map( | x | x.chars() ).map( | ch | Cell { ch, style: ghost } ) );
+
+        self.renderer.render( &self.prompt, &self.right_prompt, &cells, self.cursor )
+    }
+}
+
+fn common_prefix( words: &[String] ) -> String {
+    let mut prefix: Vec<char> = words[0].chars().collect();
+    for w in &words[1..] {
+        let len = prefix.iter().zip( w.chars() ).take_while( | ( a, b ) | *a == b ).count();
+        prefix.truncate( len );
+    }
+
+    prefix.into_iter().collect()
+}
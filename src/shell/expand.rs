@@ -0,0 +1,53 @@
+use std::fmt::{ Display, Formatter };
+use std::io::Error;
+use parsing::{ LexError, ParseError };
+use shell::parsing::{ ShellLexer, ShellParser };
+use shell::context::ExecContext;
+use shell::segments::Cmd;
+
+#[derive( Debug )]
+pub enum ExpandError {
+    Lex( LexError ),
+    Parse( ParseError ),
+    Exec( Error ),
+    NotWords,
+}
+
+impl Display for ExpandError {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        match self {
+            ExpandError::Lex( e ) => write!( formatter, "{}", e ),
+            ExpandError::Parse( e ) => write!( formatter, "{}", e ),
+            ExpandError::Exec( e ) => write!( formatter, "{}", e ),
+            ExpandError::NotWords => formatter.write_str( "input must be a plain list of words" ),
+        }
+    }
+}
+
+fn argv_of( ctx: &mut ExecContext, cmd: &Cmd ) -> Result<Vec<String>, Error> {
+    let res = cmd.command.execute( ctx, true, None )?;
+    if res.code() != Some( 0 ) {
+        return Err( Error::other( "expanding the first word failed" ) );
+    }
+
+    let mut argv = vec![ res.stdout().map( | x | x.join( "" ) ).unwrap_or_default() ];
+    argv.append( &mut cmd.expand_args( ctx )? );
+
+    Ok( argv )
+}
+
+// Performs the same quoting, interpolation, variable and glob expansion the
+// shell applies to a command line and returns the resulting argv.
+pub fn expand( ctx: &mut ExecContext, source: &str ) -> Result<Vec<String>, ExpandError> {
+    if source.trim().is_empty() {
+        return Ok( Vec::new() );
+    }
+
+    let tokens = ShellLexer::new( source ).tokenize().map_err( ExpandError::Lex )?;
+    let seg = ShellParser::new( tokens ).parse_all().map_err( ExpandError::Parse )?;
+
+    match seg.as_any().downcast_ref::<Cmd>() {
+        Some( cmd ) => argv_of( ctx, cmd ).map_err( ExpandError::Exec ),
+        None => Err( ExpandError::NotWords ),
+    }
+}
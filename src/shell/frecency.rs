@@ -0,0 +1,156 @@
+use std::fs::{ read_to_string, write };
+use std::io::Result;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use dirs::home_dir;
+
+lazy_static! {
+    // directories the interactive session has changed to, for `z`
+    pub static ref VISITS: Mutex<Visits> = Mutex::new( Visits::new() );
+}
+
+// once the ranks add up to this much, they're all scaled down and the
+// least used directories are forgotten
+const MAX_TOTAL_RANK: f64 = 10000.0;
+
+pub fn default_path() -> Option<PathBuf> {
+    home_dir().map( | home | home.join( ".lumi_dirs" ) )
+}
+
+struct Entry {
+    path: PathBuf,
+
+    // grows by one with every visit
+    rank: f64,
+
+    // seconds since the epoch
+    last_visit: u64,
+}
+
+impl Entry {
+    // Frequency weighted by recency, the same as z and zoxide use.
+    fn score( &self, now: u64 ) -> f64 {
+        let weight = match now.saturating_sub( self.last_visit ) {
+            0 ..= 3599 => 4.0,
+            3600 ..= 86399 => 2.0,
+            86400 ..= 604799 => 0.5,
+            _ => 0.25,
+        };
+
+        self.rank * weight
+    }
+}
+
+// Whether `path` contains the terms in order, ignoring case, with the last one
+// in its final component, so `z proj` finds ~/code/my-project but not ~/project/src.
+fn matches( path: &Path, terms: &[String] ) -> bool {
+    let text = path.to_string_lossy().to_lowercase();
+    let mut from = 0;
+
+    for term in terms {
+        let term = term.to_lowercase();
+        match text[from ..].find( &term ) {
+            Some( i ) => from += i + term.len(),
+            None => return false,
+        }
+    }
+
+    let name = path.file_name().map( | x | x.to_string_lossy().to_lowercase() ).unwrap_or_default();
+    terms.last().is_none_or( | x | name.contains( &x.to_lowercase() ) )
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since( UNIX_EPOCH ).map( | x | x.as_secs() ).unwrap_or( 0 )
+}
+
+pub struct Visits {
+    entries: Vec<Entry>,
+    path: Option<PathBuf>,
+}
+
+impl Default for Visits {
+    fn default() -> Visits {
+        Visits::new()
+    }
+}
+
+impl Visits {
+    // An in-memory database that is never written to disk.
+    pub fn new() -> Visits {
+        Visits {
+            entries: Vec::new(),
+            path: None,
+        }
+    }
+
+    // Loads the database stored at `path`, one `rank|last visit|directory` per line.
+    pub fn load( path: PathBuf ) -> Result<Visits> {
+        let mut entries = Vec::new();
+        if path.exists() {
+            for line in read_to_string( &path )?.lines() {
+                let mut parts = line.splitn( 3, '|' );
+                let ( rank, last_visit, dir ) = match ( parts.next(), parts.next(), parts.next() ) {
+                    ( Some( a ), Some( b ), Some( c ) ) => ( a, b, c ),
+                    _ => continue,
+                };
+
+                if let ( Ok( rank ), Ok( last_visit ) ) = ( rank.parse(), last_visit.parse() ) {
+                    entries.push( Entry { path: PathBuf::from( dir ), rank, last_visit } );
+                }
+            }
+        }
+
+        Ok( Visits {
+            entries,
+            path: Some( path ),
+        } )
+    }
+
+    pub fn visit( &mut self, dir: &Path ) -> Result<()> {
+        let now = now();
+        match self.entries.iter_mut().find( | x | x.path == dir ) {
+            Some( entry ) => {
+                entry.rank += 1.0;
+                entry.last_visit = now;
+            },
+
+            None => self.entries.push( Entry { path: dir.to_path_buf(), rank: 1.0, last_visit: now } ),
+        }
+
+        if self.entries.iter().map( | x | x.rank ).sum::<f64>() > MAX_TOTAL_RANK {
+            for entry in &mut self.entries {
+                entry.rank *= 0.9;
+            }
+
+            self.entries.retain( | x | x.rank >= 1.0 );
+        }
+
+        self.save()
+    }
+
+    fn save( &self ) -> Result<()> {
+        let path = match self.path {
+            Some( ref x ) => x,
+            None => return Ok( () ),
+        };
+
+        let text: String = self.entries.iter()
+            .map( | x | format!( "{}|{}|{}\n", x.rank, x.last_visit, x.path.to_string_lossy() ) )
+            .collect();
+
+        write( path, text )
+    }
+
+    // Existing directories matching `terms`, best first, with their scores.
+    pub fn ranked( &self, terms: &[String] ) -> Vec<( f64, PathBuf )> {
+        let now = now();
+        let mut found: Vec<( f64, PathBuf )> = self.entries.iter()
+            .filter( | x | matches( &x.path, terms ) && x.path.is_dir() )
+            .map( | x | ( x.score( now ), x.path.clone() ) )
+            .collect();
+
+        found.sort_by( | a, b | b.0.total_cmp( &a.0 ) );
+        found
+    }
+}
@@ -0,0 +1,45 @@
+// Edit distance between two words, counting a swap of neighbouring
+// characters as a single edit (so "grpe" is one step away from "grep").
+pub fn distance( a: &str, b: &str ) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // rows[i][j] is the distance between the first i chars of a and the first j of b
+    let mut rows = vec![ vec![ 0; b.len() + 1 ]; a.len() + 1 ];
+    for ( i, row ) in rows.iter_mut().enumerate() { row[0] = i; }
+    for ( j, cell ) in rows[0].iter_mut().enumerate() { *cell = j; }
+
+    for i in 1 ..= a.len() {
+        for j in 1 ..= b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = ( rows[i - 1][j] + 1 ).min( rows[i][j - 1] + 1 ).min( rows[i - 1][j - 1] + cost );
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min( rows[i - 2][j - 2] + 1 );
+            }
+
+            rows[i][j] = best;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+// Up to `limit` candidates close enough to `word` to be a likely typo, best first.
+pub fn closest<I, S>( word: &str, candidates: I, limit: usize ) -> Vec<String>
+    where I: IntoIterator<Item = S>, S: AsRef<str>
+{
+    // short words need a near match, otherwise everything looks similar
+    let max = ( word.chars().count() / 3 ).clamp( 1, 3 );
+
+    let mut found: Vec<( usize, String )> = candidates.into_iter()
+        .map( | x | x.as_ref().to_string() )
+        .filter( | x | x != word )
+        .map( | x | ( distance( word, &x ), x ) )
+        .filter( | ( d, _ ) | *d <= max )
+        .collect();
+
+    found.sort();
+    found.dedup();
+    found.into_iter().take( limit ).map( | ( _, x ) | x ).collect()
+}
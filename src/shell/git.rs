@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, TryRecvError };
+use std::thread;
+use std::time::Duration;
+
+// how long the prompt waits for `git status` before drawing without it; the
+// answer is filled in when it arrives
+const DIRTY_TIMEOUT: Duration = Duration::from_millis( 50 );
+
+pub struct GitInfo {
+    pub branch: String,
+
+    // None until the first `git status` in this repository has finished
+    pub dirty: Option<bool>,
+}
+
+impl GitInfo {
+    // e.g. main, or main* with uncommitted changes
+    pub fn summary( &self ) -> String {
+        match self.dirty {
+            Some( true ) => format!( "{}*", self.branch ),
+            _ => self.branch.clone(),
+        }
+    }
+}
+
+// Branch names are read straight from .git/HEAD. Checking for changes needs
+// `git status`, which can be slow in large repositories, so it runs on a
+// thread and the last known answer is used until it finishes.
+#[derive( Default )]
+pub struct GitWatcher {
+    pending: Option<( PathBuf, Receiver<bool> )>,
+    dirty: HashMap<PathBuf, bool>,
+}
+
+impl GitWatcher {
+    pub fn new() -> GitWatcher {
+        GitWatcher::default()
+    }
+
+    pub fn status( &mut self, dir: &Path ) -> Option<GitInfo> {
+        let ( root, git_dir ) = find_repository( dir )?;
+        let branch = read_branch( &git_dir )?;
+
+        // a check that is still running for this repository is as good as a new one
+        let rx = match self.pending.take() {
+            Some( ( path, rx ) ) if path == root => rx,
+            _ => spawn_check( &root ),
+        };
+
+        match rx.recv_timeout( DIRTY_TIMEOUT ) {
+            Ok( dirty ) => {
+                self.dirty.insert( root.clone(), dirty );
+            },
+
+            Err( RecvTimeoutError::Timeout ) => self.pending = Some( ( root.clone(), rx ) ),
+
+            // git isn't installed, or this isn't a repository it understands
+            Err( RecvTimeoutError::Disconnected ) => {},
+        }
+
+        Some( GitInfo {
+            branch,
+            dirty: self.dirty.get( &root ).cloned(),
+        } )
+    }
+
+    // Whether a `git status` is still running.
+    pub fn pending( &self ) -> bool {
+        self.pending.is_some()
+    }
+
+    // Collects the result of a check that has finished since `status` was
+    // called, returning the repository it was for and whether it has changes.
+    pub fn poll( &mut self ) -> Option<( PathBuf, bool )> {
+        let ( root, rx ) = self.pending.take()?;
+        match rx.try_recv() {
+            Ok( dirty ) => {
+                self.dirty.insert( root.clone(), dirty );
+                Some( ( root, dirty ) )
+            },
+
+            Err( TryRecvError::Empty ) => {
+                self.pending = Some( ( root, rx ) );
+                None
+            },
+
+            Err( TryRecvError::Disconnected ) => None,
+        }
+    }
+}
+
+// The branch checked out in the repository containing `dir`.
+pub fn branch( dir: &Path ) -> Option<String> {
+    let ( _, git_dir ) = find_repository( dir )?;
+    read_branch( &git_dir )
+}
+
+// The working tree root and its git directory, which is somewhere else for
+// worktrees and submodules where .git is a file pointing to it.
+fn find_repository( dir: &Path ) -> Option<( PathBuf, PathBuf )> {
+    for root in dir.ancestors() {
+        let dot_git = root.join( ".git" );
+        if dot_git.is_dir() {
+            return Some( ( root.to_path_buf(), dot_git ) );
+        }
+
+        if dot_git.is_file() {
+            let text = read_to_string( &dot_git ).ok()?;
+            let target = text.trim().strip_prefix( "gitdir:" )?.trim();
+            return Some( ( root.to_path_buf(), root.join( target ) ) );
+        }
+    }
+
+    None
+}
+
+// The checked-out branch, or the abbreviated commit on a detached HEAD.
+fn read_branch( git_dir: &Path ) -> Option<String> {
+    let head = read_to_string( git_dir.join( "HEAD" ) ).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix( "ref:" ) {
+        Some( name ) => {
+            let name = name.trim();
+            Some( name.strip_prefix( "refs/heads/" ).unwrap_or( name ).to_string() )
+        },
+
+        None => Some( head.chars().take( 7 ).collect() ),
+    }
+}
+
+fn spawn_check( root: &Path ) -> Receiver<bool> {
+    let ( tx, rx ) = channel();
+    let root = root.to_path_buf();
+
+    thread::spawn( move || {
+        let output = Command::new( "git" )
+            .arg( "-C" )
+            .arg( &root )
+            .args( [ "status", "--porcelain" ] )
+            .stdin( Stdio::null() )
+            .stderr( Stdio::null() )
+            .output();
+
+        if let Ok( output ) = output {
+            if output.status.success() {
+                let _ = tx.send( !output.stdout.is_empty() );
+            }
+        }
+    } );
+
+    rx
+}
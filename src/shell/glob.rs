@@ -0,0 +1,156 @@
+use std::fs::read_dir;
+use std::path::{ Path, PathBuf };
+
+// A backslash makes the character after it literal, the same as outside a
+// pattern, so `\*` only matches a file named `*`.
+pub fn is_pattern( s: &str ) -> bool {
+    let mut chars = s.chars();
+    while let Some( c ) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '*' | '?' | '[' => return true,
+            _ => {},
+        }
+    }
+
+    false
+}
+
+// Makes every character in `text` match only itself.
+pub fn escape( text: &str ) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if matches!( c, '*' | '?' | '[' | '\\' | '~' ) {
+            out.push( '\\' );
+        }
+
+        out.push( c );
+    }
+
+    out
+}
+
+// The text a pattern stands for when it matches nothing.
+pub fn unescape( pattern: &str ) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some( c ) = chars.next() {
+        match c {
+            '\\' => out.push( chars.next().unwrap_or( '\\' ) ),
+            _ => out.push( c ),
+        }
+    }
+
+    out
+}
+
+// Matches a single path component against a pattern supporting *, ? and [...] classes.
+pub fn matches( pattern: &str, name: &str ) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    match_from( &pattern, &name )
+}
+
+fn match_from( pattern: &[char], name: &[char] ) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some( '*' ) => {
+            ( 0 ..= name.len() ).any( | i | match_from( &pattern[1..], &name[i..] ) )
+        },
+        Some( '?' ) => !name.is_empty() && match_from( &pattern[1..], &name[1..] ),
+        Some( '\\' ) if pattern.len() > 1 => {
+            name.first() == Some( &pattern[1] ) && match_from( &pattern[2..], &name[1..] )
+        },
+        Some( '[' ) => match ( name.first(), match_class( &pattern[1..] ) ) {
+            ( Some( c ), Some( ( class, rest ) ) ) => {
+                class.contains( *c ) && match_from( rest, &name[1..] )
+            },
+
+            // an unterminated class is just a literal bracket
+            ( Some( '[' ), None ) => match_from( &pattern[1..], &name[1..] ),
+            _ => false,
+        },
+        Some( p ) => name.first() == Some( p ) && match_from( &pattern[1..], &name[1..] ),
+    }
+}
+
+struct CharClass {
+    negated: bool,
+    ranges: Vec<( char, char )>,
+}
+
+impl CharClass {
+    fn contains( &self, c: char ) -> bool {
+        self.ranges.iter().any( | &( lo, hi ) | lo <= c && c <= hi ) != self.negated
+    }
+}
+
+fn match_class( pattern: &[char] ) -> Option<( CharClass, &[char] )> {
+    let mut i = 0;
+    let negated = matches!( pattern.first(), Some( '!' ) | Some( '^' ) );
+    if negated { i += 1; }
+
+    let mut ranges = Vec::new();
+    let start = i;
+    while i < pattern.len() {
+        let c = pattern[i];
+        if c == ']' && i > start {
+            return Some( ( CharClass { negated, ranges }, &pattern[( i + 1 )..] ) );
+        }
+
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            ranges.push( ( c, pattern[i + 2] ) );
+            i += 3;
+        } else {
+            ranges.push( ( c, c ) );
+            i += 1;
+        }
+    }
+
+    None
+}
+
+// Expands a pattern against the file system, returning sorted matches.
+pub fn expand( pattern: &str ) -> Vec<String> {
+    let is_absolute = pattern.starts_with( '/' );
+    let mut found = vec![ if is_absolute { PathBuf::from( "/" ) } else { PathBuf::new() } ];
+
+    for part in pattern.split( '/' ).filter( | x | !x.is_empty() ) {
+        let mut next = Vec::new();
+        for base in &found {
+            if !is_pattern( part ) {
+                let candidate = base.join( unescape( part ) );
+                if candidate.exists() { next.push( candidate ); }
+                continue;
+            }
+
+            let dir = if base.as_os_str().is_empty() { Path::new( "." ) } else { base.as_path() };
+            let entries = match read_dir( dir ) {
+                Ok( x ) => x,
+                Err( _ ) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with( '.' ) && !part.starts_with( '.' ) {
+                    continue;
+                }
+
+                if matches( part, &name ) {
+                    next.push( base.join( name ) );
+                }
+            }
+        }
+
+        found = next;
+    }
+
+    let mut paths: Vec<String> = found.into_iter()
+        .map( | p | p.to_string_lossy().into_owned() )
+        .filter( | p | !p.is_empty() )
+        .collect();
+
+    paths.sort();
+    paths
+}
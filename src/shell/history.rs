@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fs::{ File, OpenOptions, metadata, write };
+use std::io::{ Read, Result, Seek, SeekFrom, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use dirs::home_dir;
+use shell::glob;
+
+lazy_static! {
+    // the interactive session's history, shared with the `history` builtin
+    pub static ref HISTORY: Mutex<History> = Mutex::new( History::new( 0 ) );
+}
+
+// A command line that was run, and what's known about how it went.
+#[derive( Debug, Clone, PartialEq )]
+pub struct Entry {
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+
+    // seconds since the epoch, when it was entered
+    pub timestamp: Option<u64>,
+
+    // these two are filled in once it has finished
+    pub duration: Option<Duration>,
+    pub status: Option<i32>,
+}
+
+impl Entry {
+    pub fn new( command: &str ) -> Entry {
+        Entry {
+            command: command.to_string(),
+            cwd: None,
+            timestamp: None,
+            duration: None,
+            status: None,
+        }
+    }
+}
+
+// Where history is kept between sessions.
+pub trait Backend: Send {
+    // The newest `limit` entries, oldest first.
+    fn load( &mut self, limit: usize ) -> Result<Vec<Entry>>;
+
+    fn append( &mut self, entry: &Entry ) -> Result<()>;
+
+    // Saves how the most recently appended entry went, once it has finished.
+    fn finish( &mut self, entry: &Entry ) -> Result<()>;
+
+    // Called with what's left after the oldest entries were dropped to stay
+    // within the size limit.
+    fn trim( &mut self, entries: &[Entry] ) -> Result<()>;
+
+    fn clear( &mut self ) -> Result<()>;
+
+    // Everything again, if other sessions have added to it since this one
+    // last loaded or wrote to it.
+    fn reload( &mut self, limit: usize ) -> Result<Option<Vec<Entry>>>;
+}
+
+// One command per line, as ~/.lumi_history always has been. Nothing but the
+// commands themselves survives the session. Every session appends to the same
+// file, locking it while they do.
+pub struct FileBackend {
+    path: PathBuf,
+
+    // the file's length when this session last read or wrote it; any other
+    // means another session has written to it since
+    seen: u64,
+    stale: bool,
+}
+
+impl FileBackend {
+    pub fn new( path: PathBuf ) -> FileBackend {
+        FileBackend { path, seen: 0, stale: false }
+    }
+
+    // Notes whether anyone else wrote to `file` since this session last did.
+    fn check( &mut self, file: &File ) -> Result<()> {
+        if file.metadata()?.len() != self.seen {
+            self.stale = true;
+        }
+
+        Ok( () )
+    }
+}
+
+impl Backend for FileBackend {
+    fn load( &mut self, limit: usize ) -> Result<Vec<Entry>> {
+        if !self.path.exists() {
+            return Ok( Vec::new() );
+        }
+
+        let mut file = File::open( &self.path )?;
+        file.lock_shared()?;
+
+        let mut text = String::new();
+        file.read_to_string( &mut text )?;
+        self.seen = text.len() as u64;
+        self.stale = false;
+
+        let lines: Vec<&str> = text.lines().collect();
+        Ok( lines[lines.len().saturating_sub( limit )..].iter().map( | x | Entry::new( x ) ).collect() )
+    }
+
+    fn append( &mut self, entry: &Entry ) -> Result<()> {
+        let mut file = OpenOptions::new().create( true ).append( true ).open( &self.path )?;
+        file.lock()?;
+        self.check( &file )?;
+
+        writeln!( file, "{}", entry.command )?;
+        self.seen = file.metadata()?.len();
+        Ok( () )
+    }
+
+    fn finish( &mut self, _entry: &Entry ) -> Result<()> {
+        Ok( () )
+    }
+
+    // Other sessions' entries are kept too, so the newest of everyone's are what's left.
+    fn trim( &mut self, entries: &[Entry] ) -> Result<()> {
+        let mut file = OpenOptions::new().read( true ).write( true ).create( true ).truncate( false ).open( &self.path )?;
+        file.lock()?;
+        self.check( &file )?;
+
+        let mut text = String::new();
+        file.read_to_string( &mut text )?;
+        let lines: Vec<&str> = text.lines().collect();
+        let kept: String = lines[lines.len().saturating_sub( entries.len() )..].iter().map( | x | format!( "{}\n", x ) ).collect();
+
+        file.set_len( 0 )?;
+        file.seek( SeekFrom::Start( 0 ) )?;
+        file.write_all( kept.as_bytes() )?;
+        self.seen = kept.len() as u64;
+        Ok( () )
+    }
+
+    fn clear( &mut self ) -> Result<()> {
+        self.seen = 0;
+        if self.path.exists() { write( &self.path, "" ) } else { Ok( () ) }
+    }
+
+    fn reload( &mut self, limit: usize ) -> Result<Option<Vec<Entry>>> {
+        let len = match metadata( &self.path ) {
+            Ok( x ) => x.len(),
+            Err( _ ) => return Ok( None ),
+        };
+
+        if len == self.seen && !self.stale {
+            return Ok( None );
+        }
+
+        self.load( limit ).map( Some )
+    }
+}
+
+pub struct History {
+    entries: Vec<Entry>,
+
+    // the command of each entry, which is all most callers want
+    commands: Vec<String>,
+
+    backend: Option<Box<dyn Backend>>,
+    max_size: usize,
+
+    // glob patterns for commands that are never kept
+    ignore: Vec<String>,
+
+    // whether the line last given to `add` was kept, so `finish` knows
+    // whether the most recent entry is the one that just ran
+    kept: bool,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    home_dir().map( | home | home.join( ".lumi_history" ) )
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since( UNIX_EPOCH ).map( | x | x.as_secs() ).unwrap_or( 0 )
+}
+
+impl History {
+    // An in-memory history that is never written to disk.
+    pub fn new( max_size: usize ) -> History {
+        History {
+            entries: Vec::new(),
+            commands: Vec::new(),
+            backend: None,
+            max_size,
+            ignore: Vec::new(),
+            kept: false,
+        }
+    }
+
+    // Loads the history stored at `path`, which is created on the first `add` if it doesn't exist.
+    pub fn load( path: PathBuf, max_size: usize ) -> Result<History> {
+        History::open( Box::new( FileBackend::new( path ) ), max_size )
+    }
+
+    // Loads the history kept by `backend`, which new entries are saved to as well.
+    pub fn open( mut backend: Box<dyn Backend>, max_size: usize ) -> Result<History> {
+        let entries = backend.load( max_size )?;
+        Ok( History {
+            commands: entries.iter().map( | x | x.command.clone() ).collect(),
+            entries,
+            backend: Some( backend ),
+            max_size,
+            ignore: Vec::new(),
+            kept: false,
+        } )
+    }
+
+    pub fn set_ignore( &mut self, patterns: Vec<String> ) {
+        self.ignore = patterns;
+    }
+
+    // Whether `line` is one that's never kept: typed with a space in front,
+    // or matching one of the patterns to ignore.
+    pub fn ignores( &self, line: &str ) -> bool {
+        line.starts_with( ' ' ) || self.ignore.iter().any( | x | glob::matches( x, line.trim() ) )
+    }
+
+    pub fn entries( &self ) -> &[String] {
+        &self.commands
+    }
+
+    // The same as entries, with everything else that's known about them.
+    pub fn records( &self ) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn clear( &mut self ) -> Result<()> {
+        self.entries.clear();
+        self.commands.clear();
+        match self.backend {
+            Some( ref mut x ) => x.clear(),
+            None => Ok( () ),
+        }
+    }
+
+    // Adds a line that's about to be run in `cwd`.
+    pub fn add( &mut self, line: &str, cwd: &Path ) -> Result<()> {
+        // finish only applies to a line that made it into the history
+        self.kept = false;
+        let ignored = self.ignores( line );
+
+        let line = line.trim();
+        if line.is_empty() || line.contains( '\n' ) || self.max_size == 0 || ignored {
+            return Ok( () );
+        }
+
+        if self.commands.last().is_some_and( | x | x == line ) {
+            return Ok( () );
+        }
+
+        let entry = Entry {
+            cwd: Some( cwd.to_path_buf() ),
+            timestamp: Some( now() ),
+            ..Entry::new( line )
+        };
+
+        self.commands.push( entry.command.clone() );
+        self.entries.push( entry );
+        self.kept = true;
+        let overflow = self.entries.len() > self.max_size;
+        if overflow {
+            self.entries.remove( 0 );
+            self.commands.remove( 0 );
+        }
+
+        let backend = match self.backend {
+            Some( ref mut x ) => x,
+            None => return Ok( () ),
+        };
+
+        backend.append( self.entries.last().unwrap() )?;
+        if overflow {
+            backend.trim( &self.entries )?;
+        }
+
+        Ok( () )
+    }
+
+    // Picks up what other sessions have saved since this one last looked, so
+    // their commands can be recalled here too.
+    pub fn reload( &mut self ) -> Result<()> {
+        let mut loaded = match self.backend {
+            Some( ref mut x ) => match x.reload( self.max_size )? {
+                Some( x ) => x,
+                None => return Ok( () ),
+            },
+
+            None => return Ok( () ),
+        };
+
+        // what this session knows about its own entries is kept, even when the
+        // backend doesn't save it
+        let known: HashMap<&str, &Entry> = self.entries.iter().map( | x | ( x.command.as_str(), x ) ).collect();
+        for entry in &mut loaded {
+            if let Some( x ) = known.get( entry.command.as_str() ).filter( | _ | entry.timestamp.is_none() ) {
+                *entry = ( *x ).clone();
+            }
+        }
+
+        self.commands = loaded.iter().map( | x | x.command.clone() ).collect();
+        self.entries = loaded;
+        Ok( () )
+    }
+
+    // Records how the most recent entry went.
+    pub fn finish( &mut self, status: i32, duration: Duration ) -> Result<()> {
+        let entry = match self.entries.last_mut() {
+            Some( x ) if self.kept => x,
+            _ => return Ok( () ),
+        };
+
+        entry.status = Some( status );
+        entry.duration = Some( duration );
+        match self.backend {
+            Some( ref mut x ) => x.finish( entry ),
+            None => Ok( () ),
+        }
+    }
+}
+
+// Replaces history references before a line is parsed: !! is the previous
+// command, !n the n-th entry and !-n the n-th one back. References inside
+// single quotes are left alone. Returns None when there was nothing to replace.
+pub fn expand( line: &str, entries: &[String] ) -> std::result::Result<Option<String>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::new();
+    let mut quoted = false;
+    let mut changed = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            quoted = !quoted;
+        }
+
+        if c != '!' || quoted {
+            out.push( c );
+            i += 1;
+            continue;
+        }
+
+        let ( entry, len ) = match chars.get( i + 1 ) {
+            Some( '!' ) => ( entries.last(), 2 ),
+            Some( x ) if x.is_ascii_digit() || *x == '-' => {
+                let back = *x == '-';
+                let start = if back { i + 2 } else { i + 1 };
+                let digits: String = chars[start..].iter().take_while( | x | x.is_ascii_digit() ).collect();
+                if digits.is_empty() {
+                    out.push( c );
+                    i += 1;
+                    continue;
+                }
+
+                let n: usize = digits.parse().map_err( | _ | format!( "!{}: event not found", digits ) )?;
+                let entry = match ( back, n ) {
+                    ( _, 0 ) => None,
+                    ( true, n ) => entries.len().checked_sub( n ).and_then( | x | entries.get( x ) ),
+                    ( false, n ) => entries.get( n - 1 ),
+                };
+
+                ( entry, start - i + digits.len() )
+            },
+
+            _ => {
+                out.push( c );
+                i += 1;
+                continue;
+            },
+        };
+
+        match entry {
+            Some( x ) => out.push_str( x ),
+            None => return Err( format!( "{}: event not found", chars[i .. i + len].iter().collect::<String>() ) ),
+        }
+
+        changed = true;
+        i += len;
+    }
+
+    Ok( if changed { Some( out ) } else { None } )
+}
@@ -0,0 +1,45 @@
+use std::io::{ stdout, Write };
+use std::path::Path;
+use whoami::host;
+
+// Escape sequences that tell the terminal where prompts, commands and their
+// output are, and which directory the shell is in, so it can jump between
+// prompts and open new tabs in the same place. Terminals that don't know
+// them leave them out.
+
+fn osc( body: &str ) {
+    print!( "\x1B]{}\x07", body );
+    let _ = stdout().flush();
+}
+
+// Marks where the prompt starts and where what's typed after it starts.
+pub fn mark_prompt( prompt: &str ) -> String {
+    format!( "\x1B]133;A\x07{}\x1B]133;B\x07", prompt )
+}
+
+// Sent once the line is accepted, just before the command's output.
+pub fn command_started() {
+    osc( "133;C" );
+}
+
+pub fn command_finished( code: i32 ) {
+    osc( &format!( "133;D;{}", code ) );
+}
+
+// Reports `dir` as a file:// URL, with anything that isn't safe in one escaped.
+pub fn report_cwd( dir: &Path ) {
+    let path = dir.to_string_lossy().replace( '\\', "/" );
+    let mut url = format!( "file://{}", host() );
+    if !path.starts_with( '/' ) {
+        url.push( '/' );
+    }
+
+    for b in path.bytes() {
+        match b {
+            b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => url.push( b as char ),
+            _ => url.push_str( &format!( "%{:02X}", b ) ),
+        }
+    }
+
+    osc( &format!( "7;{}", url ) );
+}
@@ -0,0 +1,171 @@
+use std::io::Result;
+use std::process::Child;
+use std::sync::Mutex;
+use kernel::{ process_status, resume_process, send_signal, signal_number, wait_or_stop, Foreground, ProcessStatus };
+
+// status of a foreground job stopped with Ctrl-Z, 128 + SIGTSTP like other shells
+pub const STOPPED: i32 = 148;
+
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+
+    // suspended, e.g. with Ctrl-Z, until `fg` or `bg` resumes it
+    pub stopped: bool,
+
+    // every process in the job's pipeline, the last one decides its exit status
+    children: Vec<Child>,
+}
+
+impl Job {
+    pub fn pids( &self ) -> Vec<u32> {
+        self.children.iter().map( | x | x.id() ).collect()
+    }
+
+    // Returns the exit status once every process in the job has exited.
+    fn poll( &mut self ) -> Result<Option<i32>> {
+        let mut code = Some( 0 );
+        for child in self.children.iter_mut() {
+            match child.try_wait()? {
+                Some( status ) => code = Some( process_status( status ).code().unwrap_or( 1 ) ),
+                None => return Ok( None ),
+            }
+        }
+
+        Ok( code )
+    }
+
+    // Continues the job with the terminal handed to it, and waits for it like
+    // any other foreground command.
+    pub fn foreground( mut self ) -> Result<ProcessStatus> {
+        let _guards: Vec<Foreground> = self.pids().into_iter().map( Foreground::new ).collect();
+        self.resume()?;
+
+        wait_foreground( self.command, self.children )
+    }
+
+    // Sends `signal` to every process in the job. A stopped job is continued
+    // afterwards, since it wouldn't act on the signal until then.
+    pub fn signal( &mut self, signal: i32 ) -> Result<()> {
+        for child in &self.children {
+            send_signal( child.id(), signal )?;
+        }
+
+        let stops = [ "STOP", "TSTP" ].iter().any( | x | signal_number( x ) == Some( signal ) );
+        if stops {
+            self.stopped = true;
+        } else if self.stopped {
+            self.resume()?;
+        }
+
+        Ok( () )
+    }
+
+    pub fn resume( &mut self ) -> Result<()> {
+        for child in &self.children {
+            resume_process( child.id() )?;
+        }
+
+        self.stopped = false;
+        Ok( () )
+    }
+}
+
+// Waits for the processes of a foreground pipeline and returns the exit status of
+// the last one. If they're stopped instead they become a job, so the shell can
+// carry on and they can be resumed later with `fg` or `bg`.
+pub fn wait_foreground( command: String, children: Vec<Child> ) -> Result<ProcessStatus> {
+    Ok( match wait_foreground_each( command, children )? {
+        Some( statuses ) => statuses.last().cloned().unwrap_or( ProcessStatus::Exited( 0 ) ),
+        None => ProcessStatus::Exited( STOPPED ),
+    } )
+}
+
+// Like wait_foreground, but with every process's status in order. None means
+// they were stopped and are now a job.
+pub fn wait_foreground_each( command: String, mut children: Vec<Child> ) -> Result<Option<Vec<ProcessStatus>>> {
+    let mut statuses = Vec::new();
+    for i in 0 .. children.len() {
+        match wait_or_stop( &mut children[i] )? {
+            Some( status ) => statuses.push( process_status( status ) ),
+            None => {
+                let mut table = JOBS.lock().unwrap();
+                let job = table.add( command, children );
+                job.stopped = true;
+
+                eprintln!();
+                eprintln!( "[{}] Stopped  {}", job.id, job.command );
+                return Ok( None );
+            }
+        }
+    }
+
+    Ok( Some( statuses ) )
+}
+
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+
+lazy_static! {
+    pub static ref JOBS: Mutex<JobTable> = Mutex::new( JobTable { jobs: Vec::new() } );
+}
+
+impl JobTable {
+    pub fn add( &mut self, command: String, children: Vec<Child> ) -> &mut Job {
+        let id = self.jobs.iter().map( | x | x.id ).max().unwrap_or( 0 ) + 1;
+        self.jobs.push( Job { id, command, stopped: false, children } );
+        self.jobs.last_mut().unwrap()
+    }
+
+    pub fn jobs( &self ) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get( &mut self, id: Option<usize> ) -> Option<&mut Job> {
+        match id {
+            Some( id ) => self.jobs.iter_mut().find( | x | x.id == id ),
+            None => self.jobs.last_mut(),
+        }
+    }
+
+    // Removes a job from the table, e.g. to bring it to the foreground. Without an
+    // id the most recently started job is taken.
+    pub fn take( &mut self, id: Option<usize> ) -> Option<Job> {
+        let index = match id {
+            Some( id ) => self.jobs.iter().position( | x | x.id == id )?,
+            None => self.jobs.len().checked_sub( 1 )?,
+        };
+
+        Some( self.jobs.remove( index ) )
+    }
+
+    // Forgets jobs that have finished, returning them with their exit status.
+    pub fn reap( &mut self ) -> Vec<( Job, i32 )> {
+        let mut done = Vec::new();
+        let mut i = 0;
+        while i < self.jobs.len() {
+            match self.jobs[i].poll() {
+                Ok( Some( code ) ) => done.push( ( self.jobs.remove( i ), code ) ),
+                Err( _ ) => done.push( ( self.jobs.remove( i ), 1 ) ),
+                Ok( None ) => i += 1,
+            }
+        }
+
+        done
+    }
+}
+
+// What's said about a job once it has finished, e.g. "[1] done: make -j8",
+// or "[2] exit 2: cargo test" when it failed.
+pub fn done_message( job: &Job, code: i32 ) -> String {
+    match code {
+        0 => format!( "[{}] done: {}", job.id, job.command ),
+        x => format!( "[{}] exit {}: {}", job.id, x, job.command ),
+    }
+}
+
+// Accepts job ids written either as `2` or `%2`.
+pub fn parse_id( s: &str ) -> Option<usize> {
+    s.trim_start_matches( '%' ).parse().ok()
+}
@@ -0,0 +1,34 @@
+use std::env::{ current_exe, set_var, var_os };
+use dirs::home_dir;
+use kernel::DEFAULT_PATH;
+
+// Shells started by login(1), sshd and the like are told to act as a login
+// shell by a leading dash on their name, e.g. "-lumi".
+pub fn is_login_name( argv0: &str ) -> bool {
+    argv0.starts_with( '-' )
+}
+
+// Fills in what the rest of the shell expects to find in the environment. A
+// login shell may be started with next to nothing, so HOME and PATH get
+// defaults if they're missing and SHELL is pointed at this program.
+pub fn prepare_environment( login: bool ) {
+    set_var( "LUMI_VERSION", env!( "CARGO_PKG_VERSION" ) );
+
+    if var_os( "PATH" ).is_none_or( | x | x.is_empty() ) {
+        set_var( "PATH", DEFAULT_PATH );
+    }
+
+    // Windows programs find the home directory through the user profile
+    // instead, and some of them behave differently if HOME is set
+    if cfg!( not( windows ) ) && var_os( "HOME" ).is_none_or( | x | x.is_empty() ) {
+        if let Some( home ) = home_dir() {
+            set_var( "HOME", home );
+        }
+    }
+
+    if login {
+        if let Ok( exe ) = current_exe() {
+            set_var( "SHELL", exe );
+        }
+    }
+}
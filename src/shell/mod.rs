@@ -1,2 +1,33 @@
 pub mod segments;
+pub mod builtins;
+pub mod condition;
 pub mod parsing;
+pub mod remote;
+pub mod completion;
+pub mod editor;
+pub mod render;
+pub mod history;
+pub mod sqlite_history;
+pub mod frecency;
+pub mod expand;
+pub mod glob;
+pub mod path;
+pub mod fuzzy;
+pub mod suggest;
+pub mod pager;
+pub mod tilde;
+pub mod secret;
+pub mod config;
+pub mod prompt;
+pub mod git;
+pub mod repl;
+pub mod context;
+pub mod jobs;
+pub mod script;
+pub mod testing;
+pub mod transcript;
+pub mod diagnostics;
+pub mod login;
+pub mod integration;
+pub mod profile;
+pub mod structured;
@@ -0,0 +1,119 @@
+use std::io::{ stdin, stdout, Result, Write };
+use std::process::Stdio;
+use std::sync::Mutex;
+use crossterm::terminal;
+use yansi::Paint;
+use kernel::{ enable_raw_mode, disable_raw_mode, stdin_is_tty, stdout_is_tty };
+use shell::editor::{ Key, KeyReader };
+use shell::path;
+
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub enum Pager {
+    // the one built in, moved through with j/k, space/b and g/G and left with q
+    Builtin,
+
+    // a program given the output on its stdin, e.g. "less -R"
+    Command( String ),
+
+    // output always goes straight to the terminal
+    Off,
+}
+
+lazy_static! {
+    // only the interactive shell pages; scripts print everything as it is
+    static ref PAGER: Mutex<Pager> = Mutex::new( Pager::Off );
+}
+
+pub fn set_pager( pager: Pager ) {
+    *PAGER.lock().unwrap() = pager;
+}
+
+// Shows `lines` in the pager if they won't fit on the screen. Returns false,
+// leaving them to be printed as usual, when there's no need or no pager.
+pub fn page( lines: &[String] ) -> Result<bool> {
+    let pager = PAGER.lock().unwrap().clone();
+    if pager == Pager::Off || !stdin_is_tty() || !stdout_is_tty() {
+        return Ok( false );
+    }
+
+    let ( _, height ) = terminal().terminal_size();
+    if height == 0 || lines.len() < height as usize {
+        return Ok( false );
+    }
+
+    if let Pager::Command( ref command ) = pager {
+        // a pager that can't be started is no reason to lose the output
+        if run_command( command, lines ).is_ok() {
+            return Ok( true );
+        }
+    }
+
+    show( lines, height as usize )?;
+    Ok( true )
+}
+
+fn run_command( command: &str, lines: &[String] ) -> Result<()> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let mut proc = path::command( name ).ok_or_else( || std::io::Error::from( std::io::ErrorKind::NotFound ) )?;
+    proc.args( words ).stdin( Stdio::piped() );
+
+    let mut child = proc.spawn()?;
+    if let Some( mut input ) = child.stdin.take() {
+        for line in lines {
+            // the pager may be quit before it has read everything
+            if writeln!( input, "{}", line ).is_err() { break; }
+        }
+    }
+
+    child.wait()?;
+    Ok( () )
+}
+
+// The built-in pager, drawn on the alternate screen with line wrapping off so
+// long lines are cut at the edge rather than pushing the rest down.
+fn show( lines: &[String], height: usize ) -> Result<()> {
+    let rows = height - 1;
+    let last = lines.len().saturating_sub( rows );
+    let mut top = 0;
+
+    let mut out = stdout();
+    write!( out, "\x1B[?1049h\x1B[?7l" )?;
+
+    unsafe { enable_raw_mode()?; }
+    let res = ( || -> Result<()> {
+        let stdin = stdin();
+        let mut keys = KeyReader::new( stdin.lock() );
+        loop {
+            write!( out, "\x1B[H" )?;
+            for line in lines.iter().skip( top ).take( rows ) {
+                write!( out, "{}\x1B[K\r\n", line )?;
+            }
+
+            for _ in lines.len().saturating_sub( top ) .. rows {
+                write!( out, "~\x1B[K\r\n" )?;
+            }
+
+            let status = format!( " lines {}-{} of {}  (j/k, space/b, g/G, q to quit) ", top + 1, ( top + rows ).min( lines.len() ), lines.len() );
+            write!( out, "{}\x1B[K", Paint::new( status ).invert() )?;
+            out.flush()?;
+
+            top = match keys.next_key()? {
+                Some( Key::Char( 'j' ) ) | Some( Key::Down ) | Some( Key::Enter ) => ( top + 1 ).min( last ),
+                Some( Key::Char( 'k' ) ) | Some( Key::Up ) => top.saturating_sub( 1 ),
+                Some( Key::Char( ' ' ) ) | Some( Key::Char( 'f' ) ) => ( top + rows ).min( last ),
+                Some( Key::Char( 'b' ) ) => top.saturating_sub( rows ),
+                Some( Key::Char( 'g' ) ) | Some( Key::Home ) => 0,
+                Some( Key::Char( 'G' ) ) | Some( Key::End ) => last,
+                Some( Key::Char( 'q' ) ) | Some( Key::Interrupt ) | Some( Key::EndOfFile ) | None => return Ok( () ),
+                _ => top,
+            };
+        }
+    } )();
+
+    unsafe { disable_raw_mode()?; }
+    write!( out, "\x1B[?7h\x1B[?1049l" )?;
+    out.flush()?;
+
+    res
+}
@@ -1,56 +1,147 @@
 use parsing::*;
-use std::mem::discriminant;
-use std::string::ToString;
+use std::fmt::{ Display, Formatter };
 use shell::segments::*;
-use std::collections::{ HashSet, HashMap };
+use shell::glob;
+use std::collections::HashSet;
+use std::rc::Rc;
 
 #[derive( Debug, Clone, Eq, PartialEq, Hash )]
 pub enum ShellTokenKind {
-    String( String ),
-    Interp( Vec<ShellToken> ),
+    // payloads are reference counted so tokens are cheap to hand out of a TokenStream
+    String( Rc<str> ),
+    // an unquoted word with wildcards or a leading ~, expanded when it runs
+    Pattern( Rc<str> ),
+    Interp( Rc<[ShellToken]> ),
+
+    // a word that's only a $(...), whose output is split into arguments
+    Subst( Rc<[ShellToken]> ),
+    Keyword( Keyword ),
+
+    // a word starting with an unquoted NAME=, with the name and the whole word;
+    // before a command it sets a variable for that command, elsewhere it's the word
+    Assign( Rc<str>, Rc<ShellToken> ),
 
     Dollar,
     Semi,
+
+    // ends a command the same as ; does, but is skipped where a command carries on
+    Newline,
     Amp,
     Pipe,
 
+    // |&
+    PipeBoth,
+    AndAnd,
+    OrOr,
+
     // <
     StdIn,
 
     // >
     StdOut,
 
-    // >>
+    // 2>
     StdErr,
 
-    // >>>
+    // <<<
+    HereString,
+
+    // <<WORD, followed by the body as a String or Interp token
+    HereDoc,
+
+    // &>
     StdBoth,
 
+    // >>
+    AppendOut,
+
+    // 2>>
+    AppendErr,
+
+    // &>>
+    AppendBoth,
+
     LParen,
     RParen,
 
+    // { and }, which group commands
+    LBrace,
+    RBrace,
+
     EndOfInput,
 }
 
-impl ToString for ShellTokenKind {
-    fn to_string( &self ) -> String {
-        use ShellTokenKind::*;
+impl Display for ShellTokenKind {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        use self::ShellTokenKind::*;
+
+        match self {
+            String( x ) => formatter.write_str( x ),
+            Pattern( x ) => formatter.write_str( x ),
+            Interp( _ ) => formatter.write_str( "string interpolation" ),
+            Subst( _ ) => formatter.write_str( "command substitution" ),
+            Keyword( x ) => formatter.write_str( x.as_str() ),
+            Assign( _, word ) => word.fmt( formatter ),
+
+            Dollar => formatter.write_str( "$" ),
+            Semi => formatter.write_str( ";" ),
+            Newline => formatter.write_str( "newline" ),
+            Amp => formatter.write_str( "&" ),
+            Pipe => formatter.write_str( "|" ),
+            PipeBoth => formatter.write_str( "|&" ),
+            AndAnd => formatter.write_str( "&&" ),
+            OrOr => formatter.write_str( "||" ),
+            StdIn => formatter.write_str( "<" ),
+            StdOut => formatter.write_str( ">" ),
+            StdErr => formatter.write_str( "2>" ),
+            HereString => formatter.write_str( "<<<" ),
+            HereDoc => formatter.write_str( "<<" ),
+            StdBoth => formatter.write_str( "&>" ),
+            AppendOut => formatter.write_str( ">>" ),
+            AppendErr => formatter.write_str( "2>>" ),
+            AppendBoth => formatter.write_str( "&>>" ),
+            LParen => formatter.write_str( "(" ),
+            RParen => formatter.write_str( ")" ),
+            LBrace => formatter.write_str( "{" ),
+            RBrace => formatter.write_str( "}" ),
+            EndOfInput => formatter.write_str( "<end-of-input>" ),
+        }
+    }
+}
+
+// Unquoted words with a meaning in the grammar. They're only keywords at the
+// start of a command; as arguments they're ordinary text, e.g. `echo if`.
+#[derive( Debug, Clone, Copy, Eq, PartialEq, Hash )]
+pub enum Keyword {
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Par,
+}
+
+impl Keyword {
+    fn named( word: &str ) -> Option<Keyword> {
+        match word {
+            "if" => Some( Keyword::If ),
+            "else" => Some( Keyword::Else ),
+            "while" => Some( Keyword::While ),
+            "for" => Some( Keyword::For ),
+            "in" => Some( Keyword::In ),
+            "par" => Some( Keyword::Par ),
+            _ => None,
+        }
+    }
 
+    pub fn as_str( &self ) -> &'static str {
         match self {
-            String( x ) => x.clone(),
-            Interp( _ ) => "string interpolation".to_string(),
-
-            Dollar => "$".to_string(),
-            Semi => ";".to_string(),
-            Amp => "&".to_string(),
-            Pipe => "|".to_string(),
-            StdIn => "<".to_string(),
-            StdOut => ">".to_string(),
-            StdErr => ">>".to_string(),
-            StdBoth => ">>>".to_string(),
-            LParen => "(".to_string(),
-            RParen => ")".to_string(),
-            EndOfInput => "<end-of-input>".to_string(),
+            Keyword::If => "if",
+            Keyword::Else => "else",
+            Keyword::While => "while",
+            Keyword::For => "for",
+            Keyword::In => "in",
+            Keyword::Par => "par",
         }
     }
 }
@@ -61,9 +152,9 @@ pub struct ShellToken {
     span: TextSpan,
 }
 
-impl ToString for ShellToken {
-    fn to_string( &self ) -> String {
-        self.kind.to_string()
+impl Display for ShellToken {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        self.kind.fmt( formatter )
     }
 }
 
@@ -79,36 +170,253 @@ impl SyntaxToken for ShellToken {
     }
 }
 
-#[derive( Clone )]
-pub struct ShellLexer {
-    scanner: Scanner,
-    mode: LexerMode,
+// Escaping rules
+//
+// Outside quotes a backslash takes the special meaning away from the character
+// after it, so `foo\ bar` is one word and `\*`, `\$HOME` and `\;` are literal.
+// That works for whitespace, operators, quotes, wildcards, `~` and `\` itself;
+// before any other character the backslash is kept, so Windows paths such as
+// C:\Users\me need no quoting.
+//
+// Inside quotes `\\`, `\{`, `\}` and an escaped closing quote stand for the
+// character itself. Double quotes, backticks and here-documents also take `\$`,
+// and double quotes alone know `\n`, `\t`, `\r`, `\0` and `\e` (escape). Any
+// other backslash in quotes is kept as it is.
+
+// The pieces a word is made of, joined once the word ends.
+enum WordPart {
+    // unquoted text, where wildcards and a leading ~ keep their meaning
+    Literal( String ),
+
+    // a character after a backslash
+    Escaped( char ),
+
+    // a quoted string with nothing to expand
+    Quoted( String ),
+
+    // the pieces of a quoted string with variables or {...} in it
+    Interp( Rc<[ShellToken]> ),
+
+    // $name, ${name} or $?
+    Var( ShellToken ),
+
+    // $(...), whose output becomes part of the word
+    Subst( ShellToken ),
+}
+
+// The token for a whole word: text when there's nothing to expand, a pattern when
+// it has unquoted wildcards or starts with an unquoted ~, otherwise an interpolation.
+fn word_kind( parts: Vec<WordPart>, span: &TextSpan ) -> ShellTokenKind {
+    match parts[..] {
+        [ WordPart::Literal( ref x ) ] => if let Some( keyword ) = Keyword::named( x ) {
+            return ShellTokenKind::Keyword( keyword );
+        },
+
+        [ WordPart::Subst( ShellToken { kind: ShellTokenKind::Interp( ref tks ), .. } ) ] => return ShellTokenKind::Subst( tks.clone() ),
+        _ => {},
+    }
+
+    let is_dynamic = parts.iter().any( | x | matches!( x, WordPart::Interp( _ ) | WordPart::Var( _ ) | WordPart::Subst( _ ) ) );
+    if is_dynamic {
+        let mut tokens = Vec::new();
+        let mut buf = String::new();
+        for part in parts {
+            let pieces = match part {
+                WordPart::Literal( x ) | WordPart::Quoted( x ) => { buf.push_str( &x ); continue; },
+                WordPart::Escaped( x ) => { buf.push( x ); continue; },
+                WordPart::Interp( tks ) => tks.to_vec(),
+                WordPart::Var( tk ) | WordPart::Subst( tk ) => vec![ tk ],
+            };
+
+            if !buf.is_empty() {
+                tokens.push( ShellToken { kind: ShellTokenKind::String( buf.as_str().into() ), span: span.clone() } );
+                buf.clear();
+            }
+
+            tokens.extend( pieces );
+        }
+
+        if !buf.is_empty() {
+            tokens.push( ShellToken { kind: ShellTokenKind::String( buf.into() ), span: span.clone() } );
+        }
+
+        return ShellTokenKind::Interp( tokens.into() );
+    }
+
+    let is_pattern = parts.iter().any( | x | matches!( x, WordPart::Literal( x ) if glob::is_pattern( x ) ) ) ||
+        matches!( parts.first(), Some( WordPart::Literal( x ) ) if x.starts_with( '~' ) );
+
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            // a backslash kept in unquoted text is literal in a pattern too
+            WordPart::Literal( x ) if is_pattern => text.push_str( &x.replace( '\\', "\\\\" ) ),
+            WordPart::Literal( x ) => text.push_str( &x ),
+            WordPart::Quoted( x ) if is_pattern => text.push_str( &glob::escape( &x ) ),
+            WordPart::Quoted( x ) => text.push_str( &x ),
+            WordPart::Escaped( x ) if is_pattern => text.push_str( &glob::escape( &x.to_string() ) ),
+            WordPart::Escaped( x ) => text.push( x ),
+            WordPart::Interp( _ ) | WordPart::Var( _ ) | WordPart::Subst( _ ) => unreachable!(),
+        }
+    }
+
+    if is_pattern {
+        ShellTokenKind::Pattern( text.into() )
+    } else {
+        ShellTokenKind::String( text.into() )
+    }
+}
+
+// The NAME in text starting with NAME=, where the name is a valid variable name.
+fn assignment_name( text: &str ) -> Option<&str> {
+    let ( name, _ ) = text.split_once( '=' )?;
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    if ( first.is_alphabetic() || first == '_' ) && chars.all( | c | c.is_alphanumeric() || c == '_' ) {
+        Some( name )
+    } else {
+        None
+    }
+}
+
+// The word an assignment token holds, less its NAME= at the front.
+fn assignment_value( name: &str, word: &ShellToken ) -> ShellToken {
+    let strip = | x: &str | x[( name.len() + 1 )..].to_string();
+    let kind = match word.kind() {
+        ShellTokenKind::String( x ) => ShellTokenKind::String( strip( x ).into() ),
+        ShellTokenKind::Pattern( x ) => ShellTokenKind::Pattern( strip( x ).into() ),
+        ShellTokenKind::Interp( tks ) => {
+            // the text before the first variable is always a String token
+            let mut tks = tks.to_vec();
+            let rest = match tks[0].kind() {
+                ShellTokenKind::String( x ) => strip( x ),
+                _ => unreachable!(),
+            };
+
+            if rest.is_empty() {
+                tks.remove( 0 );
+            } else {
+                tks[0].kind = ShellTokenKind::String( rest.into() );
+            }
+
+            ShellTokenKind::Interp( tks.into() )
+        },
+
+        _ => unreachable!(),
+    };
+
+    ShellToken { kind, span: word.span.clone() }
+}
+
+// What `\c` stands for inside a string closed by `term`, or None when the
+// backslash is kept.
+fn quoted_escape( term: Option<char>, c: char ) -> Option<char> {
+    match ( term, c ) {
+        ( _, '\\' ) | ( _, '{' ) | ( _, '}' ) => Some( c ),
+        ( Some( t ), _ ) if t == c => Some( c ),
+        ( Some( '\'' ), _ ) => None,
+        ( _, '$' ) => Some( c ),
+        ( Some( '"' ), 'n' ) => Some( '\n' ),
+        ( Some( '"' ), 't' ) => Some( '\t' ),
+        ( Some( '"' ), 'r' ) => Some( '\r' ),
+        ( Some( '"' ), '0' ) => Some( '\0' ),
+        ( Some( '"' ), 'e' ) => Some( '\x1B' ),
+        _ => None,
+    }
+}
+
+pub struct ShellLexer<'a> {
+    scanner: Scanner<'a>,
+    modes: Vec<LexerMode>,
     special: HashSet<char>,
-    punct: HashMap<&'static str, ShellTokenKind>,
+    punct: Vec<( &'static str, ShellTokenKind )>,
+
+    // delimiter and whether the body is interpolated, for the last <<WORD lexed
+    heredoc: Option<( String, bool )>,
+}
+
+// How deeply blocks, groups and interpolations may nest. The lexer, parser
+// and segments all recurse into them, and this stays well short of where
+// that would run out of stack.
+pub const MAX_DEPTH: usize = 100;
+
+const HEREDOC_UNTERMINATED: &str = "here-document does not terminate";
+const STRING_UNTERMINATED: &str = "string does not terminate";
+const INTERP_UNTERMINATED: &str = "string interpolation does not terminate";
+
+// Whether `source` stops partway through a command that carries on over the
+// following lines: inside a here-document, a quoted string or a `{` block,
+// after an operator such as `|` or `&&`, or after a backslash ending the line.
+pub fn needs_more_input( source: &str ) -> bool {
+    let tokens = match ShellLexer::new( source ).tokenize() {
+        Err( e ) => return match e.kind() {
+            LexErrorKind::UnexpectedEOI { reason } => [ HEREDOC_UNTERMINATED, STRING_UNTERMINATED, INTERP_UNTERMINATED ].contains( reason ),
+            _ => false,
+        },
+
+        Ok( x ) => x,
+    };
+
+    let backslashes = source.chars().rev().take_while( | c | *c == '\\' ).count();
+    if backslashes % 2 == 1 {
+        return true;
+    }
+
+    // the parser ran out of tokens where it needed more
+    let end = ShellTokenKind::EndOfInput.to_string();
+    match ShellParser::new( tokens ).parse_all() {
+        Err( e ) => match e.kind() {
+            ParseErrorKind::UnexpectedEOI => true,
+            ParseErrorKind::Unexpected { found, .. } | ParseErrorKind::ExpectSegment { found } => *found == end,
+            _ => false,
+        },
+
+        Ok( _ ) => false,
+    }
 }
 
 #[derive( Debug, Clone, Eq, PartialEq )]
 enum LexerMode {
     Normal,
+
+    // "{...}", ends at the closing brace
     Interp,
+
+    // "$(...)", ends at the closing parenthesis
+    Subst,
 }
 
-impl ShellLexer {
-    pub fn new( source: String ) -> ShellLexer {
+impl<'a> ShellLexer<'a> {
+    pub fn new( source: &'a str ) -> ShellLexer<'a> {
         use self::ShellTokenKind::*;
 
-        let mut punct = HashMap::new();
-        punct.insert( "$", Dollar );
-        punct.insert( ";", Semi );
-        punct.insert( "&", Amp );
-        punct.insert( "|", Pipe );
-        punct.insert( "(", LParen );
-        punct.insert( ")", RParen );
-        punct.insert( "<", StdIn );
-        // these must be kept sorted by length in descending order
-        punct.insert( ">>>", StdBoth );
-        punct.insert( ">>", StdErr );
-        punct.insert( ">", StdOut );
+        let mut punct = vec![
+            ( "$", Dollar ),
+            ( ";", Semi ),
+            ( "&", Amp ),
+            ( "|", Pipe ),
+            ( "|&", PipeBoth ),
+            ( "&&", AndAnd ),
+            ( "||", OrOr ),
+            ( "(", LParen ),
+            ( ")", RParen ),
+            ( "{", LBrace ),
+            ( "}", RBrace ),
+            ( "<", StdIn ),
+            ( "<<<", HereString ),
+            ( ">", StdOut ),
+            ( "1>", StdOut ),
+            ( "2>", StdErr ),
+            ( "&>", StdBoth ),
+            ( ">>", AppendOut ),
+            ( "1>>", AppendOut ),
+            ( "2>>", AppendErr ),
+            ( "&>>", AppendBoth ),
+        ];
+
+        // longest operators are tried first, so "<<<" is a here-string rather than
+        // three "<", and "2>>" or "&>>" append rather than lexing as "2>" ">"
+        punct.sort_by_key( | ( op, _ ) | std::cmp::Reverse( op.len() ) );
 
         let mut special = HashSet::new();
         special.insert( '$' );
@@ -127,39 +435,92 @@ impl ShellLexer {
 
         ShellLexer {
             scanner: Scanner::new( source, 0, 1, 1 ),
-            mode: LexerMode::Normal,
+            modes: vec![ LexerMode::Normal ],
             special,
             punct,
+            heredoc: None,
         }
     }
 
     pub fn tokenize( &mut self ) -> Result<Vec<ShellToken>, LexError> {
         let tokenizers = &[
             ShellLexer::try_lex_quoted,
+            ShellLexer::try_lex_heredoc,
+            ShellLexer::try_lex_variable,
             ShellLexer::try_lex_punct,
             ShellLexer::try_lex_unquoted,
         ];
 
         let mut tokens = Vec::new();
+
+        // here-documents waiting for their body: delimiter, interpolation, body token index
+        let mut heredocs: Vec<( String, bool, usize )> = Vec::new();
+
+        // brackets opened inside a "{...}" or "$(...)", which don't end it when they close
+        let mut braces: usize = 0;
+        let mut parens: usize = 0;
         while !self.scanner.is_empty() {
-            self.scanner.skip_while( | c | c.is_whitespace() );
+            self.scanner.skip_while( | c | c.is_whitespace() && c != '\n' );
 
             if self.scanner.is_empty() { break; }
 
             let c = self.scanner.peek().unwrap();
-            if self.mode == LexerMode::Interp && c == '}' {
-                break;
+            if c == '\n' {
+                self.scanner.push_mark();
+                self.scanner.consume();
+                let span = self.scanner.pop_span().unwrap();
+                for ( delimiter, interpolate, slot ) in heredocs.drain( .. ) {
+                    tokens[slot] = self.lex_heredoc_body( &delimiter, interpolate )?;
+                }
+
+                tokens.push( ShellToken { kind: ShellTokenKind::Newline, span } );
+                continue;
+            }
+
+            // a backslash at the end of a line joins it to the next
+            if c == '\\' && self.at_line_continuation( 1 ) {
+                self.skip_line_continuation();
+                continue;
+            }
+
+            // comments run from a # at the start of a word to the end of the line
+            if c == '#' {
+                self.scanner.skip_while( | c | c != '\n' );
+                continue;
+            }
+
+            match self.modes.last() {
+                Some( LexerMode::Interp ) if c == '}' && braces == 0 => break,
+                Some( LexerMode::Subst ) if c == ')' && parens == 0 => break,
+                _ => {},
             }
 
             let mut found = false;
             for tokenizer in tokenizers {
                 if let Some( token ) = tokenizer( self, c )? {
+                    match token.kind {
+                        ShellTokenKind::LParen => parens += 1,
+                        ShellTokenKind::RParen => parens = parens.saturating_sub( 1 ),
+                        ShellTokenKind::LBrace => braces += 1,
+                        ShellTokenKind::RBrace => braces = braces.saturating_sub( 1 ),
+                        _ => {},
+                    }
+
                     tokens.push( token );
                     found = true;
                     break;
                 }
             }
 
+            // the body only starts on the next line, so its token is filled in later
+            if let Some( ( delimiter, interpolate ) ) = self.heredoc.take() {
+                heredocs.push( ( delimiter, interpolate, tokens.len() ) );
+                tokens.push( ShellToken {
+                    kind: ShellTokenKind::String( "".into() ),
+                    span: tokens.last().unwrap().span.clone(),
+                } );
+            }
+
             if !found {
                 self.scanner.push_mark();
                 return Err( LexError::unexpected_char( c, self.scanner.pop_span().unwrap() ) );
@@ -168,6 +529,10 @@ impl ShellLexer {
 
         self.scanner.push_mark();
         let span = self.scanner.pop_span().unwrap();
+        if !heredocs.is_empty() {
+            return Err( LexError::unexpected_eoi( HEREDOC_UNTERMINATED, span ) );
+        }
+
         tokens.push( ShellToken {
             kind: ShellTokenKind::EndOfInput,
             span,
@@ -177,18 +542,171 @@ impl ShellLexer {
     }
 
     fn try_lex_unquoted( &mut self, c: char ) -> Result<Option<ShellToken>, LexError> {
-        let special = &self.special;
-        if c.is_whitespace() || c.is_control() || special.contains( &c ) {
+        if c.is_whitespace() || c.is_control() || self.special.contains( &c ) {
             return Ok( None );
         }
 
+        self.lex_word().map( Some )
+    }
+
+    // A word starting with a variable or command substitution, like $HOME/bin,
+    // ${name} or $(pwd)/bin. A variable on its own is left to the Dollar token,
+    // since `... | $name` assigns to it.
+    fn try_lex_variable( &mut self, c: char ) -> Result<Option<ShellToken>, LexError> {
+        let is_subst = c == '$' && self.scanner.peek_ahead( 1 ) == Some( '(' );
+        if !is_subst && ( c != '$' || !self.at_variable() || self.at_bare_variable() ) {
+            return Ok( None );
+        }
+
+        self.lex_word().map( Some )
+    }
+
+    // A word: unquoted text, escaped characters, quoted strings, variables and
+    // command substitutions with nothing between them, so `pre"$x"post` and
+    // `NAME=$(cmd)` are each a single argument.
+    fn lex_word( &mut self ) -> Result<ShellToken, LexError> {
         self.scanner.push_mark();
-        let s = self.scanner.take_while( | c | !c.is_whitespace() && !c.is_control() && !special.contains( &c ) );
+        let mut parts = Vec::new();
+        while let Some( c ) = self.scanner.peek() {
+            match c {
+                '\\' if self.at_line_continuation( 1 ) => self.skip_line_continuation(),
+                '\\' => {
+                    self.scanner.consume();
+                    match self.scanner.peek().filter( | x | self.is_escapable( *x ) ) {
+                        Some( x ) => {
+                            self.scanner.consume();
+                            parts.push( WordPart::Escaped( x ) );
+                        },
+
+                        None => parts.push( WordPart::Literal( "\\".to_string() ) ),
+                    }
+                },
+
+                '"' | '\'' | '`' => {
+                    self.scanner.consume();
+                    parts.push( match self.lex_template( Some( c ) )? {
+                        ShellTokenKind::Interp( tks ) => WordPart::Interp( tks ),
+                        kind => WordPart::Quoted( kind.to_string() ),
+                    } );
+                },
+
+                '$' if self.at_variable() => parts.push( WordPart::Var( self.lex_variable()? ) ),
+                '$' if self.scanner.peek_ahead( 1 ) == Some( '(' ) => parts.push( WordPart::Subst( self.lex_interp( "$(", LexerMode::Subst, ')' )? ) ),
+                _ if c.is_whitespace() || c.is_control() || self.special.contains( &c ) => break,
+                _ => {
+                    let special = &self.special;
+                    let text = self.scanner.take_while( | c | {
+                        !c.is_whitespace() && !c.is_control() && !special.contains( &c ) && c != '\\'
+                    } );
+
+                    parts.push( WordPart::Literal( text.to_string() ) );
+                },
+            }
+        }
+
         let span = self.scanner.pop_span().unwrap();
-        Ok( Some( ShellToken {
+        let name = match parts.first() {
+            Some( WordPart::Literal( x ) ) => assignment_name( x ).map( | x | x.to_string() ),
+            _ => None,
+        };
+
+        let word = ShellToken {
+            kind: word_kind( parts, &span ),
             span,
-            kind: ShellTokenKind::String( s ),
-        } ) )
+        };
+
+        Ok( match name {
+            Some( name ) => ShellToken {
+                kind: ShellTokenKind::Assign( name.into(), Rc::new( word.clone() ) ),
+                span: word.span,
+            },
+
+            None => word,
+        } )
+    }
+
+    // Whether a line ends `offset` characters ahead, allowing for \r\n.
+    fn at_line_continuation( &self, offset: usize ) -> bool {
+        match self.scanner.peek_ahead( offset ) {
+            Some( '\n' ) => true,
+            Some( '\r' ) => self.scanner.peek_ahead( offset + 1 ) == Some( '\n' ),
+            _ => false,
+        }
+    }
+
+    // Skips a backslash and the line break after it.
+    fn skip_line_continuation( &mut self ) {
+        self.scanner.consume();
+        self.scanner.take_if_next( "\r" );
+        self.scanner.consume();
+    }
+
+    // Outside quotes, the characters a backslash takes the meaning away from.
+    fn is_escapable( &self, c: char ) -> bool {
+        ( c.is_whitespace() && c != '\n' ) || self.special.contains( &c ) || matches!( c, '*' | '?' | '[' | '~' | '\\' )
+    }
+
+    // $name, ${name} or $?
+    fn at_variable( &self ) -> bool {
+        self.scanner.peek() == Some( '$' ) && self.scanner.peek_ahead( 1 ).is_some_and( | c | {
+            c.is_alphabetic() || c == '_' || c == '{' || c == '?'
+        } )
+    }
+
+    // Whether the variable ahead is a whole word by itself.
+    fn at_bare_variable( &self ) -> bool {
+        let mut i = 1;
+        match self.scanner.peek_ahead( i ) {
+            Some( '{' ) => return false,
+            Some( '?' ) => i += 1,
+            _ => while self.scanner.peek_ahead( i ).is_some_and( | c | c.is_alphanumeric() || c == '_' ) {
+                i += 1;
+            },
+        }
+
+        match self.scanner.peek_ahead( i ) {
+            Some( '$' ) | Some( '"' ) | Some( '\'' ) | Some( '`' ) | Some( '\\' ) => false,
+            Some( c ) => c.is_whitespace() || c.is_control() || self.special.contains( &c ),
+            None => true,
+        }
+    }
+
+    // Lexes a variable reference as an interpolation holding `$ name`, which
+    // parses to the same Var segment a bare $name does.
+    fn lex_variable( &mut self ) -> Result<ShellToken, LexError> {
+        self.scanner.push_mark();
+        self.scanner.push_mark();
+        self.scanner.consume();
+        let dollar = self.scanner.pop_span().unwrap();
+
+        self.scanner.push_mark();
+        let name = match self.scanner.peek() {
+            Some( '{' ) => {
+                self.scanner.consume();
+                let name = self.scanner.take_while( | c | c != '}' && c != '\n' );
+                if self.scanner.consume() != Some( '}' ) {
+                    return Err( LexError::unexpected_eoi( "variable name does not terminate", self.scanner.pop_span().unwrap() ) );
+                }
+
+                name
+            },
+
+            Some( '?' ) => self.scanner.take_if_next( "?" ).unwrap(),
+            _ => self.scanner.take_while( | c | c.is_alphanumeric() || c == '_' ),
+        };
+
+        let name_span = self.scanner.pop_span().unwrap();
+        let span = self.scanner.pop_span().unwrap();
+        let tokens = vec![
+            ShellToken { kind: ShellTokenKind::Dollar, span: dollar },
+            ShellToken { kind: ShellTokenKind::String( name.into() ), span: name_span },
+            ShellToken { kind: ShellTokenKind::EndOfInput, span: span.clone() },
+        ];
+
+        Ok( ShellToken {
+            kind: ShellTokenKind::Interp( tokens.into() ),
+            span,
+        } )
     }
 
     fn try_lex_quoted( &mut self, c: char ) -> Result<Option<ShellToken>, LexError> {
@@ -196,111 +714,190 @@ impl ShellLexer {
             return Ok( None );
         }
 
-        self.scanner.push_mark();
-        let term = self.scanner.consume().unwrap();
-        self.scanner.push_mark();
+        self.lex_word().map( Some )
+    }
+
+    // The inside of a quoted string, up to and including the closing quote; with no
+    // terminator (a here-document body) it runs to the end of the input instead.
+    fn lex_template( &mut self, term: Option<char> ) -> Result<ShellTokenKind, LexError> {
+        // variables are expanded everywhere except in single quotes
+        let expand_vars = term != Some( '\'' );
+
         let mut tokens = Vec::<ShellToken>::new();
         let mut buf = String::new();
-        let mut mark = false;
-        let mut take = false;
-        while !self.scanner.is_empty() && self.scanner.peek().unwrap() != c {
-            let c = self.scanner.peek().unwrap();
-
-            if mark {
-                self.scanner.push_mark();
-                mark = false;
+        self.scanner.push_mark();
+        while let Some( c ) = self.scanner.peek().filter( | x | Some( *x ) != term ) {
+            let is_subst = expand_vars && c == '$' && self.scanner.peek_ahead( 1 ) == Some( '(' );
+            let is_var = expand_vars && c == '$' && self.at_variable();
+            if c == '\\' && expand_vars && self.at_line_continuation( 1 ) {
+                self.skip_line_continuation();
                 continue;
             }
 
-            if take {
-                buf.push( self.scanner.consume().unwrap() );
-                take = false;
+            // there's nothing to run in an empty {}, so it's left as it is for
+            // commands like each that use it as a placeholder
+            if c == '{' && self.scanner.peek_ahead( 1 ) == Some( '}' ) {
+                self.scanner.take_if_next( "{}" );
+                buf.push_str( "{}" );
                 continue;
             }
 
-            match c {
-                '\\' => {
-                    if self.scanner.peek_ahead( 1 ).map_or( false, | c | c == term ) {
-                        self.scanner.consume().unwrap();
-                        take = true;
-                        continue;
-                    } else {
-                        take = true;
-                        continue;
-                    }
-                },
+            if c != '{' && !is_subst && !is_var {
+                self.scanner.consume();
+                match self.scanner.peek().filter( | _ | c == '\\' ).and_then( | x | quoted_escape( term, x ) ) {
+                    Some( x ) => {
+                        self.scanner.consume();
+                        buf.push( x );
+                    },
 
-                '{' => {
-                    let tk = ShellToken {
-                        span: self.scanner.pop_span().unwrap(),
-                        kind: ShellTokenKind::String( buf.clone() ),
-                    };
-
-                    buf.clear();
-                    tokens.push( tk );
-                    mark = true;
-
-                    let mut interp = self.clone();
-                    interp.mode = LexerMode::Interp;
-                    interp.scanner.push_mark();
-                    interp.scanner.consume().unwrap();
-
-                    let tks = interp.tokenize()?;
-                    if interp.scanner.is_empty() || interp.scanner.consume().unwrap() != '}' {
-                        return Err( LexError::unexpected_eoi(
-                            "string interpolation does not terminate",
-                            self.scanner.pop_span().unwrap(),
-                        ) );
-                    }
+                    None => buf.push( c ),
+                }
 
-                    let tk = ShellToken {
-                        span: interp.scanner.pop_span().unwrap(),
-                        kind: ShellTokenKind::Interp( tks ),
-                    };
+                continue;
+            }
 
-                    tokens.push( tk );
-                    self.scanner = interp.scanner;
-                },
+            let span = self.scanner.pop_span().unwrap();
+            if !buf.is_empty() {
+                tokens.push( ShellToken {
+                    span,
+                    kind: ShellTokenKind::String( buf.as_str().into() ),
+                } );
 
-                _ => buf.push( self.scanner.consume().unwrap() ),
+                buf.clear();
             }
+
+            tokens.push( if c == '{' {
+                self.lex_interp( "{", LexerMode::Interp, '}' )?
+            } else if is_subst {
+                self.lex_interp( "$(", LexerMode::Subst, ')' )?
+            } else {
+                self.lex_variable()?
+            } );
+
+            self.scanner.push_mark();
         }
 
-        if self.scanner.is_empty() || self.scanner.consume().unwrap() != term {
-            return Err( LexError::unexpected_eoi(
-                "string does not terminate",
-                self.scanner.pop_span().unwrap(),
-            ) );
+        let span = self.scanner.pop_span().unwrap();
+        if term.is_some() && self.scanner.consume() != term {
+            return Err( LexError::unexpected_eoi( STRING_UNTERMINATED, span ) );
         }
 
-        let is_interp = tokens.len() > 0;
-        if is_interp && buf.len() > 0 {
-            //self.scanner.push_mark();
-            let tk = ShellToken {
-                span: self.scanner.pop_span().unwrap(),
-                kind: ShellTokenKind::String( buf.clone() ),
-            };
+        if tokens.is_empty() {
+            return Ok( ShellTokenKind::String( buf.into() ) );
+        }
 
-            tokens.push( tk );
+        if !buf.is_empty() {
+            tokens.push( ShellToken {
+                span,
+                kind: ShellTokenKind::String( buf.into() ),
+            } );
         }
 
-        Ok( Some( ShellToken {
+        Ok( ShellTokenKind::Interp( tokens.into() ) )
+    }
+
+    // A command embedded in a string. The body is lexed in place, and `mode`
+    // stops it at the closing delimiter.
+    fn lex_interp( &mut self, open: &str, mode: LexerMode, close: char ) -> Result<ShellToken, LexError> {
+        self.scanner.push_mark();
+        self.scanner.take_if_next( open );
+
+        if self.modes.len() > MAX_DEPTH {
+            return Err( LexError::too_deep( MAX_DEPTH, self.scanner.pop_span().unwrap() ) );
+        }
+
+        self.modes.push( mode );
+        let tks = self.tokenize();
+        self.modes.pop();
+
+        let tks = tks?;
+        if self.scanner.consume() != Some( close ) {
+            return Err( LexError::unexpected_eoi( INTERP_UNTERMINATED, self.scanner.pop_span().unwrap() ) );
+        }
+
+        Ok( ShellToken {
             span: self.scanner.pop_span().unwrap(),
-            kind: if is_interp {
-                ShellTokenKind::Interp( tokens )
-            } else {
-                ShellTokenKind::String( buf.clone() )
+            kind: ShellTokenKind::Interp( tks.into() ),
+        } )
+    }
+
+    // <<WORD, or <<'WORD' to take the body literally. `<<<` is left to try_lex_punct.
+    fn try_lex_heredoc( &mut self, _: char ) -> Result<Option<ShellToken>, LexError> {
+        if !self.scanner.is_next( "<<" ) || self.scanner.is_next( "<<<" ) {
+            return Ok( None );
+        }
+
+        self.scanner.push_mark();
+        self.scanner.take_if_next( "<<" );
+        self.scanner.skip_while( | c | c == ' ' || c == '\t' );
+
+        let quote = self.scanner.peek().filter( | c | *c == '\'' || *c == '"' );
+        let delimiter = match quote {
+            Some( q ) => {
+                self.scanner.consume();
+                let word = self.scanner.take_while( | c | c != q && c != '\n' );
+                if self.scanner.consume() != Some( q ) {
+                    return Err( LexError::unexpected_eoi( STRING_UNTERMINATED, self.scanner.pop_span().unwrap() ) );
+                }
+
+                word
             },
+
+            None => {
+                let special = &self.special;
+                self.scanner.take_while( | c | !c.is_whitespace() && !c.is_control() && !special.contains( &c ) )
+            },
+        };
+
+        let span = self.scanner.pop_span().unwrap();
+        if delimiter.is_empty() {
+            return Err( LexError::unexpected_eoi( "here-document needs a delimiter", span ) );
+        }
+
+        self.heredoc = Some( ( delimiter.to_string(), quote.is_none() ) );
+        Ok( Some( ShellToken {
+            kind: ShellTokenKind::HereDoc,
+            span,
         } ) )
     }
 
+    // Reads lines up to the delimiter, which has to be on a line of its own.
+    fn lex_heredoc_body( &mut self, delimiter: &str, interpolate: bool ) -> Result<ShellToken, LexError> {
+        self.scanner.push_mark();
+        let mut lines = Vec::new();
+        loop {
+            if self.scanner.is_empty() {
+                return Err( LexError::unexpected_eoi( HEREDOC_UNTERMINATED, self.scanner.pop_span().unwrap() ) );
+            }
+
+            let line = self.scanner.take_while( | c | c != '\n' );
+            self.scanner.consume();
+
+            if line.trim_end_matches( '\r' ) == delimiter {
+                break;
+            }
+
+            lines.push( line );
+        }
+
+        let span = self.scanner.pop_span().unwrap();
+        let body = lines.join( "\n" );
+        let kind = if interpolate {
+            ShellLexer::new( &body ).lex_template( None )?
+        } else {
+            ShellTokenKind::String( body.into() )
+        };
+
+        Ok( ShellToken { kind, span } )
+    }
+
     fn try_lex_punct( &mut self, _: char ) -> Result<Option<ShellToken>, LexError> {
         self.scanner.push_mark();
-        for ( k, v ) in &self.punct {
-            if self.scanner.take_if_next( k ).is_some() {
+        for ( op, kind ) in &self.punct {
+            if self.scanner.take_if_next( op ).is_some() {
                 let span = self.scanner.pop_span().unwrap();
                 return Ok( Some( ShellToken {
-                    kind: (*v).clone(),
+                    kind: kind.clone(),
                     span,
                 } ) );
             }
@@ -315,14 +912,21 @@ impl ShellLexer {
 enum Precedence {
     Invalid = 0,
     Seq = 1,
-    Pipe = 2,
-    Redir = 3,
-    Cmd = 4,
+    AndOr = 2,
+    Pipe = 3,
+    Redir = 4,
+    Cmd = 5,
 }
 
 pub struct ShellParser {
     tokens: TokenStream<ShellToken>,
     parse_commands: bool,
+
+    // mistakes the parser was able to carry on past
+    errors: Vec<ParseError>,
+
+    // how many segments are being parsed inside one another, see MAX_DEPTH
+    depth: usize,
 }
 
 impl ShellParser {
@@ -330,44 +934,137 @@ impl ShellParser {
         ShellParser {
             tokens: TokenStream::new( tokens ),
             parse_commands: true,
+            errors: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn shared( tokens: Rc<[ShellToken]> ) -> ShellParser {
+        ShellParser {
+            tokens: TokenStream::shared( tokens ),
+            parse_commands: true,
+            errors: Vec::new(),
+            depth: 0,
         }
     }
 
     pub fn parse_all( &mut self ) -> Result<Exec, ParseError> {
-        if self.tokens.is_empty() {
-            return Ok( Box::new( Empty ) );
+        self.parse_recovering().map_err( | mut errors | errors.remove( 0 ) )
+    }
+
+    // Like parse_all, but rather than stopping at the first mistake it skips
+    // to the next statement and carries on, so they can all be reported at once.
+    pub fn parse_recovering( &mut self ) -> Result<Exec, Vec<ParseError>> {
+        self.skip_newlines();
+
+        let mut tree: Exec = Box::new( Empty );
+        while !self.tokens.is_empty() && !self.tokens.match_a( &ShellTokenKind::EndOfInput ) {
+            match self.parse( Precedence::Invalid ) {
+                Ok( x ) => match self.tokens.consume_a( &ShellTokenKind::EndOfInput ) {
+                    Ok( _ ) => {
+                        tree = x;
+                        break;
+                    },
+
+                    Err( e ) => self.errors.push( e ),
+                },
+
+                Err( e ) => self.errors.push( e ),
+            }
+
+            self.synchronize();
+        }
+
+        if self.errors.is_empty() {
+            Ok( tree )
+        } else {
+            Err( std::mem::take( &mut self.errors ) )
         }
+    }
 
-        let tree = self.parse( Precedence::Invalid )?;
-        self.tokens.consume_a( &ShellTokenKind::EndOfInput )?;
+    // Skips what's left of the statement a mistake was found in, up to the next
+    // `;` or line break that isn't inside braces.
+    fn synchronize( &mut self ) {
+        use self::ShellTokenKind::*;
 
-        Ok( tree )
+        let mut depth = 0;
+        loop {
+            let stop = match self.tokens.peek().map( | x | x.kind() ) {
+                None | Some( EndOfInput ) => return,
+                Some( Semi ) | Some( Newline ) => depth <= 0,
+                Some( LBrace ) => { depth += 1; false },
+                Some( RBrace ) => { depth -= 1; false },
+                _ => false,
+            };
+
+            if stop { break; }
+            let _ = self.tokens.consume();
+        }
+
+        while self.tokens.match_a( &Semi ) || self.tokens.match_a( &Newline ) {
+            let _ = self.tokens.consume();
+        }
     }
 
     fn parse( &mut self, prec: Precedence ) -> Result<Exec, ParseError> {
-        use ShellTokenKind::*;
+        if self.depth >= MAX_DEPTH {
+            let tk = self.tokens.consume()?;
+            return Err( ParseError::too_deep( MAX_DEPTH, tk.span().clone() ) );
+        }
+
+        self.depth += 1;
+        let res = self.parse_segment( prec );
+        self.depth -= 1;
+        res
+    }
+
+    fn parse_segment( &mut self, prec: Precedence ) -> Result<Exec, ParseError> {
+        use self::ShellTokenKind::*;
 
         let mut tk = self.tokens.consume()?;
 
+        // NAME=value only assigns in front of a command; anywhere else it's a word
+        let word = match tk.kind() {
+            Assign( _, word ) if !self.parse_commands => Some( ( **word ).clone() ),
+            _ => None,
+        };
+
+        if let Some( word ) = word {
+            tk = word;
+        }
+
         let mut left: Exec = match tk.kind() {
+            Assign( name, word ) => self.parse_assignment( name, word )?,
+            String( s ) if self.parse_commands && s.len() > 1 && s.starts_with( '@' )
+                => self.parse_remote( &s[1..] )?,
             String( s ) => self.parse_string( s )?,
+            Pattern( s ) => self.parse_pattern( s )?,
             Interp( tks ) => self.parse_interp( tks )?,
+            Subst( tks ) => self.parse_subst( tks )?,
+            LBrace if self.parse_commands => self.parse_group()?,
+            Keyword( self::Keyword::If ) if self.parse_commands => self.parse_if()?,
+            Keyword( self::Keyword::While ) if self.parse_commands => self.parse_while()?,
+            Keyword( self::Keyword::For ) if self.parse_commands => self.parse_for()?,
+            Keyword( self::Keyword::Par ) if self.parse_commands && self.tokens.match_a( &LBrace ) => self.parse_par()?,
+
+            // without a block after it, `par` is just the name of a program
+            Keyword( self::Keyword::Par ) if self.parse_commands => self.parse_string( "par" )?,
+            Keyword( x ) if !self.parse_commands => Box::new( Text( x.as_str().to_string() ) ),
             Dollar => {
-                if self.tokens.match_a( &LParen ) {
-                    self.tokens.consume_a( &LParen )?;
-                    let seg = self.with_commands( | p | p.parse( Precedence::Invalid ) )?;
-                    self.tokens.consume_a( &RParen )?;
-
-                    Box::new( CmdInterp( seg ) )
-                } else {
-                    let tk = self.tokens.consume_a( &String( std::string::String::new() ) )?;
-                    let name = match tk.kind() {
-                        String( s ) => s,
-                        _ => unreachable!()
-                    };
-
-                    Box::new( Var( name.clone() ) )
-                }
+                let tk = self.tokens.consume()?;
+                let name = match tk.kind() {
+                    String( s ) => s,
+
+                    // $? lexes as a wildcard, but it names the last exit status
+                    Pattern( s ) if &**s == "?" => s,
+                    _ => return Err( ParseError::unexpected(
+                        "variable name".to_string(),
+                        tk.to_string(),
+                        tk.span().clone()
+                    ) )
+                };
+
+                Box::new( Var( name.to_string() ) )
             },
             _ => return Err( ParseError::expect_segment(
                 tk.to_string(),
@@ -375,41 +1072,66 @@ impl ShellParser {
             ) )
         };
 
+        // statements gathered so far when this turns out to be a sequence of them
+        let mut statements: Vec<Exec> = Vec::new();
+
         while prec < get_prec( self.tokens.peek() ) {
             tk = self.tokens.consume()?;
             left = match tk.kind() {
                 Amp => {
-                    let right = self.parse( Precedence::Seq )?;
-                    Box::new( Seq {
-                        safe: true,
-                        left: left,
-                        right: right,
-                    } )
+                    let left = Box::new( Background( left ) );
+                    if self.has_segment() {
+                        statements.push( left );
+                        self.parse( Precedence::Seq )?
+                    } else {
+                        left
+                    }
                 },
-                Semi => {
-                    let right = self.parse( Precedence::Seq )?;
-                    Box::new( Seq {
-                        safe: false,
-                        left: left.into(),
-                        right: right.into(),
+                Semi | Newline => {
+                    // a trailing ; is allowed, and needed before the } of a group
+                    if self.has_segment() {
+                        statements.push( left );
+                        self.parse( Precedence::Seq )?
+                    } else {
+                        left
+                    }
+                },
+                AndAnd | OrOr => {
+                    self.skip_newlines();
+                    let right = self.parse( Precedence::AndOr )?;
+                    Box::new( AndOr {
+                        mode: if *tk.kind() == AndAnd { AndOrMode::And } else { AndOrMode::Or },
+                        left,
+                        right,
                     } )
                 },
-                Pipe => {
+                Pipe | PipeBoth => {
+                    self.skip_newlines();
                     let right = self.parse( Precedence::Pipe )?;
                     Box::new( super::segments::Pipe {
-                        left: left.into(),
-                        right: right.into(),
+                        both: *tk.kind() == PipeBoth,
+                        left,
+                        right,
                     } )
                 },
                 StdIn => self.parse_redirect( left, tk )?,
+                HereString | HereDoc => self.parse_heredoc( left )?,
                 StdOut => self.parse_redirect( left, tk )?,
                 StdErr => self.parse_redirect( left, tk )?,
                 StdBoth => self.parse_redirect( left, tk )?,
+                AppendOut => self.parse_redirect( left, tk )?,
+                AppendErr => self.parse_redirect( left, tk )?,
+                AppendBoth => self.parse_redirect( left, tk )?,
 
                 _ => unreachable!(),
             };
         }
 
+        if !statements.is_empty() {
+            statements.push( left );
+            left = Box::new( Seq { statements } );
+        }
+
         return Ok( left );
 
         fn get_prec( tk: Option<&ShellToken> ) -> Precedence {
@@ -418,11 +1140,20 @@ impl ShellParser {
                 match tk.kind() {
                     Amp => Seq,
                     Semi => Seq,
+                    Newline => Seq,
+                    AndAnd => AndOr,
+                    OrOr => AndOr,
                     ShellTokenKind::Pipe => Pipe,
+                    PipeBoth => Pipe,
                     StdIn => Redir,
+                    HereString => Redir,
+                    HereDoc => Redir,
                     StdOut => Redir,
                     StdErr => Redir,
                     StdBoth => Redir,
+                    AppendOut => Redir,
+                    AppendErr => Redir,
+                    AppendBoth => Redir,
 
                     _ => Invalid,
                 }
@@ -454,21 +1185,82 @@ impl ShellParser {
         res
     }
 
+    // Line breaks where a command can't end yet, such as after `|` or `{`.
+    fn skip_newlines( &mut self ) {
+        while self.tokens.match_a( &ShellTokenKind::Newline ) {
+            let _ = self.tokens.consume();
+        }
+    }
+
     fn has_segment( &mut self ) -> bool {
+        self.has_argument() || self.tokens.match_a( &ShellTokenKind::LBrace )
+    }
+
+    // match_a only compares token kinds, not which keyword it is
+    fn at_keyword( &mut self, keyword: Keyword ) -> bool {
         match self.tokens.peek() {
-            Some( x ) => {
-                let x = discriminant( x.kind() );
-                x == discriminant( &ShellTokenKind::String( std::string::String::new() ) ) ||
-                x == discriminant( &ShellTokenKind::Interp( Vec::new() ) ) ||
-                x == discriminant( &ShellTokenKind::Dollar )
-            },
+            Some( x ) => *x.kind() == ShellTokenKind::Keyword( keyword ),
+            None => false,
+        }
+    }
+
+    fn has_argument( &mut self ) -> bool {
+        match self.tokens.peek() {
+            Some( x ) => matches!(
+                x.kind(),
+                ShellTokenKind::String( _ ) |
+                ShellTokenKind::Pattern( _ ) |
+                ShellTokenKind::Interp( _ ) |
+                ShellTokenKind::Subst( _ ) |
+                ShellTokenKind::Keyword( _ ) |
+                ShellTokenKind::Assign( .. ) |
+                ShellTokenKind::Dollar
+            ),
 
             None => false
         }
     }
 
-    fn parse_string( &mut self, s: &String ) -> Result<Exec, ParseError> {
-        let seg = Box::new( Text( s.clone() ) );
+    // `NAME=value ... cmd args`, or just the assignments when no command follows.
+    fn parse_assignment( &mut self, name: &str, word: &ShellToken ) -> Result<Exec, ParseError> {
+        let mut vars = vec![ ( name.to_string(), self.parse_word( &assignment_value( name, word ) )? ) ];
+        while let Some( ShellTokenKind::Assign( name, word ) ) = self.tokens.peek().map( | x | x.kind().clone() ) {
+            self.tokens.consume()?;
+            vars.push( ( name.to_string(), self.parse_word( &assignment_value( &name, &word ) )? ) );
+        }
+
+        let has_command = self.has_argument() && !matches!( self.tokens.peek().map( | x | x.kind() ), Some( ShellTokenKind::Keyword( _ ) ) );
+        let command = if has_command {
+            Some( self.parse( Precedence::Redir )? )
+        } else {
+            None
+        };
+
+        Ok( Box::new( Assign { vars, command } ) )
+    }
+
+    // A single word, the same as it would be as an argument.
+    fn parse_word( &mut self, tk: &ShellToken ) -> Result<Exec, ParseError> {
+        match tk.kind() {
+            ShellTokenKind::String( s ) => Ok( Box::new( Text( s.to_string() ) ) ),
+            ShellTokenKind::Pattern( s ) => Ok( Box::new( Glob( s.to_string() ) ) ),
+            ShellTokenKind::Interp( tks ) => self.without_commands( | p | p.parse_interp( tks ) ),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_string( &mut self, s: &str ) -> Result<Exec, ParseError> {
+        let seg = Box::new( Text( s.to_string() ) );
+
+        if !self.parse_commands {
+            Ok( seg )
+        } else {
+            self.parse_args( seg )
+        }
+    }
+
+    fn parse_pattern( &mut self, s: &str ) -> Result<Exec, ParseError> {
+        let seg = Box::new( Glob( s.to_string() ) );
 
         if !self.parse_commands {
             Ok( seg )
@@ -477,13 +1269,14 @@ impl ShellParser {
         }
     }
 
-    fn parse_interp( &mut self, tks: &Vec<ShellToken> ) -> Result<Exec, ParseError> {
+    fn parse_interp( &mut self, tks: &[ShellToken] ) -> Result<Exec, ParseError> {
         let mut segs = Vec::new();
         for tk in tks {
             let seg: Exec = match tk.kind() {
-                ShellTokenKind::String( s ) => Box::new( Text( s.clone() ) ),
+                ShellTokenKind::String( s ) => Box::new( Text( s.to_string() ) ),
                 ShellTokenKind::Interp( tks ) => {
-                    let mut parser = ShellParser::new( tks.clone() );
+                    let mut parser = ShellParser::shared( tks.clone() );
+                    parser.depth = self.depth;
                     parser.parse_all()?
                 },
                 _ => unreachable!(),
@@ -501,14 +1294,22 @@ impl ShellParser {
         }
     }
 
+    // $(...) as a word of its own. Its output is split into arguments, but it
+    // isn't a command with arguments of its own.
+    fn parse_subst( &mut self, tks: &Rc<[ShellToken]> ) -> Result<Exec, ParseError> {
+        let mut parser = ShellParser::shared( tks.clone() );
+        parser.depth = self.depth;
+        Ok( Box::new( CmdInterp( parser.parse_all()? ) ) )
+    }
+
     fn parse_args( &mut self, seg: Exec ) -> Result<Exec, ParseError> {
         let mut segs = Vec::new();
-        while self.has_segment() {
+        while self.has_argument() {
             let seg = self.without_commands( | p | p.parse( Precedence::Cmd ) )?;
             segs.push( seg );
         }
 
-        if segs.len() == 0 {
+        if segs.is_empty() {
             Ok( Box::new( Cmd {
                 command: seg,
                 args: None,
@@ -521,29 +1322,176 @@ impl ShellParser {
         }
     }
 
-    fn parse_redirect( &mut self, left: Exec, tk: ShellToken ) -> Result<Exec, ParseError> {
+    // `{ cmd; cmd }`, which runs in this shell. Braces always group, so they
+    // have to be quoted to be passed as arguments.
+    fn parse_group( &mut self ) -> Result<Exec, ParseError> {
+        self.skip_newlines();
+        let inner = if self.tokens.match_a( &ShellTokenKind::RBrace ) {
+            Box::new( Empty )
+        } else {
+            self.with_commands( | p | p.parse( Precedence::Invalid ) )?
+        };
+
+        self.tokens.consume_a( &ShellTokenKind::RBrace )?;
+        Ok( Box::new( Group( inner ) ) )
+    }
+
+    // `if <pipeline> { ... }`, optionally followed by `else { ... }` or `else if ...`.
+    fn parse_if( &mut self ) -> Result<Exec, ParseError> {
+        // the condition's arguments end at the { of the body
+        let condition = self.parse( Precedence::Seq )?;
+
+        self.tokens.consume_a( &ShellTokenKind::LBrace )?;
+        let then = self.parse_group()?;
+
+        let otherwise = if self.at_keyword( Keyword::Else ) {
+            self.tokens.consume()?;
+            if self.at_keyword( Keyword::If ) {
+                self.tokens.consume()?;
+                Some( self.parse_if()? )
+            } else {
+                self.tokens.consume_a( &ShellTokenKind::LBrace )?;
+                Some( self.parse_group()? )
+            }
+        } else {
+            None
+        };
+
+        Ok( Box::new( If { condition, then, otherwise } ) )
+    }
+
+    // `while <pipeline> { ... }`
+    fn parse_while( &mut self ) -> Result<Exec, ParseError> {
+        let condition = self.parse( Precedence::Seq )?;
+
+        self.tokens.consume_a( &ShellTokenKind::LBrace )?;
+        let body = self.parse_group()?;
+
+        Ok( Box::new( While { condition, body } ) )
+    }
+
+    // `for name in <words> { ... }`, where the words are expanded like arguments.
+    fn parse_for( &mut self ) -> Result<Exec, ParseError> {
+        let tk = self.tokens.consume()?;
+        let var = match tk.kind() {
+            ShellTokenKind::String( x ) => x.to_string(),
+            _ => return Err( ParseError::unexpected(
+                "variable name".to_string(),
+                tk.to_string(),
+                tk.span().clone()
+            ) ),
+        };
+
+        if !self.at_keyword( Keyword::In ) {
+            let tk = self.tokens.consume()?;
+            return Err( ParseError::unexpected( "in".to_string(), tk.to_string(), tk.span().clone() ) );
+        }
+
+        self.tokens.consume()?;
+
+        let mut words = Vec::new();
+        while self.has_argument() {
+            words.push( self.without_commands( | p | p.parse( Precedence::Cmd ) )? );
+        }
+
+        self.tokens.consume_a( &ShellTokenKind::LBrace )?;
+        let body = self.parse_group()?;
+
+        Ok( Box::new( For { var, words, body } ) )
+    }
+
+    // `par { cmd; cmd }`, whose commands run at the same time.
+    fn parse_par( &mut self ) -> Result<Exec, ParseError> {
+        self.tokens.consume_a( &ShellTokenKind::LBrace )?;
+        self.skip_newlines();
+        let inner = if self.tokens.match_a( &ShellTokenKind::RBrace ) {
+            Box::new( Empty )
+        } else {
+            self.with_commands( | p | p.parse( Precedence::Invalid ) )?
+        };
+
+        self.tokens.consume_a( &ShellTokenKind::RBrace )?;
+        Ok( Box::new( Par( inner ) ) )
+    }
+
+    fn parse_remote( &mut self, host: &str ) -> Result<Exec, ParseError> {
+        let inner = self.with_commands( | p | p.parse( Precedence::Pipe ) )?;
+
+        Ok( Box::new( Remote {
+            host: host.to_string(),
+            inner,
+        } ) )
+    }
+
+    // The text after <<< or the body of a here-document, fed to `left` as its input.
+    fn parse_heredoc( &mut self, left: Exec ) -> Result<Exec, ParseError> {
         let span = match self.tokens.peek() {
             Some( tk ) => tk.span().clone(),
-            None => tk.span.clone()
+            None => return Err( ParseError::unexpected_eoi() ),
         };
 
-        let right = self.without_commands( | p | p.parse( Precedence::Redir ) )?;
-        let is_valid =
-            right.as_any().downcast_ref::<Text>().is_some() ||
-            right.as_any().downcast_ref::<Redirect>().is_some();
-
+        let text = self.without_commands( | p | p.parse( Precedence::Redir ) )?;
+        let any = text.as_any();
+        let is_valid = any.is::<Text>() || any.is::<TextInterp>() || any.is::<Var>() || any.is::<CmdInterp>();
         if !is_valid {
-            return Err( ParseError::expect_string( span ) )
+            return Err( ParseError::expect_string( span ) );
         }
 
-        let mode = match tk.kind() {
-            ShellTokenKind::StdIn => RedirectMode::StdIn,
-            ShellTokenKind::StdOut => RedirectMode::StdOut,
-            ShellTokenKind::StdErr => RedirectMode::StdErr,
-            ShellTokenKind::StdBoth => RedirectMode::StdBoth,
-            _ => unreachable!(),
-        };
+        Ok( Box::new( HereDoc { left, text } ) )
+    }
+
+    // A command's redirections, starting with the one after `tk`, all go in one
+    // Redirect in the order they're written.
+    fn parse_redirect( &mut self, left: Exec, tk: ShellToken ) -> Result<Exec, ParseError> {
+        let mut targets = Vec::new();
+        let mut tk = tk;
+
+        loop {
+            let span = match self.tokens.peek() {
+                Some( tk ) => tk.span().clone(),
+                None => tk.span.clone()
+            };
 
-        Ok( Box::new( Redirect { left, right, mode } ) )
+            // a missing or unusable target is noted and passed over so the
+            // rest of the command still gets checked
+            let path: Exec = if !self.has_argument() && !self.tokens.match_a( &ShellTokenKind::EndOfInput ) {
+                let found = self.tokens.peek().map( | x | x.to_string() ).unwrap_or_default();
+                self.errors.push( ParseError::expect_segment( found, span ) );
+                Box::new( Empty )
+            } else {
+                let path = self.without_commands( | p | p.parse( Precedence::Redir ) )?;
+                let any = path.as_any();
+                let is_valid = any.is::<Text>() || any.is::<Glob>() || any.is::<TextInterp>() || any.is::<Var>() || any.is::<CmdInterp>();
+                if !is_valid {
+                    self.errors.push( ParseError::expect_string( span ) );
+                }
+
+                path
+            };
+
+            let ( mode, append ) = redirect_mode( tk.kind() ).unwrap();
+            targets.push( RedirectTarget { mode, append, path } );
+
+            match self.tokens.peek() {
+                Some( next ) if redirect_mode( next.kind() ).is_some() => tk = self.tokens.consume()?,
+                _ => break,
+            }
+        }
+
+        Ok( Box::new( Redirect { left, targets } ) )
     }
 }
+
+// Which stream a redirection operator applies to, and whether it appends.
+fn redirect_mode( kind: &ShellTokenKind ) -> Option<( RedirectMode, bool )> {
+    Some( match kind {
+        ShellTokenKind::StdIn => ( RedirectMode::StdIn, false ),
+        ShellTokenKind::StdOut => ( RedirectMode::StdOut, false ),
+        ShellTokenKind::StdErr => ( RedirectMode::StdErr, false ),
+        ShellTokenKind::StdBoth => ( RedirectMode::StdBoth, false ),
+        ShellTokenKind::AppendOut => ( RedirectMode::StdOut, true ),
+        ShellTokenKind::AppendErr => ( RedirectMode::StdErr, true ),
+        ShellTokenKind::AppendBoth => ( RedirectMode::StdBoth, true ),
+        _ => return None,
+    } )
+}
@@ -0,0 +1,262 @@
+use std::collections::{ BTreeSet, HashMap };
+use std::env::{ var_os, split_paths };
+use std::ffi::OsString;
+use std::fs::{ read_dir, File };
+use std::io::Read;
+use std::path::{ Path, PathBuf, MAIN_SEPARATOR };
+use std::process::Command;
+use std::sync::Mutex;
+
+// Lookups are remembered until PATH changes, so running the same program
+// over and over doesn't search every directory each time.
+struct Cache {
+    path: Option<OsString>,
+    found: HashMap<String, Option<PathBuf>>,
+    names: Option<BTreeSet<String>>,
+}
+
+impl Cache {
+    // Drops everything that was looked up under a different PATH.
+    fn check_path( &mut self ) {
+        let path = var_os( "PATH" );
+        if self.path != path {
+            self.found.clear();
+            self.names = None;
+            self.path = path;
+        }
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new( Cache {
+        path: None,
+        found: HashMap::new(),
+        names: None,
+    } );
+}
+
+fn has_separator( name: &str ) -> bool {
+    name.contains( MAIN_SEPARATOR ) || name.contains( '/' )
+}
+
+#[cfg( windows )]
+fn is_executable( path: &Path ) -> bool {
+    path.is_file() && ( path.extension().is_some() || shebang( path ).is_some() )
+}
+
+#[cfg( not( windows ) )]
+fn is_executable( path: &Path ) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok( meta ) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err( _ ) => false,
+    }
+}
+
+// The file names `name` may have on disk: on Windows a name without an
+// extension is tried with each one in PATHEXT, then as it is in case it's a
+// script with a #! line.
+#[cfg( windows )]
+fn candidates( name: &str ) -> Vec<String> {
+    if Path::new( name ).extension().is_some() {
+        return vec![ name.to_string() ];
+    }
+
+    let exts = var_os( "PATHEXT" )
+        .map( | x | x.to_string_lossy().into_owned() )
+        .unwrap_or_else( || ".COM;.EXE;.BAT;.CMD".to_string() );
+
+    let mut names: Vec<String> = exts.split( ';' ).filter( | x | !x.is_empty() ).map( | ext | format!( "{}{}", name, ext ) ).collect();
+    names.push( name.to_string() );
+    names
+}
+
+#[cfg( not( windows ) )]
+fn candidates( name: &str ) -> Vec<String> {
+    vec![ name.to_string() ]
+}
+
+// Every match for `name` on PATH, in search order.
+pub fn search_all( name: &str ) -> Vec<PathBuf> {
+    let path = match var_os( "PATH" ) {
+        Some( x ) => x,
+        None => return Vec::new(),
+    };
+
+    let mut found = Vec::new();
+    for dir in split_paths( &path ) {
+        for file in candidates( name ) {
+            let full = dir.join( file );
+            if is_executable( &full ) {
+                found.push( full );
+            }
+        }
+    }
+
+    found
+}
+
+// The program that running `name` would start. Names containing a path
+// separator are taken as paths rather than looked up.
+pub fn resolve( name: &str ) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+
+    if has_separator( name ) {
+        return candidates( name ).into_iter().map( PathBuf::from ).find( | x | is_executable( x ) );
+    }
+
+    let mut cache = CACHE.lock().unwrap();
+    cache.check_path();
+
+    // a program that has since been removed is looked for again
+    let found = cache.found.entry( name.to_string() ).or_insert_with( || search_all( name ).into_iter().next() );
+    if found.as_ref().is_some_and( | x | !is_executable( x ) ) {
+        *found = search_all( name ).into_iter().next();
+    }
+
+    found.clone()
+}
+
+// Forgets every lookup, e.g. after installing a program into a directory already on PATH.
+pub fn clear_cache() {
+    let mut cache = CACHE.lock().unwrap();
+    cache.found.clear();
+    cache.names = None;
+}
+
+// The programs found so far, by name.
+pub fn cached() -> Vec<( String, PathBuf )> {
+    let mut cache = CACHE.lock().unwrap();
+    cache.check_path();
+
+    let mut found: Vec<( String, PathBuf )> = cache.found.iter()
+        .filter_map( | ( k, v ) | v.as_ref().map( | v | ( k.clone(), v.clone() ) ) )
+        .collect();
+
+    found.sort();
+    found
+}
+
+// A Command for `name` with the executable already located.
+pub fn command( name: &str ) -> Option<Command> {
+    let exe = resolve( name )?;
+
+    #[cfg( windows )]
+    {
+        if let Some( proc ) = script_command( &exe ) {
+            return Some( proc );
+        }
+    }
+
+    let mut proc = Command::new( exe );
+
+    // programs still see the name they were invoked by
+    #[cfg( not( windows ) )]
+    {
+        use std::os::unix::process::CommandExt;
+        proc.arg0( name );
+    }
+
+    Some( proc )
+}
+
+// Windows only starts .exe files and the like itself, so a script with a #!
+// line is handed to the interpreter it names instead, with the script's path
+// after the line's argument as the Unix kernel does.
+#[cfg( windows )]
+fn script_command( script: &Path ) -> Option<Command> {
+    let native = script.extension().is_some_and( | x | [ "exe", "com", "bat", "cmd" ].iter().any( | e | x.eq_ignore_ascii_case( e ) ) );
+    if native {
+        return None;
+    }
+
+    let ( interpreter, arg ) = shebang( script )?;
+    let ( program, args ) = interpreter_command( &interpreter, arg );
+    let mut proc = Command::new( program );
+    proc.args( args ).arg( script );
+    Some( proc )
+}
+
+// Unix paths like /usr/bin/python3 rarely exist on Windows, so an interpreter
+// that isn't there is looked for on PATH by its file name, and `env` is skipped
+// in favour of the program it would have found. A missing interpreter is kept
+// as written so starting it fails and the error names it.
+#[cfg( windows )]
+fn interpreter_command( interpreter: &str, arg: Option<String> ) -> ( PathBuf, Vec<String> ) {
+    let path = Path::new( interpreter );
+    if path.is_file() {
+        return ( path.to_path_buf(), arg.into_iter().collect() );
+    }
+
+    let name = path.file_name().map( | x | x.to_string_lossy().into_owned() ).unwrap_or_default();
+    if name == "env" {
+        // env -S splits its argument into words, which is what's done anyway
+        let mut words = arg.iter().flat_map( | x | x.split_whitespace() ).filter( | x | *x != "-S" ).map( String::from );
+        if let Some( program ) = words.next() {
+            return ( resolve( &program ).unwrap_or_else( || PathBuf::from( program ) ), words.collect() );
+        }
+    }
+
+    ( resolve( &name ).unwrap_or_else( || path.to_path_buf() ), arg.into_iter().collect() )
+}
+
+// The interpreter a script's #! line names, and the argument after it if
+// there is one, e.g. ("/usr/bin/env", Some("python3")).
+pub fn shebang( path: &Path ) -> Option<( String, Option<String> )> {
+    let mut head = [ 0; 256 ];
+    let read = File::open( path ).ok()?.read( &mut head ).ok()?;
+    let line = head[..read].strip_prefix( b"#!" )?.split( | x | *x == b'\n' ).next()?;
+    let line = std::str::from_utf8( line ).ok()?.trim();
+
+    // like Unix, everything after the interpreter is one argument
+    let ( interpreter, arg ) = match line.split_once( char::is_whitespace ) {
+        Some( ( x, arg ) ) => ( x, Some( arg.trim().to_string() ) ),
+        None => ( line, None ),
+    };
+
+    if interpreter.is_empty() { None } else { Some( ( interpreter.to_string(), arg ) ) }
+}
+
+// What `name` refers to when it doesn't resolve to a program: the file or
+// directory at that path, or a file by that name on PATH that can't be run.
+pub fn locate( name: &str ) -> Option<PathBuf> {
+    if has_separator( name ) {
+        return Some( PathBuf::from( name ) ).filter( | x | x.exists() );
+    }
+
+    let path = var_os( "PATH" )?;
+    split_paths( &path ).flat_map( | dir | candidates( name ).into_iter().map( move | x | dir.join( x ) ) ).find( | x | x.is_file() )
+}
+
+// The names of all programs on PATH.
+pub fn executables() -> BTreeSet<String> {
+    let mut cache = CACHE.lock().unwrap();
+    cache.check_path();
+    cache.names.get_or_insert_with( scan_path ).clone()
+}
+
+fn scan_path() -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let path = match var_os( "PATH" ) {
+        Some( x ) => x,
+        None => return names,
+    };
+
+    for dir in split_paths( &path ) {
+        let entries = match read_dir( &dir ) {
+            Ok( x ) => x,
+            Err( _ ) => continue,
+        };
+
+        for entry in entries.flatten() {
+            if is_executable( &entry.path() ) {
+                names.insert( entry.file_name().to_string_lossy().into_owned() );
+            }
+        }
+    }
+
+    names
+}
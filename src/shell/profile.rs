@@ -0,0 +1,73 @@
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+// Where the time goes while starting up or running a command. Nothing is
+// measured unless profiling has been started, so the hooks cost next to
+// nothing the rest of the time. Phases that run inside others, such as a
+// process spawned by $(...) while expanding, count towards both.
+
+static ENABLED: AtomicBool = AtomicBool::new( false );
+
+struct Profile {
+    started: Instant,
+
+    // phase, total time and how many times it ran, in the order first seen
+    phases: Vec<( &'static str, Duration, usize )>,
+}
+
+lazy_static! {
+    static ref PROFILE: Mutex<Option<Profile>> = Mutex::new( None );
+}
+
+pub fn start() {
+    *PROFILE.lock().unwrap() = Some( Profile { started: Instant::now(), phases: Vec::new() } );
+    ENABLED.store( true, Ordering::SeqCst );
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load( Ordering::Relaxed )
+}
+
+// Runs `f`, adding the time it took to `phase` while profiling.
+pub fn measure<T, F: FnOnce() -> T>( phase: &'static str, f: F ) -> T {
+    if !enabled() {
+        return f();
+    }
+
+    let started = Instant::now();
+    let res = f();
+    let elapsed = started.elapsed();
+
+    if let Some( ref mut profile ) = *PROFILE.lock().unwrap() {
+        match profile.phases.iter_mut().find( | x | x.0 == phase ) {
+            Some( x ) => {
+                x.1 += elapsed;
+                x.2 += 1;
+            },
+
+            None => profile.phases.push( ( phase, elapsed, 1 ) ),
+        }
+    }
+
+    res
+}
+
+// Stops profiling and describes what was measured, one phase per line with
+// the total since `start` last, e.g. "expand      0.412ms  (3)".
+pub fn stop() -> Vec<String> {
+    ENABLED.store( false, Ordering::SeqCst );
+    let profile = match PROFILE.lock().unwrap().take() {
+        Some( x ) => x,
+        None => return Vec::new(),
+    };
+
+    let line = | name: &str, time: Duration, count: Option<usize> | {
+        let count = count.map_or( String::new(), | x | format!( "  ({})", x ) );
+        format!( "{:<10} {:>10.3}ms{}", name, time.as_secs_f64() * 1000.0, count )
+    };
+
+    let mut lines: Vec<String> = profile.phases.iter().map( | x | line( x.0, x.1, Some( x.2 ) ) ).collect();
+    lines.push( line( "total", profile.started.elapsed(), None ) );
+    lines
+}
@@ -0,0 +1,426 @@
+use std::env::current_exe;
+use std::io::{ Result, Error, ErrorKind };
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::Arc;
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, TryRecvError };
+use std::thread;
+use std::time::Duration;
+use dirs::home_dir;
+use whoami::{ username, host };
+use yansi::Style;
+use kernel::{ battery, local_time };
+use shell::config::color_named;
+use shell::git::{ self, GitInfo, GitWatcher };
+
+// how long a background segment is given before the prompt is drawn without it
+const BACKGROUND_WAIT: Duration = Duration::from_millis( 50 );
+
+#[derive( Debug, Clone, Eq, PartialEq )]
+enum Part {
+    Text( String ),
+
+    // the name of a segment, e.g. {cwd}
+    Segment( String ),
+
+    // {red}, {bold}, ... apply to everything after them until {/}
+    Style( Style ),
+}
+
+// A prompt layout such as "{green}{user}{/}@{host} {cwd}> ". Literal braces are
+// written as {{ and }}, and any name that isn't a style is taken for a segment.
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub struct Template( Vec<Part> );
+
+impl Template {
+    pub fn parse( source: &str ) -> Result<Template> {
+        let invalid = | msg: String | Error::new( ErrorKind::InvalidData, msg );
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut style = Style::default();
+        let mut chars = source.chars().peekable();
+
+        while let Some( c ) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some( &'{' ) => {
+                    chars.next();
+                    text.push( '{' );
+                },
+
+                '}' if chars.peek() == Some( &'}' ) => {
+                    chars.next();
+                    text.push( '}' );
+                },
+
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some( '}' ) => break,
+                            Some( x ) => name.push( x ),
+                            None => return Err( invalid( format!( "unclosed '{{{}' in prompt template", name ) ) ),
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        parts.push( Part::Text( text.clone() ) );
+                        text.clear();
+                    }
+
+                    style = match name.as_str() {
+                        "" => return Err( invalid( "empty '{}' in prompt template".to_string() ) ),
+                        "/" => Style::default(),
+                        "bold" => style.bold(),
+                        "dim" => style.dimmed(),
+                        "italic" => style.italic(),
+                        "underline" => style.underline(),
+                        _ => match color_named( &name ) {
+                            Some( color ) => style.fg( color ),
+                            None => {
+                                parts.push( Part::Segment( name ) );
+                                continue;
+                            },
+                        },
+                    };
+
+                    parts.push( Part::Style( style ) );
+                },
+
+                _ => text.push( c ),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push( Part::Text( text ) );
+        }
+
+        Ok( Template( parts ) )
+    }
+
+    // The segments the template shows, in order.
+    pub fn names( &self ) -> Vec<&str> {
+        self.0.iter().filter_map( | x | match x {
+            Part::Segment( name ) => Some( name.as_str() ),
+            _ => None,
+        } ).collect()
+    }
+
+    // Fills in the segments with `value`, which is only asked for the ones the template uses.
+    pub fn render<F: Fn( &str ) -> String>( &self, value: F ) -> String {
+        let mut out = String::new();
+        let mut style = Style::default();
+
+        for part in &self.0 {
+            let text = match part {
+                Part::Text( x ) => x.clone(),
+                Part::Segment( x ) => value( x ),
+                Part::Style( x ) => {
+                    style = *x;
+                    continue;
+                },
+            };
+
+            if !text.is_empty() {
+                out.push_str( &style.paint( text ).to_string() );
+            }
+        }
+
+        out
+    }
+}
+
+// What segments are drawn from: the state of the shell as the prompt is shown.
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub struct PromptState {
+    pub cwd: PathBuf,
+
+    // exit code of the last command
+    pub status: i32,
+
+    // how many directories pushd has saved
+    pub dirs: usize,
+}
+
+// One piece of the prompt, such as the current directory or the git branch.
+pub trait PromptSegment {
+    // what templates call it, e.g. cwd for {cwd}
+    fn name( &self ) -> &str;
+
+    // Called before each prompt the segment is part of. Anything slow should
+    // be started here and finished in the background.
+    fn update( &mut self, _state: &PromptState ) {}
+
+    fn render( &self, state: &PromptState ) -> String;
+
+    // Whether the segment is still waiting on background work.
+    fn pending( &self ) -> bool {
+        false
+    }
+
+    // Checks on background work, returning true if render would now give something different.
+    fn poll( &mut self ) -> bool {
+        false
+    }
+}
+
+// A segment that's quick to work out from the state alone.
+struct Plain {
+    name: &'static str,
+    render: fn( &PromptState ) -> String,
+}
+
+impl PromptSegment for Plain {
+    fn name( &self ) -> &str {
+        self.name
+    }
+
+    fn render( &self, state: &PromptState ) -> String {
+        ( self.render )( state )
+    }
+}
+
+// The branch with a * for uncommitted changes, e.g. main*. The * is added in
+// place once `git status` finishes.
+#[derive( Default )]
+struct Git {
+    watcher: GitWatcher,
+    info: Option<GitInfo>,
+}
+
+impl PromptSegment for Git {
+    fn name( &self ) -> &str {
+        "git"
+    }
+
+    fn update( &mut self, state: &PromptState ) {
+        self.info = self.watcher.status( &state.cwd );
+    }
+
+    fn render( &self, _state: &PromptState ) -> String {
+        self.info.as_ref().map( | x | x.summary() ).unwrap_or_default()
+    }
+
+    fn pending( &self ) -> bool {
+        self.watcher.pending()
+    }
+
+    fn poll( &mut self ) -> bool {
+        match ( self.watcher.poll(), self.info.as_mut() ) {
+            ( Some( ( _, dirty ) ), Some( info ) ) if info.dirty != Some( dirty ) => {
+                info.dirty = Some( dirty );
+                true
+            },
+
+            _ => false,
+        }
+    }
+}
+
+// A segment that's slow to work out, computed on a thread each time the
+// prompt is shown. Until the answer arrives, the last one is shown instead.
+pub struct Background {
+    name: String,
+    compute: Arc<dyn Fn( &PromptState ) -> String + Send + Sync>,
+    text: String,
+    running: Option<( PromptState, Receiver<String> )>,
+}
+
+impl Background {
+    pub fn new<F>( name: &str, compute: F ) -> Background
+        where F: Fn( &PromptState ) -> String + Send + Sync + 'static
+    {
+        Background {
+            name: name.to_string(),
+            compute: Arc::new( compute ),
+            text: String::new(),
+            running: None,
+        }
+    }
+
+    // The first line `command` prints, run by another lumi so it can neither
+    // hold up the prompt nor change anything in the session.
+    pub fn command( name: &str, command: &str ) -> Background {
+        let command = command.to_string();
+        Background::new( name, move | state | {
+            let output = current_exe().and_then( | exe | {
+                Command::new( exe )
+                    .arg( "-c" )
+                    .arg( &command )
+                    .current_dir( &state.cwd )
+                    .stdin( Stdio::null() )
+                    .stderr( Stdio::null() )
+                    .output()
+            } );
+
+            match output {
+                Ok( x ) => String::from_utf8_lossy( &x.stdout ).lines().next().unwrap_or_default().to_string(),
+                Err( _ ) => String::new(),
+            }
+        } )
+    }
+}
+
+impl PromptSegment for Background {
+    fn name( &self ) -> &str {
+        &self.name
+    }
+
+    fn update( &mut self, state: &PromptState ) {
+        // work that is still going on for the same state is as good as starting again
+        let rx = match self.running.take() {
+            Some( ( ref x, rx ) ) if x == state => rx,
+            _ => {
+                let ( tx, rx ) = channel();
+                let compute = self.compute.clone();
+                let state = state.clone();
+                thread::spawn( move || {
+                    let _ = tx.send( compute( &state ) );
+                } );
+
+                rx
+            },
+        };
+
+        match rx.recv_timeout( BACKGROUND_WAIT ) {
+            Ok( x ) => self.text = x,
+            Err( RecvTimeoutError::Timeout ) => self.running = Some( ( state.clone(), rx ) ),
+            Err( RecvTimeoutError::Disconnected ) => {},
+        }
+    }
+
+    fn render( &self, _state: &PromptState ) -> String {
+        self.text.clone()
+    }
+
+    fn pending( &self ) -> bool {
+        self.running.is_some()
+    }
+
+    fn poll( &mut self ) -> bool {
+        let ( state, rx ) = match self.running.take() {
+            Some( x ) => x,
+            None => return false,
+        };
+
+        match rx.try_recv() {
+            Ok( x ) => {
+                let changed = x != self.text;
+                self.text = x;
+                changed
+            },
+
+            Err( TryRecvError::Empty ) => {
+                self.running = Some( ( state, rx ) );
+                false
+            },
+
+            Err( TryRecvError::Disconnected ) => false,
+        }
+    }
+}
+
+// The directory with the home directory shortened to ~.
+pub fn display_dir( dir: &Path ) -> String {
+    let dir = dir.canonicalize().unwrap_or_else( | _ | dir.to_path_buf() );
+    let home = home_dir().and_then( | x | x.canonicalize().ok() );
+    let text = match home.as_ref().and_then( | x | dir.strip_prefix( x ).ok() ) {
+        Some( rest ) => Path::new( "~" ).join( rest ).to_string_lossy().trim_end_matches( [ '/', '\\' ] ).to_string(),
+        None => dir.to_string_lossy().into_owned(),
+    };
+
+    // canonicalize gives full UNC paths, with a leading \\?\, on Windows
+    match text.strip_prefix( "\\\\?\\" ) {
+        Some( x ) => x.to_string(),
+        None => text,
+    }
+}
+
+// Reading the charge can take a while on some laptops, so it's done in the background.
+fn battery_segment() -> Background {
+    Background::new( "battery", | _ | match battery() {
+        Some( x ) if x.charging => format!( "{}%+", x.percent ),
+        Some( x ) => format!( "{}%", x.percent ),
+        None => String::new(),
+    } )
+}
+
+// Everything a prompt can show, by name.
+pub struct Segments( Vec<Box<dyn PromptSegment>> );
+
+impl Default for Segments {
+    fn default() -> Segments {
+        Segments::builtin()
+    }
+}
+
+impl Segments {
+    pub fn builtin() -> Segments {
+        let plain = | name, render | Box::new( Plain { name, render } ) as Box<dyn PromptSegment>;
+
+        Segments( vec![
+            plain( "user", | _ | username() ),
+            plain( "host", | _ | host() ),
+            plain( "cwd", | x | display_dir( &x.cwd ) ),
+            plain( "time", | _ | {
+                let ( h, m, s ) = local_time();
+                format!( "{:02}:{:02}:{:02}", h, m, s )
+            } ),
+
+            // exit code of the last command, empty when it succeeded
+            plain( "status", | x | match x.status {
+                0 => String::new(),
+                x => x.to_string(),
+            } ),
+
+            // how many directories pushd has saved, empty when none
+            plain( "dirs", | x | match x.dirs {
+                0 => String::new(),
+                x => x.to_string(),
+            } ),
+
+            // just the branch, which needs no `git status`
+            plain( "git_branch", | x | git::branch( &x.cwd ).unwrap_or_default() ),
+            Box::new( Git::default() ),
+            Box::new( battery_segment() ),
+        ] )
+    }
+
+    // Adds `segment`, replacing any other with the same name.
+    pub fn add( &mut self, segment: Box<dyn PromptSegment> ) {
+        self.0.retain( | x | x.name() != segment.name() );
+        self.0.push( segment );
+    }
+
+    pub fn contains( &self, name: &str ) -> bool {
+        self.0.iter().any( | x | x.name() == name )
+    }
+
+    // Gets the segments in `names` ready for a new prompt.
+    pub fn update( &mut self, names: &[&str], state: &PromptState ) {
+        for segment in self.0.iter_mut().filter( | x | names.contains( &x.name() ) ) {
+            segment.update( state );
+        }
+    }
+
+    // The text of the segment called `name`, empty if there isn't one.
+    pub fn render( &self, name: &str, state: &PromptState ) -> String {
+        match self.0.iter().find( | x | x.name() == name ) {
+            Some( x ) => x.render( state ),
+            None => String::new(),
+        }
+    }
+
+    pub fn pending( &self ) -> bool {
+        self.0.iter().any( | x | x.pending() )
+    }
+
+    // Checks on every segment's background work, returning true if any of them changed.
+    pub fn poll( &mut self ) -> bool {
+        let mut changed = false;
+        for segment in &mut self.0 {
+            changed |= segment.poll();
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use dirs::home_dir;
+use yaml_rust::{ Yaml, YamlLoader };
+
+// entries under this key in hosts.yaml apply to every host
+const ALL_HOSTS: &str = "*";
+
+// Quotes a string so that a POSIX shell treats it as a single literal word.
+pub fn quote_posix( s: &str ) -> String {
+    let is_plain = !s.is_empty() && s.chars().all( | c |
+        c.is_ascii_alphanumeric() || "-_./=:,+@%".contains( c )
+    );
+
+    if is_plain {
+        s.to_string()
+    } else {
+        format!( "'{}'", s.replace( '\'', "'\\''" ) )
+    }
+}
+
+fn hosts_file() -> Option<PathBuf> {
+    Some( home_dir()?.join( ".config" ).join( "lumi" ).join( "hosts.yaml" ) )
+}
+
+fn load_hosts() -> Yaml {
+    let source = match hosts_file().and_then( | p | read_to_string( p ).ok() ) {
+        Some( x ) => x,
+        None => return Yaml::Null,
+    };
+
+    match YamlLoader::load_from_str( &source ) {
+        Ok( mut docs ) if !docs.is_empty() => docs.remove( 0 ),
+        _ => Yaml::Null,
+    }
+}
+
+// Environment variables configured for a host in ~/.config/lumi/hosts.yaml,
+// with the host's own entries taking priority over the "*" entries.
+pub fn host_env( host: &str ) -> BTreeMap<String, String> {
+    let hosts = load_hosts();
+    let mut env = BTreeMap::new();
+
+    for key in &[ ALL_HOSTS, host ] {
+        if let Some( vars ) = hosts[*key].as_hash() {
+            for ( k, v ) in vars {
+                let value = match v {
+                    Yaml::String( s ) => s.clone(),
+                    Yaml::Integer( i ) => i.to_string(),
+                    Yaml::Real( r ) => r.clone(),
+                    Yaml::Boolean( b ) => b.to_string(),
+                    _ => continue,
+                };
+
+                if let Some( k ) = k.as_str() {
+                    env.insert( k.to_string(), value );
+                }
+            }
+        }
+    }
+
+    env
+}
+
+// Every host name we know about, taken from ~/.ssh/config and hosts.yaml.
+pub fn known_hosts() -> Vec<String> {
+    let mut hosts = BTreeSet::new();
+
+    let ssh_config = home_dir()
+        .map( | h | h.join( ".ssh" ).join( "config" ) )
+        .and_then( | p | read_to_string( p ).ok() )
+        .unwrap_or_default();
+
+    for line in ssh_config.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some( kw ) if kw.eq_ignore_ascii_case( "host" ) => {
+                for name in words {
+                    if !name.contains( [ '*', '?', '!' ] ) {
+                        hosts.insert( name.to_string() );
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if let Some( configured ) = load_hosts().as_hash() {
+        for k in configured.keys() {
+            match k.as_str() {
+                Some( name ) if name != ALL_HOSTS => { hosts.insert( name.to_string() ); },
+                _ => {},
+            }
+        }
+    }
+
+    hosts.into_iter().collect()
+}
+
+// Builds the command line handed to ssh for running `script` on the remote side.
+pub fn remote_command( host: &str, script: &str ) -> String {
+    let env = host_env( host );
+    let mut parts = Vec::new();
+
+    if !env.is_empty() {
+        parts.push( "env".to_string() );
+        for ( k, v ) in env {
+            parts.push( quote_posix( &format!( "{}={}", k, v ) ) );
+        }
+    }
+
+    parts.push( "sh".to_string() );
+    parts.push( "-c".to_string() );
+    parts.push( quote_posix( script ) );
+
+    parts.join( " " )
+}
@@ -0,0 +1,159 @@
+use std::io::{ Result, Write, stdout };
+use crossterm::terminal;
+use unicode_width::{ UnicodeWidthChar, UnicodeWidthStr };
+use yansi::Style;
+use shell::transcript::strip_ansi;
+
+#[derive( Debug, Clone, Copy, Eq, PartialEq )]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub fn plain( ch: char ) -> Cell {
+        Cell { ch, style: Style::default() }
+    }
+
+    // Columns the character takes up on screen: two for wide ones such as 漢,
+    // none for combining marks.
+    pub fn width( &self ) -> usize {
+        self.ch.width().unwrap_or( 0 )
+    }
+}
+
+// Keeps track of what is currently on screen for the input line so each
+// keystroke only rewrites the cells that actually changed.
+pub struct Renderer {
+    prompt: String,
+    line: Vec<Cell>,
+    cursor: usize,
+    dirty: bool,
+
+    // the right-hand prompt, and whether there was room to show it
+    right: String,
+    right_shown: bool,
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new()
+    }
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer {
+            prompt: String::new(),
+            line: Vec::new(),
+            cursor: 0,
+            dirty: true,
+            right: String::new(),
+            right_shown: false,
+        }
+    }
+
+    // Forces a full repaint on the next frame, e.g. after other output moved the cursor.
+    pub fn reset( &mut self ) {
+        self.dirty = true;
+    }
+
+    // Draws `prompt` followed by `line`, with `right` at the right edge of the
+    // terminal for as long as the line leaves room for it.
+    pub fn render( &mut self, prompt: &str, right: &str, line: &[Cell], cursor: usize ) -> Result<()> {
+        let mut frame = String::new();
+
+        // columns are counted from the start of the prompt's last line
+        let indent = width( prompt.rsplit( '\n' ).next().unwrap_or_default() );
+        let ( columns, _ ) = terminal().terminal_size();
+        let columns = columns as usize;
+        let right_width = width( right );
+
+        // a gap is left before it, and the last column stays free so the
+        // terminal never wraps on its own
+        let line_width = cells_width( line );
+        let show_right = right_width > 0 && indent + line_width + right_width + 2 <= columns;
+        let right_column = columns.saturating_sub( right_width );
+        let full = self.dirty || self.prompt != prompt || self.right != right;
+        let keep_right = self.right_shown && show_right && !full;
+
+        // cleared before the line grows into it
+        if self.right_shown && !show_right && !full {
+            frame.push_str( &format!( "\x1B[{}G\x1B[K\x1B[{}G", right_column, indent + cells_width( &self.line[..self.cursor] ) + 1 ) );
+        }
+
+        // cursor positions are cells, but the terminal moves by columns
+        let cursor_column = cells_width( &line[..cursor] );
+        let written = if full {
+            frame.push( '\r' );
+            frame.push_str( prompt );
+            push_cells( &mut frame, line );
+            frame.push_str( "\x1B[K" );
+            line_width
+        } else {
+            let common = self.line.iter().zip( line ).take_while( | ( a, b ) | a == b ).count();
+            let old_width = cells_width( &self.line );
+            if common == self.line.len() && common == line.len() {
+                cells_width( &self.line[..self.cursor] )
+            } else {
+                push_move( &mut frame, cells_width( &self.line[..self.cursor] ), cells_width( &line[..common] ) );
+                push_cells( &mut frame, &line[common..] );
+                if line_width >= old_width {
+                    line_width
+                } else if keep_right {
+                    // blanked rather than cleared, which would take the right prompt with it
+                    frame.push_str( &" ".repeat( old_width - line_width ) );
+                    old_width
+                } else {
+                    frame.push_str( "\x1B[K" );
+                    line_width
+                }
+            }
+        };
+
+        push_move( &mut frame, written, cursor_column );
+
+        if show_right && !keep_right {
+            frame.push_str( &format!( "\x1B[{}G{}\x1B[{}G", right_column, right, indent + cursor_column + 1 ) );
+        }
+
+        self.right = right.to_string();
+        self.right_shown = show_right;
+        self.prompt = prompt.to_string();
+        self.line = line.to_vec();
+        self.cursor = cursor;
+        self.dirty = false;
+
+        let mut out = stdout();
+        out.write_all( frame.as_bytes() )?;
+        out.flush()
+    }
+}
+
+// Columns taken up on screen, leaving out any escape sequences.
+pub fn width( text: &str ) -> usize {
+    strip_ansi( text ).width()
+}
+
+fn cells_width( cells: &[Cell] ) -> usize {
+    cells.iter().map( Cell::width ).sum()
+}
+
+fn push_move( frame: &mut String, from: usize, to: usize ) {
+    if to < from {
+        frame.push_str( &format!( "\x1B[{}D", from - to ) );
+    } else if to > from {
+        frame.push_str( &format!( "\x1B[{}C", to - from ) );
+    }
+}
+
+// Consecutive cells with the same style are painted as a single run.
+fn push_cells( frame: &mut String, cells: &[Cell] ) {
+    let mut i = 0;
+    while i < cells.len() {
+        let style = cells[i].style;
+        let run: String = cells[i..].iter().take_while( | c | c.style == style ).map( | c | c.ch ).collect();
+        i += run.chars().count();
+        frame.push_str( &style.paint( run ).to_string() );
+    }
+}
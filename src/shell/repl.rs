@@ -0,0 +1,631 @@
+use std::io::{ Result, Write, stdin, stdout };
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::mem::take;
+use std::path::PathBuf;
+use std::time::{ Duration, Instant };
+use yansi::Paint;
+use crossterm::terminal;
+use kernel::{ stdin_is_tty, set_title, system_profile, terminal_has_focus, notify, take_interrupt, take_child_exited, signal_name, ProcessStatus };
+use shell::config::{ Config, HistoryBackend, JobNotify, PromptStyle, set_color_enabled };
+use shell::prompt::{ display_dir, Background, PromptState, Segments };
+use shell::editor::{ LineEditor, PromptSource };
+use shell::history::{ self, Backend, FileBackend, History, HISTORY };
+use shell::sqlite_history::{ self, SqliteBackend };
+use shell::frecency::{ self, Visits, VISITS };
+use shell::jobs::{ done_message, Job, JOBS };
+use shell::script::run_script;
+use shell::parsing::*;
+use shell::context::{ ExecContext, Tee };
+use shell::transcript::Transcript;
+use shell::diagnostics::Diagnostic;
+use shell::tilde;
+use shell::pager;
+use shell::integration;
+use shell::profile;
+use shell::segments::{ call, timed };
+
+pub struct Repl {
+    config: Config,
+    context: ExecContext,
+    editor: LineEditor,
+    segments: Segments,
+
+    // while set, commands run with their output captured so it can be written here too
+    transcript: Option<Transcript>,
+
+    // background jobs that finished while the prompt was up, to be reported before the next one
+    finished: Vec<( Job, i32 )>,
+}
+
+impl Repl {
+    pub fn new( config: Config ) -> Repl {
+        set_color_enabled( config.color );
+        pager::set_pager( config.pager.clone() );
+
+        let mut history = profile::measure( "history", || open_history( &config ) ).unwrap_or_else( | e | {
+            error( &config, format!( "unable to load history (reason: {})", e ) );
+            History::new( config.history_size )
+        } );
+
+        history.set_ignore( config.history_ignore.clone() );
+        *HISTORY.lock().unwrap() = history;
+
+        if let Some( path ) = frecency::default_path() {
+            match Visits::load( path ) {
+                Ok( x ) => *VISITS.lock().unwrap() = x,
+                Err( e ) => error( &config, format!( "unable to load visited directories (reason: {})", e ) ),
+            }
+        }
+
+        let mut context = ExecContext::new();
+        context.set_aliases( config.aliases.clone() );
+        context.set_not_found_hook( config.command_not_found.clone() );
+
+        let transcript = config.transcript.as_ref().and_then( | path | match Transcript::open( path ) {
+            Ok( x ) => Some( x ),
+            Err( e ) => {
+                error( &config, format!( "unable to open transcript {} (reason: {})", path.display(), e ) );
+                None
+            },
+        } );
+
+        // without colors a suggestion couldn't be told apart from what was typed
+        let mut editor = LineEditor::new();
+        if !config.autosuggest || !config.color {
+            editor.set_suggester( None );
+        }
+
+        let mut segments = Segments::builtin();
+        for ( name, command ) in &config.segments {
+            segments.add( Box::new( Background::command( name, command ) ) );
+        }
+
+        Repl {
+            config,
+            context,
+            editor,
+            segments,
+            transcript,
+            finished: Vec::new(),
+        }
+    }
+
+    // Reads and runs command lines until the user ends the session with `exit`
+    // or end-of-input, returning the status the process should exit with.
+    pub fn run( &mut self ) -> i32 {
+        profile::measure( "scripts", || self.run_profile() );
+
+        // started with --profile-startup, which is over once the prompt is about to show
+        if profile::enabled() {
+            self.show_profile();
+        }
+        if let Some( code ) = self.context.exit_requested() {
+            return code;
+        }
+
+        loop {
+            let mut finished = take( &mut self.finished );
+            if take_child_exited() {
+                finished.extend( JOBS.lock().unwrap().reap() );
+            }
+
+            for ( job, code ) in finished {
+                println!( "{}", done_message( &job, code ) );
+            }
+
+            let precmd = self.config.precmd.clone();
+            self.run_hook( "precmd", precmd, Vec::new() );
+
+            if self.config.title {
+                set_title( &format!( "lumi: {}", display_dir( self.context.cwd() ) ) );
+            }
+
+            if self.config.shell_integration {
+                integration::report_cwd( self.context.cwd() );
+            }
+
+            if self.config.share_history {
+                if let Err( e ) = HISTORY.lock().unwrap().reload() {
+                    error( &self.config, format!( "unable to load history (reason: {})", e ) );
+                }
+            }
+
+            let prompt = self.prompt();
+            match self.read_input( &prompt, true ) {
+                Ok( Some( mut line ) ) => {
+                    // keep reading until every here-document has its closing line
+                    while needs_more_input( &line ) {
+                        match self.read_input( "> ", false ) {
+                            Ok( Some( next ) ) => {
+                                line.push( '\n' );
+                                line.push_str( &next );
+                            },
+
+                            _ => break,
+                        }
+                    }
+
+                    if line.trim().is_empty() {
+                        println!();
+                        continue;
+                    }
+
+                    // !! and !n are replaced before anything else sees the line
+                    let expanded = history::expand( &line, HISTORY.lock().unwrap().entries() );
+                    match expanded {
+                        Ok( Some( x ) ) => {
+                            println!( "{}", x );
+                            line = x;
+                        },
+
+                        Ok( None ) => {},
+                        Err( e ) => {
+                            error( &self.config, e );
+                            continue;
+                        },
+                    }
+
+                    if let Err( e ) = HISTORY.lock().unwrap().add( &line, self.context.cwd() ) {
+                        error( &self.config, format!( "unable to save history (reason: {})", e ) );
+                    }
+
+                    if let Some( ref mut transcript ) = self.transcript {
+                        if let Err( e ) = transcript.command( &prompt, &line ) {
+                            error( &self.config, format!( "unable to write transcript (reason: {})", e ) );
+                        }
+                    }
+
+                    let dir = self.context.cwd().to_path_buf();
+                    self.execute( &line );
+                    stdout().flush().unwrap();
+
+                    if self.config.shell_integration {
+                        integration::command_finished( self.context.last_status() );
+                    }
+
+                    // every directory changed to, however it happened, is remembered for `z`
+                    if self.context.cwd() != dir {
+                        if let Err( e ) = VISITS.lock().unwrap().visit( self.context.cwd() ) {
+                            error( &self.config, format!( "unable to save visited directories (reason: {})", e ) );
+                        }
+                    }
+
+                    if let Some( code ) = self.context.exit_requested() {
+                        return code;
+                    }
+                },
+                Ok( None ) => return self.context.last_status(),
+                Err( e ) => {
+                    error( &self.config, format!( "unable to read from STDIN (reason: {})", e ) );
+                }
+            }
+        }
+    }
+
+    fn show_profile( &self ) {
+        for line in profile::stop() {
+            println!( "{}", Paint::new( line ).dimmed() );
+        }
+    }
+
+    fn run_profile( &mut self ) {
+        let profile = self.config.profile.clone();
+        self.run_file( profile );
+    }
+
+    // The scripts a login shell runs once, before the usual profile: the
+    // system-wide one and then the user's.
+    pub fn run_login_profiles( &mut self ) {
+        self.run_file( system_profile() );
+
+        let profile = self.config.login_profile.clone();
+        self.run_file( profile );
+    }
+
+    fn run_file( &mut self, path: Option<PathBuf> ) {
+        let path = match path {
+            Some( x ) if x.exists() => x,
+            _ => return,
+        };
+
+        let res = read_to_string( &path ).map_err( | e | e.to_string() ).and_then( | source | {
+            run_script( &mut self.context, &source ).map_err( | e | e.to_string() )
+        } );
+
+        if let Err( e ) = res {
+            error( &self.config, format!( "{}: {}", path.display(), e ) );
+        }
+    }
+
+    // `:record FILE` starts appending the session to FILE, and `:record` alone stops.
+    fn record( &mut self, path: &str ) {
+        if path.is_empty() {
+            match self.transcript.take() {
+                Some( x ) => println!( "stopped recording to {}", x.path().display() ),
+                None => error( &self.config, "not recording" ),
+            }
+
+            return;
+        }
+
+        let path = tilde::expand( path );
+        match Transcript::open( path.as_ref() ) {
+            Ok( x ) => {
+                println!( "recording to {}", x.path().display() );
+                self.transcript = Some( x );
+            },
+
+            Err( e ) => error( &self.config, format!( "unable to open transcript {} (reason: {})", path, e ) ),
+        }
+    }
+
+    fn execute( &mut self, line: &str ) {
+        let ( line, meta ) = meta_command( line );
+        if meta == Some( ":record" ) {
+            return self.record( line.trim() );
+        }
+
+        if meta == Some( ":profile" ) {
+            profile::start();
+            self.execute( line );
+            return self.show_profile();
+        }
+
+        let mut lexer = ShellLexer::new( line );
+        let tokens = match profile::measure( "lex", || lexer.tokenize() ) {
+            Ok( tks ) => tks,
+            Err( e ) => {
+                self.show_diagnostic( Diagnostic::from( &e ), line );
+                return;
+            },
+        };
+
+        let mut parser = ShellParser::new( tokens );
+        let seg = match profile::measure( "parse", || parser.parse_recovering() ) {
+            Ok( seg ) => seg,
+            Err( errors ) => {
+                for e in &errors {
+                    self.show_diagnostic( Diagnostic::from( e ), line );
+                }
+
+                return;
+            },
+        };
+
+        match meta {
+            Some( ":ast" ) => return print!( "{}", seg.describe() ),
+            Some( _ ) => return println!( "no syntax errors" ),
+            None => {},
+        }
+
+        let preexec = self.config.preexec.clone();
+        self.run_hook( "preexec", preexec, vec![ line.to_string() ] );
+
+        if self.config.title {
+            set_title( line.split_whitespace().next().unwrap_or_default() );
+        }
+
+        if self.config.shell_integration {
+            integration::command_started();
+        }
+
+        take_interrupt();
+        let started = Instant::now();
+        let res = match self.transcript {
+            // programs write to a pipe rather than the terminal while recording,
+            // so their output can be both shown and kept
+            Some( _ ) => self.context.with_tee( Tee::ALL, | ctx | timed( &seg, ctx, true, None ) ),
+            None => timed( &seg, &mut self.context, false, None ),
+        };
+
+        if let Some( ref mut transcript ) = self.transcript {
+            let written = match res {
+                Ok( ref x ) => transcript.output( x.stdout().into_iter().flatten().chain( x.stderr().into_iter().flatten() ) ),
+                Err( ref e ) => transcript.output( &[ e.to_string() ] ),
+            };
+
+            if let Err( e ) = written {
+                error( &self.config, format!( "unable to write transcript (reason: {})", e ) );
+            }
+        }
+
+        let code = res.as_ref().ok().and_then( | r | r.code() );
+        let elapsed = res.as_ref().ok().and_then( | r | r.elapsed() ).unwrap_or_else( || started.elapsed() );
+        self.context.set_last_status( code.unwrap_or( 1 ) );
+        self.notify_if_slow( line, elapsed, code );
+
+        if let Err( e ) = HISTORY.lock().unwrap().finish( code.unwrap_or( 1 ), elapsed ) {
+            error( &self.config, format!( "unable to save history (reason: {})", e ) );
+        }
+
+        // the terminal echoes ^C without ending the line
+        if take_interrupt() {
+            println!();
+        }
+
+        if let Err( e ) = res {
+            println!();
+            error( &self.config, e );
+            println!();
+        } else if let Ok( status @ ProcessStatus::Signaled( x ) ) = res.as_ref().map( | x | x.status() ) {
+            // Ctrl-C and closed pipes are how programs are expected to stop
+            if !matches!( signal_name( x ), Some( "SIGINT" ) | Some( "SIGPIPE" ) ) {
+                error( &self.config, status );
+            }
+        }
+
+        match self.config.show_duration_after {
+            Some( limit ) if elapsed >= limit => {
+                println!( "{}", Paint::new( format!( "took {}", format_duration( elapsed ) ) ).dimmed() );
+            },
+
+            _ => {},
+        }
+    }
+
+    // Runs a hook from the config with its output going to the terminal. It
+    // doesn't count as a command, so $? is left as it was.
+    fn run_hook( &mut self, name: &str, hook: Option<String>, args: Vec<String> ) {
+        let hook = match hook {
+            Some( x ) => x,
+            None => return,
+        };
+
+        let status = self.context.last_status();
+        if let Err( e ) = call( &mut self.context, &hook, args, false, None ) {
+            error( &self.config, format!( "{} hook failed (reason: {})", name, e ) );
+        }
+
+        self.context.set_last_status( status );
+    }
+
+    fn notify_if_slow( &self, line: &str, elapsed: Duration, code: Option<i32> ) {
+        match self.config.notify_after {
+            Some( limit ) if elapsed >= limit => {},
+            _ => return,
+        }
+
+        // don't bother the user if they're already looking at the terminal
+        if terminal_has_focus() == Some( true ) {
+            return;
+        }
+
+        let status = match code {
+            Some( 0 ) => "finished".to_string(),
+            Some( x ) => format!( "failed with exit code {}", x ),
+            None => "failed".to_string(),
+        };
+
+        let body = format!( "{} ({} after {}s)", line, status, elapsed.as_secs() );
+        let _ = notify( "lumi", &body );
+    }
+
+    // Reads a line after showing `prompt`, which is kept up to date with the
+    // segments that finish while waiting when `live` is set.
+    fn read_input( &mut self, prompt: &str, live: bool ) -> Result<Option<String>> {
+        if stdin_is_tty() {
+            let history = HISTORY.lock().unwrap();
+            if !live {
+                return self.editor.read_line( prompt, history.entries() );
+            }
+
+            let mut drawer = PromptDrawer {
+                state: self.prompt_state(),
+                config: &self.config,
+                segments: &mut self.segments,
+                finished: &mut self.finished,
+            };
+
+            self.editor.set_right_prompt( &drawer.draw_right() );
+            return self.editor.read_line_with( prompt, history.entries(), Some( &mut drawer ) );
+        }
+
+        print!( "{}", prompt );
+        stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin().read_line( &mut line )? == 0 {
+            return Ok( None );
+        }
+
+        Ok( Some( line.trim_end_matches( [ '\r', '\n' ] ).to_string() ) )
+    }
+
+    // Reports a mistake in a command line, showing the part of `input` at fault.
+    fn show_diagnostic( &self, diagnostic: Diagnostic, input: &str ) {
+        let width = match terminal().terminal_size() {
+            ( 0, _ ) => 80,
+            ( x, _ ) => x as usize,
+        };
+
+        for line in diagnostic.render( input, width, self.config.palette.error ) {
+            println!( "{}", line );
+        }
+
+        stdout().flush().unwrap();
+    }
+
+    fn prompt_state( &self ) -> PromptState {
+        PromptState {
+            cwd: self.context.cwd().to_path_buf(),
+            status: self.context.last_status(),
+            dirs: self.context.dir_stack().len(),
+        }
+    }
+
+    fn prompt( &mut self ) -> String {
+        let drawer = PromptDrawer {
+            state: self.prompt_state(),
+            config: &self.config,
+            segments: &mut self.segments,
+            finished: &mut self.finished,
+        };
+
+        let names = drawer.names();
+        drawer.segments.update( &names, &drawer.state );
+        drawer.draw()
+    }
+}
+
+// Lays the segments out in the configured style.
+struct PromptDrawer<'a> {
+    config: &'a Config,
+    segments: &'a mut Segments,
+    state: PromptState,
+    finished: &'a mut Vec<( Job, i32 )>,
+}
+
+impl<'a> PromptDrawer<'a> {
+    // The segments the prompt style shows.
+    fn names( &self ) -> Vec<&'a str> {
+        let mut names = match self.config.prompt {
+            PromptStyle::Full => vec![ "user", "host", "cwd", "git" ],
+            PromptStyle::Compact => vec![ "cwd", "git" ],
+            PromptStyle::Minimal => vec![],
+            PromptStyle::Template( ref t ) => t.names(),
+        };
+
+        for template in self.config.right_prompt.iter().chain( self.config.transient_prompt.iter() ) {
+            names.extend( template.names() );
+        }
+
+        names.into_iter().filter( | x | self.config.git || !x.starts_with( "git" ) ).collect()
+    }
+
+    fn segment( &self, name: &str ) -> String {
+        if !self.config.git && name.starts_with( "git" ) {
+            return String::new();
+        }
+
+        self.segments.render( name, &self.state )
+    }
+
+    fn draw( &self ) -> String {
+        self.marked( self.layout() )
+    }
+
+    // `prompt` with the shell integration marks around it, when they're wanted.
+    fn marked( &self, prompt: String ) -> String {
+        if self.config.shell_integration { integration::mark_prompt( &prompt ) } else { prompt }
+    }
+
+    fn layout( &self ) -> String {
+        let palette = &self.config.palette;
+        let status = match self.config.failure_indicator {
+            Some( ref x ) if self.state.status != 0 => format!( "{} ", Paint::new( x ).fg( palette.error ) ),
+            _ => String::new(),
+        };
+
+        let branch = match self.segment( "git" ) {
+            ref x if x.is_empty() => String::new(),
+            x => format!( " ({})", Paint::new( x ).fg( palette.git ) ),
+        };
+
+        match self.config.prompt {
+            PromptStyle::Full => format!(
+                "{status}${user}@{machine}[{dir}]{branch}> ",
+                status  = status,
+                user    = Paint::new( self.segment( "user" ) ).fg( palette.user ),
+                machine = Paint::new( self.segment( "host" ) ).fg( palette.host ).dimmed(),
+                dir     = Paint::new( self.segment( "cwd" ) ).fg( palette.dir ).dimmed(),
+                branch  = branch
+            ),
+
+            PromptStyle::Compact => format!(
+                "{status}[{dir}]{branch}> ",
+                status  = status,
+                dir     = Paint::new( self.segment( "cwd" ) ).fg( palette.dir ).dimmed(),
+                branch  = branch
+            ),
+
+            PromptStyle::Minimal => format!( "{}> ", status ),
+            PromptStyle::Template( ref template ) => status + &template.render( | name | self.segment( name ) ),
+        }
+    }
+
+    fn draw_right( &self ) -> String {
+        match self.config.right_prompt {
+            Some( ref template ) => template.render( | name | self.segment( name ) ),
+            None => String::new(),
+        }
+    }
+}
+
+impl<'a> PromptSource for PromptDrawer<'a> {
+    // Jobs are watched for as well while they're to be announced straight away.
+    fn pending( &self ) -> bool {
+        self.segments.pending() || ( self.config.job_notify != JobNotify::Prompt && !JOBS.lock().unwrap().jobs().is_empty() )
+    }
+
+    fn refresh( &mut self ) -> Option<( String, String )> {
+        if self.config.job_notify != JobNotify::Prompt && take_child_exited() {
+            let done = JOBS.lock().unwrap().reap();
+            for ( job, code ) in &done {
+                match self.config.job_notify {
+                    JobNotify::Bell => print!( "\x07" ),
+                    JobNotify::Osc => print!( "\x1B]9;{}\x07", done_message( job, *code ) ),
+                    JobNotify::Prompt => {},
+                }
+            }
+
+            let _ = stdout().flush();
+            self.finished.extend( done );
+        }
+
+        if self.segments.poll() { Some( ( self.draw(), self.draw_right() ) ) } else { None }
+    }
+
+    fn transient( &self ) -> Option<String> {
+        self.config.transient_prompt.as_ref().map( | x | self.marked( x.render( | name | self.segment( name ) ) ) )
+    }
+}
+
+// The history saved by the configured backend, or one that isn't saved
+// anywhere when there's no home directory to keep it in.
+fn open_history( config: &Config ) -> Result<History> {
+    let backend: Box<dyn Backend> = match config.history_backend {
+        HistoryBackend::File => match history::default_path() {
+            Some( path ) => Box::new( FileBackend::new( path ) ),
+            None => return Ok( History::new( config.history_size ) ),
+        },
+
+        HistoryBackend::Sqlite => match sqlite_history::default_path() {
+            Some( path ) => Box::new( SqliteBackend::open( &path )? ),
+            None => return Ok( History::new( config.history_size ) ),
+        },
+    };
+
+    History::open( backend, config.history_size )
+}
+
+fn error<D: Display>( config: &Config, msg: D ) {
+    let painted = Paint::new( msg ).fg( config.palette.error ).dimmed();
+    println!( "{}", painted );
+}
+
+// `:ast ...` shows how the rest of the line parses and `:check ...` only reports
+// whether it does; neither runs it. `:record` starts or stops a transcript and
+// `:profile ...` runs the rest of the line, then shows where the time went.
+// Returns the rest of the line and the command.
+fn meta_command( line: &str ) -> ( &str, Option<&'static str> ) {
+    for name in [ ":ast", ":check", ":record", ":profile" ] {
+        match line.trim_start().strip_prefix( name ) {
+            Some( rest ) if rest.is_empty() || rest.starts_with( char::is_whitespace ) => return ( rest, Some( name ) ),
+            _ => {},
+        }
+    }
+
+    ( line, None )
+}
+
+// e.g. 850ms, 3.2s, 4m 10s or 2h 5m
+fn format_duration( duration: Duration ) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!( "{}ms", duration.as_millis() ),
+        1 ..= 59 => format!( "{:.1}s", duration.as_secs_f64() ),
+        60 ..= 3599 => format!( "{}m {}s", secs / 60, secs % 60 ),
+        _ => format!( "{}h {}m", secs / 3600, secs % 3600 / 60 ),
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::{ Display, Formatter };
+use shell::parsing::{ ShellLexer, ShellParser, needs_more_input };
+use shell::context::ExecContext;
+use shell::profile;
+use kernel::ProcessStatus;
+use shell::segments::{ Exec, ShellResult, concat, errexit };
+
+#[derive( Debug )]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ScriptError {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        write!( formatter, "line {}: {}", self.line, self.message )
+    }
+}
+
+// Splits a script into the statements it runs, numbered by the line each one
+// starts on. Blank lines and lines starting with # are skipped, and a statement
+// left unfinished at the end of a line (an open block, string or here-document,
+// a trailing operator or backslash) takes in the lines that finish it.
+fn statements( source: &str ) -> Vec<( usize, String )> {
+    let mut statements = Vec::new();
+
+    let mut lines = source.lines().enumerate();
+    while let Some( ( i, line ) ) = lines.next() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with( '#' ) {
+            continue;
+        }
+
+        let mut text = line.to_string();
+        while needs_more_input( &text ) {
+            match lines.next() {
+                Some( ( _, next ) ) => {
+                    text.push( '\n' );
+                    text.push_str( next );
+                },
+
+                None => break,
+            }
+        }
+
+        statements.push( ( i + 1, text ) );
+    }
+
+    statements
+}
+
+fn parse( line: usize, text: &str ) -> Result<Exec, ScriptError> {
+    let fail = | message: String | ScriptError { line, message };
+    let tokens = profile::measure( "lex", || ShellLexer::new( text ).tokenize() ).map_err( | e | fail( e.to_string() ) )?;
+    profile::measure( "parse", || ShellParser::new( tokens ).parse_all() ).map_err( | e | fail( e.to_string() ) )
+}
+
+// Runs each line of a script in turn, stopping at the first line that fails
+// to parse or execute, that asks the shell to exit, or with `set -e` that
+// finishes with a non-zero status. The result has the
+// status of the last command and, when `capture` is set, everything the
+// script printed.
+pub fn execute_script( ctx: &mut ExecContext, source: &str, capture: bool ) -> Result<ShellResult, ScriptError> {
+    let mut out: Option<ShellResult> = None;
+
+    for ( line, text ) in statements( source ) {
+        let seg = parse( line, &text )?;
+        let mut res = seg.execute( ctx, capture, None ).map_err( | e | {
+            ctx.set_last_status( 1 );
+            ScriptError { line, message: e.to_string() }
+        } )?;
+
+        // anything not captured has already been shown
+        if !capture {
+            res.stdout = None;
+            res.stderr = None;
+        }
+
+        ctx.set_last_status( res.code().unwrap_or( 1 ) );
+        let failed = errexit( ctx, &seg, &res );
+        out = Some( match out {
+            Some( x ) => concat( x, res ),
+            None => res,
+        } );
+
+        if ctx.exit_requested().is_some() || failed {
+            break;
+        }
+    }
+
+    Ok( out.unwrap_or( ShellResult::with_status( ProcessStatus::Exited( 0 ) ) ) )
+}
+
+// Runs a script with its output going straight to the terminal. Returns the
+// exit code of the last command, or the one the script exited with.
+pub fn run_script( ctx: &mut ExecContext, source: &str ) -> Result<i32, ScriptError> {
+    let res = execute_script( ctx, source, false )?;
+    Ok( ctx.exit_requested().unwrap_or_else( || res.code().unwrap_or( 1 ) ) )
+}
+
+// The parse tree of every line of a script, without running any of it.
+pub fn dump_ast( source: &str ) -> Result<String, ScriptError> {
+    let mut out = String::new();
+    for ( line, text ) in statements( source ) {
+        out.push_str( &format!( "line {}: {}\n", line, text ) );
+        out.push_str( &parse( line, &text )?.describe().to_string() );
+    }
+
+    Ok( out )
+}
+
+// Parses every line of a script without running any of it, returning the
+// problems found on all of them rather than stopping at the first.
+pub fn check_script( source: &str ) -> Vec<ScriptError> {
+    statements( source ).into_iter().filter_map( | ( line, text ) | parse( line, &text ).err() ).collect()
+}
@@ -0,0 +1,56 @@
+use std::io::{ Read, Result, Error, ErrorKind, Write, stdin, stdout };
+use kernel::{ enable_raw_mode, disable_raw_mode, load_secret };
+
+// prefix used to reference a secret from a variable expansion, e.g. $secret:API_TOKEN
+pub const PREFIX: &str = "secret:";
+
+pub fn check_name( name: &str ) -> Result<()> {
+    let is_valid = !name.is_empty() && name.chars().all( | c | c.is_ascii_alphanumeric() || c == '_' );
+    if is_valid {
+        Ok( () )
+    } else {
+        Err( Error::new(
+            ErrorKind::InvalidInput,
+            format!( "'{}' is not a valid secret name (use letters, digits and underscores)", name )
+        ) )
+    }
+}
+
+pub fn lookup( name: &str ) -> Result<String> {
+    check_name( name )?;
+    load_secret( name )?.ok_or_else( || Error::new(
+        ErrorKind::NotFound,
+        format!( "secret '{}' not found (store it with 'secret set {}')", name, name )
+    ) )
+}
+
+// Reads a line from the terminal without echoing it.
+pub fn read_hidden( prompt: &str ) -> Result<String> {
+    print!( "{}", prompt );
+    stdout().flush()?;
+
+    unsafe { enable_raw_mode()?; }
+
+    let mut value = Vec::new();
+    let mut res = Ok( () );
+    for b in stdin().lock().bytes() {
+        match b {
+            Ok( b'\r' ) | Ok( b'\n' ) => break,
+            Ok( 0x03 ) => {
+                res = Err( Error::new( ErrorKind::Interrupted, "cancelled" ) );
+                break;
+            },
+            Ok( 0x7F ) | Ok( 0x08 ) => { value.pop(); },
+            Ok( x ) => value.push( x ),
+            Err( e ) => {
+                res = Err( e );
+                break;
+            },
+        }
+    }
+
+    unsafe { disable_raw_mode()?; }
+    println!();
+
+    res.map( | _ | String::from_utf8_lossy( &value ).into_owned() )
+}
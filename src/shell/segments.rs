@@ -1,84 +1,142 @@
-use std::io::{ BufRead, BufReader, Read, Write, Result, Error, ErrorKind };
-use std::collections::HashMap;
+use std::io::{ BufRead, BufReader, PipeReader, Read, Write, Result, Error, ErrorKind, pipe };
 use std::boxed::Box;
-use std::fs::File;
+use std::fs::{ File, OpenOptions };
 use std::path::Path;
-use std::process::{ Command, Child, Stdio };
-use std::env::{ VarError, var, set_var };
-use kernel::{ get_exit_code, clear_screen };
+use std::ops::Range;
+use serde_json::Value;
+use std::process::{ Command, Child, ChildStdout, ExitStatus, Output, Stdio };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+use std::env::VarError;
+use kernel::{ process_status, ProcessStatus, interrupted, terminate, run_in_pseudo_console, new_process_group, is_exec_format_error, argument_size, argument_space, Foreground };
 use std::any::Any;
-use clap::{ App, AppSettings };
+use std::fmt::{ Display, Formatter };
+use shell::remote::{ quote_posix, remote_command };
+use shell::glob;
+use shell::profile;
+use shell::path;
+use shell::fuzzy;
+use shell::tilde;
+use shell::expand;
+use shell::secret;
+use shell::context::{ ExecContext, Tee };
+use shell::jobs::{ self, JOBS };
+use shell::builtins;
+use shell::pager;
 
-type CommandAction = fn( Vec<String>, Option<Vec<String>> ) -> Result<ShellResult>;
+#[derive( Debug )]
+pub struct ShellResult {
+    pub( crate ) status: ProcessStatus,
+    pub( crate ) stdout: Option<Vec<String>>,
+    pub( crate ) stderr: Option<Vec<String>>,
 
-macro_rules! make_app {
-    ( $y: expr ) => {{
-        App::from_yaml( $y )
-            .author( crate_authors!() )
-            .version( crate_version!() )
-            .setting( AppSettings::ColoredHelp )
-            .setting( AppSettings::ColorAuto )
-    }}
-}
+    // how long the command took, for results from `timed`
+    pub( crate ) elapsed: Option<Duration>,
 
-fn change_dir( argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
-    let yaml = load_yaml!( "cli_args/cd.yaml" );
-    match make_app!( yaml ).get_matches_from_safe( argv ) {
-        Ok( args ) => {
-            println!( "{:#?}", args.value_of( "DIR" ) );
-            ShellResult::ok()
-        },
+    // the data behind stdout when it was written as JSON, handed to the next
+    // stage of a pipeline that understands it instead of being parsed again
+    pub( crate ) value: Option<Value>,
 
-        Err( e ) => {
-            eprintln!( "{}", e );
-            ShellResult::ok()
-        }
-    }
+    // exactly what a program wrote, for when its output goes on to a file or
+    // another program rather than being used by the shell
+    pub( crate ) raw: Option<RawOutput>,
 }
 
-fn clear( _argv: Vec<String>, _input: Option<Vec<String>> ) -> Result<ShellResult> {
-    unsafe { clear_screen(); }
-    ShellResult::ok()
+// A program's output as bytes: line endings, whether it ends in a newline and
+// anything that isn't UTF-8 are all kept, which the lines lose.
+#[derive( Debug, Default )]
+pub( crate ) struct RawOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
 }
 
-lazy_static! {
-    static ref COMMANDS: HashMap<&'static str, CommandAction> = {
-        let mut map = HashMap::new();
-
-        map.insert( "cd", change_dir as CommandAction );
-        map.insert( "cls", clear as CommandAction );
-        map.insert( "clear", clear as CommandAction );
-
-        map
-    };
+// `raw` if `lines` were read from it and haven't been changed since, or else
+// the lines written out one after another.
+fn exact_bytes( lines: Option<&Vec<String>>, raw: Option<&Vec<u8>> ) -> Option<Vec<u8>> {
+    let lines = lines?;
+    match raw {
+        Some( raw ) if SubProcess::split_lines( String::from_utf8_lossy( raw ).into_owned() ) == *lines => Some( raw.clone() ),
+        _ => Some( lines.iter().flat_map( | x | x.bytes().chain( Some( b'\n' ) ) ).collect() ),
+    }
 }
 
-#[derive( Debug )]
-pub struct ShellResult {
-    code: Option<i32>,
-    stdout: Option<Vec<String>>,
-    stderr: Option<Vec<String>>
+// What to write to a program's stdin for `input`: the bytes behind it when the
+// stage before was a program, or else its lines.
+pub( crate ) fn input_bytes( ctx: &mut ExecContext, input: Option<Vec<String>> ) -> Option<Vec<u8>> {
+    exact_bytes( input.as_ref(), ctx.take_piped_bytes().as_ref() )
 }
 
 impl ShellResult {
+    // the exit status as $? shows it
     pub fn code( &self ) -> Option<i32> {
-        self.code
+        self.status.code()
     }
 
-    pub fn ok() -> Result<ShellResult> {
-        Ok( ShellResult {
-            code: Some( 0 ),
+    pub fn status( &self ) -> ProcessStatus {
+        self.status
+    }
+
+    pub fn stdout( &self ) -> Option<&Vec<String>> {
+        self.stdout.as_ref()
+    }
+
+    pub fn stderr( &self ) -> Option<&Vec<String>> {
+        self.stderr.as_ref()
+    }
+
+    pub fn value( &self ) -> Option<&Value> {
+        self.value.as_ref()
+    }
+
+    // What stdout holds as bytes, exactly as the program wrote it if it came
+    // straight from one.
+    pub( crate ) fn stdout_bytes( &self ) -> Option<Vec<u8>> {
+        exact_bytes( self.stdout.as_ref(), self.raw.as_ref().map( | x | &x.stdout ) )
+    }
+
+    pub( crate ) fn stderr_bytes( &self ) -> Option<Vec<u8>> {
+        exact_bytes( self.stderr.as_ref(), self.raw.as_ref().map( | x | &x.stderr ) )
+    }
+
+    pub fn elapsed( &self ) -> Option<Duration> {
+        self.elapsed
+    }
+
+    // A result with only an exit status, and no output.
+    pub fn with_status( status: ProcessStatus ) -> ShellResult {
+        ShellResult {
+            status,
             stdout: None,
-            stderr: None
-        } )
+            stderr: None,
+            elapsed: None,
+            value: None,
+            raw: None,
+        }
+    }
+
+    // Empty `lines` are no output at all, for both of these.
+    pub fn with_stdout( status: ProcessStatus, lines: Vec<String> ) -> ShellResult {
+        ShellResult { stdout: Some( lines ).filter( | x | !x.is_empty() ), ..ShellResult::with_status( status ) }
+    }
+
+    pub fn with_stderr( status: ProcessStatus, lines: Vec<String> ) -> ShellResult {
+        ShellResult { stderr: Some( lines ).filter( | x | !x.is_empty() ), ..ShellResult::with_status( status ) }
+    }
+
+    pub fn ok() -> Result<ShellResult> {
+        Ok( ShellResult::with_status( ProcessStatus::Exited( 0 ) ) )
     }
 
     pub fn  ok_with_text( s: String ) -> Result<ShellResult> {
-        Ok( ShellResult {
-            code: Some( 0 ),
-            stdout: Some( vec![ s ] ),
-            stderr: None
-        } )
+        Ok( ShellResult::with_stdout( ProcessStatus::Exited( 0 ), vec![ s ] ) )
+    }
+
+    pub fn failed( message: String ) -> Result<ShellResult> {
+        Ok( ShellResult::with_stderr( ProcessStatus::Exited( 1 ), vec![ message ] ) )
+    }
+
+    pub fn ok_with_lines( lines: Vec<String> ) -> Result<ShellResult> {
+        Ok( ShellResult::with_stdout( ProcessStatus::Exited( 0 ), lines ) )
     }
 }
 
@@ -90,11 +148,117 @@ macro_rules! ensure_result {
     }}
 }
 
+// One node of a parsed command line, printed as an indented tree by `:ast` and `--dump-ast`.
+#[derive( Debug, Clone, Eq, PartialEq )]
+pub struct AstNode {
+    pub label: String,
+    pub children: Vec<AstNode>,
+}
+
+impl AstNode {
+    pub fn new<S: Into<String>>( label: S, children: Vec<AstNode> ) -> AstNode {
+        AstNode { label: label.into(), children }
+    }
+
+    pub fn leaf<S: Into<String>>( label: S ) -> AstNode {
+        AstNode::new( label, Vec::new() )
+    }
+
+    fn write_children( &self, formatter: &mut Formatter<'_>, indent: &str ) -> std::fmt::Result {
+        for ( i, child ) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            writeln!( formatter, "{}{}{}", indent, if last { "└─ " } else { "├─ " }, child.label )?;
+            child.write_children( formatter, &format!( "{}{}", indent, if last { "   " } else { "│  " } ) )?;
+        }
+
+        Ok( () )
+    }
+}
+
+impl Display for AstNode {
+    fn fmt( &self, formatter: &mut Formatter<'_> ) -> std::fmt::Result {
+        writeln!( formatter, "{}", self.label )?;
+        self.write_children( formatter, "" )
+    }
+}
+
 pub trait Executable {
-    fn execute( &self, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult>;
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult>;
     fn as_any( &self ) -> &dyn Any;
+
+    // Renders the segment as an equivalent POSIX sh command line.
+    fn to_posix( &self ) -> String;
+
+    // The segment and everything inside it, for looking at how a line was parsed.
+    fn describe( &self ) -> AstNode;
+
+    // The external program this segment would run, if it is exactly one. Pipelines
+    // use this to spawn their stages concurrently with connected stdio handles.
+    fn process( &self, _ctx: &mut ExecContext ) -> Result<Option<Command>> {
+        Ok( None )
+    }
+
+    // Whether running the segment may read its input. Only builtins are known
+    // not to; any program might.
+    fn reads_input( &self ) -> bool {
+        true
+    }
+}
+
+// Hands piped input to the first of several commands that reads it. The ones
+// after it get an empty input, as they would reading a pipe that's been drained,
+// rather than the terminal.
+fn share_input( input: &mut Option<Vec<String>>, seg: &Exec ) -> Option<Vec<String>> {
+    if seg.reads_input() {
+        input.as_mut().map( std::mem::take )
+    } else {
+        None
+    }
+}
+
+// Runs `seg`, recording how long it took in the result.
+pub fn timed( seg: &Exec, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let started = Instant::now();
+    let mut res = seg.execute( ctx, capture, input )?;
+    res.elapsed = Some( started.elapsed() );
+
+    Ok( res )
+}
+
+// The result of running `left` and then `right`: right's status, with
+// whatever both of them captured.
+pub( crate ) fn concat( left: ShellResult, mut right: ShellResult ) -> ShellResult {
+    for ( before, after ) in [ ( left.stdout, &mut right.stdout ), ( left.stderr, &mut right.stderr ) ] {
+        if let Some( mut lines ) = before {
+            lines.append( &mut after.take().unwrap_or_default() );
+            *after = Some( lines );
+        }
+    }
+
+    right
+}
+
+// Runs `seg` with its output captured in a fork of `ctx`, like sh does for $(...).
+fn subshell( ctx: &mut ExecContext, seg: &Exec ) -> Result<ShellResult> {
+    let mut fork = ctx.fork();
+    let mut res = seg.execute( &mut fork, true, None );
+    ctx.restore()?;
+
+    // the output is being interpolated, so trailing blank lines go like in sh
+    if let Ok( Some( lines ) ) = res.as_mut().map( | x | &mut x.stdout ) {
+        while lines.last().is_some_and( | x | x.is_empty() ) {
+            lines.pop();
+        }
+    }
+
+    res
+}
+
+fn posix_subshell( seg: &Exec ) -> String {
+    format!( "$( {} )", seg.to_posix() )
 }
 
+#[allow( clippy::enum_variant_names )]
 #[derive( Debug, Eq, PartialEq )]
 pub enum RedirectMode {
     StdIn,
@@ -103,30 +267,52 @@ pub enum RedirectMode {
     StdBoth,
 }
 
+#[derive( Debug, Eq, PartialEq )]
+pub enum AndOrMode {
+    And,
+    Or,
+}
+
 pub type Exec = Box<dyn Executable>;
 
 pub struct Empty;
 
 impl Executable for Empty {
-    fn execute( &self, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    fn execute( &self, _ctx: &mut ExecContext, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
         ShellResult::ok()
     }
 
+    fn describe( &self ) -> AstNode {
+        AstNode::leaf( "Empty" )
+    }
+
     fn as_any( &self ) -> &dyn Any {
         self
     }
+
+    fn to_posix( &self ) -> String {
+        ":".to_string()
+    }
 }
 
 pub struct Text( pub String );
 
 impl Executable for Text {
-    fn execute( &self, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+    fn execute( &self, _ctx: &mut ExecContext, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
         ShellResult::ok_with_text( self.0.clone() )
     }
 
+    fn describe( &self ) -> AstNode {
+        AstNode::leaf( format!( "Text {:?}", self.0 ) )
+    }
+
     fn as_any( &self ) -> &dyn Any {
         self
     }
+
+    fn to_posix( &self ) -> String {
+        quote_posix( &self.0 )
+    }
 }
 
 pub struct Cmd {
@@ -134,263 +320,1765 @@ pub struct Cmd {
     pub args: Option<Vec<Exec>>,
 }
 
-impl Executable for Cmd {
-    fn execute( &self, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
-        let res = self.command.execute( true, None )?;
-        ensure_result!( res );
-
-        let name = format!( "{}", res.stdout.unwrap().join( "" ) );
-        let mut argv = Vec::new();
-        if let Some( args ) = &self.args {
-            for x in args.iter() {
-                if let Some( lines ) = x.execute( true, None )?.stdout {
-                    for line in lines { argv.push( line ); }
-                }
-            }
-        }
+// What `Cmd::process` expanded a command to when the arguments were too long to
+// start it with, so `execute` can split them up without running any command
+// substitutions in them a second time.
+#[derive( Clone )]
+pub struct Expansion {
+    cmd: usize,
+    name: String,
+    argv: Vec<String>,
+    spans: Vec<Range<usize>>,
+}
 
-        if let Some( cmd ) = COMMANDS.get( &*name ) {
-            argv.insert( 0, name );
-            return cmd( argv, input );
-        }
+// Says why `name` couldn't be started, rather than leaving it to the bare OS error.
+fn spawn_error( ctx: &ExecContext, name: &str, e: Error ) -> Error {
+    let path = Path::new( name );
+    match e.kind() {
+        // the program is there, so it's what runs it that's missing
+        ErrorKind::NotFound if path.is_file() => match path::shebang( path ) {
+            Some( ( interpreter, _ ) ) => Error::new(
+                ErrorKind::NotFound,
+                format!( "'{}' can't be run: the interpreter on its #! line, '{}', doesn't exist", name, interpreter )
+            ),
+
+            None => e,
+        },
 
-        let mut proc = Command::new( &name );
-        if input.is_some() {
-            proc.stdin( Stdio::piped() );
-        }
+        ErrorKind::NotFound => not_found( ctx, name, e ),
+        ErrorKind::PermissionDenied => Error::new( ErrorKind::PermissionDenied, format!( "found '{}' but it is not executable", name ) ),
+        _ if is_exec_format_error( &e ) => Error::new(
+            ErrorKind::InvalidData,
+            format!( "'{}' can't be run: it isn't a program for this system, nor a script with a #! line naming its interpreter", name )
+        ),
 
-        if capture {
-            proc.stdout( Stdio::piped() );
-            proc.stderr( Stdio::piped() );
-        }
+        _ => e,
+    }
+}
 
-        proc.args( argv );
+fn not_found( ctx: &ExecContext, name: &str, e: Error ) -> Error {
+    if e.kind() != ErrorKind::NotFound {
+        return e;
+    }
 
-        let subprocess = if let Some( lines ) = input {
-            let mut child = proc.spawn()?;
-            {
-                let stdin = child.stdin.as_mut();
-                if let Some( stdin ) = stdin {
-                    for line in lines {
-                        writeln!( stdin, "{}", line )?;
-                    }
-                }
-            }
+    // running something in the current directory takes an explicit ./
+    let hint = if !name.contains( '/' ) && Path::new( name ).is_file() {
+        format!( " Did you mean './{}'?", name )
+    } else {
+        suggest( ctx, name )
+    };
 
-            SubProcess::Spawned { process: child, capture }
-        } else {
-            SubProcess::Waiting { process: proc, capture }
-        };
+    Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "'{name}' is not a recognized command, script file, or executable program.{hint}",
+            name = name,
+            hint = hint
+        )
+    )
+}
 
-        match subprocess.result() {
-            Ok( x ) => Ok( x ),
-            Err( ref e ) if e.kind() == ErrorKind::NotFound
-                => Err(
-                    Error::new(
-                        ErrorKind::NotFound,
-                        format!(
-                            "'{name}' is not a recognized command, script file, or executable program.",
-                            name = name
-                        )
-                    )
-                ),
-            Err( e ) => Err( e )
-        }
+// Builtins, aliases and programs on PATH with names close to a mistyped one.
+fn suggest( ctx: &ExecContext, name: &str ) -> String {
+    if name.contains( '/' ) {
+        return String::new();
     }
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+    let mut names: Vec<String> = builtins::names().iter().map( | x | x.to_string() ).collect();
+    names.extend( ctx.aliases().keys().cloned() );
+    names.extend( path::executables() );
+
+    let quoted: Vec<String> = fuzzy::closest( name, names, 3 ).iter().map( | x | format!( "'{}'", x ) ).collect();
+    match quoted.split_last() {
+        None => String::new(),
+        Some( ( last, [] ) ) => format!( " Did you mean {}?", last ),
+        Some( ( last, rest ) ) => format!( " Did you mean {} or {}?", rest.join( ", " ), last ),
     }
 }
 
-pub struct TextInterp( pub Vec<Exec> );
+// Runs `command` with `args` after its own words, which are expanded the same
+// way as an alias's. This is how hooks from the config are called.
+pub fn call( ctx: &mut ExecContext, command: &str, args: Vec<String>, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let mut words = expand::expand( ctx, command ).map_err( | e | Error::other( format!( "'{}' is invalid: {}", command, e ) ) )?;
+    if words.is_empty() {
+        return Err( Error::other( format!( "'{}' is not a command", command ) ) );
+    }
 
-impl Executable for TextInterp {
-    fn execute( &self, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
-        let mut parts = Vec::new();
-        for seg in &self.0 {
-            let res = seg.execute( true, None )?;
-            ensure_result!( res );
+    words.extend( args );
+    let command: Exec = Box::new( Text( words.remove( 0 ) ) );
+    let args = words.into_iter().map( | x | Box::new( Text( x ) ) as Exec ).collect();
 
-            if let Some( mut lines ) = res.stdout {
-                parts.append( &mut lines );
-            }
-        }
+    Cmd { command, args: Some( args ) }.execute( ctx, capture, input )
+}
 
-        ShellResult::ok_with_text( parts.join( "" ) )
+// Runs the command-not-found hook, if there is one, in place of `name`, which
+// it's given along with the rest of the arguments. None means there's no hook
+// to run, so the usual error should be shown.
+fn run_not_found_hook( ctx: &mut ExecContext, name: &str, mut argv: Vec<String>, capture: bool, input: Option<Vec<String>> ) -> Option<Result<ShellResult>> {
+    let hook = ctx.not_found_hook()?;
+    argv.insert( 0, name.to_string() );
+
+    // a hook that is missing itself shouldn't be handed to the hook again
+    ctx.set_not_found_hook( None );
+    let res = call( ctx, &hook, argv, capture, input );
+    ctx.set_not_found_hook( Some( hook ) );
+
+    match res {
+        Err( ref e ) if e.kind() == ErrorKind::NotFound => None,
+        res => Some( res ),
     }
+}
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+// Locates an external program up front so a missing one is reported without trying to start it.
+fn program( ctx: &ExecContext, name: &str ) -> Result<Command> {
+    path::command( name ).ok_or_else( || match path::locate( name ) {
+        Some( ref x ) if x.is_dir() => Error::other( format!( "'{}' is a directory, not a program. Did you mean 'cd {}'?", name, name ) ),
+        Some( x ) => Error::new( ErrorKind::PermissionDenied, format!( "found '{}' but it is not executable", x.display() ) ),
+        None => not_found( ctx, name, Error::from( ErrorKind::NotFound ) ),
+    } )
+}
+
+// `set -x` shows each command as it's about to run, quoted so it could be pasted back in.
+fn trace( ctx: &ExecContext, name: &str, argv: &[String] ) {
+    if ctx.options().xtrace {
+        let words: Vec<String> = std::iter::once( name ).chain( argv.iter().map( | x | x.as_str() ) ).map( quote_posix ).collect();
+        eprintln!( "+ {}", words.join( " " ) );
     }
 }
 
-pub struct CmdInterp( pub Exec );
+// Replaces an aliased command name with the words of its definition.
+fn resolve_alias( ctx: &mut ExecContext, name: String, argv: Vec<String> ) -> Result<( String, Vec<String> )> {
+    let definition = match ctx.alias( &name ) {
+        Some( x ) => x,
+        None => return Ok( ( name, argv ) ),
+    };
 
-impl Executable for CmdInterp {
-    fn execute( &self, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
-        self.0.execute( true, None )
-    }
+    let mut words = expand::expand( ctx, &definition )
+        .map_err( | e | Error::other( format!( "alias '{}' is invalid: {}", name, e ) ) )?;
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+    if words.is_empty() {
+        return Err( Error::other( format!( "alias '{}' is empty", name ) ) );
     }
-}
 
-pub struct Pipe {
-    pub left: Exec,
-    pub right: Exec,
-}
+    let name = words.remove( 0 );
+    words.extend( argv );
 
-impl Executable for Pipe {
-    fn execute( &self, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
-        let left = self.left.execute( true, input )?;
-        ensure_result!( left );
+    Ok( ( name, words ) )
+}
 
-        self.right.execute( capture, left.stdout )
-    }
+// The strings a list of words stands for once variables, patterns and
+// commands in them have been run.
+fn expand_words( ctx: &mut ExecContext, words: &[Exec] ) -> Result<Vec<String>> {
+    let mut argv = Vec::new();
+    for x in words {
+        let lines = match x.execute( ctx, true, None )?.stdout {
+            Some( x ) => x,
+            None => continue,
+        };
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+        // unquoted $(...) output becomes one argument per word; "$(...)" stays whole
+        if x.as_any().is::<CmdInterp>() {
+            argv.extend( lines.iter().flat_map( | x | x.split_whitespace() ).map( | x | x.to_string() ) );
+        } else {
+            argv.extend( lines );
+        }
     }
-}
 
-pub struct Seq {
-    pub safe: bool,
-    pub left: Exec,
-    pub right: Exec,
+    Ok( argv )
 }
 
-impl Executable for Seq {
-    fn execute( &self, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
-        if self.safe {
-            let left = self.left.execute( false, None )?;
-            ensure_result!( left );
+impl Cmd {
+    pub fn expand_args( &self, ctx: &mut ExecContext ) -> Result<Vec<String>> {
+        match &self.args {
+            Some( args ) => expand_words( ctx, args ),
+            None => Ok( Vec::new() ),
+        }
+    }
 
-            self.right.execute( capture, input )
-        } else {
-            self.left.execute( false, None )?;
-            self.right.execute( capture, input )
+    // Like expand_args, but also where in the list each word's strings went.
+    fn expand_spans( &self, ctx: &mut ExecContext ) -> Result<( Vec<String>, Vec<Range<usize>> )> {
+        let mut argv = Vec::new();
+        let mut spans = Vec::new();
+        for x in self.args.iter().flatten() {
+            let start = argv.len();
+            argv.extend( expand_words( ctx, std::slice::from_ref( x ) )? );
+            spans.push( start .. argv.len() );
         }
+
+        Ok( ( argv, spans ) )
     }
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+    // The arguments after the words an alias put in front of them, and where
+    // in them each of the command's own words went.
+    fn expand_after( &self, ctx: &mut ExecContext, mut argv: Vec<String> ) -> Result<( Vec<String>, Vec<Range<usize>> )> {
+        let ( args, mut spans ) = profile::measure( "expand", || self.expand_spans( ctx ) )?;
+        let shift = argv.len();
+        spans.iter_mut().for_each( | x | *x = x.start + shift .. x.end + shift );
+        argv.extend( args );
+
+        Ok( ( argv, spans ) )
+    }
+
+    // Tells this command apart from others while it's being run.
+    fn key( &self ) -> usize {
+        self as *const Cmd as usize
+    }
+
+    // The word whose expansion takes up the most of the command line, for
+    // saying what made it too long.
+    fn largest_word( &self, argv: &[String], spans: &[Range<usize>] ) -> Option<( String, Range<usize> )> {
+        let size = | span: &Range<usize> | argv[span.clone()].iter().map( | x | argument_size( x ) ).fold( 0, usize::saturating_add );
+        let ( i, span ) = spans.iter().enumerate().max_by_key( | ( _, x ) | size( x ) )?;
+        let word = self.args.as_ref()?.get( i )?;
+
+        let kind = if word.as_any().is::<Glob>() {
+            "the pattern"
+        } else if word.as_any().is::<CmdInterp>() || word.as_any().is::<TextInterp>() {
+            "the command substitution"
+        } else {
+            "the word"
+        };
+
+        Some( ( format!( "{} `{}`", kind, word.to_posix() ), span.clone() ) )
     }
 }
 
-pub struct Var( pub String );
+// Programs that do the same thing when given their arguments a few at a time,
+// so a list too long to start them with can be split up like xargs would.
+const SPLITTABLE: &[&str] = &[ "rm", "rmdir", "mkdir", "touch", "chmod", "chown", "chgrp" ];
 
-impl Executable for Var {
-    fn execute( &self, _capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
-        match input {
-            Some( x ) => {
-                let value = x.join( " " );
-                set_var( &self.0, &value );
-                ShellResult::ok_with_text( value )
-            },
+// Whether `name` with `argv` fits in what the system lets a program be started with.
+fn fits( name: &str, argv: &[String] ) -> bool {
+    std::iter::once( name ).chain( argv.iter().map( String::as_str ) ).map( argument_size ).fold( 0, usize::saturating_add ) <= argument_space()
+}
 
-            None => match var( &self.0 ) {
-                Ok( x ) => ShellResult::ok_with_text( x ),
-                Err( e ) => match e {
-                    VarError::NotPresent => Err(
-                        Error::new(
-                            ErrorKind::Other,
-                            format!( "variable '{}' not found", self.0 )
-                        )
-                    ),
+// Says which word made a command's arguments too long to start it with.
+fn too_long( name: &str, argv: &[String], largest: Option<( String, Range<usize> )> ) -> Error {
+    let size = argv.iter().map( | x | argument_size( x ) ).fold( argument_size( name ), usize::saturating_add );
+    let message = match largest {
+        Some( ( word, span ) ) => format!(
+            "argument list too long for '{}': {} expanded to {} argument(s), taking {} of the {} bytes allowed",
+            name, word, span.len(), size, argument_space()
+        ),
 
-                    VarError::NotUnicode( _ ) => Err(
-                        Error::new(
-                            ErrorKind::Other,
-                            format!( "variable '{}' contains invalid data", self.0 )
-                        )
-                    )
-                }
+        None => format!( "argument list too long for '{}': it takes {} of the {} bytes allowed", name, size, argument_space() ),
+    };
+
+    Error::new( ErrorKind::InvalidInput, message )
+}
+
+// Runs `name` once per list of arguments, stopping at the first run that
+// fails, and puts together what they all output.
+fn run_chunks( ctx: &mut ExecContext, name: &str, chunks: Vec<Vec<String>>, env: Vec<( String, String )>, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+    let mut input = input_bytes( ctx, input );
+    let mut res = ShellResult::ok()?;
+    for argv in chunks {
+        let mut proc = program( ctx, name )?;
+        trace( ctx, name, &argv );
+        proc.args( &argv ).envs( env.iter().cloned() );
+
+        let text = std::iter::once( name ).chain( argv.iter().map( String::as_str ) ).map( quote_posix ).collect::<Vec<_>>().join( " " );
+        let next = SubProcess::launch( proc, capture, input.take() )
+            .and_then( | p | p.result( ctx, text ) )
+            .map_err( | e | spawn_error( ctx, name, e ) )?;
+
+        res.status = next.status;
+        for ( all, part ) in [ ( &mut res.stdout, next.stdout ), ( &mut res.stderr, next.stderr ) ] {
+            if let Some( part ) = part {
+                all.get_or_insert_with( Vec::new ).extend( part );
             }
         }
-    }
 
-    fn as_any( &self ) -> &dyn Any {
-        self
+        if !res.status.success() {
+            break;
+        }
     }
-}
 
-pub struct Redirect {
-    pub mode: RedirectMode,
-    pub left: Exec,
-    pub right: Exec,
+    Ok( res )
 }
 
-impl Executable for Redirect {
-    fn execute( &self, _capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
-        use self::RedirectMode::*;
-
-        let right = self.right.execute( true, None )?;
-        ensure_result!( right );
+// Splits `argv` so each part keeps the arguments outside `span` and as many of
+// those inside it as fit, or None if not even one of them does.
+fn chunk_arguments( name: &str, argv: &[String], span: Range<usize> ) -> Option<Vec<Vec<String>>> {
+    let ( lead, rest ) = argv.split_at( span.start );
+    let ( items, trail ) = rest.split_at( span.len() );
+    let fixed: Vec<String> = lead.iter().chain( trail ).cloned().collect();
+    let room = argument_space().checked_sub( std::iter::once( name ).chain( fixed.iter().map( String::as_str ) ).map( argument_size ).fold( 0, usize::saturating_add ) )?;
+
+    let mut chunks = Vec::new();
+    let mut chunk: Vec<String> = Vec::new();
+    let mut used = 0usize;
+    for item in items {
+        let size = argument_size( item );
+        if size > room {
+            return None;
+        }
 
-        let s = match right.stdout {
-            Some( x ) => x.join( "" ),
-            None => String::new()
-        };
+        if used + size > room {
+            chunks.push( std::mem::take( &mut chunk ) );
+            used = 0;
+        }
 
-        let path = Path::new( &s );
-        let input = match &self.mode {
-            StdIn => {
-                let f = File::open( path )?;
-                let mut reader = BufReader::new( f );
-                let mut lines = Vec::new();
+        chunk.push( item.clone() );
+        used += size;
+    }
 
-                for line in reader.lines() {
-                    lines.push( line? );
-                }
+    chunks.push( chunk );
+    Some( chunks.into_iter().map( | x | lead.iter().cloned().chain( x ).chain( trail.iter().cloned() ).collect() ).collect() )
+}
 
-                if lines.len() == 0 { None } else { Some( lines ) }
+impl Executable for Cmd {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        // taken before the words are expanded so commands in them don't get it
+        let env = ctx.take_command_env();
+        let ( name, mut argv, spans ) = match ctx.take_expansion() {
+            Some( x ) if x.cmd == self.key() => ( x.name, x.argv, x.spans ),
+
+            // left for the command it belongs to, which may be running this one
+            other => {
+                ctx.set_expansion( other );
+                let res = self.command.execute( ctx, true, None )?;
+                ensure_result!( res );
+
+                let name = res.stdout.unwrap().join( "" );
+                let ( name, prefix ) = resolve_alias( ctx, name, Vec::new() )?;
+                let ( argv, spans ) = self.expand_after( ctx, prefix )?;
+                ( name, argv, spans )
             },
-            _ => input,
         };
 
-        let left = self.left.execute( true, input )?;
-        ensure_result!( left );
+        trace( ctx, &name, &argv );
 
-        if &self.mode == &StdOut || &self.mode == &StdErr || &self.mode == &StdBoth {
-            let mut f = File::create( path )?;
+        if let Some( cmd ) = builtins::lookup( &name ) {
+            // builtins that start programs, like env and time, pass the variables on
+            argv.insert( 0, name );
+            ctx.set_command_env( env );
+            let res = ctx.with_capture( capture, | ctx | cmd.run( ctx, argv, input ) );
+            ctx.take_command_env();
+
+            let mut res = res?;
+
+            // external programs write straight to the terminal when their
+            // output isn't captured, so builtins should behave the same
+            let shown = if capture { ctx.tee() } else { Tee::ALL };
+            let paged = !capture && pager::page( res.stdout.as_deref().unwrap_or_default() )?;
+            if shown.stdout && !paged {
+                for line in res.stdout.iter().flatten() { println!( "{}", line ); }
+            }
 
-            if &self.mode == &StdOut || &self.mode == &StdBoth {
-                if let Some( stdout ) = left.stdout {
-                    for line in stdout {
-                        f.write( line.as_bytes() )?;
-                        f.write( b"\n" )?;
-                    }
-                }
+            if shown.stderr {
+                for line in res.stderr.iter().flatten() { eprintln!( "{}", line ); }
             }
 
-            if &self.mode == &StdErr || &self.mode == &StdBoth {
-                if let Some( stderr ) = left.stderr {
-                    for line in stderr {
-                        f.write( line.as_bytes() )?;
-                        f.write( b"\n" )?;
-                    }
-                }
+            if !capture {
+                res.stdout = None;
+                res.stderr = None;
             }
 
-            f.flush()?;
-            f.sync_all()?;
+            return Ok( res );
         }
 
-        ShellResult::ok()
+        let mut proc = match program( ctx, &name ) {
+            Ok( x ) => x,
+            Err( e ) if e.kind() == ErrorKind::NotFound => return run_not_found_hook( ctx, &name, argv, capture, input ).unwrap_or( Err( e ) ),
+            Err( e ) => return Err( e ),
+        };
+
+        if !fits( &name, &argv ) {
+            let largest = self.largest_word( &argv, &spans );
+            let splittable = Path::new( &name ).file_name().is_some_and( | x | SPLITTABLE.iter().any( | y | x == *y ) );
+            let chunks = largest.as_ref().filter( | _ | splittable ).and_then( | ( _, span ) | chunk_arguments( &name, &argv, span.clone() ) );
+            return match chunks {
+                Some( chunks ) => run_chunks( ctx, &name, chunks, env, capture, input ),
+                None => Err( too_long( &name, &argv, largest ) ),
+            };
+        }
+
+        proc.args( argv ).envs( env );
+
+        let input = input_bytes( ctx, input );
+        SubProcess::launch( proc, capture, input )
+            .and_then( | p | p.result( ctx, self.to_posix() ) )
+            .map_err( | e | spawn_error( ctx, &name, e ) )
+    }
+
+    fn describe( &self ) -> AstNode {
+        let mut children = vec![ self.command.describe() ];
+        children.extend( self.args.iter().flatten().map( | x | x.describe() ) );
+
+        AstNode::new( "Cmd", children )
     }
 
     fn as_any( &self ) -> &dyn Any {
         self
     }
-}
-
-enum SubProcess {
-    Spawned {
-        process: Child,
-        capture: bool,
-    },
+
+    fn to_posix( &self ) -> String {
+        let mut parts = vec![ self.command.to_posix() ];
+        if let Some( args ) = &self.args {
+            parts.extend( args.iter().map( | x | x.to_posix() ) );
+        }
+
+        parts.join( " " )
+    }
+
+    fn reads_input( &self ) -> bool {
+        match self.command.as_any().downcast_ref::<Text>() {
+            Some( name ) if builtins::is_builtin( &name.0 ) => builtins::reads_input( &name.0 ),
+            _ => true,
+        }
+    }
+
+    fn process( &self, ctx: &mut ExecContext ) -> Result<Option<Command>> {
+        let env = ctx.take_command_env();
+        let res = self.command.execute( ctx, true, None )?;
+        if res.code() != Some( 0 ) {
+            return Ok( None );
+        }
+
+        let name = res.stdout.unwrap_or_default().join( "" );
+        let ( name, prefix ) = resolve_alias( ctx, name, Vec::new() )?;
+
+        // anything the hook would run has to go through execute, which expands
+        // the arguments itself, so they're left alone until this is known
+        if builtins::is_builtin( &name ) || ( ctx.not_found_hook().is_some() && path::resolve( &name ).is_none() ) {
+            ctx.set_command_env( env );
+            return Ok( None );
+        }
+
+        let ( argv, spans ) = self.expand_after( ctx, prefix )?;
+
+        let mut proc = program( ctx, &name )?;
+        if !fits( &name, &argv ) {
+            // execute splits the list up if it can, or says why it can't
+            ctx.set_command_env( env );
+            ctx.set_expansion( Some( Expansion { cmd: self.key(), name, argv, spans } ) );
+            return Ok( None );
+        }
+
+        trace( ctx, &name, &argv );
+        proc.args( argv ).envs( env );
+
+        Ok( Some( proc ) )
+    }
+}
+
+// `NAME=value ... cmd`: the variables are given to the program the command
+// starts, leaving the shell's own as they were. With no command after them
+// they're set in the shell instead.
+pub struct Assign {
+    pub vars: Vec<( String, Exec )>,
+    pub command: Option<Exec>,
+}
+
+impl Assign {
+    // The values to assign, or the result of the first one that failed.
+    fn values( &self, ctx: &mut ExecContext ) -> Result<std::result::Result<Vec<( String, String )>, ShellResult>> {
+        let mut values = Vec::new();
+        for ( name, value ) in &self.vars {
+            let res = value.execute( ctx, true, None )?;
+            if res.code() != Some( 0 ) {
+                return Ok( Err( res ) );
+            }
+
+            values.push( ( name.clone(), res.stdout.unwrap_or_default().join( " " ) ) );
+        }
+
+        Ok( Ok( values ) )
+    }
+}
+
+impl Executable for Assign {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let vars = match self.values( ctx )? {
+            Ok( x ) => x,
+            Err( res ) => return Ok( res ),
+        };
+
+        let command = match self.command {
+            Some( ref x ) => x,
+            None => {
+                for ( name, value ) in vars {
+                    ctx.set_var( &name, &value );
+                }
+
+                return ShellResult::ok();
+            },
+        };
+
+        ctx.set_command_env( vars );
+        let res = command.execute( ctx, capture, input );
+
+        // a builtin never picks them up
+        ctx.take_command_env();
+        res
+    }
+
+    fn describe( &self ) -> AstNode {
+        let mut children: Vec<AstNode> = self.vars.iter()
+            .map( | ( name, value ) | AstNode::new( format!( "{}=", name ), vec![ value.describe() ] ) )
+            .collect();
+
+        children.extend( self.command.iter().map( | x | x.describe() ) );
+        AstNode::new( "Assign", children )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let mut parts: Vec<String> = self.vars.iter().map( | ( name, value ) | format!( "{}={}", name, value.to_posix() ) ).collect();
+        parts.extend( self.command.iter().map( | x | x.to_posix() ) );
+
+        parts.join( " " )
+    }
+
+    fn process( &self, ctx: &mut ExecContext ) -> Result<Option<Command>> {
+        let command = match self.command {
+            Some( ref x ) => x,
+            None => return Ok( None ),
+        };
+
+        let vars = match self.values( ctx )? {
+            Ok( x ) => x,
+            Err( _ ) => return Ok( None ),
+        };
+
+        ctx.set_command_env( vars );
+        let proc = command.process( ctx );
+        ctx.take_command_env();
+
+        proc
+    }
+}
+
+// An unquoted word containing wildcards or starting with ~; expands to the
+// matching paths, or to itself when nothing matches.
+pub struct Glob( pub String );
+
+impl Executable for Glob {
+    fn execute( &self, _ctx: &mut ExecContext, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let word = tilde::expand( &self.0 );
+        if !glob::is_pattern( &word ) {
+            return ShellResult::ok_with_text( glob::unescape( &word ) );
+        }
+
+        let paths = glob::expand( &word );
+        if paths.is_empty() {
+            ShellResult::ok_with_text( glob::unescape( &word ) )
+        } else {
+            ShellResult::ok_with_lines( paths )
+        }
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::leaf( format!( "Glob {:?}", self.0 ) )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        self.0.clone()
+    }
+}
+
+pub struct TextInterp( pub Vec<Exec> );
+
+impl Executable for TextInterp {
+    fn execute( &self, ctx: &mut ExecContext, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let mut parts = Vec::new();
+        for seg in &self.0 {
+            // embedded commands run like $(...), so their assignments don't leak out
+            let res = if seg.as_any().is::<Text>() {
+                seg.execute( ctx, true, None )?
+            } else {
+                subshell( ctx, seg )?
+            };
+            ensure_result!( res );
+
+            if let Some( lines ) = res.stdout {
+                parts.push( lines.join( "\n" ) );
+            }
+        }
+
+        ShellResult::ok_with_text( parts.join( "" ) )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "TextInterp", self.0.iter().map( | x | x.describe() ).collect() )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        self.0.iter().map( | seg | {
+            let any = seg.as_any();
+            if any.is::<Text>() || any.is::<Var>() {
+                seg.to_posix()
+            } else {
+                format!( "\"{}\"", posix_subshell( seg ) )
+            }
+        } ).collect()
+    }
+}
+
+pub struct CmdInterp( pub Exec );
+
+impl Executable for CmdInterp {
+    fn execute( &self, ctx: &mut ExecContext, _capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        subshell( ctx, &self.0 )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "CmdInterp", vec![ self.0.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        posix_subshell( &self.0 )
+    }
+}
+
+pub struct Pipe {
+    pub left: Exec,
+    pub right: Exec,
+
+    // `|&` sends the left side's stderr down the pipe along with its stdout
+    pub both: bool,
+}
+
+// What the next stage of a pipeline reads from.
+enum Upstream {
+    Lines( Option<Vec<String>> ),
+    Stream( ChildStdout ),
+
+    // stdout and stderr of a `|&` stage sharing one pipe
+    Merged( PipeReader ),
+}
+
+// What a pipeline has going while it runs.
+#[derive( Default )]
+struct Started {
+    children: Vec<Child>,
+
+    // threads feeding lines to stages that read them from the shell
+    writers: Vec<JoinHandle<()>>,
+
+    // one per stage, left as None for programs that haven't been waited for yet
+    statuses: Vec<Option<ProcessStatus>>,
+}
+
+// Puts the statuses of programs that have been waited for, in the order they
+// were started, into the places left for them.
+fn fill_statuses<I: IntoIterator<Item = ProcessStatus>>( statuses: &mut [Option<ProcessStatus>], found: I ) {
+    for ( slot, status ) in statuses.iter_mut().filter( | x | x.is_none() ).zip( found ) {
+        *slot = Some( status );
+    }
+}
+
+// Flattens nested pipes into their stages, each paired with whether
+// its stderr goes to the next stage as well.
+fn pipeline_stages<'a>( seg: &'a Exec, both: bool, stages: &mut Vec<( &'a Exec, bool )> ) {
+    // pipes nest to the left, so that side is walked in a loop however long
+    // the pipeline is, finding the stages from last to first
+    let mut rights = Vec::new();
+    let ( mut seg, mut both ) = ( seg, both );
+    while let Some( pipe ) = seg.as_any().downcast_ref::<Pipe>() {
+        rights.push( ( &pipe.right, both ) );
+        seg = &pipe.left;
+        both = pipe.both;
+    }
+
+    stages.push( ( seg, both ) );
+    for ( right, both ) in rights.into_iter().rev() {
+        pipeline_stages( right, both, stages );
+    }
+}
+
+// Everything left in a stream, as lines and as the bytes they were read from.
+type Drained = ( Option<Vec<String>>, Option<Vec<u8>> );
+
+fn read_all<R: Read>( mut stream: R ) -> Result<Drained> {
+    let mut buf = Vec::new();
+    stream.read_to_end( &mut buf )?;
+
+    let lines = SubProcess::split_lines( String::from_utf8_lossy( &buf ).into_owned() );
+    Ok( ( Some( lines ).filter( | x | !x.is_empty() ), Some( buf ) ) )
+}
+
+impl Pipe {
+    // Starts every stage for a background job and returns its processes, without
+    // waiting for any of them. If one fails to start, those already running are killed.
+    fn start( &self, ctx: &mut ExecContext ) -> Result<Vec<Child>> {
+        let mut started = Started::default();
+        match self.run( ctx, false, None, true, &mut started ) {
+            Ok( _ ) => Ok( started.children ),
+            Err( e ) => {
+                for child in started.children.iter_mut() {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+
+                Err( e )
+            }
+        }
+    }
+
+    fn run( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>>, detach: bool, started: &mut Started ) -> Result<ShellResult> {
+        let mut stages = Vec::new();
+        pipeline_stages( &self.left, self.both, &mut stages );
+        pipeline_stages( &self.right, false, &mut stages );
+
+        let mut upstream = Upstream::Lines( input );
+
+        // the bytes behind the lines upstream, when they came from a program
+        let mut raw = ctx.take_piped_bytes();
+        let mut guards = Vec::new();
+        let last = stages.len() - 1;
+
+        // programs the user interacts with share a process group, led by the first one
+        let mut leader = None;
+
+        // stderr of the earlier stages when the whole pipeline is being captured
+        let mut errors = Vec::new();
+        let mut readers: Vec<JoinHandle<Vec<String>>> = Vec::new();
+
+        // what the stage before wrote as JSON, if it was a builtin that did
+        let mut value = None;
+
+        for ( i, ( stage, both ) ) in stages.into_iter().enumerate() {
+            let merge = both && i < last;
+            let mut proc = match stage.process( ctx )? {
+                // programs only ever get the text
+                Some( x ) => {
+                    value = None;
+                    x
+                },
+
+                None => {
+                    let ( input, bytes ) = match upstream {
+                        Upstream::Lines( x ) => ( x, raw.take() ),
+                        Upstream::Stream( out ) => read_all( out )?,
+                        Upstream::Merged( out ) => read_all( out )?,
+                    };
+
+                    // builtins that understand JSON take the value behind the lines as it is,
+                    // and programs run by the stage get the bytes
+                    ctx.set_piped_value( value.take() );
+                    ctx.set_piped_bytes( bytes );
+                    let res = stage.execute( ctx, capture || i < last, input );
+                    ctx.set_piped_value( None );
+                    ctx.set_piped_bytes( None );
+                    ctx.set_expansion( None );
+
+                    let mut res = res?;
+                    started.statuses.push( Some( res.status ) );
+                    if i == last {
+                        return Ok( Pipe::with_errors( res, errors, readers ) );
+                    }
+
+                    value = res.value.take();
+                    raw = if merge { None } else { res.stdout_bytes() };
+
+                    let stderr = res.stderr.take().unwrap_or_default();
+                    let mut stdout = res.stdout.take().unwrap_or_default();
+                    if merge {
+                        stdout.extend( stderr );
+                    } else if capture {
+                        errors.extend( stderr );
+                    } else {
+                        for line in stderr { eprintln!( "{}", line ); }
+                    }
+
+                    upstream = Upstream::Lines( Some( stdout ).filter( | x | !x.is_empty() ) );
+                    continue;
+                },
+            };
+
+            let feed = match upstream {
+                Upstream::Stream( out ) => {
+                    proc.stdin( out );
+                    None
+                },
+
+                Upstream::Merged( out ) => {
+                    proc.stdin( out );
+                    None
+                },
+
+                Upstream::Lines( Some( lines ) ) => {
+                    proc.stdin( Stdio::piped() );
+                    exact_bytes( Some( &lines ), raw.take().as_ref() )
+                },
+
+                // only the first stage of a foreground pipeline may read from the terminal
+                Upstream::Lines( None ) if i > 0 || detach => {
+                    proc.stdin( Stdio::null() );
+                    None
+                },
+
+                Upstream::Lines( None ) => None,
+            };
+
+            let mut merged = None;
+            if merge {
+                let ( reader, writer ) = pipe()?;
+                proc.stdout( writer.try_clone()? );
+                proc.stderr( writer );
+                merged = Some( reader );
+            } else if i < last || capture {
+                proc.stdout( Stdio::piped() );
+            }
+
+            // streamed stderr goes straight to the terminal instead of being collected
+            if capture && !merge && !ctx.tee().stderr {
+                proc.stderr( Stdio::piped() );
+            }
+
+            if !capture {
+                new_process_group( &mut proc, leader );
+            }
+
+            let name = proc.get_program().to_string_lossy().into_owned();
+            let mut child = profile::measure( "spawn", || proc.spawn() ).map_err( | e | spawn_error( ctx, &name, e ) )?;
+            leader = leader.or( Some( child.id() ) );
+            started.statuses.push( None );
+
+            // our copies of the write end have to go, or the next stage never sees EOF
+            drop( proc );
+
+            if !detach {
+                guards.push( Foreground::new( child.id() ) );
+            }
+
+            if let ( Some( bytes ), Some( mut stdin ) ) = ( feed, child.stdin.take() ) {
+                started.writers.push( thread::spawn( move || {
+                    let _ = stdin.write_all( &bytes );
+                } ) );
+            }
+
+            if i < last {
+                if let Some( stderr ) = child.stderr.take() {
+                    readers.push( thread::spawn( move || read_all( stderr ).ok().and_then( | x | x.0 ).unwrap_or_default() ) );
+                }
+
+                upstream = match merged {
+                    Some( reader ) => Upstream::Merged( reader ),
+                    None => Upstream::Stream( child.stdout.take().unwrap() ),
+                };
+
+                started.children.push( child );
+            } else if detach {
+                started.children.push( child );
+                return ShellResult::ok();
+            } else if capture {
+                let res = SubProcess::read_child( child, ctx )?;
+                started.statuses[i] = Some( res.status );
+                return Ok( Pipe::with_errors( res, errors, readers ) );
+            } else {
+                let status = match ctx.deadline() {
+                    Some( _ ) => process_status( SubProcess::wait( &mut child, ctx )? ),
+                    None => {
+                        started.children.push( child );
+                        match jobs::wait_foreground_each( self.to_posix(), std::mem::take( &mut started.children ) )? {
+                            Some( each ) => {
+                                fill_statuses( &mut started.statuses, each );
+                                started.statuses[i].unwrap_or( ProcessStatus::Unknown )
+                            },
+
+                            // stopped; there's nothing to report until it finishes
+                            None => {
+                                started.statuses.clear();
+                                ProcessStatus::Exited( jobs::STOPPED )
+                            },
+                        }
+                    },
+                };
+
+                started.statuses[i] = Some( status );
+
+                return Ok( ShellResult::with_status( status ) );
+            }
+        }
+
+        unreachable!()
+    }
+
+    // Puts the stderr collected from the earlier stages ahead of the last stage's own.
+    fn with_errors( mut res: ShellResult, mut errors: Vec<String>, readers: Vec<JoinHandle<Vec<String>>> ) -> ShellResult {
+        for reader in readers {
+            errors.extend( reader.join().unwrap_or_default() );
+        }
+
+        if errors.is_empty() {
+            return res;
+        }
+
+        errors.extend( res.stderr.take().unwrap_or_default() );
+        res.stderr = Some( errors );
+        res
+    }
+}
+
+impl Executable for Pipe {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let mut started = Started::default();
+        let mut res = self.run( ctx, capture, input, false, &mut started );
+        let Started { mut children, writers, mut statuses } = started;
+
+        // a stage that failed to start leaves the ones before it without a reader
+        let mut waited = Vec::new();
+        for child in children.iter_mut() {
+            if res.is_err() { let _ = child.kill(); }
+            waited.push( child.wait().map( process_status ).unwrap_or( ProcessStatus::Unknown ) );
+        }
+
+        for writer in writers {
+            let _ = writer.join();
+        }
+
+        fill_statuses( &mut statuses, waited );
+        if let Ok( ref mut res ) = res {
+            if !statuses.is_empty() {
+                // a stage killed by signal n is 128 + n, as in $?; only one that never ran has no code
+                let codes: Vec<String> = statuses.iter().map( | x | x.and_then( | x | x.code() ).unwrap_or( 1 ).to_string() ).collect();
+                ctx.set_var( "pipestatus", &codes.join( " " ) );
+
+                // with pipefail the first stage that failed decides the status, not the last
+                if ctx.options().pipefail {
+                    if let Some( failed ) = statuses.iter().flatten().find( | x | !x.success() ) {
+                        res.status = *failed;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    fn describe( &self ) -> AstNode {
+        let label = if self.both { "Pipe |&" } else { "Pipe |" };
+        AstNode::new( label, vec![ self.left.describe(), self.right.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let op = if self.both { "|&" } else { "|" };
+        format!( "{} {} {}", self.left.to_posix(), op, self.right.to_posix() )
+    }
+}
+
+// Statements run one after another. They're kept in a list rather than nested
+// in pairs, since a long script would otherwise recurse once per statement to
+// run, or just to drop.
+pub struct Seq {
+    pub statements: Vec<Exec>,
+}
+
+impl Executable for Seq {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, mut input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let last = self.statements.len().saturating_sub( 1 );
+        let mut res: Option<ShellResult> = None;
+
+        for ( i, seg ) in self.statements.iter().enumerate() {
+            let out = seg.execute( ctx, capture, share_input( &mut input, seg ) )?;
+            let stop = i < last && {
+                ctx.set_last_status( out.code().unwrap_or( 1 ) );
+                ctx.exit_requested().is_some() || interrupted() || errexit( ctx, seg, &out )
+            };
+
+            res = Some( match res {
+                Some( x ) => concat( x, out ),
+                None => out,
+            } );
+
+            if stop {
+                break;
+            }
+        }
+
+        Ok( res.unwrap_or( ShellResult::with_status( ProcessStatus::Exited( 0 ) ) ) )
+    }
+
+    fn reads_input( &self ) -> bool {
+        self.statements.iter().any( | x | x.reads_input() )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "Seq", self.statements.iter().map( | x | x.describe() ).collect() )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        self.statements.iter().map( | x | x.to_posix() ).collect::<Vec<_>>().join( "; " )
+    }
+}
+
+// `command &` starts external programs and pipelines without waiting for them;
+// they are tracked in the job table until they finish.
+pub struct Background( pub Exec );
+
+impl Executable for Background {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let children = match self.0.as_any().downcast_ref::<Pipe>() {
+            Some( pipe ) => pipe.start( ctx )?,
+            None => match self.0.process( ctx )? {
+                Some( mut proc ) => {
+                    proc.stdin( Stdio::null() );
+                    new_process_group( &mut proc, None );
+
+                    let name = proc.get_program().to_string_lossy().into_owned();
+                    vec![ profile::measure( "spawn", || proc.spawn() ).map_err( | e | spawn_error( ctx, &name, e ) )? ]
+                },
+
+                None => return Err( match ctx.take_expansion() {
+                    Some( x ) => too_long( &x.name, &x.argv, None ),
+                    None => Error::other( "only external programs and pipelines can run in the background" ),
+                } ),
+            },
+        };
+
+        let mut table = JOBS.lock().unwrap();
+        let job = table.add( self.0.to_posix(), children );
+        let pids: Vec<String> = job.pids().iter().map( | x | x.to_string() ).collect();
+        let text = format!( "[{}] {}", job.id, pids.join( " " ) );
+
+        if capture {
+            ShellResult::ok_with_text( text )
+        } else {
+            println!( "{}", text );
+            ShellResult::ok()
+        }
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "Background", vec![ self.0.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        format!( "{} &", self.0.to_posix() )
+    }
+}
+
+// `left && right` runs right only if left succeeds, `left || right` only if it fails.
+pub struct AndOr {
+    pub mode: AndOrMode,
+    pub left: Exec,
+    pub right: Exec,
+}
+
+impl Executable for AndOr {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, mut input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let left = self.left.execute( ctx, capture, share_input( &mut input, &self.left ) )?;
+        let succeeded = left.code() == Some( 0 );
+        ctx.set_last_status( left.code().unwrap_or( 1 ) );
+
+        if succeeded != ( self.mode == AndOrMode::And ) || ctx.exit_requested().is_some() || interrupted() {
+            return Ok( left );
+        }
+
+        let right = self.right.execute( ctx, capture, share_input( &mut input, &self.right ) )?;
+        Ok( concat( left, right ) )
+    }
+
+    fn reads_input( &self ) -> bool {
+        self.left.reads_input() || self.right.reads_input()
+    }
+
+    fn describe( &self ) -> AstNode {
+        let label = match self.mode {
+            AndOrMode::And => "AndOr &&",
+            AndOrMode::Or => "AndOr ||",
+        };
+
+        AstNode::new( label, vec![ self.left.describe(), self.right.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let op = if self.mode == AndOrMode::And { "&&" } else { "||" };
+        format!( "{} {} {}", self.left.to_posix(), op, self.right.to_posix() )
+    }
+}
+
+// `{ cmd; cmd; }` runs its commands in this shell, so together they can be
+// redirected, piped or used as the operand of && and ||.
+pub struct Group( pub Exec );
+
+impl Executable for Group {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        self.0.execute( ctx, capture, input )
+    }
+
+    fn reads_input( &self ) -> bool {
+        self.0.reads_input()
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "Group", vec![ self.0.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        format!( "{{ {}; }}", self.0.to_posix() )
+    }
+}
+
+// `par { cmd; cmd }` runs its commands at the same time and waits for all of
+// them. Commands that start a single program are all started first; the rest,
+// like builtins and pipelines, then run in this shell while those programs do.
+// It fails with the status of the first command that failed.
+pub struct Par( pub Exec );
+
+impl Par {
+    fn statements( &self ) -> Vec<&Exec> {
+        match self.0.as_any().downcast_ref::<Seq>() {
+            Some( seq ) => seq.statements.iter().collect(),
+            None => vec![ &self.0 ],
+        }
+    }
+
+    // Starts the commands that are programs, leaving None for the others and
+    // noting in `expansions` what those have already expanded, if anything.
+    fn start( &self, ctx: &mut ExecContext, capture: bool, expansions: &mut Vec<Option<Expansion>> ) -> Result<Vec<Option<Child>>> {
+        let mut started: Vec<Option<Child>> = Vec::new();
+        let mut leader = None;
+
+        for seg in self.statements() {
+            let mut proc = match seg.process( ctx ) {
+                Ok( Some( x ) ) => x,
+                Ok( None ) => {
+                    started.push( None );
+                    expansions.push( ctx.take_expansion() );
+                    continue;
+                },
+
+                Err( e ) => return Err( Par::abandon( started, e ) ),
+            };
+
+            expansions.push( None );
+
+            // they can't all read the terminal at once
+            proc.stdin( Stdio::null() );
+            if capture {
+                proc.stdout( Stdio::piped() ).stderr( Stdio::piped() );
+            } else {
+                new_process_group( &mut proc, leader );
+            }
+
+            let name = proc.get_program().to_string_lossy().into_owned();
+            match profile::measure( "spawn", || proc.spawn() ) {
+                Ok( child ) => {
+                    leader = leader.or( Some( child.id() ) );
+                    started.push( Some( child ) );
+                },
+
+                Err( e ) => return Err( Par::abandon( started, spawn_error( ctx, &name, e ) ) ),
+            }
+        }
+
+        Ok( started )
+    }
+
+    // Stops the programs already started when a later command can't be run.
+    fn abandon( started: Vec<Option<Child>>, e: Error ) -> Error {
+        for mut child in started.into_iter().flatten() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        e
+    }
+}
+
+impl Executable for Par {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let mut expansions = Vec::new();
+        let mut started = self.start( ctx, capture, &mut expansions )?;
+        let guards: Vec<Foreground> = started.iter().flatten().map( | x | Foreground::new( x.id() ) ).collect();
+        let mut results: Vec<Option<ShellResult>> = Vec::new();
+
+        // a command that errors still leaves the programs to be stopped and reaped
+        let mut error = None;
+        for ( ( seg, child ), expansion ) in self.statements().into_iter().zip( started.iter() ).zip( expansions ) {
+            let res = match child {
+                Some( _ ) => None,
+                None if interrupted() => Some( ShellResult::with_status( ProcessStatus::Unknown ) ),
+                None => {
+                    ctx.set_expansion( expansion );
+                    match seg.execute( ctx, capture, None ) {
+                        Ok( x ) => Some( x ),
+                        Err( e ) => {
+                            error = Some( e );
+                            break;
+                        },
+                    }
+                },
+            };
+
+            results.push( res );
+        }
+
+        // captured programs are read one after another; the rest are waited for together
+        if capture && error.is_none() {
+            for ( res, child ) in results.iter_mut().zip( started.iter_mut() ) {
+                if let Some( child ) = child.take() {
+                    match SubProcess::read_child( child, ctx ) {
+                        Ok( x ) => *res = Some( x ),
+                        Err( e ) => {
+                            error = Some( e );
+                            break;
+                        },
+                    }
+                }
+            }
+        }
+
+        // none of it is wanted once the commands it was for have had their turn
+        ctx.set_expansion( None );
+        if let Some( e ) = error {
+            return Err( Par::abandon( started, e ) );
+        }
+
+        if !capture {
+            let children: Vec<Child> = started.iter_mut().filter_map( | x | x.take() ).collect();
+            let mut statuses = match jobs::wait_foreground_each( self.to_posix(), children )? {
+                Some( x ) => x.into_iter(),
+                None => return Ok( ShellResult::with_status( ProcessStatus::Exited( jobs::STOPPED ) ) ),
+            };
+
+            for res in results.iter_mut().filter( | x | x.is_none() ) {
+                let status = statuses.next().unwrap_or( ProcessStatus::Unknown );
+                *res = Some( ShellResult::with_status( status ) );
+            }
+        }
+
+        drop( guards );
+
+        let mut all = ShellResult::ok()?;
+        let mut failed = None;
+        for res in results.into_iter().flatten() {
+            if failed.is_none() && !res.status.success() {
+                failed = Some( res.status );
+            }
+
+            all = concat( all, res );
+        }
+
+        all.status = failed.unwrap_or( ProcessStatus::Exited( 0 ) );
+        Ok( all )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "Par", vec![ self.0.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let jobs: Vec<String> = self.statements().iter().map( | x | format!( "{} &", x.to_posix() ) ).collect();
+        format!( "{{ {} wait; }}", jobs.join( " " ) )
+    }
+}
+
+// `if condition { ... } else { ... }`. The condition's exit status picks the
+// branch; with no branch to run the result is success, like sh.
+pub struct If {
+    pub condition: Exec,
+    pub then: Exec,
+    pub otherwise: Option<Exec>,
+}
+
+impl Executable for If {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let condition = self.condition.execute( ctx, capture, None )?;
+        ctx.set_last_status( condition.code().unwrap_or( 1 ) );
+
+        if ctx.exit_requested().is_some() || interrupted() {
+            return Ok( condition );
+        }
+
+        let branch = if condition.code() == Some( 0 ) {
+            Some( &self.then )
+        } else {
+            self.otherwise.as_ref()
+        };
+
+        let res = match branch {
+            Some( x ) => x.execute( ctx, capture, input )?,
+            None => ShellResult::ok()?,
+        };
+
+        Ok( concat( condition, res ) )
+    }
+
+    fn describe( &self ) -> AstNode {
+        let mut children = vec![
+            AstNode::new( "condition", vec![ self.condition.describe() ] ),
+            AstNode::new( "then", vec![ self.then.describe() ] ),
+        ];
+
+        if let Some( otherwise ) = &self.otherwise {
+            children.push( AstNode::new( "else", vec![ otherwise.describe() ] ) );
+        }
+
+        AstNode::new( "If", children )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let mut out = format!( "if {}; then {}", self.condition.to_posix(), self.then.to_posix() );
+        if let Some( x ) = &self.otherwise {
+            out.push_str( &format!( "; else {}", x.to_posix() ) );
+        }
+
+        out + "; fi"
+    }
+}
+
+// Whether `set -e` stops what's running after `seg` finished with `res`. A
+// failure in an && or || list is left for the list to deal with, like in sh.
+pub fn errexit( ctx: &ExecContext, seg: &Exec, res: &ShellResult ) -> bool {
+    ctx.options().errexit && res.code() != Some( 0 ) && !seg.as_any().is::<AndOr>()
+}
+
+// Whether a loop should stop after a command finished with `res`.
+fn stop_loop( ctx: &mut ExecContext, res: &ShellResult ) -> bool {
+    ctx.set_last_status( res.code().unwrap_or( 1 ) );
+    ctx.exit_requested().is_some() || interrupted()
+}
+
+// `while condition { ... }` runs the body for as long as the condition succeeds.
+pub struct While {
+    pub condition: Exec,
+    pub body: Exec,
+}
+
+impl Executable for While {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let mut out = ShellResult::ok()?;
+        loop {
+            let condition = self.condition.execute( ctx, capture, None )?;
+            let done = stop_loop( ctx, &condition ) || condition.code() != Some( 0 );
+
+            // the loop's status is the body's, not that of the condition ending it
+            let status = out.status;
+            out = concat( out, condition );
+            out.status = status;
+
+            if done {
+                break;
+            }
+
+            let body = self.body.execute( ctx, capture, None )?;
+            let failed = errexit( ctx, &self.body, &body );
+            out = concat( out, body );
+            if stop_loop( ctx, &out ) || failed {
+                break;
+            }
+        }
+
+        Ok( out )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "While", vec![
+            AstNode::new( "condition", vec![ self.condition.describe() ] ),
+            AstNode::new( "body", vec![ self.body.describe() ] ),
+        ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        format!( "while {}; do {}; done", self.condition.to_posix(), self.body.to_posix() )
+    }
+}
+
+// `for name in words { ... }` runs the body once for each word, with the
+// variable set to it.
+pub struct For {
+    pub var: String,
+    pub words: Vec<Exec>,
+    pub body: Exec,
+}
+
+impl Executable for For {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let mut out = ShellResult::ok()?;
+        for word in expand_words( ctx, &self.words )? {
+            ctx.set_var( &self.var, &word );
+
+            let body = self.body.execute( ctx, capture, None )?;
+            let failed = errexit( ctx, &self.body, &body );
+            out = concat( out, body );
+            if stop_loop( ctx, &out ) || failed {
+                break;
+            }
+        }
+
+        Ok( out )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( format!( "For {}", self.var ), vec![
+            AstNode::new( "in", self.words.iter().map( | x | x.describe() ).collect() ),
+            AstNode::new( "body", vec![ self.body.describe() ] ),
+        ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let words: Vec<String> = self.words.iter().map( | x | x.to_posix() ).collect();
+        format!( "for {} in {}; do {}; done", self.var, words.join( " " ), self.body.to_posix() )
+    }
+}
+
+pub struct Var( pub String );
+
+impl Executable for Var {
+    fn execute( &self, ctx: &mut ExecContext, _capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        if self.0 == "?" {
+            if input.is_some() {
+                return Err( Error::other( "$? is read-only" ) );
+            }
+
+            return ShellResult::ok_with_text( ctx.last_status().to_string() );
+        }
+
+        // secrets are fetched from the keyring only at the moment they're used
+        if let Some( name ) = self.0.strip_prefix( secret::PREFIX ) {
+            if input.is_some() {
+                return Err( Error::other( format!( "secrets are read-only; use 'secret set {}' instead", name ) ) );
+            }
+
+            return ShellResult::ok_with_text( secret::lookup( name )? );
+        }
+
+        match input {
+            Some( x ) => {
+                let value = x.join( " " );
+                ctx.set_var( &self.0, &value );
+                ShellResult::ok_with_text( value )
+            },
+
+            None => match ctx.var( &self.0 ) {
+                Ok( x ) => ShellResult::ok_with_text( x ),
+
+                // with `set +u` it stands for nothing at all, not even an empty argument
+                Err( VarError::NotPresent ) if !ctx.options().nounset => ShellResult::ok(),
+                Err( e ) => match e {
+                    VarError::NotPresent => Err(
+                        Error::other(
+                            format!( "variable '{}' not found", self.0 )
+                        )
+                    ),
+
+                    VarError::NotUnicode( _ ) => Err(
+                        Error::other(
+                            format!( "variable '{}' contains invalid data", self.0 )
+                        )
+                    )
+                }
+            }
+        }
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::leaf( format!( "Var {}", self.0 ) )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        format!( "\"${{{}}}\"", self.0 )
+    }
+}
+
+pub struct Redirect {
+    pub left: Exec,
+
+    // in the order they were written; a later target for the same stream wins,
+    // though every output file is still created like in sh
+    pub targets: Vec<RedirectTarget>,
+}
+
+pub struct RedirectTarget {
+    pub mode: RedirectMode,
+
+    // output redirections add to the end of the file instead of replacing it
+    pub append: bool,
+    pub path: Exec,
+}
+
+impl RedirectTarget {
+    fn to_posix( &self ) -> String {
+        use self::RedirectMode::*;
+
+        let path = self.path.to_posix();
+        match self.mode {
+            StdIn => format!( "< {}", path ),
+            StdOut => format!( "{} {}", if self.append { ">>" } else { ">" }, path ),
+            StdErr => format!( "{} {}", if self.append { "2>>" } else { "2>" }, path ),
+            StdBoth => format!( "{} {} 2>&1", if self.append { ">>" } else { ">" }, path ),
+        }
+    }
+}
+
+
+impl Redirect {
+    // Runs the left side with `stdin` as its input when there is one. Programs are
+    // handed the file itself, however big it is; builtins and the like get its lines.
+    fn run_left( &self, ctx: &mut ExecContext, capture: bool, stdin: Option<File>, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let f = match stdin {
+            Some( x ) => x,
+            None => return self.left.execute( ctx, capture, input ),
+        };
+
+        if let Some( mut proc ) = self.left.process( ctx )? {
+            proc.stdin( Stdio::from( f ) );
+            if capture {
+                proc.stdout( Stdio::piped() );
+                proc.stderr( Stdio::piped() );
+            } else {
+                new_process_group( &mut proc, None );
+            }
+
+            let name = proc.get_program().to_string_lossy().into_owned();
+            let child = profile::measure( "spawn", || proc.spawn() ).map_err( | e | spawn_error( ctx, &name, e ) )?;
+            return SubProcess::Spawned { process: child, capture, writer: None }.result( ctx, self.to_posix() );
+        }
+
+        // programs the left side runs still get the file's bytes as they are
+        let ( input, bytes ) = read_all( f )?;
+        ctx.set_piped_bytes( bytes );
+        let res = self.left.execute( ctx, capture, input );
+        ctx.set_piped_bytes( None );
+
+        res
+    }
+}
+
+impl Executable for Redirect {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        use self::RedirectMode::*;
+
+        let mut stdin = None;
+        let mut files = Vec::new();
+        let mut stdout = None;
+        let mut stderr = None;
+
+        for target in &self.targets {
+            let res = target.path.execute( ctx, true, None )?;
+            ensure_result!( res );
+
+            let s = res.stdout.unwrap_or_default().join( "" );
+            let path = Path::new( &s );
+
+            // output is written even when the command fails; that's usually when stderr matters most
+            let f = match target.mode {
+                StdIn => {
+                    stdin = Some( File::open( path )? );
+                    continue;
+                },
+
+                _ if target.append => OpenOptions::new().create( true ).append( true ).open( path )?,
+                _ => File::create( path )?,
+            };
+
+            if target.mode != StdErr { stdout = Some( files.len() ); }
+            if target.mode != StdOut { stderr = Some( files.len() ); }
+            files.push( f );
+        }
+
+        if files.is_empty() {
+            return self.run_left( ctx, capture, stdin, input );
+        }
+
+        // whatever isn't redirected still goes where it normally would, as it arrives
+        let shown = if capture { ctx.tee() } else { Tee::ALL };
+        let tee = Tee {
+            stdout: shown.stdout && stdout.is_none(),
+            stderr: shown.stderr && stderr.is_none(),
+        };
+
+        let mut left = ctx.with_tee( tee, | ctx | self.run_left( ctx, true, stdin, input ) )?;
+
+        // written as the program wrote it when it came straight from one
+        if let Some( i ) = stdout {
+            files[i].write_all( &left.stdout_bytes().unwrap_or_default() )?;
+            left.stdout = None;
+        }
+
+        if let Some( i ) = stderr {
+            files[i].write_all( &left.stderr_bytes().unwrap_or_default() )?;
+            left.stderr = None;
+        }
+
+        if !capture {
+            left.stdout = None;
+            left.stderr = None;
+        }
+
+        for f in files.iter_mut() {
+            f.flush()?;
+        }
+
+        Ok( left )
+    }
+
+    fn describe( &self ) -> AstNode {
+        let mut children = vec![ self.left.describe() ];
+        for target in &self.targets {
+            let append = if target.append { " (append)" } else { "" };
+            children.push( AstNode::new( format!( "{:?}{}", target.mode, append ), vec![ target.path.describe() ] ) );
+        }
+
+        AstNode::new( "Redirect", children )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        let mut parts = vec![ self.left.to_posix() ];
+        parts.extend( self.targets.iter().map( | x | x.to_posix() ) );
+
+        parts.join( " " )
+    }
+}
+
+// `cmd <<< text` and here-documents: the text becomes the command's input
+// instead of the contents of a file.
+pub struct HereDoc {
+    pub left: Exec,
+    pub text: Exec,
+}
+
+impl Executable for HereDoc {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, _input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let res = self.text.execute( ctx, true, None )?;
+        ensure_result!( res );
+
+        let text = res.stdout.unwrap_or_default().join( "\n" );
+        let lines = text.lines().map( | x | x.to_string() ).collect();
+
+        // an empty body still replaces the terminal as stdin
+        self.left.execute( ctx, capture, Some( lines ) )
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( "HereDoc", vec![ self.left.describe(), self.text.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn to_posix( &self ) -> String {
+        format!( "{} <<< {}", self.left.to_posix(), self.text.to_posix() )
+    }
+}
+
+// Runs the wrapped segment on another machine through ssh, e.g. `@buildbox make -j8`.
+pub struct Remote {
+    pub host: String,
+    pub inner: Exec,
+}
+
+impl Remote {
+    fn ssh( &self ) -> Command {
+        let mut proc = Command::new( "ssh" );
+        proc.arg( &self.host );
+        proc.arg( "--" );
+        proc.arg( remote_command( &self.host, &self.inner.to_posix() ) );
+
+        proc
+    }
+}
+
+impl Executable for Remote {
+    fn execute( &self, ctx: &mut ExecContext, capture: bool, input: Option<Vec<String>> ) -> Result<ShellResult> {
+        let input = input_bytes( ctx, input );
+        match SubProcess::launch( self.ssh(), capture, input ).and_then( | p | p.result( ctx, self.to_posix() ) ) {
+            Ok( x ) => Ok( x ),
+            Err( ref e ) if e.kind() == ErrorKind::NotFound
+                => Err(
+                    Error::new(
+                        ErrorKind::NotFound,
+                        "remote execution requires an 'ssh' client on the PATH."
+                    )
+                ),
+            Err( e ) => Err( e )
+        }
+    }
+
+    fn describe( &self ) -> AstNode {
+        AstNode::new( format!( "Remote {}", self.host ), vec![ self.inner.describe() ] )
+    }
+
+    fn as_any( &self ) -> &dyn Any {
+        self
+    }
+
+    fn process( &self, _ctx: &mut ExecContext ) -> Result<Option<Command>> {
+        Ok( Some( self.ssh() ) )
+    }
+
+    fn to_posix( &self ) -> String {
+        format!(
+            "ssh {} -- {}",
+            quote_posix( &self.host ),
+            quote_posix( &remote_command( &self.host, &self.inner.to_posix() ) )
+        )
+    }
+}
+
+pub( crate ) enum SubProcess {
+    Spawned {
+        process: Child,
+        capture: bool,
+
+        // the thread writing the program's input, if the shell is giving it any
+        writer: Option<JoinHandle<()>>,
+    },
 
     Waiting {
         process: Command,
@@ -399,82 +2087,185 @@ enum SubProcess {
 }
 
 impl SubProcess {
-    pub fn result( self ) -> Result<ShellResult> {
+    pub fn launch( mut proc: Command, capture: bool, input: Option<Vec<u8>> ) -> Result<SubProcess> {
+        if input.is_some() {
+            proc.stdin( Stdio::piped() );
+        }
+
+        if capture {
+            proc.stdout( Stdio::piped() );
+            proc.stderr( Stdio::piped() );
+
+            // captured programs don't get to read from the terminal
+            if input.is_none() {
+                proc.stdin( Stdio::null() );
+            }
+        }
+
+        if let Some( bytes ) = input {
+            let mut child = profile::measure( "spawn", || proc.spawn() )?;
+
+            // written from a thread, since a program whose output is captured can fill
+            // that pipe before it's read all of this; stdin is closed once it's written,
+            // or the program never sees the end of its input
+            let writer = child.stdin.take().map( | mut stdin | thread::spawn( move || {
+                let _ = stdin.write_all( &bytes );
+            } ) );
+
+            Ok( SubProcess::Spawned { process: child, capture, writer } )
+        } else {
+            Ok( SubProcess::Waiting { process: proc, capture } )
+        }
+    }
+
+    // `command` is what the job is listed as if the program is stopped.
+    pub fn result( self, ctx: &ExecContext, command: String ) -> Result<ShellResult> {
         use self::SubProcess::*;
 
-        match self {
-            Spawned { mut process, capture: true } => SubProcess::read_child( &mut process ),
-            Spawned { mut process, capture: false } => Ok( ShellResult {
-                code: get_exit_code( process.wait()? ),
-                stdout: None,
-                stderr: None,
-            } ),
+        let ( mut child, capture, writer ) = match self {
+            Spawned { process, capture, writer } => ( process, capture, writer ),
+            Waiting { mut process, capture } => {
+                // on Windows, interactive programs may need a console of their own
+                if !capture && ctx.deadline().is_none() {
+                    if let Some( code ) = run_in_pseudo_console( &process )? {
+                        return Ok( ShellResult::with_status( ProcessStatus::Exited( code ) ) );
+                    }
+                }
+
+                if !capture {
+                    new_process_group( &mut process, None );
+                }
+
+                ( profile::measure( "spawn", || process.spawn() )?, capture, None )
+            },
+        };
+
+        let _fg = Foreground::new( child.id() );
+        if capture {
+            let res = SubProcess::read_child( child, ctx );
+            SubProcess::join_writer( writer );
+            return res;
+        }
+
+        let status = match ctx.deadline() {
+            Some( _ ) => process_status( SubProcess::wait( &mut child, ctx )? ),
+            None => jobs::wait_foreground( command, vec![ child ] )?,
+        };
 
-            Waiting { mut process, capture: true } => SubProcess::read_command( &mut process ),
-            Waiting { mut process, capture: false } => Ok( ShellResult {
-                code: get_exit_code( process.status()? ),
-                stdout: None,
-                stderr: None,
-            } )
+        // a stopped program is a job now, and may go on reading its input when it's resumed
+        if status != ProcessStatus::Exited( jobs::STOPPED ) {
+            SubProcess::join_writer( writer );
         }
+
+        Ok( ShellResult::with_status( status ) )
     }
 
-    fn read_command( proc: &mut Command ) -> Result<ShellResult> {
-        let res = proc.output()?;
+    fn from_output( res: Output ) -> Result<ShellResult> {
+        let lines = | x: &[u8] | Some( SubProcess::split_lines( String::from_utf8_lossy( x ).into_owned() ) ).filter( | _ | !x.is_empty() );
+
         Ok( ShellResult {
-            code: get_exit_code( res.status ),
-            stdout: if res.stdout.len() > 0 {
-                let buf = String::from_utf8_lossy( &res.stdout ).into_owned();
-                Some( SubProcess::split_lines( buf ) )
-            } else {
-                None
-            },
-            stderr: if res.stderr.len() > 0 {
-                let buf = String::from_utf8_lossy( &res.stderr ).into_owned();
-                Some( SubProcess::split_lines( buf ) )
-            } else {
-                None
-            }
+            status: process_status( res.status ),
+            stdout: lines( &res.stdout ),
+            stderr: lines( &res.stderr ),
+            elapsed: None,
+            value: None,
+            raw: Some( RawOutput { stdout: res.stdout, stderr: res.stderr } ),
         } )
     }
 
+    // Blank lines and surrounding whitespace are kept; line endings are not,
+    // which is what the raw output is for.
     fn split_lines( buf: String ) -> Vec<String> {
-        buf.split( "\n" )
-        .map( | x | x.trim() )
-        .filter( | x | x.len() > 0 )
-        .map( | x | x.to_string() )
-        .collect()
+        buf.lines().map( | x | x.to_string() ).collect()
     }
 
-    fn read_child( child: &mut Child ) -> Result<ShellResult> {
-        Ok( ShellResult {
-            code: get_exit_code( child.wait()? ),
-            stdout:
-            if let Some( mut stdout ) = child.stdout.take() {
-                let mut buf = String::new();
-                let sz = stdout.read_to_string( &mut buf )?;
-
-                if sz > 0 {
-                    Some( SubProcess::split_lines( buf ) )
-                } else {
-                    None
-                }
-            } else {
-                None
-            },
-            stderr:
-            if let Some( mut stderr ) = child.stderr.take() {
-                let mut buf = String::new();
-                let sz = stderr.read_to_string( &mut buf )?;
-
-                if sz > 0 {
-                    Some( SubProcess::split_lines( buf ) )
-                } else {
-                    None
-                }
-            } else {
-                None
+    // stdout and stderr are drained together so a chatty child can't fill one
+    // pipe and block while we're still waiting on the other
+    fn read_child( mut child: Child, ctx: &ExecContext ) -> Result<ShellResult> {
+        let tee = ctx.tee();
+        if tee == Tee::default() && ctx.deadline().is_none() {
+            return SubProcess::from_output( child.wait_with_output()? );
+        }
+
+        let stdout = child.stdout.take().map( | x | {
+            thread::spawn( move || SubProcess::tee_lines( x, Some( std::io::stdout() ).filter( | _ | tee.stdout ) ) )
+        } );
+
+        let stderr = child.stderr.take().map( | x | {
+            thread::spawn( move || SubProcess::tee_lines( x, Some( std::io::stderr() ).filter( | _ | tee.stderr ) ) )
+        } );
+
+        let status = SubProcess::wait( &mut child, ctx )?;
+        let join = | reader: Option<JoinHandle<Result<Vec<u8>>>> | match reader {
+            Some( x ) => x.join().map_err( | _ | Error::other( "unable to read the output of a command" ) )?,
+            None => Ok( Vec::new() ),
+        };
+
+        SubProcess::from_output( Output { status, stdout: join( stdout )?, stderr: join( stderr )? } )
+    }
+
+    // Once the program has exited, whatever was left to write to it never will be.
+    fn join_writer( writer: Option<JoinHandle<()>> ) {
+        if let Some( x ) = writer {
+            let _ = x.join();
+        }
+    }
+
+    // Waits for the child to exit, asking it to stop once the deadline passes
+    // and killing it if it takes longer than the deadline allows. A child that
+    // has to be stopped is noted in `ctx`, for `timeout` to report.
+    fn wait( child: &mut Child, ctx: &ExecContext ) -> Result<ExitStatus> {
+        let deadline = match ctx.deadline() {
+            Some( x ) => x,
+            None => return child.wait(),
+        };
+
+        let poll = Duration::from_millis( 10 );
+        while Instant::now() < deadline.at {
+            if let Some( status ) = child.try_wait()? {
+                return Ok( status );
             }
-        } )
+
+            thread::sleep( poll );
+        }
+
+        ctx.note_timed_out();
+        terminate( child )?;
+
+        let grace = match deadline.kill_after {
+            Some( x ) => Instant::now() + x,
+            None => return child.wait(),
+        };
+
+        while Instant::now() < grace {
+            if let Some( status ) = child.try_wait()? {
+                return Ok( status );
+            }
+
+            thread::sleep( poll );
+        }
+
+        child.kill()?;
+        child.wait()
+    }
+
+    // Reads a stream to the end, copying each line to `out` as soon as it's complete.
+    fn tee_lines<R: Read, W: Write>( stream: R, mut out: Option<W> ) -> Result<Vec<u8>> {
+        let mut reader = BufReader::new( stream );
+        let mut buf = Vec::new();
+
+        loop {
+            let start = buf.len();
+            if reader.read_until( b'\n', &mut buf )? == 0 {
+                break;
+            }
+
+            if let Some( out ) = out.as_mut() {
+                out.write_all( &buf[start..] )?;
+                out.flush()?;
+            }
+        }
+
+        Ok( buf )
     }
 }
@@ -0,0 +1,152 @@
+use std::io::{ Error, Result };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+use dirs::home_dir;
+use rusqlite::{ params, Connection };
+use shell::history::{ Backend, Entry };
+
+pub fn default_path() -> Option<PathBuf> {
+    home_dir().map( | home | home.join( ".lumi_history.db" ) )
+}
+
+fn error( e: rusqlite::Error ) -> Error {
+    Error::other( e )
+}
+
+// History kept in an SQLite database, along with where, when and how long each
+// command ran and how it exited.
+pub struct SqliteBackend {
+    db: Connection,
+
+    // the row of the most recently appended entry, for `finish`
+    last: Option<i64>,
+
+    // the newest row when this session last read or wrote, which anything
+    // else means another session has added to since
+    seen: i64,
+    stale: bool,
+}
+
+impl SqliteBackend {
+    // Opens the database at `path`, creating it if it doesn't exist.
+    pub fn open( path: &Path ) -> Result<SqliteBackend> {
+        SqliteBackend::new( Connection::open( path ).map_err( error )? )
+    }
+
+    // A database that only lasts as long as the backend does.
+    pub fn in_memory() -> Result<SqliteBackend> {
+        SqliteBackend::new( Connection::open_in_memory().map_err( error )? )
+    }
+
+    fn new( db: Connection ) -> Result<SqliteBackend> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY,
+                command TEXT NOT NULL,
+                cwd TEXT,
+                timestamp INTEGER,
+                duration_ms INTEGER,
+                status INTEGER
+            );"
+        ).map_err( error )?;
+
+        // sessions writing at the same moment wait for each other
+        db.busy_timeout( Duration::from_secs( 2 ) ).map_err( error )?;
+        Ok( SqliteBackend { db, last: None, seen: 0, stale: false } )
+    }
+
+    fn newest( &self ) -> Result<i64> {
+        self.db.query_row( "SELECT coalesce( max( id ), 0 ) FROM history", [], | row | row.get( 0 ) ).map_err( error )
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn load( &mut self, limit: usize ) -> Result<Vec<Entry>> {
+        let mut query = self.db.prepare(
+            "SELECT command, cwd, timestamp, duration_ms, status FROM
+                ( SELECT * FROM history ORDER BY id DESC LIMIT ?1 )
+            ORDER BY id"
+        ).map_err( error )?;
+
+        let rows = query.query_map( params![ limit.min( i64::MAX as usize ) as i64 ], | row | {
+            Ok( Entry {
+                command: row.get( 0 )?,
+                cwd: row.get::<_, Option<String>>( 1 )?.map( PathBuf::from ),
+                timestamp: row.get::<_, Option<i64>>( 2 )?.map( | x | x as u64 ),
+                duration: row.get::<_, Option<i64>>( 3 )?.map( | x | Duration::from_millis( x as u64 ) ),
+                status: row.get( 4 )?,
+            } )
+        } ).map_err( error )?;
+
+        let entries = rows.collect::<rusqlite::Result<_>>().map_err( error )?;
+        drop( query );
+
+        self.seen = self.newest()?;
+        self.stale = false;
+        Ok( entries )
+    }
+
+    fn append( &mut self, entry: &Entry ) -> Result<()> {
+        self.db.execute(
+            "INSERT INTO history ( command, cwd, timestamp, duration_ms, status ) VALUES ( ?1, ?2, ?3, ?4, ?5 )",
+            params![
+                entry.command,
+                entry.cwd.as_ref().map( | x | x.to_string_lossy().into_owned() ),
+                entry.timestamp.map( | x | x as i64 ),
+                entry.duration.map( | x | x.as_millis() as i64 ),
+                entry.status,
+            ],
+        ).map_err( error )?;
+
+        // rows are numbered one after the other, so a gap is someone else's
+        let id = self.db.last_insert_rowid();
+        if id != self.seen + 1 {
+            self.stale = true;
+        }
+
+        self.last = Some( id );
+        self.seen = id;
+        Ok( () )
+    }
+
+    fn finish( &mut self, entry: &Entry ) -> Result<()> {
+        let id = match self.last {
+            Some( x ) => x,
+            None => return Ok( () ),
+        };
+
+        self.db.execute(
+            "UPDATE history SET duration_ms = ?1, status = ?2 WHERE id = ?3",
+            params![ entry.duration.map( | x | x.as_millis() as i64 ), entry.status, id ],
+        ).map_err( error )?;
+
+        Ok( () )
+    }
+
+    fn trim( &mut self, entries: &[Entry] ) -> Result<()> {
+        self.db.execute(
+            "DELETE FROM history WHERE id NOT IN ( SELECT id FROM history ORDER BY id DESC LIMIT ?1 )",
+            params![ entries.len() as i64 ],
+        ).map_err( error )?;
+
+        Ok( () )
+    }
+
+    fn clear( &mut self ) -> Result<()> {
+        self.db.execute( "DELETE FROM history", [] ).map_err( error )?;
+        self.last = None;
+
+        // numbering starts over, which isn't another session's doing
+        self.seen = self.newest()?;
+        self.stale = false;
+        Ok( () )
+    }
+
+    fn reload( &mut self, limit: usize ) -> Result<Option<Vec<Entry>>> {
+        if self.newest()? == self.seen && !self.stale {
+            return Ok( None );
+        }
+
+        self.load( limit ).map( Some )
+    }
+}
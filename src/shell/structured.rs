@@ -0,0 +1,46 @@
+use std::io::{ Result, Error, ErrorKind };
+use serde_json::Value;
+use kernel::ProcessStatus;
+use shell::context::ExecContext;
+use shell::segments::ShellResult;
+
+// Reads lines of text as one JSON document.
+pub fn parse( lines: &[String] ) -> Result<Value> {
+    serde_json::from_str( &lines.join( "\n" ) ).map_err( | e | Error::new( ErrorKind::InvalidData, format!( "not valid JSON: {}", e ) ) )
+}
+
+// The value a builtin was given: the one the stage before it produced, or
+// else the lines piped into it read as JSON. None when nothing was piped in.
+pub fn input( ctx: &mut ExecContext, input: Option<Vec<String>> ) -> Result<Option<Value>> {
+    match ( ctx.take_piped_value(), input ) {
+        ( Some( x ), _ ) => Ok( Some( x ) ),
+        ( None, Some( lines ) ) => parse( &lines ).map( Some ),
+        ( None, None ) => Ok( None ),
+    }
+}
+
+// The lines a value is shown as. Strings are written as they are so they can be
+// used as arguments; anything else is indented JSON.
+pub fn to_lines( value: &Value ) -> Vec<String> {
+    match value {
+        Value::String( x ) => x.lines().map( | x | x.to_string() ).collect(),
+        _ => serde_json::to_string_pretty( value ).unwrap_or_default().lines().map( | x | x.to_string() ).collect(),
+    }
+}
+
+// Follows a path like items.0.name into a value: each part is the name of an
+// object's field or the index of an array's element. An empty path is the
+// value itself.
+pub fn get<'a>( value: &'a Value, path: &str ) -> Option<&'a Value> {
+    path.split( '.' ).filter( | x | !x.is_empty() ).try_fold( value, | value, key | match value {
+        Value::Object( fields ) => fields.get( key ),
+        Value::Array( items ) => key.parse::<usize>().ok().and_then( | i | items.get( i ) ),
+        _ => None,
+    } )
+}
+
+// A successful result with `lines` as its output and `value` behind them, for
+// the next stage of a pipeline to take if it understands it.
+pub fn result( lines: Vec<String>, value: Value ) -> Result<ShellResult> {
+    Ok( ShellResult { value: Some( value ), ..ShellResult::with_stdout( ProcessStatus::Exited( 0 ), lines ) } )
+}
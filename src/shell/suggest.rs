@@ -0,0 +1,23 @@
+// Offers the rest of a command line while it's being typed, shown after the
+// cursor as dimmed text that the right arrow or End accepts.
+pub trait Suggester {
+    // The text to show after `line`, or None to show nothing.
+    fn suggest( &self, line: &str, history: &[String] ) -> Option<String>;
+}
+
+// Suggests the most recent history entry that starts with what has been typed.
+pub struct FromHistory;
+
+impl Suggester for FromHistory {
+    fn suggest( &self, line: &str, history: &[String] ) -> Option<String> {
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        // entries spanning several lines, like here-documents, can't be shown inline
+        history.iter()
+            .rev()
+            .find( | x | x.len() > line.len() && x.starts_with( line ) && !x.contains( '\n' ) )
+            .map( | x | x[line.len() ..].to_string() )
+    }
+}
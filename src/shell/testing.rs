@@ -0,0 +1,71 @@
+use std::env::{ current_exe, temp_dir };
+use std::fs::{ create_dir_all, read_dir, remove_dir_all };
+use std::io::Result;
+use std::path::{ Path, PathBuf };
+use std::process::{ self, Command };
+
+pub const TEST_SUFFIX: &str = ".test.lumi";
+
+pub struct TestOutcome {
+    pub path: PathBuf,
+    pub passed: bool,
+    pub output: String,
+}
+
+// Recursively finds *.test.lumi files, skipping hidden directories and build output.
+pub fn discover( root: &Path ) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+
+    if root.is_file() {
+        found.push( root.to_path_buf() );
+        return found;
+    }
+
+    let entries = match read_dir( root ) {
+        Ok( x ) => x,
+        Err( _ ) => return found,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if path.is_dir() {
+            if !name.starts_with( '.' ) && name != "target" {
+                found.append( &mut discover( &path ) );
+            }
+        } else if name.ends_with( TEST_SUFFIX ) {
+            found.push( path );
+        }
+    }
+
+    found.sort();
+    found
+}
+
+// Runs one test file in a fresh lumi process whose working directory is an
+// empty temporary directory, so tests can't see or disturb each other.
+pub fn run_isolated( path: &Path, index: usize ) -> Result<TestOutcome> {
+    let path = path.canonicalize()?;
+    let sandbox = temp_dir().join( format!( "lumi-test-{}-{}", process::id(), index ) );
+    create_dir_all( &sandbox )?;
+
+    let res = Command::new( current_exe()? )
+        .arg( "test" )
+        .arg( "--run-file" )
+        .arg( &path )
+        .current_dir( &sandbox )
+        .output();
+
+    let _ = remove_dir_all( &sandbox );
+    let output = res?;
+
+    let mut text = String::from_utf8_lossy( &output.stdout ).into_owned();
+    text.push_str( &String::from_utf8_lossy( &output.stderr ) );
+
+    Ok( TestOutcome {
+        path,
+        passed: output.status.success(),
+        output: text,
+    } )
+}
@@ -0,0 +1,41 @@
+use std::path::{ Path, MAIN_SEPARATOR };
+use dirs::home_dir;
+use kernel::user_home;
+
+// Replaces a leading ~ or ~user with the matching home directory. Words that
+// name an unknown user are left alone.
+pub fn expand( word: &str ) -> String {
+    let rest = match word.strip_prefix( '~' ) {
+        Some( x ) => x,
+        None => return word.to_string(),
+    };
+
+    let ( user, tail ) = match rest.find( '/' ) {
+        Some( i ) => ( &rest[..i], &rest[i..] ),
+        None => ( rest, "" ),
+    };
+
+    let home = if user.is_empty() { home_dir() } else { user_home( user ) };
+    match home {
+        Some( home ) => format!( "{}{}", home.to_string_lossy(), tail ),
+        None => word.to_string(),
+    }
+}
+
+// The reverse of `expand`: a path inside the home directory written as ~/...
+pub fn abbreviate( path: &Path ) -> String {
+    let rest = match home_dir() {
+        Some( home ) => match path.strip_prefix( home ) {
+            Ok( x ) => x.to_path_buf(),
+            Err( _ ) => return path.to_string_lossy().into_owned(),
+        },
+
+        None => return path.to_string_lossy().into_owned(),
+    };
+
+    if rest.as_os_str().is_empty() {
+        "~".to_string()
+    } else {
+        format!( "~{}{}", MAIN_SEPARATOR, rest.to_string_lossy() )
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::{ File, OpenOptions };
+use std::io::{ Result, Write };
+use std::path::{ Path, PathBuf };
+use kernel::local_time;
+
+// A record of an interactive session: each command line as it was entered,
+// after its prompt, followed by what it printed.
+pub struct Transcript {
+    path: PathBuf,
+    file: File,
+}
+
+// Drops terminal escape sequences, such as the colors in a prompt.
+pub fn strip_ansi( text: &str ) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some( c ) = chars.next() {
+        if c != '\x1B' {
+            out.push( c );
+            continue;
+        }
+
+        // CSI sequences end with a letter and OSC ones with BEL or ESC \;
+        // anything else is ESC and one character
+        match chars.next() {
+            Some( '[' ) => {
+                for x in chars.by_ref() {
+                    if x.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            },
+
+            Some( ']' ) => {
+                while let Some( x ) = chars.next() {
+                    if x == '\x07' || ( x == '\x1B' && chars.next_if_eq( &'\\' ).is_some() ) {
+                        break;
+                    }
+                }
+            },
+
+            _ => {},
+        }
+    }
+
+    out
+}
+
+impl Transcript {
+    // Appends to `path`, creating it if need be.
+    pub fn open( path: &Path ) -> Result<Transcript> {
+        let file = OpenOptions::new().create( true ).append( true ).open( path )?;
+        Ok( Transcript { path: path.to_path_buf(), file } )
+    }
+
+    pub fn path( &self ) -> &Path {
+        &self.path
+    }
+
+    pub fn command( &mut self, prompt: &str, line: &str ) -> Result<()> {
+        let ( h, m, s ) = local_time();
+        writeln!( self.file, "[{:02}:{:02}:{:02}] {}{}", h, m, s, strip_ansi( prompt ), line )
+    }
+
+    pub fn output<'a, I: IntoIterator<Item = &'a String>>( &mut self, lines: I ) -> Result<()> {
+        for line in lines {
+            writeln!( self.file, "{}", strip_ansi( line ) )?;
+        }
+
+        self.file.flush()
+    }
+}
@@ -0,0 +1,49 @@
+// shared by every test binary, none of which use all of it
+#![allow( dead_code )]
+
+use std::io::Result;
+use std::sync::Mutex;
+use lumi::shell::context::ExecContext;
+use lumi::shell::parsing::{ ShellLexer, ShellParser };
+use lumi::shell::segments::{ Exec, ShellResult };
+
+lazy_static! {
+    // contexts keep the process's working directory and environment in step with
+    // them, so tests that execute anything take turns
+    static ref PROCESS: Mutex<()> = Mutex::new( () );
+}
+
+pub fn parse( source: &str ) -> Exec {
+    let tokens = ShellLexer::new( source ).tokenize().unwrap_or_else( | e | panic!( "unable to lex {:?}: {}", source, e ) );
+    ShellParser::new( tokens ).parse_all().unwrap_or_else( | e | panic!( "unable to parse {:?}: {}", source, e ) )
+}
+
+// Runs `f` while no test is executing anything.
+pub fn exclusive<T, F: FnOnce() -> T>( f: F ) -> T {
+    let _lock = PROCESS.lock().unwrap_or_else( | e | e.into_inner() );
+    f()
+}
+
+// Runs `source` in a fresh context with its output captured.
+pub fn try_run( source: &str ) -> Result<ShellResult> {
+    let _lock = PROCESS.lock().unwrap_or_else( | e | e.into_inner() );
+    let seg = parse( source );
+    let cwd = std::env::current_dir()?;
+
+    let mut ctx = ExecContext::new();
+    let res = seg.execute( &mut ctx, true, None );
+    ctx.restore()?;
+
+    // scripts that cd don't move the tests that come after them
+    std::env::set_current_dir( cwd )?;
+
+    res
+}
+
+pub fn run( source: &str ) -> ShellResult {
+    try_run( source ).unwrap_or_else( | e | panic!( "unable to run {:?}: {}", source, e ) )
+}
+
+pub fn stdout( source: &str ) -> Vec<String> {
+    run( source ).stdout().cloned().unwrap_or_default()
+}
@@ -0,0 +1,73 @@
+extern crate lumi;
+
+use std::path::Path;
+use std::time::Duration;
+use lumi::shell::history::{ FileBackend, History };
+use lumi::shell::sqlite_history::SqliteBackend;
+
+#[test]
+fn sqlite_history_keeps_how_commands_went() {
+    let mut history = History::open( Box::new( SqliteBackend::in_memory().unwrap() ), 2 ).unwrap();
+    history.add( "make", Path::new( "/src" ) ).unwrap();
+    history.finish( 2, Duration::from_millis( 1500 ) ).unwrap();
+    history.add( "make test", Path::new( "/src" ) ).unwrap();
+    history.add( "ls", Path::new( "/" ) ).unwrap();
+
+    assert_eq!( history.entries(), [ "make test", "ls" ] );
+    assert_eq!( history.records()[1].cwd.as_deref(), Some( Path::new( "/" ) ) );
+    assert_eq!( history.records()[0].status, None );
+}
+
+#[test]
+fn sessions_see_each_others_history() {
+    let path = std::env::temp_dir().join( format!( "lumi-shared-history-{}", std::process::id() ) );
+    let _ = std::fs::remove_file( &path );
+
+    let mut first = History::open( Box::new( FileBackend::new( path.clone() ) ), 10 ).unwrap();
+    let mut second = History::open( Box::new( FileBackend::new( path.clone() ) ), 10 ).unwrap();
+    first.add( "echo one", Path::new( "/" ) ).unwrap();
+    second.add( "echo two", Path::new( "/" ) ).unwrap();
+    first.reload().unwrap();
+    second.reload().unwrap();
+
+    assert_eq!( first.entries(), [ "echo one", "echo two" ] );
+    assert_eq!( second.entries(), [ "echo one", "echo two" ] );
+    assert_eq!( first.records()[0].cwd.as_deref(), Some( Path::new( "/" ) ) );
+    let _ = std::fs::remove_file( &path );
+}
+
+#[test]
+fn ignored_commands_are_never_kept() {
+    let mut history = History::new( 10 );
+    history.set_ignore( vec![ "*password*".to_string() ] );
+    history.add( "ls", Path::new( "/" ) ).unwrap();
+    history.add( "login --password hunter2", Path::new( "/" ) ).unwrap();
+    history.add( " export TOKEN=abc", Path::new( "/" ) ).unwrap();
+    history.finish( 1, Duration::from_secs( 1 ) ).unwrap();
+
+    assert_eq!( history.entries(), [ "ls" ] );
+    assert_eq!( history.records()[0].status, None );
+}
+
+#[test]
+fn only_commands_that_were_added_are_finished() {
+    let mut history = History::open( Box::new( SqliteBackend::in_memory().unwrap() ), 10 ).unwrap();
+    history.add( "make", Path::new( "/" ) ).unwrap();
+    history.finish( 2, Duration::from_secs( 1 ) ).unwrap();
+
+    // repeats, blank lines and multi-line commands aren't added, so their status isn't kept either
+    for line in [ "make", "", "echo a\necho b" ] {
+        history.add( line, Path::new( "/" ) ).unwrap();
+        history.finish( 0, Duration::from_secs( 1 ) ).unwrap();
+    }
+
+    assert_eq!( history.entries(), [ "make" ] );
+    assert_eq!( history.records()[0].status, Some( 2 ) );
+
+    history.clear().unwrap();
+    history.add( "ls", Path::new( "/" ) ).unwrap();
+    history.finish( 0, Duration::from_secs( 1 ) ).unwrap();
+    history.reload().unwrap();
+    assert_eq!( history.entries(), [ "ls" ] );
+    assert_eq!( history.records()[0].status, Some( 0 ) );
+}
@@ -0,0 +1,29 @@
+extern crate lumi;
+
+#[cfg( unix )]
+#[test]
+fn secrets_are_never_passed_as_arguments() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // stands in for both `security` and `secret-tool`, noting what it was given
+    let dir = std::env::temp_dir().join( format!( "lumi-test-keyring-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+    let script = format!( "#!/bin/sh\necho \"$@\" > '{0}/args'\ncat > '{0}/input'\n", dir.display() );
+    for tool in [ "security", "secret-tool" ] {
+        let path = dir.join( tool );
+        std::fs::write( &path, &script ).unwrap();
+        std::fs::set_permissions( &path, std::fs::Permissions::from_mode( 0o755 ) ).unwrap();
+    }
+
+    let path = format!( "{}:{}", dir.display(), std::env::var( "PATH" ).unwrap_or_default() );
+    std::env::set_var( "PATH", path );
+    lumi::kernel::store_secret( "token", "hunter2" ).unwrap();
+
+    let args = std::fs::read_to_string( dir.join( "args" ) ).unwrap();
+    let input = std::fs::read_to_string( dir.join( "input" ) ).unwrap();
+    assert!( !args.contains( "hunter2" ) && !args.contains( "68756e74657232" ) );
+    assert!( input.contains( "hunter2" ) || input.contains( "68756e74657232" ) );
+
+    assert_eq!( lumi::kernel::keychain_store_command( "token", "hunter2" ), "add-generic-password -U -s lumi -a \"token\" -X 68756e74657232\n" );
+    std::fs::remove_dir_all( &dir ).unwrap();
+}
@@ -0,0 +1,181 @@
+extern crate lumi;
+extern crate yansi;
+
+#[macro_use]
+extern crate lazy_static;
+
+mod common;
+
+use lumi::shell::parsing::{ ShellLexer, ShellParser, needs_more_input };
+use lumi::shell::segments::AstNode;
+use common::parse;
+
+fn labels( node: &AstNode ) -> Vec<String> {
+    let mut out = vec![ node.label.clone() ];
+    for child in &node.children {
+        out.extend( labels( child ) );
+    }
+
+    out
+}
+
+#[test]
+fn simple_command() {
+    assert_eq!( labels( &parse( "ls -l src" ).describe() ), [ "Cmd", "Text \"ls\"", "Text \"-l\"", "Text \"src\"" ] );
+}
+
+#[test]
+fn and_binds_tighter_than_seq() {
+    let tree = parse( "a; b && c" ).describe();
+    assert_eq!( tree.label, "Seq" );
+    assert_eq!( tree.children[1].label, "AndOr &&" );
+}
+
+#[test]
+fn pipes_and_redirects() {
+    let tree = parse( "ls | wc -l > count" ).describe();
+    assert_eq!( tree.label, "Pipe |" );
+    assert_eq!( tree.children[1].label, "Redirect" );
+    assert_eq!( tree.children[1].children[1].label, "StdOut" );
+}
+
+#[test]
+fn redirects_collect_on_one_command() {
+    let tree = parse( "cmd < in > out 2>> err" ).describe();
+    let targets: Vec<&str> = tree.children[1 ..].iter().map( | x | x.label.as_str() ).collect();
+    assert_eq!( tree.label, "Redirect" );
+    assert_eq!( targets, [ "StdIn", "StdOut", "StdErr (append)" ] );
+
+    // targets can be built from variables and commands like any argument
+    let tree = parse( "cmd < $(echo in) > $d/out 2> \"$d/err\" >> $log" ).describe();
+    assert_eq!( tree.children.len(), 5 );
+}
+
+#[test]
+fn control_flow() {
+    let tree = parse( "if test -f x { echo a } else { echo b }" ).describe();
+    let children: Vec<&str> = tree.children.iter().map( | x | x.label.as_str() ).collect();
+    assert_eq!( tree.label, "If" );
+    assert_eq!( children, [ "condition", "then", "else" ] );
+
+    assert_eq!( parse( "for x in a b { echo $x }" ).describe().label, "For x" );
+    assert_eq!( parse( "while false { echo }" ).describe().label, "While" );
+
+    // `par` only starts a block when one follows it
+    assert_eq!( parse( "par { a; b }" ).describe().label, "Par" );
+    assert_eq!( parse( "par { a; b }" ).to_posix(), "{ a & b & wait; }" );
+    assert_eq!( parse( "par -w 40" ).describe().label, "Cmd" );
+}
+
+#[test]
+fn interpolation() {
+    let tree = parse( "echo \"a {b}\"" ).describe();
+    assert_eq!( tree.children[1].label, "TextInterp" );
+}
+
+#[test]
+fn to_posix_round_trips_structure() {
+    assert_eq!( parse( "a && b || c" ).to_posix(), "a && b || c" );
+    assert_eq!( parse( "a |& b" ).to_posix(), "a |& b" );
+}
+
+#[test]
+fn unterminated_string_is_a_lex_error() {
+    assert!( ShellLexer::new( "echo \"abc" ).tokenize().is_err() );
+}
+
+#[test]
+fn dangling_operator_is_a_parse_error() {
+    let tokens = ShellLexer::new( "ls |" ).tokenize().unwrap();
+    assert!( ShellParser::new( tokens ).parse_all().is_err() );
+}
+
+#[test]
+fn adjacent_parts_form_one_word() {
+    assert_eq!( labels( &parse( "echo foo\\ bar a\"b\"'c'" ).describe() ), [ "Cmd", "Text \"echo\"", "Text \"foo bar\"", "Text \"abc\"" ] );
+    assert_eq!( parse( "echo pre\"$x\"post" ).describe().children[1].label, "TextInterp" );
+}
+
+#[test]
+fn escaped_wildcards_are_literal() {
+    assert_eq!( parse( "echo \\*" ).describe().children[1].label, "Text \"*\"" );
+    assert_eq!( parse( "echo a\\?*" ).describe().children[1].label, "Glob \"a\\\\?*\"" );
+    assert_eq!( parse( "echo C:\\Users" ).describe().children[1].label, "Text \"C:\\\\Users\"" );
+}
+
+#[test]
+fn newlines_separate_commands() {
+    assert_eq!( parse( "a\n\nb # note\n" ).describe().label, "Seq" );
+    assert_eq!( parse( "a |\n  b" ).describe().label, "Pipe |" );
+    assert_eq!( parse( "if x {\n  a\n  b\n}" ).describe().label, "If" );
+    assert_eq!( labels( &parse( "echo a \\\n  b" ).describe() ), [ "Cmd", "Text \"echo\"", "Text \"a\"", "Text \"b\"" ] );
+}
+
+#[test]
+fn unfinished_commands_need_more_input() {
+    for source in [ "if x {", "a &&", "a |", "echo \"abc", "echo a \\", "cat <<EOF" ] {
+        assert!( needs_more_input( source ), "{:?}", source );
+    }
+
+    for source in [ "echo a", "echo a \\\\", "a }" ] {
+        assert!( !needs_more_input( source ), "{:?}", source );
+    }
+}
+
+#[test]
+fn diagnostics_show_the_line_at_fault() {
+    use lumi::shell::diagnostics::Diagnostic;
+    use lumi::shell::transcript::strip_ansi;
+    use yansi::Color;
+
+    let source = "echo a\necho 漢字 && || b";
+    let tokens = ShellLexer::new( source ).tokenize().unwrap();
+    let e = ShellParser::new( tokens ).parse_all().err().unwrap();
+
+    let lines: Vec<String> = Diagnostic::from( &e ).render( source, 80, Color::Red ).iter().map( | x | strip_ansi( x ) ).collect();
+    assert_eq!( lines, [
+        "expecting shell segment, found || (line 2, column 14)",
+        "2 │ echo 漢字 && || b",
+        "  │              ^^",
+    ] );
+}
+
+#[test]
+fn parsing_carries_on_past_mistakes() {
+    let errors = |source: &str| {
+        let tokens = ShellLexer::new( source ).tokenize().unwrap();
+        ShellParser::new( tokens ).parse_recovering().err().unwrap_or_default().iter().map( | e | e.to_string() ).collect::<Vec<_>>()
+    };
+
+    assert_eq!( errors( "echo a > | cat; ls |" ), [
+        "expecting shell segment, found | at line 1, column 10",
+        "expecting shell segment, found <end-of-input> at line 1, column 21",
+    ] );
+
+    assert_eq!( errors( "echo a )\nls | ;\necho b" ), [
+        "unexpected ), expecting <end-of-input> at line 1, column 8",
+        "expecting shell segment, found ; at line 2, column 6",
+    ] );
+
+    assert!( errors( "echo a > b; ls | cat" ).is_empty() );
+}
+
+#[test]
+fn assignments_only_count_before_the_command() {
+    assert_eq!( labels( &parse( "A=1 B=\"x $y\" cmd C=2" ).describe() ), [
+        "Assign", "A=", "Text \"1\"", "B=", "TextInterp", "Text \"x \"", "Var y", "Cmd", "Text \"cmd\"", "Text \"C=2\"",
+    ] );
+
+    assert_eq!( labels( &parse( "\"A=1\" cmd" ).describe() ), [ "Cmd", "Text \"A=1\"", "Text \"cmd\"" ] );
+}
+
+#[test]
+fn nesting_too_deeply_is_an_error_not_a_crash() {
+    let source = format!( "{}echo hi{}", "{ ".repeat( 150 ), " }".repeat( 150 ) );
+    let tokens = ShellLexer::new( &source ).tokenize().unwrap();
+    let e = ShellParser::new( tokens ).parse_all().err().unwrap();
+    assert!( e.to_string().starts_with( "nested more than 100 levels deep" ) );
+
+    let source = format!( "echo \"{}x{}\"", "$(echo \"".repeat( 150 ), "\")".repeat( 150 ) );
+    assert!( ShellLexer::new( &source ).tokenize().is_err() );
+}
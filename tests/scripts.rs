@@ -0,0 +1,415 @@
+extern crate lumi;
+
+#[macro_use]
+extern crate lazy_static;
+
+mod common;
+
+use common::{ run, try_run, stdout, exclusive };
+
+#[test]
+fn builtin_output_is_captured() {
+    assert_eq!( stdout( "which which" ), [ "which: shell builtin" ] );
+}
+
+#[test]
+fn sequences_keep_all_output() {
+    assert_eq!( stdout( "echo a; echo b" ), [ "a", "b" ] );
+}
+
+#[test]
+fn and_or_follow_exit_status() {
+    assert_eq!( stdout( "false && echo no || echo yes" ), [ "yes" ] );
+    assert_eq!( run( "false" ).code(), Some( 1 ) );
+    assert_eq!( run( "true" ).code(), Some( 0 ) );
+}
+
+#[test]
+fn pipelines_connect_programs() {
+    assert_eq!( stdout( "echo hello | tr a-z A-Z" ), [ "HELLO" ] );
+}
+
+#[test]
+fn if_and_loops() {
+    assert_eq!( stdout( "if false { echo a } else { echo b }" ), [ "b" ] );
+    assert_eq!( stdout( "for x in 1 2 3 { echo $x }" ), [ "1", "2", "3" ] );
+}
+
+#[test]
+fn interpolation_runs_commands() {
+    assert_eq!( stdout( "echo \"<{echo inner}>\"" ), [ "<inner>" ] );
+}
+
+#[test]
+fn exported_variables_expand() {
+    assert_eq!( stdout( "export LUMI_TEST_VAR=1; echo $LUMI_TEST_VAR" ), [ "1" ] );
+}
+
+#[test]
+fn subshells_dont_change_their_parent() {
+    assert!( try_run( "echo \"{export LUMI_TEST_SUBSHELL=1}\"; echo $LUMI_TEST_SUBSHELL" ).is_err() );
+    assert!( std::env::var( "LUMI_TEST_SUBSHELL" ).is_err() );
+}
+
+#[test]
+fn stderr_is_captured_separately() {
+    let res = run( "sh -c 'echo out; echo err >&2'" );
+    assert_eq!( res.stdout().cloned().unwrap_or_default(), [ "out" ] );
+    assert_eq!( res.stderr().cloned().unwrap_or_default(), [ "err" ] );
+}
+
+#[test]
+fn captured_output_is_kept_as_written() {
+    assert_eq!( stdout( "printf ' a \\n\\nb\\n'" ), [ " a ", "", "b" ] );
+    assert_eq!( stdout( "echo \"[{printf 'x\\n\\n'}]\"" ), [ "[x]" ] );
+}
+
+#[test]
+fn streams_redirect_to_separate_files() {
+    let dir = std::env::temp_dir();
+    let out = dir.join( format!( "lumi-test-{}.out", std::process::id() ) );
+    let err = dir.join( format!( "lumi-test-{}.err", std::process::id() ) );
+
+    let res = run( &format!( "sh -c 'echo out; echo err >&2' > '{}' 2> '{}'", out.display(), err.display() ) );
+    assert_eq!( res.stdout().cloned().unwrap_or_default(), Vec::<String>::new() );
+    assert_eq!( std::fs::read_to_string( &out ).unwrap(), "out\n" );
+    assert_eq!( std::fs::read_to_string( &err ).unwrap(), "err\n" );
+
+    let _ = std::fs::remove_file( out );
+    let _ = std::fs::remove_file( err );
+}
+
+#[test]
+fn streams_redirect_to_devices() {
+    let res = run( "sh -c 'echo out; echo err >&2' 2> /dev/null" );
+    assert_eq!( res.stdout().cloned().unwrap_or_default(), [ "out" ] );
+    assert_eq!( stdout( "echo hi > /dev/null; echo done" ), [ "done" ] );
+}
+
+#[test]
+fn echo_handles_options_and_escapes() {
+    assert_eq!( stdout( "echo -n a b" ), [ "a b" ] );
+    assert_eq!( stdout( "echo -e 'a\\tb\\nc'" ), [ "a\tb", "c" ] );
+    assert_eq!( stdout( "echo -x 'a\\tb'" ), [ "-x a\\tb" ] );
+}
+
+#[test]
+fn set_lists_shell_variables() {
+    let lines = stdout( "echo 'a b' | $lumi_test_set; set" );
+    assert!( lines.contains( &"lumi_test_set='a b'".to_string() ) );
+}
+
+#[test]
+fn type_describes_commands() {
+    assert_eq!( stdout( "alias lumi_test_ll='ls -l'; type lumi_test_ll cd" ), [ "lumi_test_ll is aliased to 'ls -l'", "cd is a shell builtin" ] );
+    assert_eq!( run( "type lumi-test-missing" ).code(), Some( 1 ) );
+}
+
+#[test]
+fn source_runs_in_the_current_shell() {
+    let file = std::env::temp_dir().join( format!( "lumi-test-{}.lu", std::process::id() ) );
+    std::fs::write( &file, "alias lumi_test_sourced='echo sourced'\necho loaded\n" ).unwrap();
+
+    assert_eq!( stdout( &format!( "source '{}'; lumi_test_sourced", file.display() ) ), [ "loaded", "sourced" ] );
+    let _ = std::fs::remove_file( file );
+}
+
+#[test]
+fn test_evaluates_conditions() {
+    assert_eq!( run( "test 1 -lt 2" ).code(), Some( 0 ) );
+    assert_eq!( run( "test a = b" ).code(), Some( 1 ) );
+    assert_eq!( run( "[ ! -d Cargo.toml -a -f Cargo.toml ]" ).code(), Some( 0 ) );
+    assert_eq!( run( "test x -eq 1" ).code(), Some( 2 ) );
+    assert_eq!( stdout( "if [ -z '' ] { echo empty }" ), [ "empty" ] );
+}
+
+#[test]
+fn pushd_and_popd_keep_a_stack() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    let lines = stdout( &format!( "cd '{0}'; pushd /; popd; pwd", dir.display() ) );
+    assert_eq!( lines.last().map( | x | x.as_str() ), Some( dir.to_str().unwrap() ) );
+    assert_eq!( run( "popd" ).code(), Some( 1 ) );
+}
+
+#[test]
+fn cd_goes_back_through_recent_directories() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    let start = std::env::current_dir().unwrap();
+    let lines = stdout( &format!( "cd '{}'; cd /; cd -2; recent", dir.display() ) );
+    assert_eq!( lines[0], start.to_str().unwrap() );
+    assert_eq!( lines[1].trim_start(), "1  /" );
+}
+
+#[test]
+fn timeout_only_reports_commands_it_stopped() {
+    let script = std::env::temp_dir().join( format!( "lumi-test-timeout-{}", std::process::id() ) );
+
+    // runs well past the deadline, but in the shell, with no program left to stop
+    std::fs::write( &script, "for x in $(seq 1 100000) { test $x }" ).unwrap();
+    assert_eq!( stdout( &format!( "timeout 0.2 source '{}'; echo $?", script.display() ) ), [ "0" ] );
+    assert_eq!( stdout( "timeout 0.2 sleep 5; echo $?; timeout 5 sh -c 'exit 3'; echo $?" ), [ "124", "3" ] );
+
+    let _ = std::fs::remove_file( script );
+}
+
+#[test]
+fn signals_are_told_apart_from_exit_codes() {
+    let res = run( "sh -c 'kill -TERM $$'" );
+    assert_eq!( res.code(), Some( 143 ) );
+    assert_eq!( res.status().to_string(), "terminated by SIGTERM" );
+    assert_eq!( stdout( "sh -c 'kill -TERM $$' && echo yes || echo no" ), [ "no" ] );
+}
+
+#[test]
+fn backslashes_follow_the_quoting_rules() {
+    assert_eq!( stdout( "echo 'it\\'s' \"a\\tb\" 'a\\tb' `a\\tb`" ), [ "it's a\tb a\\tb a\\tb" ] );
+    assert_eq!( stdout( "echo \\$HOME \"\\$HOME\" \\{x\\}" ), [ "$HOME $HOME {x}" ] );
+    assert_eq!( stdout( "echo \"line\\nbreak\"" ), [ "line", "break" ] );
+}
+
+#[test]
+fn substitutions_join_the_words_around_them() {
+    assert_eq!( stdout( "echo x$(echo 5)y $(echo 6)z a\"$(echo b c)\"d" ), [ "x5y 6z ab cd" ] );
+    assert_eq!( stdout( "for x in $(echo a b) { echo [$x] }" ), [ "[a]", "[b]" ] );
+    assert_eq!( stdout( "echo a'$(echo 5)'b" ), [ "a$(echo 5)b" ] );
+}
+
+#[test]
+fn commands_carry_on_over_lines() {
+    let source = "for x in a b {\n    echo $x |\n        cat\n}\necho \"one\ntwo\" \\\n    three";
+    assert_eq!( stdout( source ), [ "a", "b", "one", "two three" ] );
+}
+
+#[test]
+fn rehash_forgets_found_programs() {
+    assert!( stdout( "sh -c true; rehash -l" ).iter().any( | x | x.starts_with( "sh=" ) ) );
+    assert!( stdout( "rehash; rehash -l" ).is_empty() );
+}
+
+#[test]
+fn registered_completions_are_offered() {
+    use lumi::shell::completion::complete;
+
+    run( "complete lumi-test-words alpha,beta gamma" );
+    assert_eq!( complete( "lumi-test-words " ), [ "alpha", "beta", "gamma" ] );
+    assert_eq!( complete( "lumi-test-words g" ), [ "gamma" ] );
+
+    // completion commands run in the process like any other
+    run( "complete lumi-test-cmd -c 'echo one; echo $COMP_WORD-two'" );
+    assert_eq!( exclusive( || complete( "lumi-test-cmd o" ) ), [ "o-two", "one" ] );
+
+    run( "complete -r lumi-test-words" );
+    assert_eq!( stdout( "complete lumi-test-cmd" ), [ "command: echo one; echo $COMP_WORD-two" ] );
+}
+
+#[test]
+fn builtin_help_is_output() {
+    let lines = stdout( "pwd --help" );
+    assert!( lines.iter().any( | x | x == "USAGE:" ), "{:?}", lines );
+    assert!( lines.iter().all( | x | !x.contains( '\x1B' ) ) );
+    assert_eq!( run( "pwd --bogus" ).stderr().map( | x | x[0].starts_with( "error:" ) ), Some( true ) );
+
+    // bad usage is a failure, so it stops && chains and set -e like any other
+    assert_eq!( run( "cd --bogus" ).code(), Some( 2 ) );
+    assert_eq!( stdout( "cd --bogus && echo ran; history --bogus || echo failed" ), [ "failed" ] );
+    assert_eq!( stdout( "set -e; kill --bogus; echo ran" ), Vec::<String>::new() );
+}
+
+#[test]
+fn missing_commands_are_given_to_the_hook() {
+    use lumi::shell::context::ExecContext;
+
+    let res = exclusive( || {
+        let mut ctx = ExecContext::new();
+        ctx.set_not_found_hook( Some( "echo missing:".to_string() ) );
+        let found = common::parse( "lumi-no-such-command a b" ).execute( &mut ctx, true, None );
+
+        // a hook that can't be found either is the same as none
+        ctx.set_not_found_hook( Some( "lumi-no-such-hook".to_string() ) );
+        let missing = common::parse( "lumi-no-such-command" ).execute( &mut ctx, true, None );
+
+        ( found.map( | x | x.stdout().cloned() ).ok().flatten(), missing.is_err() )
+    } );
+
+    assert_eq!( res, ( Some( vec![ "missing: lumi-no-such-command a b".to_string() ] ), true ) );
+}
+
+#[test]
+fn assignments_before_a_command_are_only_for_it() {
+    assert_eq!( stdout( "LUMI_TEST_PREFIX=a printenv LUMI_TEST_PREFIX" ), [ "a" ] );
+    assert_eq!( stdout( "LUMI_TEST_PREFIX=b printenv LUMI_TEST_PREFIX | cat" ), [ "b" ] );
+    assert_eq!( run( "LUMI_TEST_PREFIX=c true; printenv LUMI_TEST_PREFIX" ).code(), Some( 1 ) );
+    assert_eq!( stdout( "x=1; echo $x" ), [ "1" ] );
+}
+
+#[test]
+fn assignments_take_command_output() {
+    assert_eq!( stdout( "i=0; i=$(echo 5); echo $i" ), [ "5" ] );
+    assert_eq!( stdout( "i=0; while test $i -lt 3 { echo $i; i=$(expr $i + 1) }" ), [ "0", "1", "2" ] );
+    assert_eq!( stdout( "LUMI_TEST_PREFIX=$(echo d) printenv LUMI_TEST_PREFIX" ), [ "d" ] );
+    assert_eq!( stdout( "x=$(echo a b)c; echo $x" ), [ "a bc" ] );
+}
+
+#[test]
+fn set_e_stops_at_the_first_failure() {
+    assert_eq!( stdout( "set -e; echo a; false || echo b; false; echo c" ), [ "a", "b" ] );
+    assert_eq!( stdout( "set -e; for x in 1 2 { echo $x; false }" ), [ "1" ] );
+    assert_eq!( stdout( "set -e; set +e; false; echo d" ), [ "d" ] );
+}
+
+#[test]
+fn set_u_decides_whether_unset_variables_are_errors() {
+    assert!( try_run( "echo $lumi_test_unset" ).is_err() );
+    assert_eq!( stdout( "set +u; echo a $lumi_test_unset b" ), [ "a b" ] );
+}
+
+#[test]
+fn pipelines_record_every_stage_status() {
+    assert_eq!( stdout( "false | echo a | true; echo $? $pipestatus" ), [ "0 1 0 0" ] );
+    assert_eq!( stdout( "set -o pipefail; false | true; echo $? $pipestatus" ), [ "1 1 0" ] );
+    assert_eq!( stdout( "sh -c 'kill -9 $$' | true; echo $pipestatus" ), [ "137 0" ] );
+    assert_eq!( stdout( "true | sh -c 'kill -9 $$' | true; echo $pipestatus" ), [ "0 137 0" ] );
+    assert_eq!( stdout( "set -o pipefail; sh -c 'kill -9 $$' | true; echo $? $pipestatus" ), [ "137 137 0" ] );
+    assert_eq!( stdout( "echo $(sh -c 'kill -9 $$' | true; echo $pipestatus)" ), [ "137 0" ] );
+}
+
+#[test]
+fn kill_signals_jobs_and_processes() {
+    let lines = stdout( "sleep 30 &; kill -KILL %1; sleep 0.2; jobs" );
+    assert_eq!( lines.last().map( String::as_str ), Some( "[1] Done (137)  sleep 30" ) );
+    assert!( stdout( "kill -l" ).contains( &" 9 KILL".to_string() ) );
+    assert_eq!( run( "kill -s NOTASIGNAL 1" ).code(), Some( 1 ) );
+    assert_eq!( run( "kill %99" ).code(), Some( 1 ) );
+}
+
+#[test]
+fn long_scripts_and_pipelines_run() {
+    let script: String = ( 0 .. 20000 ).map( | i | format!( "x={}\n", i ) ).collect();
+    assert_eq!( stdout( &format!( "{}echo $x", script ) ), [ "19999" ] );
+    assert_eq!( stdout( &format!( "echo hi{}", " | cat".repeat( 200 ) ) ), [ "hi" ] );
+}
+
+#[test]
+fn programs_that_cant_start_say_why() {
+    let dir = std::env::temp_dir().join( format!( "lumi-test-spawn-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+    let plain = dir.join( "plain" );
+    std::fs::write( &plain, "echo hi\n" ).unwrap();
+
+    let error = | source: String | try_run( &source ).err().map( | e | e.to_string() ).unwrap_or_default();
+    assert!( error( dir.display().to_string() ).contains( "is a directory" ) );
+    assert!( error( plain.display().to_string() ).contains( "is not executable" ) );
+
+    std::fs::remove_dir_all( &dir ).unwrap();
+}
+
+#[test]
+fn long_argument_lists_are_split_or_explained() {
+    let dir = std::env::temp_dir().join( format!( "lumi-test-argv-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+
+    // far more than any system lets one program be started with
+    let source = format!( "cd '{}'; rm -f $(seq 1 300000); echo done", dir.display() );
+    assert_eq!( stdout( &source ), [ "done" ] );
+
+    let error = try_run( "sh -c : $(seq 1 300000)" ).err().map( | e | e.to_string() ).unwrap_or_default();
+    assert!( error.contains( "argument list too long for 'sh'" ) && error.contains( "seq 1 300000" ), "{}", error );
+
+    // in pipelines and par blocks too, with the list still only worked out once
+    let count = dir.join( "count" );
+    let source = format!( "cd '{0}'; rm -f $(echo . >> '{1}'; seq 1 300000) | cat; par {{ rm -f $(echo . >> '{1}'; seq 1 300000); true }}; echo done", dir.display(), count.display() );
+    assert_eq!( stdout( &source ), [ "done" ] );
+    assert_eq!( std::fs::read_to_string( &count ).unwrap(), ".\n.\n" );
+
+    let error = try_run( "sh -c : $(seq 1 300000) | cat" ).err().map( | e | e.to_string() ).unwrap_or_default();
+    assert!( error.contains( "argument list too long for 'sh'" ), "{}", error );
+
+    std::fs::remove_dir_all( &dir ).unwrap();
+}
+
+#[test]
+fn each_runs_a_command_per_line() {
+    assert_eq!( stdout( "echo \"a\nb\n\nc\" | each echo x" ), [ "x a", "x b", "x c" ] );
+    assert_eq!( stdout( "echo \"a\nb\nc\" | each -n 2 echo '<{}>' \"{}\"" ), [ "<a b> a b", "<c> c" ] );
+    assert_eq!( stdout( "echo '{}' \"{ echo x }\" '\\{\\}'" ), [ "{} x {}" ] );
+    assert_eq!( stdout( "echo \"a\nb\nc\" | each -P 2 -I @ sh -c 'echo @'" ), [ "a", "b", "c" ] );
+    assert_eq!( run( "echo \"a\nb\" | each -P 2 false" ).code(), Some( 123 ) );
+}
+
+#[test]
+fn par_runs_commands_together() {
+    let start = std::time::Instant::now();
+    assert_eq!( stdout( "par { sleep 0.3; sh -c 'sleep 0.3; echo a'; echo b }" ), [ "a", "b" ] );
+    assert!( start.elapsed() < std::time::Duration::from_millis( 550 ) );
+
+    assert_eq!( run( "par { true; sh -c 'exit 3'; false }" ).code(), Some( 3 ) );
+    assert_eq!( run( "par { }" ).code(), Some( 0 ) );
+}
+
+#[test]
+fn par_stops_its_programs_when_a_command_errors() {
+    let file = std::env::temp_dir().join( format!( "lumi-test-par-{}", std::process::id() ) );
+    let _ = std::fs::remove_file( &file );
+
+    let source = format!( "par {{ sh -c 'sleep 0.3; echo late > \"{}\"'; echo $lumi_test_unset }}", file.display() );
+    assert!( try_run( &source ).is_err() );
+    std::thread::sleep( std::time::Duration::from_millis( 600 ) );
+    assert!( !file.exists() );
+}
+
+#[test]
+fn json_passes_between_builtins() {
+    let json = "echo '\\{\"items\": [\\{\"name\": \"a\"\\}, \\{\"name\": \"b\"\\}]\\}'";
+    assert_eq!( stdout( &format!( "{} | from-json | get items.1.name", json ) ), [ "b" ] );
+    assert_eq!( stdout( &format!( "{} | get items.0 | to-json -c", json ) ), [ "{\"name\":\"a\"}" ] );
+
+    // the value only goes to builtins; a program in between passes text
+    assert_eq!( stdout( &format!( "{} | get items.0.name | to-json", json ) ), [ "\"a\"" ] );
+    assert_eq!( stdout( &format!( "{} | get items.0.name | cat | to-json -c", json ) ), [ "[\"a\"]" ] );
+
+    assert_eq!( run( &format!( "{} | get items.5", json ) ).code(), Some( 1 ) );
+    assert_eq!( run( "echo nope | from-json" ).code(), Some( 1 ) );
+}
+
+#[test]
+fn builtin_pipeline_stages_expand_once() {
+    let file = std::env::temp_dir().join( format!( "lumi-test-expand-{}", std::process::id() ) );
+    let source = format!( "echo $(echo . >> '{0}') | cat; echo $(echo . >> '{0}') x | cat", file.display() );
+    assert_eq!( stdout( &source ), [ "", "x" ] );
+    assert_eq!( std::fs::read_to_string( &file ).unwrap().lines().count(), 2 );
+    std::fs::remove_file( &file ).unwrap();
+}
+
+#[test]
+fn large_here_documents_are_fed_in_full() {
+    // bodies bigger than a pipe holds, given to a program whose output is still to be read
+    let body: Vec<String> = ( 0 .. 20000 ).map( | x | format!( "line {}", x ) ).collect();
+    let source = format!( "cat <<EOF | wc -l\n{}\nEOF", body.join( "\n" ) );
+    assert_eq!( stdout( &source )[0].trim(), "20000" );
+    assert_eq!( stdout( "cat <<< $(seq 1 100000) | wc -l" )[0].trim(), "100000" );
+}
+
+#[test]
+fn groups_in_pipelines_read_their_input() {
+    assert_eq!( stdout( "echo a | { cat; echo b }" ), [ "a", "b" ] );
+    assert_eq!( stdout( "echo a | { echo b; cat; cat }" ), [ "b", "a" ] );
+    assert_eq!( stdout( "echo a | { grep a && echo found }" ), [ "a", "found" ] );
+
+    // more than a pipe holds, so the group's program is still being fed while its output is read
+    assert_eq!( stdout( "seq 1 200000 | { cat } | wc -l" )[0].trim(), "200000" );
+}
+
+#[test]
+fn output_passes_through_pipes_and_files_unchanged() {
+    let dir = std::env::temp_dir().join( format!( "lumi-test-bytes-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+    let bytes: Vec<u8> = ( 0 .. 2048u32 ).map( | x | ( x * 7 % 256 ) as u8 ).collect();
+    std::fs::write( dir.join( "bin" ), &bytes ).unwrap();
+
+    run( &format!( "cd '{}'; printf 'a\\r\\nb' | cat > crlf; cat bin | cat > out; cat bin | {{ cat }} | cat > grouped", dir.display() ) );
+    assert_eq!( std::fs::read( dir.join( "crlf" ) ).unwrap(), b"a\r\nb" );
+    assert_eq!( std::fs::read( dir.join( "out" ) ).unwrap(), bytes );
+    assert_eq!( std::fs::read( dir.join( "grouped" ) ).unwrap(), bytes );
+
+    std::fs::remove_dir_all( &dir ).unwrap();
+}